@@ -0,0 +1,31 @@
+use std::process::Command;
+
+fn main() {
+    // Commit the agent was built from; "unknown" when building outside a git checkout
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // SOURCE_DATE_EPOCH keeps the build date reproducible when set
+    let build_date = std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(|feature| feature.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=MAESTRO_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=MAESTRO_BUILD_DATE={}", build_date);
+    println!("cargo:rustc-env=MAESTRO_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
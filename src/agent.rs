@@ -1,5 +1,8 @@
+use std::io;
+use std::path::Path;
 use uuid::Uuid;
-use std::result::Result;
+
+const AGENT_ID_FILE: &str = "agent_id";
 
 pub struct Agent {
     id: Uuid,
@@ -8,12 +11,25 @@ pub struct Agent {
 }
 
 impl Agent {
-    pub fn new(name: String, version: String) -> Self {
-        Self {
-            id: Uuid::new_v4(),
+    /// Creates the agent, reusing the id persisted in `data_dir` or generating one on first start
+    pub fn load(name: String, version: String, data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(AGENT_ID_FILE);
+        let id = match std::fs::read_to_string(&path) {
+            Ok(contents) => Uuid::parse_str(contents.trim())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let id = Uuid::new_v4();
+                std::fs::write(&path, id.to_string())?;
+                id
+            },
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            id,
             name,
             version,
-        }
+        })
     }
     
     pub fn id(&self) -> Uuid {
@@ -27,8 +43,4 @@ impl Agent {
     pub fn version(&self) -> &str {
         &self.version
     }
-    pub async fn start() -> Result<Self, std::io::Error> {
-    let agent = Agent::new("Horizon-Maestro".to_string(), env!("CARGO_PKG_VERSION").to_string());
-        Ok(agent)
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,151 @@
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+use bollard::container::LogOutput;
+use bollard::exec::ResizeExecOptions;
+use futures::{SinkExt, Stream, StreamExt};
+use rocket::data::{IoHandler, IoStream};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use crate::config::AttachConfig;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::AttachSession;
+
+type ExecOutput = Pin<Box<dyn Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>;
+type ExecInput = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// What clients send as text frames. Binary frames are keystrokes, passed to the TTY as they are.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+}
+
+/// A started TTY exec, bridged to the WebSocket once the request's connection is upgraded
+pub struct AttachBridge {
+    app_manager: AppManager,
+    exec_id: String,
+    output: ExecOutput,
+    input: ExecInput,
+    session: AttachSession,
+    idle_timeout: Duration,
+    max_duration: Duration,
+}
+
+impl AttachBridge {
+    pub fn new(app_manager: AppManager, exec_id: String, output: ExecOutput, input: ExecInput, session: AttachSession, limits: &AttachConfig) -> Self {
+        AttachBridge {
+            app_manager,
+            exec_id,
+            output,
+            input,
+            session,
+            idle_timeout: Duration::from_secs(limits.idle_timeout_secs),
+            max_duration: Duration::from_secs(limits.max_session_secs),
+        }
+    }
+
+    async fn write_input(&mut self, data: &[u8]) -> io::Result<()> {
+        self.input.write_all(data).await?;
+        self.input.flush().await?;
+        self.session.bytes_in += data.len() as u64;
+        Ok(())
+    }
+
+    // Relays until either side goes away or a limit is hit, returning why the session ended
+    async fn relay(&mut self, socket: &mut WebSocketStream<IoStream>) -> &'static str {
+        let deadline = Instant::now() + self.max_duration;
+        loop {
+            let idle_deadline = Instant::now() + self.idle_timeout;
+            tokio::select! {
+                message = socket.next() => {
+                    let result = match message {
+                        Some(Ok(Message::Binary(data))) => self.write_input(&data).await,
+                        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                            Ok(ControlMessage::Input { data }) => self.write_input(data.as_bytes()).await,
+                            Ok(ControlMessage::Resize { cols, rows }) => {
+                                let options = ResizeExecOptions { width: cols, height: rows };
+                                if let Err(e) = self.app_manager.instrument("resize_exec", self.app_manager.docker().resize_exec(&self.exec_id, options)).await {
+                                    tracing::warn!("Failed to resize attach session {}: {}", self.session.id, e);
+                                }
+                                Ok(())
+                            },
+                            Err(e) => {
+                                tracing::debug!("Ignoring an invalid message in attach session {}: {}", self.session.id, e);
+                                Ok(())
+                            },
+                        },
+                        Some(Ok(Message::Close(_))) | None => return "closed",
+                        // Pings are answered by the WebSocket itself
+                        Some(Ok(_)) => Ok(()),
+                        Some(Err(e)) => {
+                            tracing::warn!("Attach session {} WebSocket failed: {}", self.session.id, e);
+                            return "error";
+                        },
+                    };
+                    if let Err(e) = result {
+                        tracing::warn!("Failed to write to attach session {}: {}", self.session.id, e);
+                        return "error";
+                    }
+                },
+                chunk = self.output.next() => match chunk {
+                    Some(Ok(chunk)) => {
+                        let bytes = chunk.into_bytes();
+                        self.session.bytes_out += bytes.len() as u64;
+                        if let Err(e) = socket.send(Message::Binary(bytes.to_vec())).await {
+                            tracing::warn!("Attach session {} WebSocket failed: {}", self.session.id, e);
+                            return "error";
+                        }
+                    },
+                    Some(Err(e)) => {
+                        tracing::warn!("Failed to read from attach session {}: {}", self.session.id, e);
+                        return "error";
+                    },
+                    None => return "exited",
+                },
+                _ = sleep_until(idle_deadline) => return "idle_timeout",
+                _ = sleep_until(deadline) => return "max_duration",
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl IoHandler for AttachBridge {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        let mut bridge = *Pin::into_inner(self);
+        let mut socket = WebSocketStream::from_raw_socket(io, Role::Server, None).await;
+
+        let reason = bridge.relay(&mut socket).await;
+        // Hanging up the TTY ends the shell, which otherwise outlives the session
+        let _ = bridge.input.shutdown().await;
+        if reason != "closed" {
+            let frame = CloseFrame {
+                code: if reason == "error" { CloseCode::Error } else { CloseCode::Normal },
+                reason: reason.into(),
+            };
+            let _ = socket.close(Some(frame)).await;
+        }
+
+        let mut session = bridge.session;
+        session.ended_at = bridge.app_manager.now().to_rfc3339();
+        session.reason = reason.to_string();
+        tracing::info!(
+            session = %session.id,
+            instance = %session.instance_name,
+            token = session.token_id.as_deref().unwrap_or("-"),
+            bytes_in = session.bytes_in,
+            bytes_out = session.bytes_out,
+            "Attach session ended ({})", reason
+        );
+        bridge.app_manager.notifier.notify("instance.attached", json!(session));
+        Ok(())
+    }
+}
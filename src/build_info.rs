@@ -0,0 +1,14 @@
+use crate::routes::models::BuildInfo;
+
+/// Version and provenance of the running binary, embedded by build.rs
+pub fn current() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: env!("MAESTRO_GIT_COMMIT").to_string(),
+        build_date: env!("MAESTRO_BUILD_DATE").to_string(),
+        features: env!("MAESTRO_FEATURES").split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(String::from)
+            .collect(),
+    }
+}
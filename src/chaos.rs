@@ -0,0 +1,213 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use crate::clock::Clock;
+use crate::routes::models::{ChaosInjection, ChaosRequest};
+
+const KINDS: [&str; 4] = ["docker_delay", "docker_error", "drop_event", "panic"];
+const COMPONENTS: [&str; 4] = ["supervisor", "scheduler", "docker_cache", "log_shipper"];
+const MAX_DURATION_SECS: u64 = 3600;
+const MAX_DELAY_MS: u64 = 60_000;
+
+/// Source of the rolls that decide whether an injection hits, so tests can fix the outcome
+pub trait Dice: Send + Sync {
+    /// A number in [0, 1)
+    fn roll(&self) -> f64;
+}
+
+pub struct RandomDice;
+
+impl Dice for RandomDice {
+    fn roll(&self) -> f64 {
+        rand::random()
+    }
+}
+
+/// Time-bounded faults injected through /agent/chaos, only compiled in with the `chaos` feature.
+/// Every injection and every hit is logged at warn level so they can't go unnoticed.
+pub struct Chaos {
+    injections: Mutex<Vec<ChaosInjection>>,
+    clock: Arc<dyn Clock>,
+    dice: Box<dyn Dice>,
+}
+
+impl Chaos {
+    pub fn new(clock: Arc<dyn Clock>, dice: Box<dyn Dice>) -> Self {
+        Chaos {
+            injections: Mutex::new(Vec::new()),
+            clock,
+            dice,
+        }
+    }
+
+    pub fn inject(&self, request: ChaosRequest) -> Result<ChaosInjection, String> {
+        if !KINDS.contains(&request.kind.as_str()) {
+            return Err(format!("Unknown chaos kind '{}', expected one of {}", request.kind, KINDS.join(", ")));
+        }
+        let targets_component = matches!(request.kind.as_str(), "drop_event" | "panic");
+        if targets_component && !request.target.as_deref().is_some_and(|target| COMPONENTS.contains(&target)) {
+            return Err(format!("{} requires a target component: {}", request.kind, COMPONENTS.join(", ")));
+        }
+        let probability = request.probability.unwrap_or(1.0);
+        if !(probability > 0.0 && probability <= 1.0) {
+            return Err("probability must be greater than 0 and at most 1".to_string());
+        }
+        if request.count == Some(0) {
+            return Err("count must be at least 1".to_string());
+        }
+        if request.kind == "docker_delay" && !request.delay_ms.is_some_and(|delay| delay > 0 && delay <= MAX_DELAY_MS) {
+            return Err(format!("docker_delay requires delay_ms between 1 and {}", MAX_DELAY_MS));
+        }
+        if request.duration_secs == 0 || request.duration_secs > MAX_DURATION_SECS {
+            return Err(format!("duration_secs must be between 1 and {}", MAX_DURATION_SECS));
+        }
+
+        let now = self.clock.now();
+        let injection = ChaosInjection {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind: request.kind,
+            target: request.target,
+            probability,
+            remaining: request.count,
+            delay_ms: request.delay_ms,
+            hits: 0,
+            created_at: now.to_rfc3339(),
+            expires_at: (now + Duration::from_secs(request.duration_secs)).to_rfc3339(),
+        };
+        tracing::warn!("CHAOS: injecting {} into {} for {}s ({})", injection.kind, injection.target.as_deref().unwrap_or("every docker operation"), request.duration_secs, injection.id);
+        self.injections.lock().unwrap().push(injection.clone());
+        Ok(injection)
+    }
+
+    /// Active injections; expired and spent ones are dropped here
+    pub fn list(&self) -> Vec<ChaosInjection> {
+        let mut injections = self.injections.lock().unwrap();
+        prune(&mut injections, self.clock.now());
+        injections.clone()
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        let mut injections = self.injections.lock().unwrap();
+        let before = injections.len();
+        injections.retain(|injection| injection.id != id);
+        if injections.len() < before {
+            tracing::warn!("CHAOS: injection {} removed", id);
+        }
+        injections.len() < before
+    }
+
+    // Roll for the first live injection of `kind` aimed at `target`, counting the hit
+    fn hit(&self, kind: &str, target: &str) -> Option<ChaosInjection> {
+        let mut injections = self.injections.lock().unwrap();
+        prune(&mut injections, self.clock.now());
+        let injection = injections.iter_mut()
+            .find(|injection| injection.kind == kind && injection.target.as_deref().is_none_or(|aimed| aimed == target))?;
+        if self.dice.roll() >= injection.probability {
+            return None;
+        }
+
+        if let Some(remaining) = injection.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        injection.hits += 1;
+        tracing::warn!("CHAOS: {} hit {} ({})", kind, target, injection.id);
+        Some(injection.clone())
+    }
+
+    /// Delays or fails a Docker API call as injected
+    pub async fn before_docker_call(&self, operation: &str) -> Result<(), bollard::errors::Error> {
+        if let Some(injection) = self.hit("docker_delay", operation) {
+            tokio::time::sleep(Duration::from_millis(injection.delay_ms.unwrap_or_default())).await;
+        }
+        match self.hit("docker_error", operation) {
+            Some(injection) => Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 500,
+                message: format!("chaos: injected failure ({})", injection.id),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `component` should ignore the Docker event it just received
+    pub fn drops_event(&self, component: &str) -> bool {
+        self.hit("drop_event", component).is_some()
+    }
+
+    /// Panics in `component` when a panic is injected into it. With the release profile's
+    /// panic = "abort" this takes the whole agent down.
+    pub fn maybe_panic(&self, component: &str) {
+        if let Some(injection) = self.hit("panic", component) {
+            panic!("chaos: injected panic in {} ({})", component, injection.id);
+        }
+    }
+}
+
+fn prune(injections: &mut Vec<ChaosInjection>, now: DateTime<Utc>) {
+    injections.retain(|injection| {
+        let expired = chrono::DateTime::parse_from_rfc3339(&injection.expires_at).is_ok_and(|expires_at| expires_at <= now);
+        let spent = injection.remaining == Some(0);
+        if expired || spent {
+            tracing::warn!("CHAOS: injection {} of {} is over after {} hits", injection.id, injection.kind, injection.hits);
+        }
+        !(expired || spent)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+
+    struct FixedDice(f64);
+
+    impl Dice for FixedDice {
+        fn roll(&self) -> f64 {
+            self.0
+        }
+    }
+
+    fn request(kind: &str, target: Option<&str>) -> ChaosRequest {
+        ChaosRequest {
+            kind: kind.to_string(),
+            target: target.map(str::to_string),
+            probability: Some(0.5),
+            count: None,
+            delay_ms: None,
+            duration_secs: 60,
+        }
+    }
+
+    #[test]
+    fn injections_expire_with_the_clock() {
+        let clock = TestClock::at(Utc::now());
+        let chaos = Chaos::new(Arc::new(clock.clone()), Box::new(FixedDice(0.0)));
+        chaos.inject(request("drop_event", Some("scheduler"))).unwrap();
+        assert!(chaos.drops_event("scheduler"));
+        assert!(!chaos.drops_event("supervisor"));
+
+        clock.advance(chrono::TimeDelta::seconds(60));
+        assert!(!chaos.drops_event("scheduler"));
+        assert!(chaos.list().is_empty());
+    }
+
+    #[test]
+    fn rolls_at_or_over_the_probability_miss() {
+        let clock = TestClock::at(Utc::now());
+        let chaos = Chaos::new(Arc::new(clock), Box::new(FixedDice(0.5)));
+        chaos.inject(request("drop_event", Some("scheduler"))).unwrap();
+        assert!(!chaos.drops_event("scheduler"));
+        assert_eq!(chaos.list()[0].hits, 0);
+    }
+
+    #[test]
+    fn counted_injections_are_spent() {
+        let chaos = Chaos::new(Arc::new(TestClock::at(Utc::now())), Box::new(FixedDice(0.0)));
+        chaos.inject(ChaosRequest {
+            count: Some(2),
+            ..request("drop_event", Some("docker_cache"))
+        }).unwrap();
+        assert!(chaos.drops_event("docker_cache"));
+        assert!(chaos.drops_event("docker_cache"));
+        assert!(!chaos.drops_event("docker_cache"));
+    }
+}
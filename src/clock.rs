@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time for the agent's own bookkeeping (schedules, maintenance, supervision,
+/// retention and history) and the timestamps it hands out, so it can be driven by a fake clock.
+/// Times handed to Docker use the wall clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that stands still until advanced, for tests
+#[cfg(test)]
+#[derive(Clone)]
+pub struct TestClock(std::sync::Arc<std::sync::Mutex<DateTime<Utc>>>);
+
+#[cfg(test)]
+impl TestClock {
+    pub fn at(now: DateTime<Utc>) -> Self {
+        TestClock(std::sync::Arc::new(std::sync::Mutex::new(now)))
+    }
+
+    pub fn advance(&self, by: chrono::TimeDelta) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::{self, Cursor, Read, Write};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rocket::data::{ByteUnit, Data};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::{Request, Response};
+use crate::config::CompressionConfig;
+
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    // In order of preference when the client accepts several equally
+    const ALL: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::encode_all(body, ZSTD_LEVEL),
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(body)?;
+                Ok(writer.into_inner())
+            },
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            },
+        }
+    }
+}
+
+// The encoding an Accept-Encoding header ranks highest, e.g. "gzip, br;q=0.8" is gzip.
+// Encodings it doesn't name get the weight of "*", or none at all.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let weights: Vec<(&str, f32)> = accept_encoding.split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().filter(|name| !name.is_empty())?;
+            let weight = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map(|q| q.parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((name, weight))
+        })
+        .collect();
+    let weight = |name: &str| weights.iter()
+        .find(|(accepted, _)| accepted.eq_ignore_ascii_case(name))
+        .or_else(|| weights.iter().find(|(accepted, _)| *accepted == "*"))
+        .map_or(0.0, |(_, weight)| *weight);
+
+    Encoding::ALL.into_iter()
+        .map(|encoding| (encoding, weight(encoding.name())))
+        .filter(|(_, weight)| *weight > 0.0)
+        .fold(None, |best: Option<(Encoding, f32)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })
+        .map(|(encoding, _)| encoding)
+}
+
+/// Fairing compressing sized responses of the configured content types with the encoding
+/// the client prefers. Streamed bodies are left alone, since they'd have to be buffered whole.
+pub struct ResponseCompression {
+    min_size: usize,
+    content_types: Vec<String>,
+}
+
+impl ResponseCompression {
+    /// The min_size was validated with the rest of the config
+    pub fn new(config: &CompressionConfig) -> Self {
+        ResponseCompression {
+            min_size: config.min_size.parse::<ByteUnit>().map_or(1024, |size| size.as_u64() as usize),
+            content_types: config.content_types.iter().map(|content_type| content_type.to_ascii_lowercase()).collect(),
+        }
+    }
+
+    fn compressible(&self, response: &Response<'_>) -> bool {
+        let Some(content_type) = response.content_type() else { return false };
+        let media_type = format!("{}/{}", content_type.top(), content_type.sub()).to_ascii_lowercase();
+        self.content_types.contains(&media_type)
+            && response.headers().get_one("Content-Encoding").is_none()
+            && response.body().preset_size().is_some_and(|size| size >= self.min_size)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for ResponseCompression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !self.compressible(response) {
+            return;
+        }
+        // Whether the body is compressed depends on the request, which caches need to know
+        response.set_header(Header::new("Vary", "Accept-Encoding"));
+        let Some(encoding) = request.headers().get_one("Accept-Encoding").and_then(negotiate) else { return };
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::warn!("Failed to read a response body to compress: {}", e);
+                return;
+            }
+        };
+        match encoding.compress(&body) {
+            Ok(compressed) => {
+                response.set_header(Header::new("Content-Encoding", encoding.name()));
+                response.set_sized_body(compressed.len(), Cursor::new(compressed));
+            },
+            Err(e) => {
+                tracing::warn!("Failed to compress a response with {}: {}", encoding.name(), e);
+                response.set_sized_body(body.len(), Cursor::new(body));
+            }
+        }
+    }
+}
+
+/// Reads a request body of at most `limit`, decompressing it first when it was sent with
+/// `Content-Encoding: gzip`. The limit holds for the decompressed body as well, so a small
+/// upload can't inflate into an unbounded one.
+pub async fn read_body(request: &Request<'_>, data: Data<'_>, limit: ByteUnit) -> Result<Vec<u8>, (Status, String)> {
+    let gzipped = match request.headers().get_one("Content-Encoding").map(str::trim) {
+        None | Some("identity") => false,
+        Some(encoding) if encoding.eq_ignore_ascii_case("gzip") => true,
+        Some(encoding) => return Err((Status::UnsupportedMediaType, format!("Unsupported Content-Encoding {}, only gzip is accepted", encoding))),
+    };
+
+    let body = match data.open(limit).into_bytes().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        Ok(_) => return Err((Status::PayloadTooLarge, format!("Request body is larger than {}", limit))),
+        Err(e) => return Err((Status::BadRequest, format!("Failed to read the request body: {}", e))),
+    };
+    if !gzipped {
+        return Ok(body);
+    }
+
+    let mut decompressed = Vec::new();
+    if let Err(e) = GzDecoder::new(body.as_slice()).take(limit.as_u64() + 1).read_to_end(&mut decompressed) {
+        return Err((Status::BadRequest, format!("Invalid gzip request body: {}", e)));
+    }
+    if decompressed.len() as u64 > limit.as_u64() {
+        tracing::warn!("Rejected a gzip request body of {} bytes that decompresses to more than {}", body.len(), limit);
+        return Err((Status::PayloadTooLarge, format!("Decompressed request body is larger than {}", limit)));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::ContentType;
+    use serde_json::{json, Value};
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn decompress(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut decompressed = Vec::new();
+        match encoding {
+            "zstd" => decompressed = zstd::decode_all(body).unwrap(),
+            "br" => { brotli::Decompressor::new(body, 4096).read_to_end(&mut decompressed).unwrap(); },
+            "gzip" => { GzDecoder::new(body).read_to_end(&mut decompressed).unwrap(); },
+            other => panic!("unexpected encoding {}", other),
+        }
+        decompressed
+    }
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        Encoding::Gzip.compress(body).unwrap()
+    }
+
+    #[test]
+    fn negotiates_the_highest_ranked_encoding() {
+        for (accept_encoding, expected) in [
+            ("gzip", Some(Encoding::Gzip)),
+            ("gzip, br;q=0.8", Some(Encoding::Gzip)),
+            ("gzip;q=0.5, br", Some(Encoding::Brotli)),
+            ("gzip, br, zstd", Some(Encoding::Zstd)),
+            ("*", Some(Encoding::Zstd)),
+            ("*;q=0.5, gzip", Some(Encoding::Gzip)),
+            ("zstd;q=0, *", Some(Encoding::Brotli)),
+            ("identity", None),
+            ("gzip;q=0", None),
+            ("", None),
+        ] {
+            assert_eq!(negotiate(accept_encoding), expected, "{:?}", accept_encoding);
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_are_compressed_as_the_client_prefers() {
+        let agent = TestAgent::start().await;
+        let containers: Vec<Value> = (0..40)
+            .map(|i| json!({ "Id": format!("{:064x}", i), "Names": [format!("/game-{}", i)], "Image": "horizon:1", "Created": 1_772_334_000, "State": "running", "Status": "Up 2 hours" }))
+            .collect();
+        agent.docker.route("GET", "/containers/json", move |_| DockerResponse::ok(json!(containers)));
+        let client = agent.client().await;
+
+        let plain = client.get("/instances?per_page=100").dispatch().await;
+        assert!(plain.headers().get_one("Content-Encoding").is_none());
+        assert_eq!(plain.headers().get_one("Vary"), Some("Accept-Encoding"));
+        let plain = plain.into_bytes().await.unwrap();
+        assert!(plain.len() > 1024);
+
+        for encoding in ["zstd", "br", "gzip"] {
+            let response = client.get("/instances?per_page=100").header(Header::new("Accept-Encoding", encoding)).dispatch().await;
+            assert_eq!(response.headers().get_one("Content-Encoding"), Some(encoding));
+            let compressed = response.into_bytes().await.unwrap();
+            assert!(compressed.len() < plain.len() / 4, "{}: {} of {} bytes", encoding, compressed.len(), plain.len());
+            assert_eq!(decompress(encoding, &compressed), plain, "{}", encoding);
+        }
+
+        // Small responses go out as they are
+        let response = client.get("/instances/count").header(Header::new("Accept-Encoding", "gzip")).dispatch().await;
+        assert!(response.headers().get_one("Content-Encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn gzipped_request_bodies_are_inflated_within_the_limit() {
+        let agent = TestAgent::with_config(|config| config.server.import_limit = "1 MiB".to_string()).await;
+        let client = agent.client().await;
+        let import = |body: Vec<u8>, encoding: &'static str| client.post("/agent/import")
+            .header(ContentType::JSON)
+            .header(Header::new("Content-Encoding", encoding))
+            .body(body)
+            .dispatch();
+
+        let bundle = json!({ "version": 1, "exported_at": "2026-03-01T00:00:00+00:00", "agent_id": "old", "instances": [], "schedules": [], "registries": [] });
+        let response = import(gzip(bundle.to_string().as_bytes()), "gzip").await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // 64 MiB of padding that gzips down to a few dozen KiB
+        let mut bomb = bundle.to_string().into_bytes();
+        bomb.extend(std::iter::repeat_n(b' ', 64 * 1024 * 1024));
+        let bomb = gzip(&bomb);
+        assert!(bomb.len() < 1024 * 1024);
+        let response = import(bomb, "gzip").await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"]["code"], "payload_too_large");
+
+        assert_eq!(import(b"not gzip".to_vec(), "gzip").await.status(), Status::BadRequest);
+        assert_eq!(import(bundle.to_string().into_bytes(), "br").await.status(), Status::UnsupportedMediaType);
+    }
+}
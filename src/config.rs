@@ -0,0 +1,678 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use rocket::data::ByteUnit;
+use serde::{Deserialize, Serialize};
+use crate::crypto;
+use crate::paths::Locations;
+use crate::tls;
+use crate::routes::auth::{ADMIN_PERMISSION, ATTACH_PERMISSION, IMAGE_POLICY_BYPASS_PERMISSION, TUNNEL_PERMISSION};
+
+/// Commented template printed by `--print-default-config`
+pub const DEFAULT_CONFIG_TEMPLATE: &str = r#"# Horizon-Maestro agent configuration
+#
+# Every value below is the built-in default. Environment variables override the
+# file: MAESTRO_BIND_ADDRESS, MAESTRO_PORT, MAESTRO_DATA_DIR, MAESTRO_DOCKER_ENDPOINT,
+# MAESTRO_DOCKER_STARTUP_TIMEOUT, MAESTRO_AUTH_TOKEN and MAESTRO_ENCRYPTION_KEY.
+
+# Name reported for this agent
+name = "Horizon-Maestro 1"
+
+# Directory holding the agent id and persisted instance state. --data-dir and
+# MAESTRO_DATA_DIR take precedence. Defaults to $XDG_DATA_HOME/maestro (or
+# ~/.local/share/maestro), and /var/lib/maestro when started with --system.
+# data_dir = "/var/lib/maestro"
+
+# Base64-encoded 32-byte key used to encrypt instance secrets and registry passwords
+# at rest, e.g. the output of `openssl rand -base64 32`. Neither can be stored without it.
+# encryption_key = ""
+
+[server]
+# Address and port the HTTP API binds to
+address = "0.0.0.0"
+port = 8000
+
+# Largest JSON request body accepted, and the larger allowance for POST /agent/import
+# bundles. Bigger requests are rejected with 413 before they're buffered.
+json_limit = "1 MiB"
+import_limit = "16 MiB"
+
+# Serve the API over HTTPS. With client_ca set, clients presenting a certificate must
+# have one signed by it; require_client_cert also refuses clients presenting none, so
+# only the master (or whoever holds a signed certificate) gets past the handshake.
+# [server.tls]
+# cert = "/etc/maestro/tls/agent.pem"
+# key = "/etc/maestro/tls/agent-key.pem"
+# client_ca = "/etc/maestro/tls/master-ca.pem"
+# require_client_cert = true
+
+# Compress responses with zstd, br or gzip, whichever the client's Accept-Encoding prefers.
+# Only bodies of at least min_size with one of content_types are compressed; streamed
+# responses such as followed logs are always sent as they are.
+[server.compression]
+enabled = true
+min_size = "1 KiB"
+content_types = ["application/json", "text/plain"]
+
+[docker]
+# Daemon endpoint: unix:///var/run/docker.sock, tcp://host:2376 or http://host:2375.
+# Leave unset to use the platform's local default.
+# endpoint = "unix:///var/run/docker.sock"
+
+# Seconds to keep retrying the daemon at startup before giving up
+startup_timeout_secs = 30
+
+# Serve container, image, network and volume listings from a cache kept current by the
+# daemon's event stream, fully re-listed every cache_reconcile_secs to correct any drift
+cache = true
+cache_reconcile_secs = 60
+
+[attach]
+# Interactive shells opened through the /instances/<id>/attach WebSocket, for tokens with
+# the "attach" permission; with no auth.tokens configured attaching is refused. A session
+# is closed after idle_timeout_secs without traffic in either direction, and after
+# max_session_secs regardless.
+idle_timeout_secs = 600
+max_session_secs = 3600
+
+# TLS client certificates for tcp:// endpoints
+# [docker.tls]
+# ca = "/etc/maestro/docker/ca.pem"
+# cert = "/etc/maestro/docker/cert.pem"
+# key = "/etc/maestro/docker/key.pem"
+
+[auth]
+# Bearer tokens accepted on mutating routes. With no tokens configured the API is open.
+# Each token needs a unique id, which logs identify the caller by.
+# Tokens can be granted permissions: "admin" to manage the image policy,
+# "image_policy_bypass" to skip it with the X-Maestro-Image-Policy-Bypass header,
+# "attach" to open interactive shells in instances and "tunnel" to open TCP tunnels to
+# their ports. Routes that need a permission are refused while no tokens are configured.
+# [[auth.tokens]]
+# id = "master"
+# token = "change-me"
+# permissions = ["admin"]
+
+[instances]
+# Prefix for the names of containers the agent creates, e.g. "maestro-" runs instance "web"
+# as container maestro-web. Instances are addressed by their container name or id, and the
+# name without the prefix is kept in the maestro.instance label.
+name_prefix = ""
+
+[supervisor]
+# Restart policy for instances created with "supervise": true. Restarts back off
+# exponentially from initial_backoff_secs up to max_backoff_secs; after max_attempts
+# consecutive crashes the instance is marked failed. Staying up for healthy_uptime_secs
+# resets the attempt counter.
+max_attempts = 5
+initial_backoff_secs = 1
+max_backoff_secs = 60
+healthy_uptime_secs = 300
+
+[log_shipping]
+# Output of instances created with "ship_logs": true is batched and POSTed as JSON to
+# url, authenticated with token as a bearer token when set. While url is unreachable,
+# batches are spooled under the data dir, oldest dropped past spool_max_mb, and sent
+# in order once it's back.
+# url = "https://dashboard.example.com/logs/ingest"
+# token = ""
+batch_lines = 500
+flush_interval_secs = 5
+spool_max_mb = 64
+
+[stats_history]
+# CPU, memory and network usage of tracked instances is sampled every interval_secs
+# and kept for GET /instances/<id>/stats/history, up to max_points samples per instance
+# (a day at the defaults). With persist the history survives agent restarts.
+enabled = true
+interval_secs = 30
+max_points = 2880
+persist = false
+
+[desired_state]
+# Once a desired state has been PUT to /state, drift from it (instances changed, added
+# or removed through other routes) is corrected every reconcile_secs
+reconcile_secs = 60
+
+[proxy]
+# Proxy for outbound HTTP, i.e. webhook deliveries and log shipping. Whatever is left unset
+# comes from HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY. no_proxy entries match a host
+# and its subdomains, IP addresses in a CIDR range, or every host with "*". Check the result
+# with --net-check.
+# http = "http://proxy.internal:3128"
+# https = "http://proxy.internal:3128"
+# no_proxy = ["localhost", "127.0.0.0/8", "internal.example.com"]
+
+# Webhooks notified about instance lifecycle events. An empty events list means all events.
+# [[webhooks]]
+# url = "https://hooks.example.com/maestro"
+# events = ["instance.created", "instance.deleted", "instance.crashed", "instance.failed"]
+"#;
+
+/// Agent configuration loaded from agent.toml plus environment overrides
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    pub name: String,
+    pub data_dir: PathBuf,
+    pub encryption_key: Option<String>,
+    pub server: ServerConfig,
+    pub docker: DockerConfig,
+    pub auth: AuthConfig,
+    pub supervisor: SupervisorConfig,
+    pub log_shipping: LogShippingConfig,
+    pub stats_history: StatsHistoryConfig,
+    pub desired_state: DesiredStateConfig,
+    pub attach: AttachConfig,
+    pub instances: InstancesConfig,
+    pub proxy: ProxyConfig,
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    pub json_limit: String,
+    pub import_limit: String,
+    pub tls: Option<ServerTlsConfig>,
+    pub compression: CompressionConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerTlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub min_size: String,
+    pub content_types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    pub endpoint: Option<String>,
+    pub startup_timeout_secs: u64,
+    pub cache: bool,
+    pub cache_reconcile_secs: u64,
+    pub tls: Option<DockerTlsConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DockerTlsConfig {
+    pub ca: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub tokens: Vec<ApiTokenConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiTokenConfig {
+    pub id: String,
+    pub token: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub max_attempts: u32,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub healthy_uptime_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogShippingConfig {
+    pub url: Option<String>,
+    pub token: Option<String>,
+    pub batch_lines: usize,
+    pub flush_interval_secs: u64,
+    pub spool_max_mb: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsHistoryConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub max_points: usize,
+    pub persist: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DesiredStateConfig {
+    pub reconcile_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub http: Option<String>,
+    pub https: Option<String>,
+    pub no_proxy: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachConfig {
+    pub idle_timeout_secs: u64,
+    pub max_session_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstancesConfig {
+    pub name_prefix: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            name: "Horizon-Maestro 1".to_string(),
+            data_dir: PathBuf::new(),
+            encryption_key: None,
+            server: ServerConfig::default(),
+            docker: DockerConfig::default(),
+            auth: AuthConfig::default(),
+            supervisor: SupervisorConfig::default(),
+            log_shipping: LogShippingConfig::default(),
+            stats_history: StatsHistoryConfig::default(),
+            desired_state: DesiredStateConfig::default(),
+            attach: AttachConfig::default(),
+            instances: InstancesConfig::default(),
+            proxy: ProxyConfig::default(),
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: "0.0.0.0".to_string(),
+            port: 8000,
+            json_limit: "1 MiB".to_string(),
+            import_limit: "16 MiB".to_string(),
+            tls: None,
+            compression: CompressionConfig::default(),
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_size: "1 KiB".to_string(),
+            content_types: vec!["application/json".to_string(), "text/plain".to_string()],
+        }
+    }
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        DockerConfig {
+            endpoint: None,
+            startup_timeout_secs: 30,
+            cache: true,
+            cache_reconcile_secs: 60,
+            tls: None,
+        }
+    }
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        SupervisorConfig {
+            max_attempts: 5,
+            initial_backoff_secs: 1,
+            max_backoff_secs: 60,
+            healthy_uptime_secs: 300,
+        }
+    }
+}
+
+impl Default for LogShippingConfig {
+    fn default() -> Self {
+        LogShippingConfig {
+            url: None,
+            token: None,
+            batch_lines: 500,
+            flush_interval_secs: 5,
+            spool_max_mb: 64,
+        }
+    }
+}
+
+impl Default for StatsHistoryConfig {
+    fn default() -> Self {
+        StatsHistoryConfig {
+            enabled: true,
+            interval_secs: 30,
+            max_points: 2880,
+            persist: false,
+        }
+    }
+}
+
+impl Default for DesiredStateConfig {
+    fn default() -> Self {
+        DesiredStateConfig {
+            reconcile_secs: 60,
+        }
+    }
+}
+
+impl Default for AttachConfig {
+    fn default() -> Self {
+        AttachConfig {
+            idle_timeout_secs: 600,
+            max_session_secs: 3600,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Loads the configuration from the given or default config file, applies environment
+    /// and command line overrides and validates the result.
+    ///
+    /// Errors are field-level messages suitable for printing at startup.
+    pub fn load(locations: &Locations) -> Result<Self, Vec<String>> {
+        let mut config = match locations.config.clone().or_else(|| locations.default_config()) {
+            Some(path) => Self::from_file(&path)?,
+            None => AgentConfig::default(),
+        };
+
+        config.apply_env()?;
+        if let Some(data_dir) = &locations.data_dir {
+            config.data_dir = data_dir.clone();
+        }
+        if config.data_dir.as_os_str().is_empty() {
+            config.data_dir = locations.default_data_dir();
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, Vec<String>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| vec![format!("{}: {}", path.display(), e)])?;
+        toml::from_str(&contents)
+            .map_err(|e| vec![format!("{}: {}", path.display(), e.to_string().trim_end())])
+    }
+
+    fn apply_env(&mut self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if let Ok(address) = std::env::var("MAESTRO_BIND_ADDRESS") {
+            self.server.address = address;
+        }
+        if let Ok(port) = std::env::var("MAESTRO_PORT") {
+            match port.parse() {
+                Ok(port) => self.server.port = port,
+                Err(_) => errors.push(format!("MAESTRO_PORT: '{}' is not a valid port", port)),
+            }
+        }
+        if let Ok(data_dir) = std::env::var("MAESTRO_DATA_DIR") {
+            self.data_dir = PathBuf::from(data_dir);
+        }
+        if let Ok(endpoint) = std::env::var("MAESTRO_DOCKER_ENDPOINT") {
+            self.docker.endpoint = Some(endpoint);
+        }
+        if let Ok(timeout) = std::env::var("MAESTRO_DOCKER_STARTUP_TIMEOUT") {
+            match timeout.parse() {
+                Ok(timeout) => self.docker.startup_timeout_secs = timeout,
+                Err(_) => errors.push(format!("MAESTRO_DOCKER_STARTUP_TIMEOUT: '{}' is not a number of seconds", timeout)),
+            }
+        }
+        if let Ok(token) = std::env::var("MAESTRO_AUTH_TOKEN") {
+            self.auth.tokens.push(ApiTokenConfig {
+                id: "env".to_string(),
+                token,
+                permissions: Vec::new(),
+            });
+        }
+        if let Ok(key) = std::env::var("MAESTRO_ENCRYPTION_KEY") {
+            self.encryption_key = Some(key);
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Checks every field, collecting all problems rather than stopping at the first
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.name.trim().is_empty() {
+            errors.push("name: must not be empty".to_string());
+        }
+        if self.server.address.parse::<IpAddr>().is_err() {
+            errors.push(format!("server.address: '{}' is not a valid IP address", self.server.address));
+        }
+        if self.server.port == 0 {
+            errors.push("server.port: must be between 1 and 65535".to_string());
+        }
+        for (field, limit) in [("json_limit", &self.server.json_limit), ("import_limit", &self.server.import_limit)] {
+            if limit.parse::<ByteUnit>().is_err() {
+                errors.push(format!("server.{}: '{}' is not a size like \"1 MiB\" or \"512 KiB\"", field, limit));
+            }
+        }
+        if self.server.compression.min_size.parse::<ByteUnit>().is_err() {
+            errors.push(format!("server.compression.min_size: '{}' is not a size like \"1 KiB\"", self.server.compression.min_size));
+        }
+        for content_type in &self.server.compression.content_types {
+            if !content_type.contains('/') || content_type.contains(';') {
+                errors.push(format!("server.compression.content_types: '{}' is not a media type like \"application/json\"", content_type));
+            }
+        }
+        if let Some(tls) = &self.server.tls {
+            let client_ca = tls.client_ca.as_ref().map(|ca| ("client_ca", ca));
+            for (field, path) in [("cert", &tls.cert), ("key", &tls.key)].into_iter().chain(client_ca) {
+                if !path.exists() {
+                    errors.push(format!("server.tls.{}: {} does not exist", field, path.display()));
+                }
+            }
+            if tls.cert.exists() {
+                if let Err(e) = tls::certificate_expiry(&tls.cert) {
+                    errors.push(format!("server.tls.cert: {}", e));
+                }
+            }
+            if tls.require_client_cert && tls.client_ca.is_none() {
+                errors.push("server.tls.require_client_cert: requires server.tls.client_ca".to_string());
+            }
+        }
+
+        if let Some(endpoint) = &self.docker.endpoint {
+            let known_scheme = ["unix://", "tcp://", "http://", "https://"]
+                .iter()
+                .any(|scheme| endpoint.starts_with(scheme));
+            if !known_scheme {
+                errors.push(format!("docker.endpoint: '{}' must start with unix://, tcp://, http:// or https://", endpoint));
+            }
+        }
+        if self.docker.cache && self.docker.cache_reconcile_secs == 0 {
+            errors.push("docker.cache_reconcile_secs: must be at least 1".to_string());
+        }
+        if let Some(tls) = &self.docker.tls {
+            if self.docker.endpoint.as_deref().is_none_or(|endpoint| endpoint.starts_with("unix://")) {
+                errors.push("docker.tls: requires a tcp:// or https:// docker.endpoint".to_string());
+            }
+            for (field, path) in [("ca", &tls.ca), ("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.exists() {
+                    errors.push(format!("docker.tls.{}: {} does not exist", field, path.display()));
+                }
+            }
+        }
+
+        for (index, token) in self.auth.tokens.iter().enumerate() {
+            if token.id.trim().is_empty() {
+                errors.push(format!("auth.tokens[{}].id: must not be empty", index));
+            } else if self.auth.tokens[..index].iter().any(|earlier| earlier.id == token.id) {
+                // Callers are told apart by the id in logs, so two tokens must not share one
+                errors.push(format!("auth.tokens[{}].id: '{}' is already used by another token", index, token.id));
+            }
+            if token.token.trim().is_empty() {
+                errors.push(format!("auth.tokens[{}].token: must not be empty", index));
+            }
+            for permission in &token.permissions {
+                if ![ADMIN_PERMISSION, IMAGE_POLICY_BYPASS_PERMISSION, ATTACH_PERMISSION, TUNNEL_PERMISSION].contains(&permission.as_str()) {
+                    errors.push(format!("auth.tokens[{}].permissions: unknown permission {}", index, permission));
+                }
+            }
+        }
+
+        if let Some(key) = &self.encryption_key {
+            if let Err(e) = crypto::decode_key(key) {
+                errors.push(format!("encryption_key: must be 32 bytes encoded as base64 ({})", e));
+            }
+        }
+
+        if self.supervisor.initial_backoff_secs == 0 {
+            errors.push("supervisor.initial_backoff_secs: must be at least 1".to_string());
+        }
+        if self.supervisor.max_backoff_secs < self.supervisor.initial_backoff_secs {
+            errors.push("supervisor.max_backoff_secs: must not be less than initial_backoff_secs".to_string());
+        }
+
+        if let Some(url) = &self.log_shipping.url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                errors.push(format!("log_shipping.url: '{}' must be an http(s) URL", url));
+            }
+        }
+        if self.log_shipping.batch_lines == 0 {
+            errors.push("log_shipping.batch_lines: must be at least 1".to_string());
+        }
+        if self.log_shipping.flush_interval_secs == 0 {
+            errors.push("log_shipping.flush_interval_secs: must be at least 1".to_string());
+        }
+
+        if self.stats_history.enabled && self.stats_history.interval_secs == 0 {
+            errors.push("stats_history.interval_secs: must be at least 1".to_string());
+        }
+        if self.stats_history.enabled && self.stats_history.max_points == 0 {
+            errors.push("stats_history.max_points: must be at least 1".to_string());
+        }
+
+        if self.desired_state.reconcile_secs == 0 {
+            errors.push("desired_state.reconcile_secs: must be at least 1".to_string());
+        }
+
+        if self.attach.idle_timeout_secs == 0 {
+            errors.push("attach.idle_timeout_secs: must be at least 1".to_string());
+        }
+        if self.attach.max_session_secs == 0 {
+            errors.push("attach.max_session_secs: must be at least 1".to_string());
+        }
+
+        // Docker container names are [a-zA-Z0-9][a-zA-Z0-9_.-]*
+        let prefix = &self.instances.name_prefix;
+        if prefix.starts_with(['_', '.', '-']) || !prefix.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+            errors.push(format!("instances.name_prefix: '{}' must start with a letter or digit and contain only letters, digits, '_', '.' and '-'", prefix));
+        }
+
+        for (field, proxy) in [("http", &self.proxy.http), ("https", &self.proxy.https)] {
+            if let Some(proxy) = proxy {
+                if !(proxy.starts_with("http://") || proxy.starts_with("https://")) || reqwest::Url::parse(proxy).is_err() {
+                    errors.push(format!("proxy.{}: '{}' must be an http(s) URL", field, proxy));
+                }
+            }
+        }
+
+        for (index, webhook) in self.webhooks.iter().enumerate() {
+            if !(webhook.url.starts_with("http://") || webhook.url.starts_with("https://")) {
+                errors.push(format!("webhooks[{}].url: '{}' must be an http(s) URL", index, webhook.url));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(id: &str, secret: &str, permissions: &[&str]) -> ApiTokenConfig {
+        ApiTokenConfig {
+            id: id.to_string(),
+            token: secret.to_string(),
+            permissions: permissions.iter().map(|permission| permission.to_string()).collect(),
+        }
+    }
+
+    fn errors(tokens: Vec<ApiTokenConfig>) -> Vec<String> {
+        let mut config = AgentConfig::default();
+        config.auth.tokens = tokens;
+        config.validate().err().unwrap_or_default()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(AgentConfig::default().validate(), Ok(()));
+        assert!(errors(vec![token("deployer", "a", &[]), token("ops", "b", &[ADMIN_PERMISSION, TUNNEL_PERMISSION])]).is_empty());
+    }
+
+    #[test]
+    fn rejected_tokens_name_the_offending_field() {
+        let cases: [(Vec<ApiTokenConfig>, &[&str]); 5] = [
+            (vec![token("ci", "a", &[]), token("ci", "b", &[])], &["auth.tokens[1].id: 'ci' is already used by another token"]),
+            (vec![token("", "a", &[])], &["auth.tokens[0].id: must not be empty"]),
+            (vec![token("ci", " ", &[])], &["auth.tokens[0].token: must not be empty"]),
+            (vec![token("ci", "a", &["admin", "root"])], &["auth.tokens[0].permissions: unknown permission root"]),
+            (vec![token("ci", "", &["shell"]), token("ci", "b", &[])], &[
+                "auth.tokens[0].token: must not be empty",
+                "auth.tokens[0].permissions: unknown permission shell",
+                "auth.tokens[1].id: 'ci' is already used by another token",
+            ]),
+        ];
+        for (tokens, expected) in cases {
+            assert_eq!(errors(tokens.clone()), expected, "{:?}", tokens);
+        }
+    }
+
+    #[test]
+    fn problems_are_collected_across_sections() {
+        let mut config = AgentConfig::default();
+        config.server.port = 0;
+        config.auth.tokens = vec![token("ci", "", &[])];
+        config.webhooks = vec![WebhookConfig {
+            url: "ftp://hooks.example.com".to_string(),
+            events: Vec::new(),
+        }];
+
+        assert_eq!(config.validate().unwrap_err(), [
+            "server.port: must be between 1 and 65535",
+            "auth.tokens[0].token: must not be empty",
+            "webhooks[0].url: 'ftp://hooks.example.com' must be an http(s) URL",
+        ]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use bollard::container::{LogOutput, LogsOptions};
+use futures::stream::TryStreamExt;
+use serde_json::json;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::CrashReport;
+use crate::store;
+
+const CRASH_REPORTS_FILE: &str = "crash_reports.json";
+const CRASH_LOG_LINES: usize = 100;
+// Older reports of an instance are dropped once it has more than this many
+const REPORTS_PER_INSTANCE: usize = 20;
+// and the oldest overall once there are more than this many, e.g. from instances long deleted
+const MAX_REPORTS: usize = 500;
+
+/// Crash reports of supervised instances, oldest first
+pub struct CrashReportStore {
+    path: PathBuf,
+    reports: Mutex<Vec<CrashReport>>,
+}
+
+impl CrashReportStore {
+    pub fn load(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(CRASH_REPORTS_FILE);
+        let reports = store::load_json(&path)?;
+
+        Ok(CrashReportStore {
+            path,
+            reports: Mutex::new(reports),
+        })
+    }
+
+    fn add(&self, report: CrashReport) {
+        let mut reports = self.reports.lock().unwrap();
+        let instance_id = report.instance_id.clone();
+        reports.push(report);
+
+        let own = reports.iter().filter(|report| report.instance_id == instance_id).count();
+        let mut excess = own.saturating_sub(REPORTS_PER_INSTANCE);
+        reports.retain(|report| {
+            let expired = excess > 0 && report.instance_id == instance_id;
+            if expired {
+                excess -= 1;
+            }
+            !expired
+        });
+        let overflow = reports.len().saturating_sub(MAX_REPORTS);
+        reports.drain(..overflow);
+
+        if let Err(e) = store::save_json(&self.path, &*reports) {
+            tracing::warn!("Failed to persist crash reports: {}", e);
+        }
+    }
+
+    /// Reports of the instance with the full id `key` or the name `key`, including deleted ones
+    pub fn for_instance(&self, key: &str) -> Vec<CrashReport> {
+        self.reports.lock().unwrap().iter()
+            .filter(|report| report.instance_id == key || report.instance_name == key)
+            .cloned()
+            .collect()
+    }
+
+    /// Every report captured at or after `since`
+    pub fn since(&self, since: chrono::DateTime<chrono::Utc>) -> Vec<CrashReport> {
+        self.reports.lock().unwrap().iter()
+            .filter(|report| chrono::DateTime::parse_from_rfc3339(&report.captured_at).is_ok_and(|captured| captured >= since))
+            .cloned()
+            .collect()
+    }
+}
+
+async fn last_logs(app_manager: &AppManager, id: &str) -> Vec<String> {
+    let options = Some(LogsOptions::<String> {
+        stdout: true,
+        stderr: true,
+        timestamps: true,
+        tail: CRASH_LOG_LINES.to_string(),
+        ..Default::default()
+    });
+    match app_manager.instrument("logs", app_manager.docker().logs(id, options).try_collect::<Vec<_>>()).await {
+        Ok(output) => output.into_iter()
+            .filter(|chunk| !matches!(chunk, LogOutput::StdIn { .. }))
+            .flat_map(|chunk| String::from_utf8_lossy(&chunk.into_bytes()).lines().map(str::to_string).collect::<Vec<_>>())
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Failed to read the last logs of crashed instance {}: {}", id, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Records why a supervised instance died and sends instance.crashed. Runs right after the die
+/// event, before the supervisor's restart, so the logs and container state are the crash's own.
+pub async fn capture(app_manager: AppManager, id: String, exit_code: Option<i64>, restart_attempt: u32) {
+    let Some(instance) = app_manager.instance(&id) else { return };
+    let state = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(container) => container.state.unwrap_or_default(),
+        Err(e) => {
+            tracing::warn!("Failed to inspect crashed instance {}: {}", id, e);
+            Default::default()
+        }
+    };
+
+    let report = CrashReport {
+        id: uuid::Uuid::new_v4().to_string(),
+        instance_id: id.clone(),
+        instance_name: instance.name,
+        image: instance.image,
+        exit_code: state.exit_code.or(exit_code),
+        oom_killed: state.oom_killed.unwrap_or(false),
+        error: state.error.filter(|error| !error.is_empty()),
+        finished_at: state.finished_at,
+        captured_at: app_manager.now().to_rfc3339(),
+        restart_attempt,
+        last_logs: last_logs(&app_manager, &id).await,
+        last_stats: app_manager.stats.latest(&id),
+    };
+    if report.oom_killed {
+        tracing::warn!("Instance {} was killed for running out of memory", report.instance_name);
+    }
+    app_manager.crashes.add(report.clone());
+    // Like instance.failed, crashes during maintenance are recorded without alerting
+    if app_manager.maintenance().is_none() {
+        app_manager.notifier.notify("instance.crashed", json!(report));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(instance_id: &str, instance_name: &str) -> CrashReport {
+        CrashReport {
+            id: uuid::Uuid::new_v4().to_string(),
+            instance_id: instance_id.to_string(),
+            instance_name: instance_name.to_string(),
+            image: "horizon:1".to_string(),
+            exit_code: Some(1),
+            oom_killed: false,
+            error: None,
+            finished_at: None,
+            captured_at: "2026-03-01T03:00:00+00:00".to_string(),
+            restart_attempt: 1,
+            last_logs: Vec::new(),
+            last_stats: None,
+        }
+    }
+
+    #[test]
+    fn reports_are_found_by_exact_id_or_name() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let crashes = CrashReportStore::load(data_dir.path()).unwrap();
+        crashes.add(report("ab12", "game"));
+        crashes.add(report("ab12cd", "ab"));
+
+        let found = |key| crashes.for_instance(key).into_iter().map(|report| report.instance_id).collect::<Vec<_>>();
+        assert_eq!(found("ab12"), ["ab12"]);
+        assert_eq!(found("game"), ["ab12"]);
+        assert_eq!(found("ab"), ["ab12cd"]);
+        assert!(found("ab1").is_empty());
+        assert!(found("gam").is_empty());
+    }
+}
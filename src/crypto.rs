@@ -0,0 +1,100 @@
+use std::io;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+const NONCE_LEN: usize = 12;
+
+/// Decodes the base64 encryption_key from agent.toml into an AES-256 key
+pub fn decode_key(key: &str) -> Result<Key<Aes256Gcm>, String> {
+    let bytes = BASE64.decode(key.trim()).map_err(|e| e.to_string())?;
+    if bytes.len() != 32 {
+        return Err(format!("expected 32 bytes, got {}", bytes.len()));
+    }
+    Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+}
+
+/// An encrypted value as persisted in the data dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    pub nonce: String,
+    pub secret: String,
+}
+
+/// AES-256-GCM encryption of values persisted at rest
+#[derive(Clone)]
+pub struct Sealer {
+    cipher: Aes256Gcm,
+}
+
+impl Sealer {
+    /// Builds a sealer from the configured key, or None when encryption_key isn't set
+    pub fn from_key(key: Option<&str>) -> Option<Self> {
+        // The key was already validated with the rest of the config
+        let key = decode_key(key?).ok()?;
+        Some(Sealer {
+            cipher: Aes256Gcm::new(&key),
+        })
+    }
+
+    pub fn seal(&self, plaintext: &str) -> io::Result<Sealed> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let secret = self.cipher.encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|e| io::Error::other(format!("Failed to encrypt value: {}", e)))?;
+        Ok(Sealed {
+            nonce: BASE64.encode(nonce),
+            secret: BASE64.encode(secret),
+        })
+    }
+
+    /// Decrypts a sealed value; None if it was sealed under a different key or is corrupt
+    pub fn open(&self, sealed: &Sealed) -> Option<String> {
+        let nonce = BASE64.decode(&sealed.nonce).ok().filter(|nonce| nonce.len() == NONCE_LEN)?;
+        let secret = BASE64.decode(&sealed.secret).ok()?;
+        let plaintext = self.cipher.decrypt(Nonce::from_slice(&nonce), secret.as_slice()).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const OTHER_KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn sealed_values_open_under_the_same_key() {
+        let sealer = Sealer::from_key(Some(KEY)).unwrap();
+        let sealed = sealer.seal("hunter2").unwrap();
+        assert!(!sealed.secret.contains("hunter2"));
+        assert_eq!(sealer.open(&sealed).as_deref(), Some("hunter2"));
+
+        // Every seal gets a fresh nonce
+        let again = sealer.seal("hunter2").unwrap();
+        assert_ne!(again.nonce, sealed.nonce);
+        assert_ne!(again.secret, sealed.secret);
+    }
+
+    #[test]
+    fn sealed_values_dont_open_under_another_key_or_when_tampered_with() {
+        let sealed = Sealer::from_key(Some(KEY)).unwrap().seal("hunter2").unwrap();
+        assert_eq!(Sealer::from_key(Some(OTHER_KEY)).unwrap().open(&sealed), None);
+
+        let sealer = Sealer::from_key(Some(KEY)).unwrap();
+        let mut secret = BASE64.decode(&sealed.secret).unwrap();
+        secret[0] ^= 1;
+        assert_eq!(sealer.open(&Sealed { secret: BASE64.encode(secret), ..sealed.clone() }), None);
+        assert_eq!(sealer.open(&Sealed { nonce: BASE64.encode([0; 4]), ..sealed }), None);
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes_of_base64() {
+        assert!(decode_key(KEY).is_ok());
+        assert!(decode_key("not base64!").is_err());
+        assert_eq!(decode_key("AAAA").unwrap_err(), "expected 32 bytes, got 3");
+        assert!(Sealer::from_key(None).is_none());
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::labels::{self, Conventions};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::errors::AgentError;
+use crate::routes::instance_routes::{delete_instance, forget_instance, launch_instance};
+use crate::routes::models::{AppInstance, AppInstanceRequest, DesiredState, StateChange, StatePlan};
+use crate::routes::replace_routes::replace_instance;
+use crate::store;
+use crate::timeline;
+
+const DESIRED_STATE_FILE: &str = "desired_state.json";
+// Who changes made to converge are attributed to in instance timelines
+const RECONCILER_ACTOR: &str = "reconciler";
+
+/// The last desired state PUT to /state, persisted so reconciling carries on after a restart
+pub struct DesiredStateStore {
+    path: PathBuf,
+    desired: Mutex<Option<DesiredState>>,
+    // One reconcile at a time, whether from PUT /state or the loop
+    reconciling: tokio::sync::Mutex<()>,
+}
+
+impl DesiredStateStore {
+    pub fn load(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(DESIRED_STATE_FILE);
+        let desired = store::load_json(&path)?;
+
+        Ok(DesiredStateStore {
+            path,
+            desired: Mutex::new(desired),
+            reconciling: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn get(&self) -> Option<DesiredState> {
+        self.desired.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, desired: DesiredState) -> io::Result<()> {
+        let mut current = self.desired.lock().unwrap();
+        store::save_json(&self.path, &Some(&desired))?;
+        *current = Some(desired);
+        Ok(())
+    }
+}
+
+/// The declarative definition a tracked instance would have in a desired state document
+pub fn definition(conventions: &Conventions, instance: &AppInstance) -> AppInstanceRequest {
+    AppInstanceRequest {
+        name: conventions.name_of(instance),
+        image: instance.image.clone(),
+        ports: Some(instance.ports.clone()),
+        environment: Some(instance.environment.clone()),
+        volumes: Some(instance.volumes.clone()),
+        supervise: Some(instance.supervision.is_some()),
+        secrets: None,
+        ship_logs: Some(instance.ship_logs),
+        deployment: instance.labels.get(labels::DEPLOYMENT).cloned(),
+        project: instance.labels.get(labels::PROJECT).cloned(),
+    }
+}
+
+// What the replace flow would have to change to bring `instance` in line with `desired`
+fn differences(instance: &AppInstance, desired: &AppInstanceRequest) -> Vec<String> {
+    let mut differences = Vec::new();
+    if instance.image != desired.image {
+        differences.push(format!("image {} instead of {}", instance.image, desired.image));
+    }
+    if instance.environment != desired.environment.clone().unwrap_or_default() {
+        differences.push("environment".to_string());
+    }
+    let (mut ports, mut desired_ports): (Vec<_>, Vec<_>) = (
+        instance.ports.iter().map(|port| (port.host_port, port.container_port, port.protocol.clone())).collect(),
+        desired.ports.iter().flatten().map(|port| (port.host_port, port.container_port, port.protocol.clone())).collect(),
+    );
+    ports.sort();
+    desired_ports.sort();
+    if ports != desired_ports {
+        differences.push("ports".to_string());
+    }
+    let (mut volumes, mut desired_volumes): (Vec<_>, Vec<_>) = (
+        instance.volumes.iter().map(|volume| (&volume.host_path, &volume.container_path)).collect(),
+        desired.volumes.iter().flatten().map(|volume| (&volume.host_path, &volume.container_path)).collect(),
+    );
+    volumes.sort();
+    desired_volumes.sort();
+    if volumes != desired_volumes {
+        differences.push("volumes".to_string());
+    }
+    if instance.supervision.is_some() != desired.supervise.unwrap_or(false) {
+        differences.push("supervise".to_string());
+    }
+    if instance.ship_logs != desired.ship_logs.unwrap_or(false) {
+        differences.push("ship_logs".to_string());
+    }
+    for (label, wanted) in [(labels::DEPLOYMENT, &desired.deployment), (labels::PROJECT, &desired.project)] {
+        if instance.labels.get(label) != wanted.as_ref() {
+            differences.push(label.to_string());
+        }
+    }
+    differences
+}
+
+fn change(action: &str, name: &str, instance_id: Option<&str>, differences: Vec<String>) -> StateChange {
+    StateChange {
+        action: action.to_string(),
+        name: name.to_string(),
+        instance_id: instance_id.map(str::to_string),
+        differences,
+        outcome: if action == "keep" { "skipped" } else { "planned" }.to_string(),
+        message: None,
+    }
+}
+
+/// Changes that would converge the tracked instances on `desired`, in the order they're applied:
+/// removals first so their host ports are free, then replacements, then creations
+pub fn plan(app_manager: &AppManager, desired: &DesiredState) -> Vec<StateChange> {
+    plan_changes(&app_manager.conventions, app_manager.instances(), desired)
+}
+
+fn plan_changes(conventions: &Conventions, instances: Vec<AppInstance>, desired: &DesiredState) -> Vec<StateChange> {
+    // Instances are matched by the maestro.instance label, so documents can name them with or
+    // without the configured prefix
+    let tracked: HashMap<String, AppInstance> = instances.into_iter()
+        .map(|instance| (conventions.name_of(&instance), instance))
+        .collect();
+
+    let (mut removals, mut replacements, mut creations) = (Vec::new(), Vec::new(), Vec::new());
+    let mut names: Vec<&String> = tracked.keys().collect();
+    names.sort();
+    for name in names {
+        if desired.instances.iter().any(|instance| conventions.instance_name(&instance.name) == name) {
+            continue;
+        }
+        let action = if desired.protected.iter().any(|protected| conventions.instance_name(protected) == name) { "keep" } else { "remove" };
+        removals.push(change(action, name, Some(&tracked[name].id), Vec::new()));
+    }
+    for desired in &desired.instances {
+        match tracked.get(conventions.instance_name(&desired.name)) {
+            Some(instance) => {
+                let differences = differences(instance, desired);
+                if !differences.is_empty() {
+                    replacements.push(change("replace", &desired.name, Some(&instance.id), differences));
+                }
+            },
+            None => creations.push(change("create", &desired.name, None, Vec::new())),
+        }
+    }
+
+    removals.extend(replacements);
+    removals.extend(creations);
+    removals
+}
+
+/// Plans and, unless `dry_run`, applies the changes that converge on `desired`. A failed change
+/// is reported in the plan and doesn't stop the others; the next reconcile tries it again.
+/// Images are checked against the current image policy without a bypass, since the policy may
+/// have changed since the desired state was set.
+pub async fn reconcile(app_manager: &AppManager, desired: &DesiredState, dry_run: bool) -> StatePlan {
+    let _reconciling = app_manager.desired_state.reconciling.lock().await;
+    let mut changes = plan(app_manager, desired);
+    let state = State::from(app_manager);
+
+    for change in changes.iter_mut().filter(|change| !dry_run && change.action != "keep") {
+        let id = change.instance_id.clone().unwrap_or_default();
+        let definition = desired.instances.iter().find(|instance| instance.name == change.name).cloned();
+        if let Some(Err(e)) = definition.as_ref().map(|definition| app_manager.image_policy.check(&definition.image, false)) {
+            tracing::warn!("Desired state: not applying {} of {}: {}", change.action, change.name, e);
+            change.outcome = "failed".to_string();
+            change.message = Some(e.to_string());
+            continue;
+        }
+        // A tracked instance whose container is gone is forgotten: removing it is then done, and replacing it is a fresh create
+        let result = match (change.action.as_str(), definition) {
+            ("remove", _) => match delete_instance(id.clone(), state, ApiToken(RECONCILER_ACTOR.to_string()), DockerAvailable).await {
+                Err(AgentError::NotFound(_)) => {
+                    forget_instance(app_manager, &id);
+                    Ok(())
+                },
+                result => result.map(|_| ()),
+            },
+            ("replace", Some(mut definition)) => {
+                // Secrets aren't part of the document, so the instance keeps the ones it has
+                let secrets = app_manager.secrets.get(&id);
+                definition.secrets = (!secrets.is_empty()).then_some(secrets);
+                timeline::record(app_manager, &id, &change.name, "drifted", RECONCILER_ACTOR, Some(change.differences.join(", ")));
+                // The document asks for its ports, so converging accepts a cutover to hand them over
+                match replace_instance(id.clone(), Some(true), Json(definition.clone()), state, ApiToken(RECONCILER_ACTOR.to_string()), DockerAvailable, ImagePolicyBypass(false)).await {
+                    Err(AgentError::NotFound(_)) => {
+                        forget_instance(app_manager, &id);
+                        launch_instance(definition, app_manager, RECONCILER_ACTOR).await.map(|_| ())
+                    },
+                    result => result.map(|_| ()),
+                }
+            },
+            ("create", Some(definition)) => launch_instance(definition, app_manager, RECONCILER_ACTOR).await.map(|_| ()),
+            _ => continue,
+        };
+        match result {
+            Ok(()) => {
+                tracing::info!("Desired state: {} {}", change.action, change.name);
+                change.outcome = "applied".to_string();
+            },
+            Err(e) => {
+                tracing::warn!("Desired state: failed to {} {}: {}", change.action, change.name, e);
+                change.outcome = "failed".to_string();
+                change.message = Some(e.to_string());
+            }
+        }
+    }
+
+    StatePlan {
+        changes,
+        computed_at: app_manager.now().to_rfc3339(),
+    }
+}
+
+/// Re-applies the desired state every `interval`, undoing drift introduced through other routes.
+/// Nothing happens until a desired state has been set, or while the agent is in maintenance.
+pub async fn run(app_manager: AppManager, interval: Duration) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let Some(desired) = app_manager.desired_state.get() else { continue };
+        if app_manager.maintenance().is_some() || !app_manager.docker_handle.is_connected() {
+            continue;
+        }
+
+        let plan = reconcile(&app_manager, &desired, false).await;
+        let applied = plan.changes.iter().filter(|change| change.outcome == "applied").count();
+        if applied > 0 {
+            tracing::info!("Reconciled {} instances that drifted from the desired state", applied);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AgentConfig;
+    use crate::routes::models::ImagePolicy;
+    use crate::testing::TestAgent;
+
+    fn conventions() -> Conventions {
+        let mut config = AgentConfig::default();
+        config.instances.name_prefix = "maestro-".to_string();
+        Conventions::new(&config)
+    }
+
+    fn request(name: &str, image: &str) -> AppInstanceRequest {
+        AppInstanceRequest {
+            name: name.to_string(),
+            image: image.to_string(),
+            ports: None,
+            environment: None,
+            volumes: None,
+            supervise: None,
+            secrets: None,
+            ship_logs: None,
+            deployment: None,
+            project: None,
+        }
+    }
+
+    // A tracked instance as created from `request(name, image)`
+    fn tracked(id: &str, name: &str, image: &str) -> AppInstance {
+        let conventions = conventions();
+        AppInstance {
+            id: id.to_string(),
+            name: conventions.container_name(name),
+            image: image.to_string(),
+            status: "running".to_string(),
+            created_at: "2026-01-01T00:00:00+00:00".to_string(),
+            ports: Vec::new(),
+            environment: HashMap::new(),
+            volumes: Vec::new(),
+            agent_id: "current".to_string(),
+            supervision: None,
+            secrets: HashMap::new(),
+            ship_logs: false,
+            labels: conventions.labels(&request(name, image)),
+            recent_events: Vec::new(),
+        }
+    }
+
+    fn desired(instances: Vec<AppInstanceRequest>, protected: &[&str]) -> DesiredState {
+        DesiredState {
+            instances,
+            protected: protected.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn summary(changes: &[StateChange]) -> Vec<(&str, &str, Option<&str>, &str)> {
+        changes.iter()
+            .map(|change| (change.action.as_str(), change.name.as_str(), change.instance_id.as_deref(), change.outcome.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn missing_instances_are_created() {
+        let changes = plan_changes(&conventions(), Vec::new(), &desired(vec![request("web", "nginx:1.27")], &[]));
+        assert_eq!(summary(&changes), [("create", "web", None, "planned")]);
+    }
+
+    #[test]
+    fn matching_instances_are_left_alone() {
+        let instances = vec![tracked("a1", "web", "nginx:1.27")];
+        // Documents can name instances with or without the prefix
+        for name in ["web", "maestro-web"] {
+            assert!(plan_changes(&conventions(), instances.clone(), &desired(vec![request(name, "nginx:1.27")], &[])).is_empty());
+        }
+    }
+
+    #[test]
+    fn drifted_instances_are_replaced() {
+        let instances = vec![tracked("a1", "web", "nginx:1.26")];
+        let mut wanted = request("web", "nginx:1.27");
+        wanted.environment = Some(HashMap::from([("MODE".to_string(), "prod".to_string())]));
+        wanted.deployment = Some("blue".to_string());
+
+        let changes = plan_changes(&conventions(), instances, &desired(vec![wanted], &[]));
+        assert_eq!(summary(&changes), [("replace", "web", Some("a1"), "planned")]);
+        assert_eq!(changes[0].differences, ["image nginx:1.26 instead of nginx:1.27", "environment", labels::DEPLOYMENT]);
+    }
+
+    #[test]
+    fn unlisted_instances_are_removed_unless_protected() {
+        let instances = vec![tracked("a1", "web", "nginx:1.27"), tracked("b2", "db", "postgres:16"), tracked("c3", "cache", "redis:7")];
+        let changes = plan_changes(&conventions(), instances, &desired(vec![request("web", "nginx:1.27")], &["maestro-db"]));
+        assert_eq!(summary(&changes), [("remove", "cache", Some("c3"), "planned"), ("keep", "db", Some("b2"), "skipped")]);
+    }
+
+    #[test]
+    fn removals_come_before_replacements_and_creations() {
+        let instances = vec![tracked("a1", "web", "nginx:1.26"), tracked("b2", "old", "nginx:1.26")];
+        let changes = plan_changes(&conventions(), instances, &desired(vec![request("new", "nginx:1.27"), request("web", "nginx:1.27")], &[]));
+        assert_eq!(summary(&changes), [
+            ("remove", "old", Some("b2"), "planned"),
+            ("replace", "web", Some("a1"), "planned"),
+            ("create", "new", None, "planned"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn reconcile_fails_changes_the_image_policy_denies() {
+        let agent = TestAgent::start().await;
+        agent.app_manager.image_policy.set(ImagePolicy {
+            denied_tags: vec!["latest".to_string()],
+            ..ImagePolicy::default()
+        }).unwrap();
+
+        let plan = reconcile(&agent.app_manager, &desired(vec![request("web", "nginx")], &[]), false).await;
+        assert_eq!(summary(&plan.changes), [("create", "web", None, "failed")]);
+        assert!(plan.changes[0].message.as_deref().is_some_and(|message| message.contains("denied_tags")));
+        assert!(agent.docker.requests_to("POST", "/images/create").is_empty());
+        assert!(agent.docker.requests_to("POST", "/containers/create").is_empty());
+    }
+}
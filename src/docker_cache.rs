@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::RwLock;
+use std::time::Duration;
+use bollard::container::ListContainersOptions;
+use bollard::errors::Error;
+use bollard::image::ListImagesOptions;
+use bollard::models::{ContainerSummary, EventMessage, EventMessageTypeEnum, ImageSummary, Network, Volume};
+use bollard::system::EventsOptions;
+use chrono::{DateTime, Utc};
+use futures::{FutureExt, StreamExt};
+use rocket::http::Header;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::Request;
+use serde::Serialize;
+use crate::routes::app_manager::AppManager;
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Response header with the time a listing was read from the daemon
+pub const STATE_AS_OF_HEADER: &str = "X-Maestro-State-As-Of";
+
+/// Response header saying whether a listing came from the cache ("hit") or the daemon ("miss")
+pub const CACHE_HEADER: &str = "X-Maestro-Cache";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Resource {
+    Containers,
+    Images,
+    Networks,
+    Volumes,
+}
+
+impl Resource {
+    const ALL: [Resource; 4] = [Resource::Containers, Resource::Images, Resource::Networks, Resource::Volumes];
+
+    fn name(self) -> &'static str {
+        match self {
+            Resource::Containers => "containers",
+            Resource::Images => "images",
+            Resource::Networks => "networks",
+            Resource::Volumes => "volumes",
+        }
+    }
+
+    // The listings an event changes
+    fn touched_by(event: &EventMessage) -> &'static [Resource] {
+        let action = event.action.as_deref().unwrap_or_default();
+        match event.typ {
+            Some(EventMessageTypeEnum::CONTAINER) if action.starts_with("exec_") => &[],
+            // Committing a container adds an image
+            Some(EventMessageTypeEnum::CONTAINER) if action == "commit" => &[Resource::Containers, Resource::Images],
+            Some(EventMessageTypeEnum::CONTAINER) => &[Resource::Containers],
+            Some(EventMessageTypeEnum::IMAGE) => &[Resource::Images],
+            Some(EventMessageTypeEnum::NETWORK) => &[Resource::Networks],
+            Some(EventMessageTypeEnum::VOLUME) => &[Resource::Volumes],
+            _ => &[],
+        }
+    }
+}
+
+struct Entry<T> {
+    items: Vec<T>,
+    taken_at: DateTime<Utc>,
+}
+
+/// Listed daemon objects and when they were read, served as JSON with freshness headers
+pub struct Listing<T> {
+    pub items: T,
+    as_of: DateTime<Utc>,
+    cached: bool,
+}
+
+impl<T> Listing<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Listing<U> {
+        Listing {
+            items: f(self.items),
+            as_of: self.as_of,
+            cached: self.cached,
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Listing<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Json(self.items).respond_to(request)?;
+        response.set_header(Header::new(STATE_AS_OF_HEADER, self.as_of.to_rfc3339()));
+        response.set_header(Header::new(CACHE_HEADER, if self.cached { "hit" } else { "miss" }));
+        Ok(response)
+    }
+}
+
+/// Container, image, network and volume listings kept current from the daemon's event stream,
+/// so polling the list endpoints doesn't cost a daemon round trip per request. While the cache
+/// isn't populated (disabled, or the event stream is down) listings go to the daemon.
+#[derive(Default)]
+pub struct DockerCache {
+    containers: RwLock<Option<Entry<ContainerSummary>>>,
+    images: RwLock<Option<Entry<ImageSummary>>>,
+    networks: RwLock<Option<Entry<Network>>>,
+    volumes: RwLock<Option<Entry<Volume>>>,
+}
+
+async fn read<T: Clone>(slot: &RwLock<Option<Entry<T>>>, as_of: DateTime<Utc>, load: impl Future<Output = Result<Vec<T>, Error>>) -> Result<Listing<Vec<T>>, Error> {
+    if let Some(entry) = slot.read().unwrap().as_ref() {
+        return Ok(Listing {
+            items: entry.items.clone(),
+            as_of: entry.taken_at,
+            cached: true,
+        });
+    }
+
+    let items = load.await?;
+    Ok(Listing {
+        items,
+        as_of,
+        cached: false,
+    })
+}
+
+async fn store<T>(slot: &RwLock<Option<Entry<T>>>, resource: Resource, taken_at: DateTime<Utc>, load: impl Future<Output = Result<Vec<T>, Error>>) {
+    let entry = match load.await {
+        Ok(items) => Some(Entry {
+            items,
+            taken_at,
+        }),
+        Err(e) => {
+            tracing::warn!("Failed to refresh cached {}: {}", resource.name(), e);
+            None
+        }
+    };
+    *slot.write().unwrap() = entry;
+}
+
+async fn list_containers(app_manager: &AppManager) -> Result<Vec<ContainerSummary>, Error> {
+    let options = Some(ListContainersOptions::<String> {
+        all: true,
+        ..Default::default()
+    });
+    app_manager.instrument("list_containers", app_manager.docker().list_containers(options)).await
+}
+
+async fn list_images(app_manager: &AppManager) -> Result<Vec<ImageSummary>, Error> {
+    let options = Some(ListImagesOptions::<String> {
+        all: false,
+        ..Default::default()
+    });
+    app_manager.instrument("list_images", app_manager.docker().list_images(options)).await
+}
+
+async fn list_networks(app_manager: &AppManager) -> Result<Vec<Network>, Error> {
+    app_manager.instrument("list_networks", app_manager.docker().list_networks::<String>(None)).await
+}
+
+async fn list_volumes(app_manager: &AppManager) -> Result<Vec<Volume>, Error> {
+    app_manager.instrument("list_volumes", app_manager.docker().list_volumes::<String>(None)).await
+        .map(|response| response.volumes.unwrap_or_default())
+}
+
+impl DockerCache {
+    /// Every container, including stopped ones
+    pub async fn containers(&self, app_manager: &AppManager) -> Result<Listing<Vec<ContainerSummary>>, Error> {
+        read(&self.containers, app_manager.now(), list_containers(app_manager)).await
+    }
+
+    pub async fn images(&self, app_manager: &AppManager) -> Result<Listing<Vec<ImageSummary>>, Error> {
+        read(&self.images, app_manager.now(), list_images(app_manager)).await
+    }
+
+    pub async fn networks(&self, app_manager: &AppManager) -> Result<Listing<Vec<Network>>, Error> {
+        read(&self.networks, app_manager.now(), list_networks(app_manager)).await
+    }
+
+    pub async fn volumes(&self, app_manager: &AppManager) -> Result<Listing<Vec<Volume>>, Error> {
+        read(&self.volumes, app_manager.now(), list_volumes(app_manager)).await
+    }
+
+    async fn refresh(&self, app_manager: &AppManager, resource: Resource) {
+        match resource {
+            Resource::Containers => store(&self.containers, resource, app_manager.now(), list_containers(app_manager)).await,
+            Resource::Images => store(&self.images, resource, app_manager.now(), list_images(app_manager)).await,
+            Resource::Networks => store(&self.networks, resource, app_manager.now(), list_networks(app_manager)).await,
+            Resource::Volumes => store(&self.volumes, resource, app_manager.now(), list_volumes(app_manager)).await,
+        }
+    }
+
+    fn invalidate(&self) {
+        *self.containers.write().unwrap() = None;
+        *self.images.write().unwrap() = None;
+        *self.networks.write().unwrap() = None;
+        *self.volumes.write().unwrap() = None;
+    }
+}
+
+/// Keeps the cache in step with the daemon: everything is listed when the event stream is
+/// (re)subscribed and every `reconcile_interval`, and each burst of events re-lists what it touched
+pub async fn run(app_manager: AppManager, reconcile_interval: Duration) {
+    let cache = app_manager.cache.clone();
+    loop {
+        let mut filters = HashMap::new();
+        filters.insert("type".to_string(), vec!["container".to_string(), "image".to_string(), "network".to_string(), "volume".to_string()]);
+        // Replaying from before the full listing covers changes made while it was taken
+        let options = Some(EventsOptions::<String> {
+            since: Some(Utc::now().timestamp().to_string()),
+            filters,
+            ..Default::default()
+        });
+
+        let mut events = app_manager.docker().events(options);
+        for resource in Resource::ALL {
+            cache.refresh(&app_manager, resource).await;
+        }
+
+        let mut reconcile = tokio::time::interval_at(tokio::time::Instant::now() + reconcile_interval, reconcile_interval);
+        loop {
+            tokio::select! {
+                event = events.next() => {
+                    #[cfg(feature = "chaos")]
+                    app_manager.chaos.maybe_panic("docker_cache");
+                    let mut touched = HashSet::new();
+                    match event {
+                        #[cfg(feature = "chaos")]
+                        Some(Ok(_)) if app_manager.chaos.drops_event("docker_cache") => continue,
+                        Some(Ok(event)) => touched.extend(Resource::touched_by(&event)),
+                        Some(Err(e)) => {
+                            tracing::warn!("Docker cache lost the event stream: {}", e);
+                            break;
+                        },
+                        None => break,
+                    }
+                    // Fold events that are already waiting into the same refresh
+                    let mut lost = false;
+                    while let Some(event) = events.next().now_or_never() {
+                        match event {
+                            Some(Ok(event)) => touched.extend(Resource::touched_by(&event)),
+                            Some(Err(e)) => {
+                                tracing::warn!("Docker cache lost the event stream: {}", e);
+                                lost = true;
+                                break;
+                            },
+                            None => {
+                                lost = true;
+                                break;
+                            },
+                        }
+                    }
+                    if lost {
+                        break;
+                    }
+                    for resource in touched {
+                        cache.refresh(&app_manager, resource).await;
+                    }
+                },
+                _ = reconcile.tick() => {
+                    for resource in Resource::ALL {
+                        cache.refresh(&app_manager, resource).await;
+                    }
+                },
+            }
+        }
+
+        // Listings can't be trusted without events, so requests go to the daemon until resubscribed
+        cache.invalidate();
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn event(typ: &str, action: &str) -> Value {
+        json!({ "Type": typ, "Action": action, "Actor": { "ID": "ab12", "Attributes": {} }, "time": 1_772_334_000 })
+    }
+
+    async fn eventually(condition: impl AsyncFn() -> bool) {
+        for _ in 0..500 {
+            if condition().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never held");
+    }
+
+    #[tokio::test]
+    async fn listings_follow_the_event_stream_until_it_ends() {
+        let agent = TestAgent::start().await;
+        let running = Arc::new(AtomicUsize::new(0));
+        let listed = running.clone();
+        agent.docker.route("GET", "/containers/json", move |_| {
+            let containers: Vec<Value> = (0..listed.load(Ordering::SeqCst))
+                .map(|i| json!({ "Id": format!("{:04}", i), "Names": [format!("/game-{}", i)], "State": "running" }))
+                .collect();
+            DockerResponse::ok(json!(containers))
+        });
+        let (events, receiver) = mpsc::unbounded_channel();
+        let receiver = Mutex::new(Some(receiver));
+        agent.docker.route("GET", "/events", move |_| {
+            // A resubscription gets a stream that ends straight away
+            DockerResponse::Stream(receiver.lock().unwrap().take().unwrap_or_else(|| mpsc::unbounded_channel().1))
+        });
+        let cache = agent.app_manager.cache.clone();
+        tokio::spawn(run(agent.app_manager.clone(), Duration::from_secs(3600)));
+
+        let containers = async || cache.containers(&agent.app_manager).await.unwrap();
+        eventually(async || containers().await.cached).await;
+        assert!(containers().await.items.is_empty());
+
+        // A container event re-lists containers, and only them
+        let images_listed = agent.docker.requests_to("GET", "/images/json").len();
+        running.store(2, Ordering::SeqCst);
+        events.send(event("container", "start")).unwrap();
+        events.send(event("container", "exec_start: sh")).unwrap();
+        eventually(async || containers().await.items.len() == 2).await;
+        assert!(containers().await.cached);
+        assert_eq!(agent.docker.requests_to("GET", "/images/json").len(), images_listed);
+
+        events.send(event("image", "pull")).unwrap();
+        eventually(async || agent.docker.requests_to("GET", "/images/json").len() == images_listed + 1).await;
+
+        // An event followed by the end of the stream leaves nothing cached
+        running.store(3, Ordering::SeqCst);
+        events.send(event("container", "create")).unwrap();
+        drop(events);
+        eventually(async || !containers().await.cached).await;
+        assert_eq!(containers().await.items.len(), 3);
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::Request;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::routes::app_manager::AppManager;
+use crate::routes::errors::AgentError;
+use crate::store;
+
+const IDEMPOTENCY_FILE: &str = "idempotency.json";
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Request header carrying the client's idempotency key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Response header set on responses replayed from an earlier request with the same key
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "Idempotent-Replayed";
+
+/// How long a key and its response are kept
+fn ttl() -> Duration {
+    Duration::hours(24)
+}
+
+/// Status and body of the request that first used a key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredResponse {
+    status: u16,
+    /// The JSON body as it was sent, so a replay is byte for byte the same
+    body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    /// Method and path the key was first used on
+    route: String,
+    body_hash: String,
+    created_at: DateTime<Utc>,
+    /// None while the first request is still being handled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<StoredResponse>,
+}
+
+enum Claim {
+    Fresh,
+    Replay(StoredResponse),
+    Conflict(String),
+}
+
+/// Idempotency keys with the response of the request that first used them, persisted so
+/// a retry after an agent restart still replays instead of creating a duplicate
+pub struct IdempotencyStore {
+    path: PathBuf,
+    records: Mutex<HashMap<String, Record>>,
+}
+
+impl IdempotencyStore {
+    pub fn load(data_dir: &Path, now: DateTime<Utc>) -> io::Result<Self> {
+        let path = data_dir.join(IDEMPOTENCY_FILE);
+        let mut records: HashMap<String, Record> = store::load_json(&path)?;
+        // A request that never finished before the restart is free to run again
+        records.retain(|_, record| record.response.is_some() && record.created_at + ttl() > now);
+
+        Ok(IdempotencyStore {
+            path,
+            records: Mutex::new(records),
+        })
+    }
+
+    fn claim(&self, key: &str, route: &str, body_hash: &str, now: DateTime<Utc>) -> Claim {
+        let mut records = self.records.lock().unwrap();
+        records.retain(|_, record| record.created_at + ttl() > now);
+
+        let Some(record) = records.get(key) else {
+            records.insert(key.to_string(), Record {
+                route: route.to_string(),
+                body_hash: body_hash.to_string(),
+                created_at: now,
+                response: None,
+            });
+            return Claim::Fresh;
+        };
+        if record.route != route {
+            return Claim::Conflict(format!("{} was already used for {}", IDEMPOTENCY_KEY_HEADER, record.route));
+        }
+        if record.body_hash != body_hash {
+            return Claim::Conflict(format!("{} was already used with a different request body", IDEMPOTENCY_KEY_HEADER));
+        }
+        match &record.response {
+            Some(response) => Claim::Replay(response.clone()),
+            None => Claim::Conflict(format!("A request with this {} is still in progress", IDEMPOTENCY_KEY_HEADER)),
+        }
+    }
+
+    fn complete(&self, key: &str, response: StoredResponse) {
+        let mut records = self.records.lock().unwrap();
+        if let Some(record) = records.get_mut(key) {
+            record.response = Some(response);
+        }
+        let completed: HashMap<&String, &Record> = records.iter()
+            .filter(|(_, record)| record.response.is_some())
+            .collect();
+        if let Err(e) = store::save_json(&self.path, &completed) {
+            tracing::warn!("Failed to persist idempotency keys: {}", e);
+        }
+    }
+
+    fn release(&self, key: &str) {
+        self.records.lock().unwrap().remove(key);
+    }
+}
+
+// Held while a fresh request runs; a key that's never completed (the handler failed or
+// was dropped) is released so the client can retry with it
+struct Pending {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    completed: bool,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.release(&self.key);
+        }
+    }
+}
+
+struct Keyed {
+    key: String,
+    route: String,
+    body_hash: String,
+}
+
+/// A JSON request body that may come with an Idempotency-Key header. The body is read under
+/// the "json" limit and rejected like `Json<T>` would be; the key is only checked by `respond`
+/// so request guards such as authentication run before it's claimed.
+pub struct Idempotent<T> {
+    body: T,
+    keyed: Option<Keyed>,
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for Idempotent<T> {
+    type Error = String;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = request.limits().get("json").unwrap_or(Limits::JSON);
+        let bytes = match data.open(limit).into_bytes().await {
+            Ok(bytes) if bytes.is_complete() => bytes.into_inner(),
+            Ok(_) => return data::Outcome::Error((Status::PayloadTooLarge, format!("Request body is larger than {}", limit))),
+            Err(e) => return data::Outcome::Error((Status::BadRequest, format!("Failed to read request body: {}", e))),
+        };
+        let body = match serde_json::from_slice(&bytes) {
+            Ok(body) => body,
+            Err(e) => return data::Outcome::Error((Status::UnprocessableEntity, format!("Invalid request body: {}", e))),
+        };
+
+        let keyed = request.headers().get_one(IDEMPOTENCY_KEY_HEADER).map(|key| Keyed {
+            key: key.to_string(),
+            route: format!("{} {}", request.method(), request.uri().path()),
+            body_hash: format!("{:x}", Sha256::digest(&bytes)),
+        });
+        data::Outcome::Success(Idempotent {
+            body,
+            keyed,
+        })
+    }
+}
+
+impl<T> Idempotent<T> {
+    /// Runs `handler` on the body unless the key was already used: a finished request with the
+    /// same body is replayed, and the key is a conflict when it was used with a different body
+    /// or route, or that request is still running. Only successful responses are stored.
+    pub async fn respond<R, F>(self, app_manager: &AppManager, handler: impl FnOnce(T) -> F) -> Result<IdempotentJson<R>, AgentError>
+    where
+        R: Serialize,
+        F: Future<Output = Result<Json<R>, AgentError>>,
+    {
+        let Some(keyed) = self.keyed else {
+            return handler(self.body).await.map(IdempotentJson::Fresh);
+        };
+        if keyed.key.is_empty() || keyed.key.len() > MAX_KEY_LENGTH {
+            return Err(AgentError::BadRequest(format!("{} must be between 1 and {} characters", IDEMPOTENCY_KEY_HEADER, MAX_KEY_LENGTH)));
+        }
+
+        match app_manager.idempotency.claim(&keyed.key, &keyed.route, &keyed.body_hash, app_manager.now()) {
+            Claim::Fresh => {},
+            Claim::Replay(response) => {
+                tracing::info!("Replaying {} for {} {}", keyed.route, IDEMPOTENCY_KEY_HEADER, keyed.key);
+                return Ok(IdempotentJson::Replayed(response));
+            },
+            Claim::Conflict(message) => return Err(AgentError::Conflict(message)),
+        }
+
+        let mut pending = Pending {
+            store: app_manager.idempotency.clone(),
+            key: keyed.key,
+            completed: false,
+        };
+        let response = handler(self.body).await?;
+        match serde_json::to_string(&response.0) {
+            Ok(body) => {
+                pending.store.complete(&pending.key, StoredResponse {
+                    status: Status::Ok.code,
+                    body,
+                });
+                pending.completed = true;
+            },
+            Err(e) => tracing::warn!("Failed to store the response for {} {}: {}", IDEMPOTENCY_KEY_HEADER, pending.key, e),
+        }
+        Ok(IdempotentJson::Fresh(response))
+    }
+}
+
+/// A JSON response, either fresh or replayed for a repeated Idempotency-Key
+pub enum IdempotentJson<T> {
+    Fresh(Json<T>),
+    Replayed(StoredResponse),
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for IdempotentJson<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            IdempotentJson::Fresh(body) => body.respond_to(request),
+            IdempotentJson::Replayed(stored) => {
+                let mut response = (ContentType::JSON, stored.body).respond_to(request)?;
+                response.set_status(Status::from_code(stored.status).unwrap_or(Status::Ok));
+                response.set_header(Header::new(IDEMPOTENT_REPLAY_HEADER, "true"));
+                Ok(response)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::clock::{Clock, TestClock};
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn response() -> StoredResponse {
+        StoredResponse {
+            status: 200,
+            body: "{}".to_string(),
+        }
+    }
+
+    #[test]
+    fn keys_are_forgotten_after_the_ttl() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::at(Utc::now());
+        let store = IdempotencyStore::load(data_dir.path(), clock.now()).unwrap();
+
+        assert!(matches!(store.claim("key", "POST /instances", "hash", clock.now()), Claim::Fresh));
+        store.complete("key", response());
+        clock.advance(Duration::hours(23));
+        assert!(matches!(store.claim("key", "POST /instances", "hash", clock.now()), Claim::Replay(_)));
+        assert!(matches!(store.claim("key", "POST /instances", "other", clock.now()), Claim::Conflict(_)));
+
+        clock.advance(Duration::hours(1));
+        assert!(matches!(store.claim("key", "POST /instances", "other", clock.now()), Claim::Fresh));
+    }
+
+    #[test]
+    fn expired_and_unfinished_keys_are_dropped_on_load() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::at(Utc::now());
+        let store = IdempotencyStore::load(data_dir.path(), clock.now()).unwrap();
+        assert!(matches!(store.claim("old", "POST /instances", "hash", clock.now()), Claim::Fresh));
+        store.complete("old", response());
+        clock.advance(Duration::hours(12));
+        assert!(matches!(store.claim("new", "POST /instances", "hash", clock.now()), Claim::Fresh));
+        store.complete("new", response());
+        assert!(matches!(store.claim("unfinished", "POST /instances", "hash", clock.now()), Claim::Fresh));
+
+        clock.advance(Duration::hours(13));
+        let reloaded = IdempotencyStore::load(data_dir.path(), clock.now()).unwrap();
+        let mut kept: Vec<String> = reloaded.records.lock().unwrap().keys().cloned().collect();
+        kept.sort();
+        assert_eq!(kept, ["new"]);
+    }
+
+    #[tokio::test]
+    async fn retried_creations_replay_the_first_response() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({})));
+        agent.docker.route("POST", "/containers/create", |_| DockerResponse::Json(201, json!({ "Id": "game-id", "Warnings": [] })));
+        agent.docker.route("POST", "/containers/game-id/start", |_| DockerResponse::ok(json!({})));
+        let client = agent.client().await;
+        let create = |body: &'static str| client.post("/instances")
+            .header(ContentType::JSON)
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, "create-game-1"))
+            .body(body)
+            .dispatch();
+
+        let first = create(r#"{"name": "game", "image": "horizon:1"}"#).await;
+        assert_eq!(first.status(), Status::Ok);
+        assert!(first.headers().get_one(IDEMPOTENT_REPLAY_HEADER).is_none());
+        let first = first.into_string().await.unwrap();
+
+        let retry = create(r#"{"name": "game", "image": "horizon:1"}"#).await;
+        assert_eq!(retry.status(), Status::Ok);
+        assert_eq!(retry.headers().get_one(IDEMPOTENT_REPLAY_HEADER), Some("true"));
+        assert_eq!(retry.into_string().await.unwrap(), first);
+        assert_eq!(agent.docker.requests_to("POST", "/containers/create").len(), 1);
+
+        let reused = create(r#"{"name": "lobby", "image": "horizon:1"}"#).await;
+        assert_eq!(reused.status(), Status::Conflict);
+        let body: Value = reused.into_json().await.unwrap();
+        assert!(body["error"]["message"].as_str().unwrap().contains("different request body"));
+        assert_eq!(agent.docker.requests_to("POST", "/containers/create").len(), 1);
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::registries::{image_repository, normalize_server, select_server};
+use crate::routes::adopt_routes::glob_match;
+use crate::routes::errors::AgentError;
+use crate::routes::models::ImagePolicy;
+use crate::store;
+
+const IMAGE_POLICY_FILE: &str = "image_policy.json";
+
+/// A policy rule an image breaks, named after the ImagePolicy field that sets it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Image policy rule {} violated: {}", self.rule, self.message)
+    }
+}
+
+// The parts of an image reference, e.g. "ghcr.io/acme/app:1.2@sha256:..." is
+// ("ghcr.io/acme/app", Some("1.2"), Some("sha256:..."))
+fn split_reference(image: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (image, digest) = match image.split_once('@') {
+        Some((image, digest)) => (image, Some(digest)),
+        None => (image, None),
+    };
+    // A colon after the last slash is a tag, one before it is a registry port
+    match image.rfind(':') {
+        Some(colon) if colon > image.rfind('/').unwrap_or(0) => (&image[..colon], Some(&image[colon + 1..]), digest),
+        _ => (image, None, digest),
+    }
+}
+
+/// Checks `image` against every rule of `policy`, returning the first one it breaks
+pub fn evaluate(policy: &ImagePolicy, image: &str) -> Result<(), Violation> {
+    let (name, tag, digest) = split_reference(image.trim());
+    let repository = image_repository(image.trim());
+
+    if !policy.allowed_registries.is_empty() {
+        let allowed: Vec<String> = policy.allowed_registries.iter().map(|registry| normalize_server(registry)).collect();
+        if select_server(allowed.iter().map(String::as_str), &repository).is_none() {
+            return Err(Violation {
+                rule: "allowed_registries",
+                message: format!("{} is not in an allowed registry", repository),
+            });
+        }
+    }
+
+    if !policy.allowed_images.is_empty()
+        && !policy.allowed_images.iter().any(|pattern| glob_match(pattern, name) || glob_match(pattern, &repository)) {
+        return Err(Violation {
+            rule: "allowed_images",
+            message: format!("{} matches none of the allowed image patterns", name),
+        });
+    }
+
+    if policy.require_digest && !digest.is_some_and(|digest| digest.starts_with("sha256:") && digest.len() > "sha256:".len()) {
+        return Err(Violation {
+            rule: "require_digest",
+            message: format!("{} is not pinned with @sha256:<digest>", image),
+        });
+    }
+
+    // What a digest-pinned image runs doesn't depend on its tag, and one without a tag runs as latest
+    let denied = |wanted: &str| policy.denied_tags.iter().any(|denied| denied == wanted);
+    if digest.is_none() && tag.map_or(denied("latest") || denied(""), denied) {
+        return Err(Violation {
+            rule: "denied_tags",
+            message: match tag {
+                Some(tag) => format!("{} uses the denied tag {}", image, tag),
+                None => format!("{} has no tag, so it runs as the denied tag latest", image),
+            },
+        });
+    }
+
+    Ok(())
+}
+
+/// The image policy set through PUT /policies/images, persisted in the data dir
+pub struct ImagePolicyStore {
+    path: PathBuf,
+    policy: Mutex<ImagePolicy>,
+}
+
+impl ImagePolicyStore {
+    pub fn load(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(IMAGE_POLICY_FILE);
+        let policy = store::load_json(&path)?;
+
+        Ok(ImagePolicyStore {
+            path,
+            policy: Mutex::new(policy),
+        })
+    }
+
+    pub fn get(&self) -> ImagePolicy {
+        self.policy.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, policy: ImagePolicy) -> io::Result<()> {
+        let mut current = self.policy.lock().unwrap();
+        store::save_json(&self.path, &policy)?;
+        *current = policy;
+        Ok(())
+    }
+
+    /// Rejects `image` with 403 when it breaks the policy, unless the request may and did ask to bypass it
+    pub fn check(&self, image: &str, bypass: bool) -> Result<(), AgentError> {
+        match evaluate(&self.policy.lock().unwrap(), image) {
+            Ok(()) => Ok(()),
+            Err(violation) if bypass => {
+                tracing::warn!("Bypassing the image policy for {}: {}", image, violation);
+                Ok(())
+            },
+            Err(violation) => Err(AgentError::Forbidden(violation.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(configure: impl FnOnce(&mut ImagePolicy)) -> ImagePolicy {
+        let mut policy = ImagePolicy::default();
+        configure(&mut policy);
+        policy
+    }
+
+    const DIGEST: &str = "sha256:4c1e3a2b6d0f9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3a2f1e0d9c8b7a6f5e4d";
+
+    #[test]
+    fn evaluates_every_rule() {
+        let deny_latest = policy(|policy| policy.denied_tags = vec!["latest".to_string()]);
+        let deny_untagged = policy(|policy| policy.denied_tags = vec!["".to_string()]);
+        let registries = policy(|policy| policy.allowed_registries = vec!["ghcr.io/acme".to_string(), "docker.io".to_string()]);
+        let digests = policy(|policy| policy.require_digest = true);
+        let images = policy(|policy| policy.allowed_images = vec!["nginx".to_string(), "ghcr.io/acme/game-*".to_string()]);
+        let pinned = format!("nginx:latest@{}", DIGEST);
+        let untagged_pinned = format!("nginx@{}", DIGEST);
+
+        let cases: [(&str, &ImagePolicy, &str, Option<&str>); 20] = [
+            ("empty policy", &ImagePolicy::default(), "nginx:latest", None),
+            ("denied tag", &deny_latest, "nginx:latest", Some("denied_tags")),
+            ("untagged runs as latest", &deny_latest, "nginx", Some("denied_tags")),
+            ("untagged with a registry port", &deny_latest, "localhost:5000/game", Some("denied_tags")),
+            ("other tag", &deny_latest, "nginx:1.27", None),
+            ("tag named like a denied one", &deny_latest, "nginx:latest-alpine", None),
+            ("pinned by digest", &deny_latest, &pinned, None),
+            ("untagged pinned by digest", &deny_latest, &untagged_pinned, None),
+            ("denied empty tag", &deny_untagged, "nginx", Some("denied_tags")),
+            ("empty tag denies only untagged", &deny_untagged, "nginx:latest", None),
+            ("allowed registry prefix", &registries, "ghcr.io/acme/game:1", None),
+            ("other path in an allowed registry", &registries, "ghcr.io/other/game:1", Some("allowed_registries")),
+            ("docker hub short name", &registries, "nginx:1.27", None),
+            ("registry not allowed", &registries, "quay.io/acme/game:1", Some("allowed_registries")),
+            ("digest required", &digests, "nginx:1.27", Some("require_digest")),
+            ("digest given", &digests, &pinned, None),
+            ("empty digest", &digests, "nginx@sha256:", Some("require_digest")),
+            ("allowed image", &images, "nginx:1.27", None),
+            ("allowed image pattern", &images, "ghcr.io/acme/game-eu:2", None),
+            ("image not allowed", &images, "redis:7", Some("allowed_images")),
+        ];
+
+        for (case, policy, image, broken) in cases {
+            assert_eq!(evaluate(policy, image).err().map(|violation| violation.rule), broken, "{}: {}", case, image);
+        }
+    }
+
+    #[test]
+    fn untagged_violation_names_latest() {
+        let policy = policy(|policy| policy.denied_tags = vec!["latest".to_string()]);
+        let violation = evaluate(&policy, "nginx").unwrap_err();
+        assert_eq!(violation.message, "nginx has no tag, so it runs as the denied tag latest");
+    }
+}
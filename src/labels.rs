@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use crate::config::AgentConfig;
+use crate::routes::models::{AppInstance, AppInstanceRequest};
+
+/// Set to "true" on every container the agent creates
+pub const MANAGED: &str = "maestro.managed";
+/// The instance name without the configured prefix
+pub const INSTANCE: &str = "maestro.instance";
+/// Name of the agent that created the container
+pub const AGENT: &str = "maestro.agent";
+pub const DEPLOYMENT: &str = "maestro.deployment";
+pub const PROJECT: &str = "maestro.project";
+
+const NAMESPACE: &str = "maestro.";
+
+/// How the agent names and labels the containers it creates, so they can be told apart from
+/// unmanaged ones in `docker ps` and found again by label
+pub struct Conventions {
+    name_prefix: String,
+    agent: String,
+}
+
+impl Conventions {
+    pub fn new(config: &AgentConfig) -> Self {
+        Conventions {
+            name_prefix: config.instances.name_prefix.clone(),
+            agent: config.name.clone(),
+        }
+    }
+
+    /// The container name for an instance, prefixed unless it already is
+    pub fn container_name(&self, name: &str) -> String {
+        if name.starts_with(&self.name_prefix) {
+            name.to_string()
+        } else {
+            format!("{}{}", self.name_prefix, name)
+        }
+    }
+
+    /// The instance name a container name stands for, without the prefix
+    pub fn instance_name<'a>(&self, container_name: &'a str) -> &'a str {
+        container_name.strip_prefix(self.name_prefix.as_str()).unwrap_or(container_name)
+    }
+
+    /// The name an instance goes by in desired state documents: its maestro.instance label, or for
+    /// instances created before labeling, its name without the prefix
+    pub fn name_of(&self, instance: &AppInstance) -> String {
+        instance.labels.get(INSTANCE).cloned().unwrap_or_else(|| self.instance_name(&instance.name).to_string())
+    }
+
+    /// The labels a container created from `app_req` gets
+    pub fn labels(&self, app_req: &AppInstanceRequest) -> HashMap<String, String> {
+        let mut labels = HashMap::from([
+            (MANAGED.to_string(), "true".to_string()),
+            (INSTANCE.to_string(), self.instance_name(&app_req.name).to_string()),
+            (AGENT.to_string(), self.agent.clone()),
+        ]);
+        if let Some(deployment) = &app_req.deployment {
+            labels.insert(DEPLOYMENT.to_string(), deployment.clone());
+        }
+        if let Some(project) = &app_req.project {
+            labels.insert(PROJECT.to_string(), project.clone());
+        }
+        labels
+    }
+}
+
+/// The labels of a container that belong to the agent's conventions
+pub fn maestro_labels(labels: Option<HashMap<String, String>>) -> HashMap<String, String> {
+    labels.unwrap_or_default().into_iter()
+        .filter(|(key, _)| key.starts_with(NAMESPACE))
+        .collect()
+}
+
+/// A label selector as Docker takes them: "key" matches any value, "key=value" only that one
+pub struct Selector {
+    key: String,
+    value: Option<String>,
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Self, String> {
+        let (key, value) = match selector.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (selector, None),
+        };
+        if key.is_empty() {
+            return Err(format!("Label selector '{}' has no key", selector));
+        }
+        Ok(Selector {
+            key: key.to_string(),
+            value,
+        })
+    }
+
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        labels.get(&self.key).is_some_and(|value| self.value.as_ref().is_none_or(|wanted| wanted == value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::instance;
+
+    fn conventions() -> Conventions {
+        let mut config = AgentConfig {
+            name: "eu-west-1".to_string(),
+            ..AgentConfig::default()
+        };
+        config.instances.name_prefix = "hz-".to_string();
+        Conventions::new(&config)
+    }
+
+    fn request(name: &str) -> AppInstanceRequest {
+        AppInstanceRequest {
+            name: name.to_string(),
+            image: "horizon:1".to_string(),
+            ports: None,
+            environment: None,
+            volumes: None,
+            supervise: None,
+            secrets: None,
+            ship_logs: None,
+            deployment: None,
+            project: None,
+        }
+    }
+
+    #[test]
+    fn container_names_are_prefixed_once() {
+        let conventions = conventions();
+        assert_eq!(conventions.container_name("game"), "hz-game");
+        assert_eq!(conventions.container_name("hz-game"), "hz-game");
+        assert_eq!(conventions.container_name(&conventions.container_name("game")), "hz-game");
+        assert_eq!(conventions.instance_name("hz-game"), "game");
+        assert_eq!(conventions.instance_name("game"), "game");
+    }
+
+    #[test]
+    fn instances_are_named_by_their_label_before_their_container() {
+        let conventions = conventions();
+        assert_eq!(conventions.name_of(&instance("abc123", "hz-game")), "game");
+        let labeled = AppInstance {
+            labels: HashMap::from([(INSTANCE.to_string(), "lobby".to_string())]),
+            ..instance("abc123", "hz-game")
+        };
+        assert_eq!(conventions.name_of(&labeled), "lobby");
+    }
+
+    #[test]
+    fn created_containers_carry_the_conventional_labels() {
+        let conventions = conventions();
+        let labels = conventions.labels(&AppInstanceRequest {
+            deployment: Some("lobby".to_string()),
+            ..request("hz-game")
+        });
+        assert_eq!(labels, HashMap::from([
+            (MANAGED.to_string(), "true".to_string()),
+            (INSTANCE.to_string(), "game".to_string()),
+            (AGENT.to_string(), "eu-west-1".to_string()),
+            (DEPLOYMENT.to_string(), "lobby".to_string()),
+        ]));
+        assert!(!conventions.labels(&request("game")).contains_key(PROJECT));
+    }
+
+    #[test]
+    fn only_maestro_labels_are_kept() {
+        let labels = maestro_labels(Some(HashMap::from([
+            (MANAGED.to_string(), "true".to_string()),
+            ("com.docker.compose.project".to_string(), "game".to_string()),
+            ("maestro".to_string(), "not namespaced".to_string()),
+        ])));
+        assert_eq!(labels, HashMap::from([(MANAGED.to_string(), "true".to_string())]));
+        assert!(maestro_labels(None).is_empty());
+    }
+
+    #[test]
+    fn selectors_match_keys_or_exact_values() {
+        let labels = HashMap::from([
+            (DEPLOYMENT.to_string(), "lobby".to_string()),
+            ("url".to_string(), "a=b".to_string()),
+            ("empty".to_string(), String::new()),
+        ]);
+        for (selector, expected) in [
+            ("maestro.deployment", true),
+            ("maestro.deployment=lobby", true),
+            ("maestro.deployment=Lobby", false),
+            ("maestro.deployment=lob", false),
+            ("maestro.project", false),
+            // Only the first '=' separates the key
+            ("url=a=b", true),
+            ("empty=", true),
+            ("empty", true),
+        ] {
+            assert_eq!(Selector::parse(selector).unwrap().matches(&labels), expected, "{:?}", selector);
+        }
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("=lobby").is_err());
+    }
+}
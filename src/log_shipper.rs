@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use bollard::container::{LogOutput, LogsOptions};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use prometheus::IntCounterVec;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::config::LogShippingConfig;
+use crate::routes::app_manager::AppManager;
+use crate::store;
+
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(10);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const LINE_BUFFER: usize = 10_000;
+const SPOOL_DIR: &str = "log-spool";
+const CURSORS_FILE: &str = "log_cursors.json";
+
+// Timestamp of the last line handed off (shipped or spooled) per instance id
+type Cursors = Arc<Mutex<HashMap<String, DateTime<Utc>>>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LogLine {
+    instance_id: String,
+    instance_name: String,
+    /// "stdout" or "stderr"
+    stream: String,
+    timestamp: String,
+    line: String,
+}
+
+/// Body POSTed to the ingestion URL
+#[derive(Debug, Serialize, Deserialize)]
+struct LogBatch {
+    agent_id: String,
+    lines: Vec<LogLine>,
+}
+
+/// Forwards the output of instances created with `ship_logs` to the configured ingestion URL
+pub struct LogShipper {
+    config: LogShippingConfig,
+    spool_dir: PathBuf,
+    cursors_path: PathBuf,
+    client: reqwest::Client,
+    lines: IntCounterVec,
+}
+
+impl LogShipper {
+    pub fn new(config: LogShippingConfig, data_dir: &Path, client: reqwest::Client, lines: IntCounterVec) -> Self {
+        LogShipper {
+            config,
+            spool_dir: data_dir.join(SPOOL_DIR),
+            cursors_path: data_dir.join(CURSORS_FILE),
+            client,
+            lines,
+        }
+    }
+
+    /// Whether log_shipping.url is configured
+    pub fn is_enabled(&self) -> bool {
+        self.config.url.is_some()
+    }
+
+    async fn send(&self, batch: &LogBatch) -> Result<(), reqwest::Error> {
+        let Some(url) = &self.config.url else { return Ok(()) };
+        let mut request = self.client.post(url)
+            .timeout(DELIVERY_TIMEOUT)
+            .json(batch);
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        self.lines.with_label_values(&["shipped"]).inc_by(batch.lines.len() as u64);
+        Ok(())
+    }
+
+    // Spooled batches sorted oldest first; file names start with the spool time
+    fn spooled(&self) -> Vec<(PathBuf, u64)> {
+        let Ok(entries) = std::fs::read_dir(&self.spool_dir) else { return Vec::new() };
+        let mut files: Vec<(PathBuf, u64)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "json"))
+            .map(|entry| (entry.path(), entry.metadata().map(|metadata| metadata.len()).unwrap_or_default()))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn spool(&self, batch: &LogBatch) -> io::Result<()> {
+        std::fs::create_dir_all(&self.spool_dir)?;
+        let name = format!("{:020}-{}.json", Utc::now().timestamp_nanos_opt().unwrap_or_default(), batch.lines.len());
+        store::save_json(&self.spool_dir.join(name), batch)?;
+        self.lines.with_label_values(&["spooled"]).inc_by(batch.lines.len() as u64);
+
+        // Past the cap the oldest batches go, so what's kept is the most recent output
+        let mut files = self.spooled();
+        let cap = self.config.spool_max_mb * 1024 * 1024;
+        let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+        while total > cap && !files.is_empty() {
+            let (path, size) = files.remove(0);
+            let dropped = path.file_stem()
+                .and_then(|stem| stem.to_str()?.rsplit('-').next()?.parse().ok())
+                .unwrap_or(0);
+            tracing::warn!("Log spool is over {} MB, dropping {} spooled lines", self.config.spool_max_mb, dropped);
+            std::fs::remove_file(&path)?;
+            self.lines.with_label_values(&["dropped"]).inc_by(dropped);
+            total -= size;
+        }
+        Ok(())
+    }
+
+    // Sends spooled batches in order, stopping at the first failure. True once the spool is empty.
+    async fn resume(&self) -> bool {
+        for (path, _) in self.spooled() {
+            let batch: LogBatch = match std::fs::read(&path).map(|bytes| serde_json::from_slice(&bytes)) {
+                Ok(Ok(batch)) => batch,
+                _ => {
+                    tracing::warn!("Discarding unreadable spooled log batch {}", path.display());
+                    let _ = std::fs::remove_file(&path);
+                    continue;
+                }
+            };
+            if let Err(e) = self.send(&batch).await {
+                tracing::debug!("Log ingestion is still unreachable: {}", e);
+                return false;
+            }
+            if let Err(e) = std::fs::remove_file(&path) {
+                tracing::warn!("Failed to remove shipped log batch {}: {}", path.display(), e);
+            }
+        }
+        tracing::info!("Shipped every spooled log batch");
+        true
+    }
+
+    // Moves the cursors past the lines of `batch` and persists them, once the batch is shipped or spooled
+    fn advance(&self, cursors: &Cursors, batch: &LogBatch) {
+        let mut cursors = cursors.lock().unwrap();
+        for line in &batch.lines {
+            let Ok(timestamp) = DateTime::parse_from_rfc3339(&line.timestamp) else { continue };
+            let timestamp = timestamp.with_timezone(&Utc);
+            let cursor = cursors.entry(line.instance_id.clone()).or_insert(timestamp);
+            *cursor = (*cursor).max(timestamp);
+        }
+        self.save_cursors(&cursors);
+    }
+
+    // Callers hold the cursors lock, so writes can't interleave on the temp file
+    fn save_cursors(&self, cursors: &HashMap<String, DateTime<Utc>>) {
+        if let Err(e) = store::save_json(&self.cursors_path, cursors) {
+            tracing::warn!("Failed to persist log cursors: {}", e);
+        }
+    }
+}
+
+/// Follows the logs of every tracked instance with `ship_logs` and delivers them in batches.
+/// Followers are (re)started every FOLLOW_INTERVAL, picking up after the last line shipped.
+pub async fn run(app_manager: AppManager, agent_id: String) {
+    let shipper = app_manager.logs.clone();
+    if !shipper.is_enabled() {
+        return;
+    }
+
+    // Last timestamp shipped per instance id, so a restarted follower doesn't repeat lines
+    let cursors: HashMap<String, DateTime<Utc>> = match store::load_json(&shipper.cursors_path) {
+        Ok(cursors) => cursors,
+        Err(e) => {
+            tracing::warn!("Failed to load log cursors, shipping from the current time: {}", e);
+            HashMap::new()
+        }
+    };
+    let cursors: Cursors = Arc::new(Mutex::new(cursors));
+    let (sender, receiver) = mpsc::channel(LINE_BUFFER);
+    tokio::spawn(deliver(shipper.clone(), agent_id, receiver, cursors.clone()));
+    let started = app_manager.now();
+    let mut followers: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+    loop {
+        #[cfg(feature = "chaos")]
+        app_manager.chaos.maybe_panic("log_shipper");
+        let wanted: HashMap<String, String> = app_manager.instances().into_iter()
+            .filter(|instance| instance.ship_logs)
+            .map(|instance| (instance.id, instance.name))
+            .collect();
+
+        followers.retain(|id, follower| {
+            if !wanted.contains_key(id) {
+                follower.abort();
+            }
+            wanted.contains_key(id) && !follower.is_finished()
+        });
+        let unfollowed: Vec<(&String, &String)> = wanted.iter()
+            .filter(|(id, _)| !followers.contains_key(*id))
+            .collect();
+        for (id, name) in unfollowed {
+            // Without a cursor shipping starts at startup, which is the first line of anything created since
+            let since = cursors.lock().unwrap().get(id).copied().unwrap_or(started);
+            tracing::debug!("Following logs of {}", name);
+            followers.insert(id.clone(), tokio::spawn(follow(app_manager.clone(), id.clone(), name.clone(), since, cursors.clone(), sender.clone())));
+        }
+
+        {
+            let mut cursors = cursors.lock().unwrap();
+            let before = cursors.len();
+            cursors.retain(|id, _| wanted.contains_key(id));
+            if cursors.len() != before {
+                shipper.save_cursors(&cursors);
+            }
+        }
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+    }
+}
+
+async fn follow(app_manager: AppManager, id: String, name: String, since: DateTime<Utc>, cursors: Cursors, sender: mpsc::Sender<LogLine>) {
+    let cursor = cursors.lock().unwrap().get(&id).copied();
+    let options = Some(LogsOptions::<String> {
+        follow: true,
+        stdout: true,
+        stderr: true,
+        timestamps: true,
+        since: since.timestamp(),
+        ..Default::default()
+    });
+
+    let mut logs = app_manager.docker().logs(&id, options);
+    while let Some(output) = logs.next().await {
+        let (stream, message) = match output {
+            Ok(LogOutput::StdOut { message }) | Ok(LogOutput::Console { message }) => ("stdout", message),
+            Ok(LogOutput::StdErr { message }) => ("stderr", message),
+            Ok(LogOutput::StdIn { .. }) => continue,
+            Err(e) => {
+                tracing::debug!("Stopped following logs of {}: {}", name, e);
+                return;
+            }
+        };
+
+        for raw in String::from_utf8_lossy(&message).lines() {
+            let (timestamp, line) = raw.split_once(' ').unwrap_or((raw, ""));
+            let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp).map(|timestamp| timestamp.with_timezone(&Utc)) else { continue };
+            // `since` has second precision, so the first lines can repeat what was already shipped
+            if cursor.is_some_and(|cursor| timestamp <= cursor) {
+                continue;
+            }
+
+            let log_line = LogLine {
+                instance_id: id.clone(),
+                instance_name: name.clone(),
+                stream: stream.to_string(),
+                timestamp: timestamp.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+                line: line.to_string(),
+            };
+            // The cursor only moves once the line is delivered, so nothing queued is lost on a restart
+            if sender.send(log_line).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn deliver(shipper: Arc<LogShipper>, agent_id: String, mut receiver: mpsc::Receiver<LogLine>, cursors: Cursors) {
+    let mut lines = Vec::new();
+    let mut flush = tokio::time::interval(Duration::from_secs(shipper.config.flush_interval_secs));
+    let mut backlog = !shipper.spooled().is_empty();
+    let mut retry_delay = MIN_RETRY_DELAY;
+    let mut retry_at = tokio::time::Instant::now();
+
+    loop {
+        tokio::select! {
+            line = receiver.recv() => match line {
+                Some(line) => {
+                    lines.push(line);
+                    if lines.len() < shipper.config.batch_lines {
+                        continue;
+                    }
+                },
+                None => break,
+            },
+            _ = flush.tick() => {},
+        }
+
+        if lines.is_empty() && !backlog {
+            continue;
+        }
+
+        let batch = LogBatch {
+            agent_id: agent_id.clone(),
+            lines: std::mem::take(&mut lines),
+        };
+        let mut delivered = false;
+        if tokio::time::Instant::now() >= retry_at {
+            // Spooled lines go out before anything newer
+            if backlog {
+                backlog = !shipper.resume().await;
+            }
+            delivered = !backlog && (batch.lines.is_empty() || match shipper.send(&batch).await {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::warn!("Failed to ship {} log lines, spooling them: {}", batch.lines.len(), e);
+                    false
+                }
+            });
+            if delivered {
+                shipper.advance(&cursors, &batch);
+                retry_delay = MIN_RETRY_DELAY;
+            } else {
+                retry_at = tokio::time::Instant::now() + retry_delay;
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+
+        if !delivered && !batch.lines.is_empty() {
+            match shipper.spool(&batch) {
+                Ok(()) => shipper.advance(&cursors, &batch),
+                Err(e) => {
+                    tracing::warn!("Failed to spool {} log lines: {}", batch.lines.len(), e);
+                    shipper.lines.with_label_values(&["dropped"]).inc_by(batch.lines.len() as u64);
+                }
+            }
+            backlog = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use serde_json::json;
+    use crate::metrics::AgentMetrics;
+    use crate::testing::{DockerResponse, FakeDocker};
+
+    fn line(timestamp: &str, text: &str) -> LogLine {
+        LogLine {
+            instance_id: "ab12".to_string(),
+            instance_name: "game".to_string(),
+            stream: "stdout".to_string(),
+            timestamp: timestamp.to_string(),
+            line: text.to_string(),
+        }
+    }
+
+    fn shipped(batch: &LogBatch) -> Vec<&str> {
+        batch.lines.iter().map(|line| line.line.as_str()).collect()
+    }
+
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never held");
+    }
+
+    #[tokio::test]
+    async fn lines_are_spooled_while_ingestion_is_down_and_shipped_after_a_restart() {
+        // The fake daemon doubles as the ingestion endpoint
+        let ingestion = FakeDocker::start().await;
+        let up = Arc::new(AtomicBool::new(false));
+        let accepting = up.clone();
+        ingestion.route("POST", "/ingest", move |_| match accepting.load(Ordering::SeqCst) {
+            true => DockerResponse::ok(json!({})),
+            false => DockerResponse::error(503, "down"),
+        });
+        let data_dir = tempfile::tempdir().unwrap();
+        let config = LogShippingConfig {
+            url: Some(format!("{}/ingest", ingestion.endpoint().replace("tcp://", "http://"))),
+            batch_lines: 2,
+            ..LogShippingConfig::default()
+        };
+        let metrics = AgentMetrics::new().unwrap();
+        let shipper = Arc::new(LogShipper::new(config, data_dir.path(), reqwest::Client::new(), metrics.shipped_log_lines.clone()));
+
+        // Ingestion is down: the batch is spooled, and only then does the cursor move
+        let cursors: Cursors = Arc::default();
+        let (sender, receiver) = mpsc::channel(LINE_BUFFER);
+        let delivery = tokio::spawn(deliver(shipper.clone(), "agent".to_string(), receiver, cursors.clone()));
+        sender.send(line("2026-03-01T03:00:01Z", "one")).await.unwrap();
+        assert!(cursors.lock().unwrap().is_empty());
+        sender.send(line("2026-03-01T03:00:02Z", "two")).await.unwrap();
+        eventually(|| shipper.spooled().len() == 1).await;
+        eventually(|| !cursors.lock().unwrap().is_empty()).await;
+
+        // A restart picks the cursor up from disk
+        drop(sender);
+        delivery.await.unwrap();
+        let persisted: HashMap<String, DateTime<Utc>> = store::load_json(&data_dir.path().join(CURSORS_FILE)).unwrap();
+        assert_eq!(persisted["ab12"].to_rfc3339(), "2026-03-01T03:00:02+00:00");
+
+        // With ingestion back the spooled batch goes out first, then the new one
+        up.store(true, Ordering::SeqCst);
+        let cursors: Cursors = Arc::new(Mutex::new(persisted));
+        let (sender, receiver) = mpsc::channel(LINE_BUFFER);
+        tokio::spawn(deliver(shipper.clone(), "agent".to_string(), receiver, cursors.clone()));
+        sender.send(line("2026-03-01T03:00:03Z", "three")).await.unwrap();
+        sender.send(line("2026-03-01T03:00:04Z", "four")).await.unwrap();
+        eventually(|| ingestion.requests_to("POST", "/ingest").len() == 3).await;
+
+        let batches: Vec<LogBatch> = ingestion.requests_to("POST", "/ingest").iter()
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect();
+        // The first is the attempt refused while ingestion was down
+        assert_eq!(shipped(&batches[0]), ["one", "two"]);
+        assert_eq!(shipped(&batches[1]), ["one", "two"]);
+        assert_eq!(shipped(&batches[2]), ["three", "four"]);
+        assert!(shipper.spooled().is_empty());
+        eventually(|| cursors.lock().unwrap()["ab12"].to_rfc3339() == "2026-03-01T03:00:04+00:00").await;
+    }
+}
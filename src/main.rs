@@ -1,13 +1,77 @@
 use colored::Colorize;
-use rocket::routes;
+use std::path::PathBuf;
+use rocket::{catchers, routes};
 
 pub mod routes;
-use routes::{index, instances};
+use routes::{catchers, index, instances, migration_routes};
 use routes::instances::AppManager;
 
 mod agent;
 use agent::Agent;
 
+mod attach;
+
+mod build_info;
+
+#[cfg(feature = "chaos")]
+mod chaos;
+
+mod clock;
+use clock::SystemClock;
+
+mod compression;
+
+mod config;
+use config::AgentConfig;
+
+mod crash_reports;
+
+mod crypto;
+
+mod desired_state;
+
+mod docker_cache;
+
+mod idempotency;
+
+mod image_policy;
+
+mod labels;
+
+mod log_shipper;
+
+mod metrics;
+
+mod paths;
+use paths::Locations;
+
+mod proxy;
+
+mod registries;
+
+mod scheduler;
+
+mod secrets;
+
+mod stats_history;
+
+mod store;
+
+mod supervisor;
+
+mod telemetry;
+
+#[cfg(test)]
+mod testing;
+
+mod timeline;
+
+mod tls;
+
+mod tunnels;
+
+mod webhooks;
+
 
 
 const BANNER: &str = r#"
@@ -18,33 +82,185 @@ const BANNER: &str = r#"
  |_| |_|\___/|_|  |_/___\___/|_| |_| |_|  |_|\__,_|\___||___/\__|_|  \___/ 
                               Version: {}
 "#;
+
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
-    println!("{}", BANNER.replace("{}", &env!("CARGO_PKG_VERSION")));
-    let agent = Agent::new("Horizon-Maestro 1".to_string(), env!("CARGO_PKG_VERSION").to_string());
+async fn main() -> Result<(), Box<rocket::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--print-default-config") {
+        print!("{}", config::DEFAULT_CONFIG_TEMPLATE);
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--version") {
+        println!("{}", serde_json::to_string_pretty(&build_info::current()).unwrap_or_default());
+        return Ok(());
+    }
+
+    let tracer_provider = telemetry::init();
+
+    // --config takes precedence over MAESTRO_CONFIG, then the default locations, then built-in defaults
+    let mut locations = Locations::from_args(&args);
+    locations.config = locations.config.or_else(|| std::env::var("MAESTRO_CONFIG").ok().map(PathBuf::from));
+
+    let mut config = match AgentConfig::load(&locations) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid agent configuration:");
+            for error in errors {
+                eprintln!("  - {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    if args.iter().any(|arg| arg == "--net-check") {
+        let reachable = proxy::check(&config).await;
+        std::process::exit(if reachable { 0 } else { 1 });
+    }
+
+    if config.data_dir == locations.default_data_dir() {
+        config.data_dir = paths::migrate_legacy_data_dir(&config.data_dir);
+    }
+    if let Err(e) = std::fs::create_dir_all(&config.data_dir) {
+        eprintln!("Failed to create data directory {}: {}", config.data_dir.display(), e);
+        std::process::exit(1);
+    }
+    // Resolved once, so nothing depends on the working directory staying the same
+    if let Ok(data_dir) = std::path::absolute(&config.data_dir) {
+        config.data_dir = data_dir;
+    }
+    tracing::info!("Using data directory {}", config.data_dir.display());
+
+    let build = build_info::current();
+    println!("{}", BANNER.replace("{}", &format!("{} ({}, built {})", build.version, build.git_commit, build.build_date)));
+    let agent = match Agent::load(config.name.clone(), env!("CARGO_PKG_VERSION").to_string(), &config.data_dir) {
+        Ok(agent) => agent,
+        Err(e) => {
+            eprintln!("Failed to load agent id: {}", e);
+            std::process::exit(1);
+        }
+    };
     println!("+-----------------------------------------------------------------");
     println!("| Selected UUID for agent: {}", agent.id().to_string().bright_green());
     println!("| Agent name: {}", agent.name().bright_blue());
+    println!("| Agent version: {}", agent.version());
     println!("+-----------------------------------------------------------------");
 
-    let routes = routes![
+    let app_manager = match AppManager::new(&config, std::sync::Arc::new(SystemClock)).await {
+        Ok(manager) => manager,
+        Err(e) => {
+            tracing::error!("Failed to initialize AppManager: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if config.docker.cache {
+        tokio::spawn(docker_cache::run(app_manager.clone(), std::time::Duration::from_secs(config.docker.cache_reconcile_secs)));
+    }
+    if config.stats_history.enabled {
+        tokio::spawn(stats_history::run(app_manager.clone()));
+    }
+    tokio::spawn(desired_state::run(app_manager.clone(), std::time::Duration::from_secs(config.desired_state.reconcile_secs)));
+    tokio::spawn(log_shipper::run(app_manager.clone(), agent.id().to_string()));
+    tokio::spawn(supervisor::run(app_manager.clone(), config.supervisor.clone()));
+    tokio::spawn(scheduler::run(app_manager.clone()));
+    let tunnels = app_manager.tunnels.clone();
+    let timeline = app_manager.timeline.clone();
+
+    #[cfg(feature = "chaos")]
+    tracing::warn!("Built with the chaos feature: faults can be injected into this agent through /agent/chaos");
+    if config.auth.tokens.is_empty() {
+        tracing::warn!("No auth tokens configured, mutating routes are open to any caller");
+    }
+
+    // The certificate was checked with the rest of the config, so this only fails if it changed since
+    let certificate = match &config.server.tls {
+        Some(tls_config) => match tls::ServerCertificate::load(tls_config) {
+            Ok(certificate) => {
+                let info = certificate.info(app_manager.now());
+                tracing::info!("Serving HTTPS with a certificate expiring {} (client certificates: {})", info.expires_at, info.client_auth);
+                if certificate.is_expiring(app_manager.now()) {
+                    tracing::warn!("TLS certificate expires in {} days", info.days_remaining);
+                }
+                Some(certificate)
+            },
+            Err(e) => {
+                tracing::error!("Failed to read server.tls.cert: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let rocket_instance = agent_rocket(config, app_manager, agent, certificate);
+
+    // Collect routes information before launch
+    index::collect_routes(&rocket_instance);
+    
+    // Launch the server
+    let result = rocket_instance.launch().await;
+    tunnels.close_all();
+    timeline.flush();
+    if let Err(e) = tracer_provider.shutdown() {
+        eprintln!("Failed to flush pending spans: {}", e);
+    }
+    result.map_err(Box::new)?;
+
+    Ok(())
+}
+
+/// The agent's routes, catchers, fairings and managed state, ready to launch
+fn agent_rocket(config: AgentConfig, app_manager: AppManager, agent: Agent, certificate: Option<tls::ServerCertificate>) -> rocket::Rocket<rocket::Build> {
+    #[allow(unused_mut)]
+    let mut routes = routes![
         index::     index,
         instances:: list_instances,
+        instances:: count_instances,
         instances:: get_instance,
         instances:: create_instance,
+        instances:: adopt_instances,
         instances:: start_instance,
         instances:: stop_instance,
         instances:: restart_instance,
         instances:: update_instance,
+        instances:: replace_instance,
+        instances:: update_secrets,
         instances:: delete_instance,
         instances:: list_images,
+        instances:: pull_image,
+        instances:: list_registries,
+        instances:: create_registry,
+        instances:: delete_registry,
         instances:: stream_events,
         instances:: health_check,
+        instances:: get_version,
         instances:: get_instance_logs,
         instances:: get_instance_stats,
+        instances:: get_instance_stats_history,
+        instances:: get_instance_crashes,
+        instances:: list_crashes,
+        instances:: put_state,
+        instances:: get_state,
+        instances:: get_state_drift,
+        instances:: get_image_policy,
+        instances:: put_image_policy,
         instances:: pause_instance,
         instances:: unpause_instance,
         instances:: inspect_instance,
+        instances:: open_tunnel,
+        instances:: list_tunnels,
+        instances:: close_tunnel,
+        instances:: attach_instance,
+        instances:: get_instance_timeline,
+        instances:: search_instances,
+        instances:: migrate_labels,
+        instances:: create_snapshot,
+        instances:: list_snapshots,
+        instances:: delete_snapshot,
+        instances:: clone_snapshot,
+        instances:: create_schedule,
+        instances:: list_schedules,
+        instances:: get_schedule_history,
+        instances:: delete_schedule,
         instances:: list_volumes,
         instances:: create_volume,
         instances:: delete_volume,
@@ -53,34 +269,54 @@ async fn main() -> Result<(), rocket::Error> {
         instances:: delete_network,
         instances:: connect_instance_to_network,
         instances:: disconnect_instance_from_network,
-        instances:: get_agent_info
+        instances:: get_agent_info,
+        instances:: get_maintenance,
+        instances:: start_maintenance,
+        instances:: end_maintenance,
+        instances:: create_maintenance_window,
+        instances:: list_maintenance_windows,
+        instances:: get_upcoming_maintenance,
+        instances:: delete_maintenance_window,
+        instances:: export_state,
+        instances:: import_state,
+        instances:: get_metrics
 
     ];
+    #[cfg(feature = "chaos")]
+    routes.extend(routes![
+        instances:: inject_chaos,
+        instances:: list_chaos,
+        instances:: remove_chaos,
+    ]);
 
     let routes_clone = routes.clone();
-    let app_manager = match AppManager::new() {
-        Ok(manager) => manager,
-        Err(e) => {
-            eprintln!("Failed to initialize AppManager: {}", e);
-            std::process::exit(1);
-        }
-    };
 
-    let rocket_instance = rocket::build()
-        .mount("/", routes)
-        .configure(rocket::Config {
-            address: "0.0.0.0".parse().unwrap(),
-            ..rocket::Config::default()
-        })
-        .manage(routes_clone)
-        .manage(app_manager);
+    // Both limits were validated with the rest of the config
+    let limits = rocket::data::Limits::default()
+        .limit("json", config.server.json_limit.parse().unwrap_or(rocket::data::Limits::JSON))
+        .limit(migration_routes::BUNDLE_LIMIT, config.server.import_limit.parse().unwrap_or(rocket::data::Limits::JSON));
+    let mut figment = rocket::Config::figment()
+        .merge(("address", &config.server.address))
+        .merge(("port", config.server.port))
+        .merge(("limits", limits));
 
-    // Collect routes information before launch
-    index::collect_routes(&rocket_instance);
-    
-    // Launch the server
-    let _server = rocket_instance.launch().await?;
-    
+    if let (Some(tls_config), Some(_)) = (&config.server.tls, &certificate) {
+        figment = figment.merge(("tls", tls::rocket_tls(tls_config)));
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    let compression = config.server.compression.enabled
+        .then(|| compression::ResponseCompression::new(&config.server.compression));
+    let rocket_instance = rocket::custom(figment)
+        .mount("/", telemetry::traced(routes))
+        .register("/", catchers![catchers::service_unavailable, catchers::default_catcher])
+        .attach(telemetry::RequestTracer)
+        .manage(routes_clone)
+        .manage(app_manager)
+        .manage(agent)
+        .manage(certificate)
+        .manage(config);
+    match compression {
+        Some(compression) => rocket_instance.attach(compression),
+        None => rocket_instance,
+    }
+}
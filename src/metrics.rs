@@ -0,0 +1,91 @@
+use std::future::Future;
+use std::time::Instant;
+use prometheus::{Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+
+/// Prometheus registry and collectors owned by the AppManager
+#[derive(Clone)]
+pub struct AgentMetrics {
+    registry: Registry,
+    pub instances: IntGaugeVec,
+    pub docker_requests: IntCounterVec,
+    pub docker_errors: IntCounterVec,
+    pub docker_latency: HistogramVec,
+    pub host_resources: GaugeVec,
+    pub webhook_deliveries: IntCounterVec,
+    pub shipped_log_lines: IntCounterVec,
+}
+
+impl AgentMetrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("maestro".to_string()), None)?;
+
+        let instances = IntGaugeVec::new(
+            Opts::new("instances", "Containers on this host by status"),
+            &["status"],
+        )?;
+        let docker_requests = IntCounterVec::new(
+            Opts::new("docker_requests_total", "Docker API calls by operation"),
+            &["operation"],
+        )?;
+        let docker_errors = IntCounterVec::new(
+            Opts::new("docker_errors_total", "Failed Docker API calls by operation"),
+            &["operation"],
+        )?;
+        let docker_latency = HistogramVec::new(
+            HistogramOpts::new("docker_request_duration_seconds", "Docker API call latency by operation"),
+            &["operation"],
+        )?;
+        let host_resources = GaugeVec::new(
+            Opts::new("host_resources", "Host resources as reported in AgentInfo"),
+            &["resource"],
+        )?;
+        let webhook_deliveries = IntCounterVec::new(
+            Opts::new("webhook_deliveries_total", "Webhook deliveries by outcome"),
+            &["outcome"],
+        )?;
+        let shipped_log_lines = IntCounterVec::new(
+            Opts::new("log_lines_total", "Shipped instance log lines by outcome"),
+            &["outcome"],
+        )?;
+
+        registry.register(Box::new(instances.clone()))?;
+        registry.register(Box::new(docker_requests.clone()))?;
+        registry.register(Box::new(docker_errors.clone()))?;
+        registry.register(Box::new(docker_latency.clone()))?;
+        registry.register(Box::new(host_resources.clone()))?;
+        registry.register(Box::new(webhook_deliveries.clone()))?;
+        registry.register(Box::new(shipped_log_lines.clone()))?;
+
+        Ok(AgentMetrics {
+            registry,
+            instances,
+            docker_requests,
+            docker_errors,
+            docker_latency,
+            host_resources,
+            webhook_deliveries,
+            shipped_log_lines,
+        })
+    }
+
+    /// Awaits a Docker API call, recording its latency and outcome under `operation`
+    pub async fn observe<T, E>(&self, operation: &str, call: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        let started = Instant::now();
+        let result = call.await;
+
+        self.docker_requests.with_label_values(&[operation]).inc();
+        self.docker_latency.with_label_values(&[operation]).observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.docker_errors.with_label_values(&[operation]).inc();
+        }
+
+        result
+    }
+
+    /// Renders every registered collector in the Prometheus text exposition format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
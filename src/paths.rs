@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "maestro";
+const CONFIG_FILE: &str = "agent.toml";
+const SYSTEM_DATA_DIR: &str = "/var/lib/maestro";
+const SYSTEM_CONFIG_DIR: &str = "/etc/maestro";
+
+/// Data directory used before it moved to the platform's data location, relative to the working directory
+pub const LEGACY_DATA_DIR: &str = "maestro-data";
+
+/// Config file read from the working directory before the platform's config location existed
+pub const LEGACY_CONFIG_FILE: &str = "agent.toml";
+
+/// Where the agent reads its config and keeps its state, as given on the command line:
+/// `--config <file>`, `--data-dir <dir>` and `--system` for the system-wide locations
+/// (/etc/maestro and /var/lib/maestro) instead of the user's
+pub struct Locations {
+    pub config: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub system: bool,
+}
+
+impl Locations {
+    pub fn from_args(args: &[String]) -> Self {
+        let value = |flag: &str| args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|index| args.get(index + 1))
+            .map(PathBuf::from);
+
+        Locations {
+            config: value("--config"),
+            data_dir: value("--data-dir"),
+            system: args.iter().any(|arg| arg == "--system"),
+        }
+    }
+
+    /// The config file read when none is given: the platform's config location if it has one,
+    /// then agent.toml in the working directory for setups that predate it
+    pub fn default_config(&self) -> Option<PathBuf> {
+        let config = if self.system {
+            Some(Path::new(SYSTEM_CONFIG_DIR).join(CONFIG_FILE))
+        } else {
+            user_dir("XDG_CONFIG_HOME", ".config", "APPDATA").map(|dir| dir.join(APP_DIR).join(CONFIG_FILE))
+        };
+        match config {
+            Some(config) if config.exists() => Some(config),
+            _ if Path::new(LEGACY_CONFIG_FILE).exists() => {
+                tracing::info!(
+                    "Reading {} from the working directory; move it to {} so the agent finds it wherever it's started",
+                    LEGACY_CONFIG_FILE,
+                    config.as_deref().unwrap_or(Path::new(SYSTEM_CONFIG_DIR)).display()
+                );
+                Some(PathBuf::from(LEGACY_CONFIG_FILE))
+            },
+            _ => None,
+        }
+    }
+
+    /// The data directory used when neither the command line, MAESTRO_DATA_DIR nor the config set one
+    pub fn default_data_dir(&self) -> PathBuf {
+        if self.system {
+            return PathBuf::from(SYSTEM_DATA_DIR);
+        }
+        user_dir("XDG_DATA_HOME", ".local/share", "LOCALAPPDATA")
+            .map(|dir| dir.join(APP_DIR))
+            .unwrap_or_else(|| PathBuf::from(LEGACY_DATA_DIR))
+    }
+}
+
+// The XDG base directory in `xdg_var`, else `home_fallback` under $HOME; on Windows the
+// known folder in `windows_var`
+fn user_dir(xdg_var: &str, home_fallback: &str, windows_var: &str) -> Option<PathBuf> {
+    let non_empty = |var: &str| std::env::var_os(var).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if cfg!(windows) {
+        return non_empty(windows_var);
+    }
+    non_empty(xdg_var)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| non_empty("HOME").map(|home| home.join(home_fallback)))
+}
+
+/// Moves state left in ./maestro-data by older agents to `data_dir`, unless something is there
+/// already. If it can't be moved, e.g. across filesystems, the old directory keeps being used
+/// rather than starting over with empty state. Returns the directory to use.
+pub fn migrate_legacy_data_dir(data_dir: &Path) -> PathBuf {
+    let legacy = Path::new(LEGACY_DATA_DIR);
+    if !legacy.is_dir() || data_dir.exists() || data_dir == legacy {
+        return data_dir.to_path_buf();
+    }
+
+    let moved = match data_dir.parent() {
+        Some(parent) => std::fs::create_dir_all(parent).and_then(|_| std::fs::rename(legacy, data_dir)),
+        None => std::fs::rename(legacy, data_dir),
+    };
+    match moved {
+        Ok(()) => {
+            tracing::info!("Moved the data directory from {} in the working directory to {}", LEGACY_DATA_DIR, data_dir.display());
+            data_dir.to_path_buf()
+        },
+        Err(e) => {
+            tracing::warn!("Failed to move {} to {}, still using it: {}", LEGACY_DATA_DIR, data_dir.display(), e);
+            legacy.to_path_buf()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // The working directory is per process, so tests that change it take turns
+    static WORKING_DIR: Mutex<()> = Mutex::new(());
+
+    // Runs `test` with a fresh temporary directory as the working directory, passing its path
+    fn in_scratch_dir(test: impl FnOnce(&Path)) {
+        let _turn = WORKING_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let scratch = tempfile::tempdir().unwrap();
+        let previous = std::env::current_dir().unwrap();
+        std::env::set_current_dir(scratch.path()).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(scratch.path())));
+        std::env::set_current_dir(previous).unwrap();
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    fn legacy_state(scratch: &Path) {
+        std::fs::create_dir(scratch.join(LEGACY_DATA_DIR)).unwrap();
+        std::fs::write(scratch.join(LEGACY_DATA_DIR).join("instances.json"), "{\"abc123\": {}}").unwrap();
+    }
+
+    #[test]
+    fn legacy_state_moves_to_the_new_data_dir() {
+        in_scratch_dir(|scratch| {
+            legacy_state(scratch);
+            let data_dir = scratch.join("home/.local/share/maestro");
+
+            assert_eq!(migrate_legacy_data_dir(&data_dir), data_dir);
+            assert_eq!(std::fs::read_to_string(data_dir.join("instances.json")).unwrap(), "{\"abc123\": {}}");
+            assert!(!scratch.join(LEGACY_DATA_DIR).exists());
+            // Nothing left to move the second time
+            assert_eq!(migrate_legacy_data_dir(&data_dir), data_dir);
+        });
+    }
+
+    #[test]
+    fn existing_state_is_never_overwritten() {
+        in_scratch_dir(|scratch| {
+            legacy_state(scratch);
+            let data_dir = scratch.join("data");
+            std::fs::create_dir(&data_dir).unwrap();
+
+            assert_eq!(migrate_legacy_data_dir(&data_dir), data_dir);
+            assert!(!data_dir.join("instances.json").exists());
+            assert!(scratch.join(LEGACY_DATA_DIR).join("instances.json").exists());
+        });
+    }
+
+    #[test]
+    fn without_legacy_state_nothing_is_created() {
+        in_scratch_dir(|scratch| {
+            let data_dir = scratch.join("data");
+            assert_eq!(migrate_legacy_data_dir(&data_dir), data_dir);
+            assert!(!data_dir.exists());
+            assert_eq!(migrate_legacy_data_dir(Path::new(LEGACY_DATA_DIR)), Path::new(LEGACY_DATA_DIR));
+        });
+    }
+
+    #[test]
+    fn legacy_state_that_cant_be_moved_keeps_being_used() {
+        in_scratch_dir(|scratch| {
+            legacy_state(scratch);
+            // A file where the new data dir's parent should be
+            std::fs::write(scratch.join("occupied"), "").unwrap();
+
+            assert_eq!(migrate_legacy_data_dir(&scratch.join("occupied/maestro")), Path::new(LEGACY_DATA_DIR));
+            assert!(scratch.join(LEGACY_DATA_DIR).join("instances.json").exists());
+        });
+    }
+
+    #[test]
+    fn locations_come_from_the_command_line() {
+        let args: Vec<String> = ["maestro-agent", "--system", "--data-dir", "/srv/maestro"].iter().map(|arg| arg.to_string()).collect();
+        let locations = Locations::from_args(&args);
+        assert!(locations.system);
+        assert_eq!(locations.data_dir.as_deref(), Some(Path::new("/srv/maestro")));
+        assert!(locations.config.is_none());
+        assert_eq!(locations.default_data_dir(), Path::new(SYSTEM_DATA_DIR));
+    }
+}
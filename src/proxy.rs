@@ -0,0 +1,230 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use reqwest::Url;
+use crate::config::{AgentConfig, ProxyConfig};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Proxies for the agent's outbound HTTP, from the [proxy] config with the usual environment
+/// variables filling in what it leaves unset
+#[derive(Debug, Clone, Default)]
+pub struct ProxySettings {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+// The first of `vars` that's set, upper and lower case alike since both are in use
+fn env_proxy(vars: &[&str]) -> Option<Url> {
+    let (var, value) = vars.iter()
+        .flat_map(|var| [var.to_string(), var.to_ascii_lowercase()])
+        .find_map(|var| std::env::var(&var).ok().filter(|value| !value.is_empty()).map(|value| (var, value)))?;
+    match Url::parse(&value) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            tracing::warn!("Ignoring {}: '{}' is not a URL ({})", var, value, e);
+            None
+        }
+    }
+}
+
+// Proxy URLs may carry credentials, which don't belong in logs or output
+fn redacted(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("***"));
+    }
+    url.to_string()
+}
+
+// Whether `ip` is in the network `network`/`prefix`; malformed ranges match nothing
+fn in_network(ip: IpAddr, network: &str, prefix: &str) -> bool {
+    let (Ok(network), Ok(prefix)) = (network.parse::<IpAddr>(), prefix.parse::<u32>()) else { return false };
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        },
+        (IpAddr::V6(ip), IpAddr::V6(network)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        },
+        _ => false,
+    }
+}
+
+impl ProxySettings {
+    /// The proxy URLs were validated with the rest of the config
+    pub fn from_config(config: &ProxyConfig) -> Self {
+        let configured = |url: &Option<String>| url.as_deref().and_then(|url| Url::parse(url).ok());
+        let no_proxy = if config.no_proxy.is_empty() {
+            std::env::var("NO_PROXY").or_else(|_| std::env::var("no_proxy"))
+                .map(|hosts| hosts.split(',').map(str::trim).filter(|host| !host.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default()
+        } else {
+            config.no_proxy.clone()
+        };
+
+        ProxySettings {
+            http: configured(&config.http).or_else(|| env_proxy(&["HTTP_PROXY", "ALL_PROXY"])),
+            https: configured(&config.https).or_else(|| env_proxy(&["HTTPS_PROXY", "ALL_PROXY"])),
+            no_proxy,
+        }
+    }
+
+    // no_proxy entries match the host itself and its subdomains, IP addresses in a CIDR range
+    // like 10.0.0.0/8, or with "*" every host
+    fn bypassed(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        let ip = host.parse::<IpAddr>().ok();
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.').to_ascii_lowercase();
+            if let (Some(ip), Some((network, prefix))) = (ip, entry.split_once('/')) {
+                return in_network(ip, network, prefix);
+            }
+            entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+        })
+    }
+
+    /// The proxy a request to `url` goes through, or None when it goes direct
+    pub fn proxy_for(&self, url: &Url) -> Option<&Url> {
+        if url.host_str().is_some_and(|host| self.bypassed(host)) {
+            return None;
+        }
+        match url.scheme() {
+            "https" => self.https.as_ref(),
+            _ => self.http.as_ref(),
+        }
+    }
+
+    /// An HTTP client sending every request through `proxy_for`
+    pub fn client(&self) -> reqwest::Client {
+        let settings = self.clone();
+        let proxy = reqwest::Proxy::custom(move |url| settings.proxy_for(url).cloned());
+        reqwest::Client::builder()
+            .proxy(proxy)
+            .build()
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to build the proxied HTTP client, connecting directly: {}", e);
+                reqwest::Client::new()
+            })
+    }
+}
+
+/// Tries every configured outbound endpoint through the proxy it would use and prints the
+/// outcome, for `--net-check`. Any HTTP response counts as reachable. Returns whether all were.
+pub async fn check(config: &AgentConfig) -> bool {
+    let settings = ProxySettings::from_config(&config.proxy);
+    let show = |proxy: &Option<Url>| proxy.as_ref().map_or("none".to_string(), redacted);
+    println!("http proxy:  {}", show(&settings.http));
+    println!("https proxy: {}", show(&settings.https));
+    println!("no_proxy:    {}", if settings.no_proxy.is_empty() { "none".to_string() } else { settings.no_proxy.join(", ") });
+
+    let endpoints: Vec<(&str, &str)> = config.webhooks.iter()
+        .map(|hook| ("webhook", hook.url.as_str()))
+        .chain(config.log_shipping.url.as_deref().map(|url| ("log shipping", url)))
+        .collect();
+    if endpoints.is_empty() {
+        println!("No outbound endpoints configured");
+        return true;
+    }
+
+    let client = settings.client();
+    let mut reachable = true;
+    for (kind, endpoint) in endpoints {
+        let Ok(url) = Url::parse(endpoint) else { continue };
+        let route = settings.proxy_for(&url).map_or("direct".to_string(), |proxy| format!("via {}", redacted(proxy)));
+        let started = Instant::now();
+        match client.head(url).timeout(CHECK_TIMEOUT).send().await {
+            Ok(response) => println!("ok    {} {} {} (HTTP {}, {} ms)", kind, endpoint, route, response.status().as_u16(), started.elapsed().as_millis()),
+            Err(e) => {
+                reachable = false;
+                println!("FAIL  {} {} {}: {}", kind, endpoint, route, e);
+            }
+        }
+    }
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::testing::{DockerResponse, FakeDocker};
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn settings(no_proxy: &[&str]) -> ProxySettings {
+        ProxySettings::from_config(&ProxyConfig {
+            http: Some("http://proxy.internal:3128".to_string()),
+            https: Some("http://secure-proxy.internal:3128".to_string()),
+            no_proxy: no_proxy.iter().map(|entry| entry.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn requests_go_through_the_proxy_for_their_scheme() {
+        let settings = settings(&["localhost"]);
+        assert_eq!(settings.proxy_for(&url("http://hooks.example.com/maestro")), Some(&url("http://proxy.internal:3128")));
+        assert_eq!(settings.proxy_for(&url("https://hooks.example.com/maestro")), Some(&url("http://secure-proxy.internal:3128")));
+    }
+
+    #[test]
+    fn no_proxy_matches_hosts_domains_ranges_and_wildcards() {
+        let settings = settings(&["localhost", ".corp.example", "Internal.Example.com", "10.0.0.0/8", "192.168.1.7", "fd00::/8"]);
+        for (target, direct) in [
+            ("http://localhost:8080/", true),
+            // Exact hosts, case-insensitively
+            ("http://internal.example.com/", true),
+            ("http://INTERNAL.example.com/", true),
+            ("http://example.com/", false),
+            ("http://notinternal.example.com/", false),
+            // Domains and their subdomains
+            ("http://corp.example/", true),
+            ("https://logs.eu.corp.example/", true),
+            ("http://corp.example.org/", false),
+            // Addresses and ranges
+            ("http://10.20.30.40/", true),
+            ("http://11.0.0.1/", false),
+            ("http://192.168.1.7/", true),
+            ("http://192.168.1.8/", false),
+            ("http://[fd12:3456::1]/", true),
+            ("http://[fe80::1]/", false),
+        ] {
+            assert_eq!(settings.proxy_for(&url(target)).is_none(), direct, "{}", target);
+        }
+
+        let everything = self::settings(&["*"]);
+        assert!(everything.proxy_for(&url("https://hooks.example.com/")).is_none());
+        // Malformed ranges match nothing
+        let malformed = self::settings(&["10.0.0.0/33", "10.0.0.0/x", "::/8"]);
+        assert!(malformed.proxy_for(&url("http://10.0.0.1/")).is_some());
+    }
+
+    #[tokio::test]
+    async fn clients_send_through_the_selected_proxy() {
+        // Fake daemons standing in for a forward proxy and a directly reachable endpoint
+        let proxy = FakeDocker::start().await;
+        proxy.route("POST", "http://hooks.example.com/maestro", |_| DockerResponse::ok(json!({})));
+        let direct = FakeDocker::start().await;
+        direct.route("POST", "/maestro", |_| DockerResponse::ok(json!({})));
+        let direct_url = format!("{}/maestro", direct.endpoint().replace("tcp://", "http://"));
+
+        let client = ProxySettings::from_config(&ProxyConfig {
+            http: Some(proxy.endpoint().replace("tcp://", "http://")),
+            https: None,
+            no_proxy: vec!["127.0.0.0/8".to_string()],
+        }).client();
+
+        let proxied = client.post("http://hooks.example.com/maestro").json(&json!({ "event": "test" })).send().await.unwrap();
+        assert_eq!(proxied.status(), reqwest::StatusCode::OK);
+        assert_eq!(proxy.requests_to("POST", "http://hooks.example.com/maestro")[0].json()["event"], "test");
+
+        let bypassed = client.post(&direct_url).json(&json!({ "event": "test" })).send().await.unwrap();
+        assert_eq!(bypassed.status(), reqwest::StatusCode::OK);
+        assert_eq!(direct.requests_to("POST", "/maestro").len(), 1);
+        assert_eq!(proxy.requests().len(), 1);
+    }
+}
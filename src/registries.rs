@@ -0,0 +1,273 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use bollard::auth::DockerCredentials;
+use serde::{Deserialize, Serialize};
+use crate::clock::Clock;
+use crate::crypto::{Sealed, Sealer};
+use crate::routes::models::{RegistryCredentialRequest, RegistryInfo};
+use crate::store;
+
+const REGISTRIES_FILE: &str = "registries.json";
+const DOCKER_HUB: &str = "docker.io";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredCredential {
+    server: String,
+    username: String,
+    #[serde(flatten)]
+    password: Sealed,
+    created_at: String,
+    updated_at: String,
+}
+
+impl StoredCredential {
+    fn info(&self) -> RegistryInfo {
+        RegistryInfo {
+            server: self.server.clone(),
+            username: self.username.clone(),
+            created_at: self.created_at.clone(),
+            updated_at: self.updated_at.clone(),
+        }
+    }
+}
+
+/// Canonical form of a registry server: no scheme or API suffix, Docker Hub aliases folded into docker.io
+pub fn normalize_server(server: &str) -> String {
+    let server = server.trim()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .trim_end_matches('/');
+    let server = server.strip_suffix("/v1").or_else(|| server.strip_suffix("/v2")).unwrap_or(server);
+
+    match server.split_once('/') {
+        Some((host, path)) => format!("{}/{}", canonical_host(host), path),
+        None => canonical_host(server).to_string(),
+    }
+}
+
+fn canonical_host(host: &str) -> &str {
+    match host {
+        "index.docker.io" | "registry-1.docker.io" | "registry.hub.docker.com" => DOCKER_HUB,
+        host => host,
+    }
+}
+
+/// Fully qualified repository for an image reference, e.g. "nginx:1.25" -> "docker.io/library/nginx"
+pub fn image_repository(image: &str) -> String {
+    let image = image.split('@').next().unwrap_or(image);
+    // A colon after the last slash is a tag, one before it is a registry port
+    let image = match image.rfind(':') {
+        Some(colon) if colon > image.rfind('/').unwrap_or(0) => &image[..colon],
+        _ => image,
+    };
+
+    match image.split_once('/') {
+        Some((first, _)) if first.contains('.') || first.contains(':') || first == "localhost" => normalize_server(image),
+        Some(_) => format!("{}/{}", DOCKER_HUB, image),
+        None => format!("{}/library/{}", DOCKER_HUB, image),
+    }
+}
+
+/// Picks the server that is the longest prefix of `repository`, matching whole path segments only
+pub fn select_server<'a>(servers: impl Iterator<Item = &'a str>, repository: &str) -> Option<&'a str> {
+    servers
+        .filter(|server| {
+            repository == *server
+                || repository.strip_prefix(*server).is_some_and(|rest| rest.starts_with('/'))
+        })
+        .max_by_key(|server| server.len())
+}
+
+/// Registry credentials persisted in the data dir, with passwords encrypted under encryption_key
+pub struct RegistryStore {
+    sealer: Option<Sealer>,
+    path: PathBuf,
+    entries: Mutex<Vec<StoredCredential>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RegistryStore {
+    pub fn load(sealer: Option<Sealer>, data_dir: &Path, clock: Arc<dyn Clock>) -> io::Result<Self> {
+        let path = data_dir.join(REGISTRIES_FILE);
+        let entries = store::load_json(&path)?;
+
+        Ok(RegistryStore {
+            sealer,
+            path,
+            entries: Mutex::new(entries),
+            clock,
+        })
+    }
+
+    /// Whether a key is configured, i.e. whether credentials can be stored at all
+    pub fn is_enabled(&self) -> bool {
+        self.sealer.is_some()
+    }
+
+    pub fn list(&self) -> Vec<RegistryInfo> {
+        self.entries.lock().unwrap().iter()
+            .map(StoredCredential::info)
+            .collect()
+    }
+
+    /// Adds a credential, or rotates the existing one for the same server.
+    /// Lookups happen at pull time, so running instances pick up rotated secrets on their next pull.
+    pub fn upsert(&self, request: &RegistryCredentialRequest) -> io::Result<RegistryInfo> {
+        let sealer = self.sealer.as_ref()
+            .ok_or_else(|| io::Error::other("encryption_key is not configured"))?;
+        let password = sealer.seal(&request.password)?;
+
+        let server = normalize_server(&request.server);
+        let now = self.clock.now().to_rfc3339();
+        let mut entries = self.entries.lock().unwrap();
+        let created_at = entries.iter()
+            .find(|entry| entry.server == server)
+            .map(|entry| entry.created_at.clone())
+            .unwrap_or_else(|| now.clone());
+        entries.retain(|entry| entry.server != server);
+        entries.push(StoredCredential {
+            server: server.clone(),
+            username: request.username.clone(),
+            password,
+            created_at: created_at.clone(),
+            updated_at: now.clone(),
+        });
+        store::save_json(&self.path, &*entries)?;
+
+        Ok(RegistryInfo {
+            server,
+            username: request.username.clone(),
+            created_at,
+            updated_at: now,
+        })
+    }
+
+    /// Every credential with its decrypted password (None if it can't be decrypted), for export bundles
+    pub fn export(&self) -> Vec<(RegistryInfo, Option<String>)> {
+        self.entries.lock().unwrap().iter()
+            .map(|entry| (entry.info(), self.sealer.as_ref().and_then(|sealer| sealer.open(&entry.password))))
+            .collect()
+    }
+
+    /// Removes the credential for `server`, returning whether there was one
+    pub fn remove(&self, server: &str) -> io::Result<bool> {
+        let server = normalize_server(server);
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|entry| entry.server != server);
+        if entries.len() == before {
+            return Ok(false);
+        }
+        store::save_json(&self.path, &*entries)?;
+        Ok(true)
+    }
+
+    /// Credentials to send with a pull of `image`, if any stored server matches it
+    pub fn credentials_for(&self, image: &str) -> Option<DockerCredentials> {
+        let sealer = self.sealer.as_ref()?;
+        let repository = image_repository(image);
+        let entries = self.entries.lock().unwrap();
+        let server = select_server(entries.iter().map(|entry| entry.server.as_str()), &repository)?;
+        let entry = entries.iter().find(|entry| entry.server == server)?;
+
+        let Some(password) = sealer.open(&entry.password) else {
+            tracing::warn!("Stored credential for {} can't be decrypted with the configured key", entry.server);
+            return None;
+        };
+
+        let host = entry.server.split('/').next().unwrap_or(&entry.server);
+        Some(DockerCredentials {
+            username: Some(entry.username.clone()),
+            password: Some(password),
+            serveraddress: Some(host.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use crate::clock::TestClock;
+
+    #[test]
+    fn servers_are_normalized() {
+        for (server, expected) in [
+            ("docker.io", "docker.io"),
+            ("https://index.docker.io/v1/", "docker.io"),
+            ("registry-1.docker.io", "docker.io"),
+            ("registry.hub.docker.com/v2", "docker.io"),
+            ("ghcr.io", "ghcr.io"),
+            ("https://ghcr.io/my-org/", "ghcr.io/my-org"),
+            (" registry.internal:5000 ", "registry.internal:5000"),
+            ("http://registry.internal:5000/v2/", "registry.internal:5000"),
+            ("localhost:5000/team", "localhost:5000/team"),
+        ] {
+            assert_eq!(normalize_server(server), expected, "{:?}", server);
+        }
+    }
+
+    #[test]
+    fn images_resolve_to_fully_qualified_repositories() {
+        for (image, expected) in [
+            ("nginx", "docker.io/library/nginx"),
+            ("nginx:1.25", "docker.io/library/nginx"),
+            ("nginx@sha256:0123abcd", "docker.io/library/nginx"),
+            ("farbeyond/horizon:latest", "docker.io/farbeyond/horizon"),
+            ("docker.io/farbeyond/horizon", "docker.io/farbeyond/horizon"),
+            ("index.docker.io/farbeyond/horizon:2", "docker.io/farbeyond/horizon"),
+            ("ghcr.io/my-org/game:1.0", "ghcr.io/my-org/game"),
+            ("registry.internal:5000/game", "registry.internal:5000/game"),
+            ("registry.internal:5000/team/game:3", "registry.internal:5000/team/game"),
+            ("localhost/game:dev", "localhost/game"),
+        ] {
+            assert_eq!(image_repository(image), expected, "{:?}", image);
+        }
+    }
+
+    #[test]
+    fn the_longest_whole_segment_prefix_wins() {
+        let servers = ["docker.io", "ghcr.io", "ghcr.io/my-org", "ghcr.io/my-org/game", "registry.internal:5000"];
+        for (repository, expected) in [
+            ("docker.io/library/nginx", Some("docker.io")),
+            ("ghcr.io/other-org/game", Some("ghcr.io")),
+            ("ghcr.io/my-org/lobby", Some("ghcr.io/my-org")),
+            ("ghcr.io/my-org/game", Some("ghcr.io/my-org/game")),
+            // Not a whole segment
+            ("ghcr.io/my-org-fork/game", Some("ghcr.io")),
+            ("ghcr.io/my-org/gameserver", Some("ghcr.io/my-org")),
+            ("registry.internal:5000/game", Some("registry.internal:5000")),
+            ("registry.internal:50001/game", None),
+            ("quay.io/game", None),
+        ] {
+            assert_eq!(select_server(servers.into_iter(), repository), expected, "{:?}", repository);
+        }
+    }
+
+    #[test]
+    fn rotation_keeps_the_creation_time() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let clock = TestClock::at(Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap());
+        let sealer = Sealer::from_key(Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=")).unwrap();
+        let store = RegistryStore::load(Some(sealer), data_dir.path(), Arc::new(clock.clone())).unwrap();
+        let request = |password: &str| RegistryCredentialRequest {
+            server: "https://ghcr.io/my-org/".to_string(),
+            username: "deployer".to_string(),
+            password: password.to_string(),
+        };
+
+        let added = store.upsert(&request("first")).unwrap();
+        assert_eq!(added.created_at, "2026-03-01T12:00:00+00:00");
+        clock.advance(TimeDelta::days(1));
+        let rotated = store.upsert(&request("second")).unwrap();
+        assert_eq!((rotated.created_at.as_str(), rotated.updated_at.as_str()), ("2026-03-01T12:00:00+00:00", "2026-03-02T12:00:00+00:00"));
+        assert_eq!(store.list().len(), 1);
+
+        let credentials = store.credentials_for("ghcr.io/my-org/game:1").unwrap();
+        assert_eq!(credentials.password.as_deref(), Some("second"));
+        assert_eq!(credentials.serveraddress.as_deref(), Some("ghcr.io"));
+        assert!(store.credentials_for("ghcr.io/other-org/game:1").is_none());
+    }
+}
@@ -0,0 +1,285 @@
+use rocket::post;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::HashMap;
+use bollard::container::ListContainersOptions;
+use bollard::models::ContainerInspectResponse;
+use serde_json::json;
+use crate::desired_state;
+use crate::labels;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::instance_routes::find_tracked;
+use crate::routes::models::{AdoptRequest, AppInstance, ImportItem, ImportReport, PortMapping, SupervisionState, VolumeMapping};
+use crate::timeline;
+
+// Shell-style match where '*' is any run of characters and '?' any single one
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Rebuild the AppInstance we'd have tracked had the agent created this container
+pub(crate) fn instance_from_container(container: ContainerInspectResponse, supervision: Option<SupervisionState>) -> AppInstance {
+    let config = container.config.unwrap_or_default();
+    let host_config = container.host_config.unwrap_or_default();
+
+    let environment = config.env.unwrap_or_default().iter()
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    let mut ports = Vec::new();
+    for (container_port, bindings) in host_config.port_bindings.unwrap_or_default() {
+        let (container_port, protocol) = container_port.split_once('/').unwrap_or((&container_port, "tcp"));
+        let Ok(container_port) = container_port.parse() else { continue };
+        for binding in bindings.unwrap_or_default() {
+            if let Some(host_port) = binding.host_port.and_then(|port| port.parse().ok()) {
+                ports.push(PortMapping {
+                    host_port,
+                    container_port,
+                    protocol: protocol.to_string(),
+                });
+            }
+        }
+    }
+
+    let volumes = host_config.binds.unwrap_or_default().iter()
+        .filter_map(|bind| {
+            let mut parts = bind.splitn(3, ':');
+            Some(VolumeMapping {
+                host_path: parts.next()?.to_string(),
+                container_path: parts.next()?.to_string(),
+            })
+        })
+        .collect();
+
+    AppInstance {
+        id: container.id.unwrap_or_default(),
+        name: container.name.unwrap_or_default().trim_start_matches('/').to_string(),
+        image: config.image.unwrap_or_default(),
+        status: "running".to_string(),
+        created_at: container.created.unwrap_or_default(),
+        ports,
+        environment,
+        volumes,
+        agent_id: "current".to_string(),
+        supervision,
+        secrets: HashMap::new(),
+        ship_logs: false,
+        labels: labels::maestro_labels(config.labels),
+        recent_events: Vec::new(),
+    }
+}
+
+pub(crate) fn adopt_item(name: &str, outcome: &str, message: Option<String>) -> ImportItem {
+    ImportItem {
+        kind: "instance".to_string(),
+        name: name.to_string(),
+        outcome: outcome.to_string(),
+        message,
+    }
+}
+
+/// Starts tracking running containers that were created outside the agent, without touching them.
+/// Containers are selected by `name_pattern` (a glob) and/or a `label` ("key" or "key=value").
+/// A container whose name is already tracked under a different id is reported as a conflict.
+/// `apply_labels` gives unlabeled containers the labels the agent's own containers carry.
+#[post("/instances/adopt", format = "json", data = "<adopt_req>")]
+pub async fn adopt_instances(adopt_req: Json<AdoptRequest>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<ImportReport>, AgentError> {
+    if adopt_req.name_pattern.is_none() && adopt_req.label.is_none() {
+        return Err(AgentError::BadRequest("Adopting requires a name_pattern or a label to select containers".to_string()));
+    }
+
+    let mut filters = HashMap::new();
+    filters.insert("status".to_string(), vec!["running".to_string()]);
+    if let Some(label) = &adopt_req.label {
+        filters.insert("label".to_string(), vec![label.clone()]);
+    }
+    let options = Some(ListContainersOptions {
+        filters,
+        ..Default::default()
+    });
+    let containers = match app_manager.instrument("list_containers", app_manager.docker().list_containers(options)).await {
+        Ok(containers) => containers,
+        Err(e) => return Err(AgentError::docker("Failed to list containers", e)),
+    };
+
+    let mut items = Vec::new();
+    let mut adopted = Vec::new();
+    for container in containers {
+        let (Some(id), Some(name)) = (container.id, container.names.and_then(|names| names.into_iter().next())) else { continue };
+        let name = name.trim_start_matches('/').to_string();
+        if adopt_req.name_pattern.as_deref().is_some_and(|pattern| !glob_match(pattern, &name)) {
+            continue;
+        }
+
+        if app_manager.instance(&id).is_some() {
+            items.push(adopt_item(&name, "skipped", Some("already managed by this agent".to_string())));
+            continue;
+        }
+
+        let inspected = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+            Ok(inspected) => inspected,
+            Err(e) => {
+                items.push(adopt_item(&name, "failed", Some(format!("Failed to inspect container: {}", e))));
+                continue;
+            }
+        };
+        let mut instance = instance_from_container(inspected, adopt_req.supervise.then(|| SupervisionState::started(app_manager.now())));
+        let label = adopt_req.apply_labels && !instance.labels.contains_key(labels::MANAGED);
+        if label {
+            instance.labels = app_manager.conventions.labels(&desired_state::definition(&app_manager.conventions, &instance));
+        }
+
+        // A tracked record under this name means the container was recreated behind the agent's back
+        if let Some(tracked) = find_tracked(app_manager, &name).filter(|tracked| tracked.name == name) {
+            let mut differences = vec![format!("tracked as {}", tracked.id)];
+            if tracked.image != instance.image {
+                differences.push(format!("image {} instead of {}", instance.image, tracked.image));
+            }
+            let (mut tracked_ports, mut ports): (Vec<_>, Vec<_>) = (
+                tracked.ports.iter().map(|port| (port.host_port, port.container_port)).collect(),
+                instance.ports.iter().map(|port| (port.host_port, port.container_port)).collect(),
+            );
+            tracked_ports.sort();
+            ports.sort();
+            if tracked_ports != ports {
+                differences.push("different port bindings".to_string());
+            }
+            items.push(adopt_item(&name, "conflict", Some(differences.join(", "))));
+            continue;
+        }
+
+        let message = format!("{} ({}){}", instance.image, id, if label { ", labels recorded by the agent only" } else { "" });
+        if adopt_req.dry_run {
+            items.push(adopt_item(&name, "would_adopt", Some(message)));
+            continue;
+        }
+        items.push(adopt_item(&name, "adopted", Some(message)));
+        adopted.push(instance);
+    }
+
+    if !adopted.is_empty() {
+        app_manager.upsert_instances(adopted.iter().cloned());
+        for instance in adopted {
+            tracing::info!("Adopted container {} ({})", instance.name, instance.id);
+            timeline::record(app_manager, &instance.id, &instance.name, "adopted", &auth.0, None);
+            app_manager.notifier.notify("instance.adopted", json!(instance));
+        }
+    }
+
+    Ok(Json(ImportReport {
+        items,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::ContentType;
+    use serde_json::{json, Value};
+    use crate::labels;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn container(id: &str, name: &str, labels: Value) -> (Value, Value) {
+        let summary = json!({ "Id": id, "Names": [format!("/{}", name)], "Image": "horizon:1", "Created": 1_772_366_400, "State": "running", "Status": "Up 2 days", "Labels": labels });
+        let inspected = json!({
+            "Id": id,
+            "Name": format!("/{}", name),
+            "Created": "2026-03-01T12:00:00Z",
+            "Config": { "Image": "horizon:1", "Env": ["MODE=lobby"], "Labels": labels },
+            "HostConfig": { "PortBindings": { "7777/udp": [{ "HostPort": "27777" }] }, "Binds": ["/srv/horizon:/data"] },
+        });
+        (summary, inspected)
+    }
+
+    // A legacy container started by hand, one the agent labeled before losing track of it, and an
+    // unrelated database
+    async fn agent() -> TestAgent {
+        let agent = TestAgent::with_config(|config| {
+            config.name = "eu-1".to_string();
+            config.instances.name_prefix = "hz-".to_string();
+        }).await;
+        let containers = [
+            container("legacy-id", "hz-legacy", json!({ "com.example.team": "games" })),
+            container("lobby-id", "hz-lobby", json!({ "maestro.managed": "true", "maestro.instance": "lobby", "maestro.agent": "eu-0" })),
+            container("db-id", "postgres", json!({})),
+        ];
+        let summaries: Vec<Value> = containers.iter().map(|(summary, _)| summary.clone()).collect();
+        agent.docker.route("GET", "/containers/json", move |_| DockerResponse::ok(json!(summaries)));
+        for (summary, inspected) in containers {
+            agent.docker.route("GET", &format!("/containers/{}/json", summary["Id"].as_str().unwrap()), move |_| DockerResponse::ok(inspected.clone()));
+        }
+        agent
+    }
+
+    #[tokio::test]
+    async fn adopted_containers_get_the_management_labels() {
+        let agent = agent().await;
+        let client = agent.client().await;
+        let adopt = |body: &'static str| client.post("/instances/adopt").header(ContentType::JSON).body(body).dispatch();
+
+        let report: Value = adopt(r#"{"name_pattern": "hz-*", "apply_labels": true, "dry_run": true}"#).await.into_json().await.unwrap();
+        assert_eq!(report["items"][0]["outcome"], "would_adopt");
+        assert_eq!(report["items"][0]["message"], "horizon:1 (legacy-id), labels recorded by the agent only");
+        assert_eq!(report["items"][1]["message"], "horizon:1 (lobby-id)");
+        assert!(agent.app_manager.instances().is_empty());
+
+        let report: Value = adopt(r#"{"name_pattern": "hz-*", "apply_labels": true}"#).await.into_json().await.unwrap();
+        let outcomes: Vec<(&str, &str)> = report["items"].as_array().unwrap().iter()
+            .map(|item| (item["name"].as_str().unwrap(), item["outcome"].as_str().unwrap()))
+            .collect();
+        assert_eq!(outcomes, [("hz-legacy", "adopted"), ("hz-lobby", "adopted")]);
+
+        let legacy = agent.app_manager.instance("legacy-id").unwrap();
+        assert_eq!(legacy.labels.get(labels::MANAGED).map(String::as_str), Some("true"));
+        assert_eq!(legacy.labels.get(labels::INSTANCE).map(String::as_str), Some("legacy"));
+        assert_eq!(legacy.labels.get(labels::AGENT).map(String::as_str), Some("eu-1"));
+        assert_eq!((legacy.ports[0].host_port, legacy.ports[0].container_port, legacy.ports[0].protocol.as_str()), (27777, 7777, "udp"));
+        assert_eq!(legacy.volumes[0].container_path, "/data");
+        // Labels already on the container are left as they are
+        assert_eq!(agent.app_manager.instance("lobby-id").unwrap().labels.get(labels::AGENT).map(String::as_str), Some("eu-0"));
+
+        // Adopting only reads from Docker
+        assert!(agent.docker.requests().iter().all(|request| request.method == "GET"), "{:?}", agent.docker.requests());
+
+        let found: Value = client.get("/instances/search?label=maestro.instance=legacy").dispatch().await.into_json().await.unwrap();
+        assert_eq!(found[0]["id"], "legacy-id");
+    }
+
+    #[tokio::test]
+    async fn containers_are_left_unlabeled_unless_asked() {
+        let agent = agent().await;
+        let client = agent.client().await;
+
+        let response = client.post("/instances/adopt").header(ContentType::JSON).body(r#"{"name_pattern": "hz-leg*"}"#).dispatch().await;
+        let report: Value = response.into_json().await.unwrap();
+        assert_eq!(report["items"].as_array().unwrap().len(), 1);
+        assert_eq!(report["items"][0]["outcome"], "adopted");
+        assert!(agent.app_manager.instance("legacy-id").unwrap().labels.is_empty());
+    }
+}
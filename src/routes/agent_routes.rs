@@ -1,28 +1,67 @@
 use rocket::get;
 use rocket::serde::json::Json;
 use rocket::State;
-use uuid;
 use hostname;
 use num_cpus;
 use sys_info;
+use crate::agent::Agent;
+use crate::build_info;
 use crate::routes::app_manager::AppManager;
-use crate::routes::models::{AgentInfo, SystemResources};
+use crate::routes::models::{AgentInfo, BuildInfo, SystemResources};
+use crate::tls::ServerCertificate;
 
 // Agent Management Routes
 
+// Collect host resources, falling back to zeros where the platform can't report them
+pub fn system_resources() -> SystemResources {
+    let memory_info = sys_info::mem_info().unwrap_or(sys_info::MemInfo {
+        total: 0,
+        free: 0,
+        avail: 0,
+        buffers: 0,
+        cached: 0,
+        swap_total: 0,
+        swap_free: 0,
+    });
+    
+    let disk_info = sys_info::disk_info().unwrap_or(sys_info::DiskInfo {
+        total: 0,
+        free: 0,
+    });
+    
+    SystemResources {
+        cpu_count: num_cpus::get(),
+        memory_total: memory_info.total * 1024,
+        memory_available: memory_info.avail * 1024,
+        disk_total: disk_info.total * 1024,
+        disk_available: disk_info.free * 1024,
+    }
+}
+
+/// Agent and host details. The status is degraded while Docker is unreachable or the
+/// TLS certificate is about to expire.
 #[get("/agent/info")]
-pub async fn get_agent_info(app_manager: &State<AppManager>) -> Json<AgentInfo> {
-    // Get Docker engine info
-    let info = match app_manager.docker.info().await {
+pub async fn get_agent_info(agent: &State<Agent>, app_manager: &State<AppManager>, certificate: &State<Option<ServerCertificate>>) -> Json<AgentInfo> {
+    let now = app_manager.now();
+    let tls = certificate.as_ref().map(|certificate| certificate.info(now));
+    // Get Docker engine info, skipping the daemon entirely while we know it's unreachable
+    let info = if app_manager.docker_handle.is_connected() {
+        app_manager.instrument("info", app_manager.docker().info()).await
+            .map_err(|e| e.to_string())
+    } else {
+        Err("Docker daemon is unavailable".to_string())
+    };
+    
+    let info = match info {
         Ok(info) => info,
         Err(e) => {
-            eprintln!("Failed to get Docker info: {}", e);
+            tracing::warn!("Failed to get Docker info: {}", e);
             return Json(AgentInfo {
-                id: uuid::Uuid::new_v4().to_string(),
+                id: agent.id().to_string(),
                 name: hostname::get().unwrap_or_default().to_string_lossy().to_string(),
                 version: "unknown".to_string(),
                 platform: "unknown".to_string(),
-                instance_count: app_manager.instances.lock().unwrap().len(),
+                instance_count: app_manager.instance_count(),
                 status: "degraded".to_string(),
                 resources: SystemResources {
                     cpu_count: num_cpus::get(),
@@ -31,46 +70,60 @@ pub async fn get_agent_info(app_manager: &State<AppManager>) -> Json<AgentInfo>
                     disk_total: 0,
                     disk_available: 0,
                 },
+                maintenance: app_manager.maintenance(),
+                build: build_info::current(),
+                tls,
             });
         }
     };
     
-    // Get system resources
-    let memory_info = sys_info::mem_info().unwrap_or(sys_info::MemInfo {
-        total: 0,
-        free: 0,
-        avail: 0,
-        buffers: 0,
-        cached: 0,
-        swap_total: 0,
-        swap_free: 0,
-    });
-    
-    let disk_info = sys_info::disk_info().unwrap_or(sys_info::DiskInfo {
-        total: 0,
-        free: 0,
-    });
-    
     Json(AgentInfo {
-        id: uuid::Uuid::new_v4().to_string(),
+        id: agent.id().to_string(),
         name: hostname::get().unwrap_or_default().to_string_lossy().to_string(),
         version: info.server_version.unwrap_or_default(),
         platform: format!("{} / {}", 
             info.operating_system.unwrap_or_default(),
             info.architecture.unwrap_or_default()),
-        instance_count: app_manager.instances.lock().unwrap().len(),
-        status: "healthy".to_string(),
-        resources: SystemResources {
-            cpu_count: num_cpus::get(),
-            memory_total: memory_info.total * 1024,
-            memory_available: memory_info.avail * 1024,
-            disk_total: disk_info.total * 1024,
-            disk_available: disk_info.free * 1024,
-        },
+        instance_count: app_manager.instance_count(),
+        status: if certificate.as_ref().is_some_and(|certificate| certificate.is_expiring(now)) { "degraded" } else { "healthy" }.to_string(),
+        resources: system_resources(),
+        maintenance: app_manager.maintenance(),
+        build: build_info::current(),
+        tls,
     })
 }
 
+#[get("/version")]
+pub fn get_version() -> Json<BuildInfo> {
+    Json(build_info::current())
+}
+
 #[get("/health")]
 pub fn health_check() -> String {
     "App Manager is healthy".to_string()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use crate::routes::models::BuildInfo;
+    use crate::testing::TestAgent;
+
+    #[tokio::test]
+    async fn version_reports_how_the_binary_was_built() {
+        let agent = TestAgent::start().await;
+        let client = agent.client().await;
+
+        let response = client.get("/version").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let build: BuildInfo = response.into_json().await.unwrap();
+
+        assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+        assert!(build.git_commit == "unknown"
+            || (build.git_commit.len() == 12 && build.git_commit.chars().all(|c| c.is_ascii_hexdigit())),
+            "unexpected commit {:?}", build.git_commit);
+        assert!(chrono::DateTime::parse_from_rfc3339(&build.build_date).is_ok(), "unexpected build date {:?}", build.build_date);
+        assert_eq!(build.features.contains(&"chaos".to_string()), cfg!(feature = "chaos"));
+        assert!(build.features.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
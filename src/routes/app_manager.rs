@@ -1,26 +1,460 @@
-use std::sync::{Arc, Mutex};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::collections::HashMap;
-use bollard::Docker;
-use crate::routes::models::AppInstance;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use bollard::{Docker, API_DEFAULT_VERSION};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde_json::json;
+use tracing::Instrument;
+use crate::clock::Clock;
+use crate::config::{AgentConfig, DockerConfig};
+use crate::crash_reports::CrashReportStore;
+use crate::crypto::Sealer;
+use crate::desired_state::DesiredStateStore;
+use crate::docker_cache::DockerCache;
+use crate::idempotency::IdempotencyStore;
+use crate::image_policy::ImagePolicyStore;
+use crate::labels::Conventions;
+use crate::log_shipper::LogShipper;
+use crate::metrics::AgentMetrics;
+use crate::proxy::ProxySettings;
+use crate::registries::RegistryStore;
+use crate::scheduler;
+use crate::secrets::SecretStore;
+use crate::stats_history::StatsRecorder;
+use crate::timeline::TimelineStore;
+use crate::tunnels::TunnelRegistry;
+use crate::routes::models::{AppInstance, MaintenanceState, ScheduleBook, Snapshot};
+use crate::store;
+use crate::webhooks::Notifier;
 
-// Docker client wrapper
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const DOCKER_REQUEST_TIMEOUT_SECS: u64 = 120;
+const INSTANCES_FILE: &str = "instances.json";
+const SCHEDULES_FILE: &str = "schedules.json";
+const MAINTENANCE_FILE: &str = "maintenance.json";
+const SNAPSHOTS_FILE: &str = "snapshots.json";
+const SCHEDULED_MAINTENANCE_MESSAGE: &str = "Agent is in a scheduled maintenance window";
+
+/// Reconnecting Docker client shared between request handlers and the health monitor
+pub struct DockerHandle {
+    config: DockerConfig,
+    client: RwLock<Docker>,
+    connected: AtomicBool,
+    retry_after_secs: AtomicU64,
+}
+
+impl DockerHandle {
+    // Connect to the configured endpoint and make sure the daemon actually answers
+    async fn connect(config: &DockerConfig) -> Result<Docker, String> {
+        let docker = match (&config.endpoint, &config.tls) {
+            (None, _) => Docker::connect_with_local_defaults(),
+            (Some(endpoint), _) if endpoint.starts_with("unix://") => {
+                Docker::connect_with_unix(endpoint, DOCKER_REQUEST_TIMEOUT_SECS, API_DEFAULT_VERSION)
+            },
+            (Some(endpoint), Some(tls)) => {
+                Docker::connect_with_ssl(endpoint, &tls.key, &tls.cert, &tls.ca, DOCKER_REQUEST_TIMEOUT_SECS, API_DEFAULT_VERSION)
+            },
+            (Some(endpoint), None) => {
+                Docker::connect_with_http(endpoint, DOCKER_REQUEST_TIMEOUT_SECS, API_DEFAULT_VERSION)
+            },
+        };
+        let docker = docker.map_err(|e| format!("Failed to connect to Docker: {}", e))?;
+        docker.ping().await
+            .map_err(|e| format!("Docker daemon is not responding: {}", e))?;
+        Ok(docker)
+    }
+
+    pub fn client(&self) -> Docker {
+        self.client.read().unwrap().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    /// Seconds a client should wait before retrying while the daemon is unreachable
+    pub fn retry_after(&self) -> u64 {
+        self.retry_after_secs.load(Ordering::Relaxed)
+    }
+
+    /// Pings the daemon periodically and re-establishes the client with backoff when it drops
+    pub async fn monitor(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+
+            if self.client().ping().await.is_ok() {
+                continue;
+            }
+
+            tracing::warn!("Lost connection to the Docker daemon, reconnecting");
+            self.connected.store(false, Ordering::Release);
+
+            let mut delay = MIN_RECONNECT_DELAY;
+            loop {
+                self.retry_after_secs.store(delay.as_secs(), Ordering::Relaxed);
+                tokio::time::sleep(delay).await;
+
+                match Self::connect(&self.config).await {
+                    Ok(docker) => {
+                        *self.client.write().unwrap() = docker;
+                        self.connected.store(true, Ordering::Release);
+                        tracing::info!("Reconnected to the Docker daemon");
+                        break;
+                    },
+                    Err(e) => {
+                        tracing::warn!("Docker reconnection failed: {}", e);
+                        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Orders the writes of instances.json, which happen off the instances lock: every change takes
+// the next version under the lock, and a snapshot older than the one on disk is never written
+#[derive(Default)]
+struct InstanceWrites {
+    version: AtomicU64,
+    written: Mutex<u64>,
+}
+
+// Docker client wrapper; clones share the same client, state and collectors
+#[derive(Clone)]
 pub struct AppManager {
-    pub docker: Docker,
-    pub instances: Arc<Mutex<HashMap<String, AppInstance>>>,
+    pub docker_handle: Arc<DockerHandle>,
+    pub cache: Arc<DockerCache>,
+    // Only reached through the instance accessors below, which never hold the lock across an await
+    instances: Arc<Mutex<HashMap<String, AppInstance>>>,
+    instance_writes: Arc<InstanceWrites>,
+    pub schedules: Arc<Mutex<ScheduleBook>>,
+    pub snapshots: Arc<Mutex<Vec<Snapshot>>>,
+    pub registries: Arc<RegistryStore>,
+    pub secrets: Arc<SecretStore>,
+    pub logs: Arc<LogShipper>,
+    pub stats: Arc<StatsRecorder>,
+    pub crashes: Arc<CrashReportStore>,
+    pub timeline: Arc<TimelineStore>,
+    pub desired_state: Arc<DesiredStateStore>,
+    pub idempotency: Arc<IdempotencyStore>,
+    pub image_policy: Arc<ImagePolicyStore>,
+    pub conventions: Arc<Conventions>,
+    maintenance: Arc<Mutex<Option<MaintenanceState>>>,
+    pub tunnels: Arc<TunnelRegistry>,
+    #[cfg(feature = "chaos")]
+    pub chaos: Arc<crate::chaos::Chaos>,
+    pub metrics: AgentMetrics,
+    pub notifier: Notifier,
+    pub clock: Arc<dyn Clock>,
+    pub data_dir: PathBuf,
 }
 
 impl AppManager {
-    pub fn new() -> Result<Self, String> {
-        // Connect to Docker with default configuration
-        // Works across platforms without additional config
-        let docker = match Docker::connect_with_local_defaults() {
-            Ok(docker) => docker,
-            Err(e) => return Err(format!("Failed to connect to Docker: {}", e)),
+    /// Connects to the configured Docker daemon, retrying for up to `docker.startup_timeout_secs`
+    /// so the agent can boot before dockerd does, and restores persisted instances from the data dir
+    pub async fn new(config: &AgentConfig, clock: Arc<dyn Clock>) -> Result<Self, String> {
+        let deadline = Instant::now() + Duration::from_secs(config.docker.startup_timeout_secs);
+        let mut delay = MIN_RECONNECT_DELAY;
+
+        // Without an explicit endpoint this uses the platform's local defaults
+        let docker = loop {
+            match DockerHandle::connect(&config.docker).await {
+                Ok(docker) => break docker,
+                Err(e) if Instant::now() + delay < deadline => {
+                    tracing::warn!("{}, retrying in {}s", e, delay.as_secs());
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                },
+                Err(e) => return Err(e),
+            }
+        };
+
+        let metrics = match AgentMetrics::new() {
+            Ok(metrics) => metrics,
+            Err(e) => return Err(format!("Failed to register metrics: {}", e)),
         };
-        
+
+        let instances: HashMap<String, AppInstance> = store::load_json(&config.data_dir.join(INSTANCES_FILE))
+            .map_err(|e| format!("Failed to load persisted instances: {}", e))?;
+        let schedules: ScheduleBook = store::load_json(&config.data_dir.join(SCHEDULES_FILE))
+            .map_err(|e| format!("Failed to load persisted schedules: {}", e))?;
+        let snapshots: Vec<Snapshot> = store::load_json(&config.data_dir.join(SNAPSHOTS_FILE))
+            .map_err(|e| format!("Failed to load snapshots: {}", e))?;
+        let maintenance: Option<MaintenanceState> = store::load_json(&config.data_dir.join(MAINTENANCE_FILE))
+            .map_err(|e| format!("Failed to load maintenance state: {}", e))?;
+        let sealer = Sealer::from_key(config.encryption_key.as_deref());
+        let registries = RegistryStore::load(sealer.clone(), &config.data_dir, clock.clone())
+            .map_err(|e| format!("Failed to load registry credentials: {}", e))?;
+        let secrets = SecretStore::load(sealer, &config.data_dir)
+            .map_err(|e| format!("Failed to load instance secrets: {}", e))?;
+        let crashes = CrashReportStore::load(&config.data_dir)
+            .map_err(|e| format!("Failed to load crash reports: {}", e))?;
+        let timeline = TimelineStore::load(&config.data_dir)
+            .map_err(|e| format!("Failed to load the instance timeline: {}", e))?;
+        let desired_state = DesiredStateStore::load(&config.data_dir)
+            .map_err(|e| format!("Failed to load the desired state: {}", e))?;
+        let idempotency = IdempotencyStore::load(&config.data_dir, clock.now())
+            .map_err(|e| format!("Failed to load idempotency keys: {}", e))?;
+        let image_policy = ImagePolicyStore::load(&config.data_dir)
+            .map_err(|e| format!("Failed to load the image policy: {}", e))?;
+
+        // Webhooks and log shipping share one client, and with it the proxy settings
+        let client = ProxySettings::from_config(&config.proxy).client();
+        let logs = LogShipper::new(config.log_shipping.clone(), &config.data_dir, client.clone(), metrics.shipped_log_lines.clone());
+        let stats = StatsRecorder::load(&config.stats_history, &config.data_dir);
+        let notifier = Notifier::new(config.webhooks.clone(), client, metrics.webhook_deliveries.clone(), clock.clone());
+
+        let docker_handle = Arc::new(DockerHandle {
+            config: config.docker.clone(),
+            client: RwLock::new(docker),
+            connected: AtomicBool::new(true),
+            retry_after_secs: AtomicU64::new(MIN_RECONNECT_DELAY.as_secs()),
+        });
+        tokio::spawn(docker_handle.clone().monitor());
+
         Ok(AppManager {
-            docker,
-            instances: Arc::new(Mutex::new(HashMap::new())),
+            docker_handle,
+            cache: Arc::new(DockerCache::default()),
+            instances: Arc::new(Mutex::new(instances)),
+            instance_writes: Arc::default(),
+            schedules: Arc::new(Mutex::new(schedules)),
+            snapshots: Arc::new(Mutex::new(snapshots)),
+            registries: Arc::new(registries),
+            secrets: Arc::new(secrets),
+            logs: Arc::new(logs),
+            stats: Arc::new(stats),
+            crashes: Arc::new(crashes),
+            timeline: Arc::new(timeline),
+            desired_state: Arc::new(desired_state),
+            idempotency: Arc::new(idempotency),
+            image_policy: Arc::new(image_policy),
+            conventions: Arc::new(Conventions::new(config)),
+            maintenance: Arc::new(Mutex::new(maintenance)),
+            tunnels: Arc::new(TunnelRegistry::new(config.server.address.clone(), clock.clone())),
+            #[cfg(feature = "chaos")]
+            chaos: Arc::new(crate::chaos::Chaos::new(clock.clone(), Box::new(crate::chaos::RandomDice))),
+            metrics,
+            notifier,
+            clock,
+            data_dir: config.data_dir.clone(),
+        })
+    }
+
+    // Write the tracked instances to the data dir; failures are logged since the containers themselves are fine.
+    // Callers hold the lock, so only the snapshot is taken under it and the file is written on the blocking pool.
+    fn persist_instances(&self, instances: &HashMap<String, AppInstance>) {
+        let version = self.instance_writes.version.fetch_add(1, Ordering::SeqCst) + 1;
+        let snapshot = instances.clone();
+        let writes = self.instance_writes.clone();
+        let path = self.data_dir.join(INSTANCES_FILE);
+        let write = move || {
+            // Held while writing, so writes can't interleave on the temp file
+            let mut written = writes.written.lock().unwrap();
+            if *written >= version {
+                return;
+            }
+            match store::save_json(&path, &snapshot) {
+                Ok(()) => *written = version,
+                Err(e) => tracing::warn!("Failed to persist instances: {}", e),
+            }
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+    }
+
+    /// The tracked instance with id `id`
+    pub fn instance(&self, id: &str) -> Option<AppInstance> {
+        self.instances.lock().unwrap().get(id).cloned()
+    }
+
+    /// The first tracked instance matching `predicate`
+    pub fn find_instance(&self, predicate: impl Fn(&AppInstance) -> bool) -> Option<AppInstance> {
+        self.instances.lock().unwrap().values().find(|instance| predicate(instance)).cloned()
+    }
+
+    /// A copy of every tracked instance, in no particular order
+    pub fn instances(&self) -> Vec<AppInstance> {
+        self.instances.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instances.lock().unwrap().len()
+    }
+
+    /// Tracks `instances`, replacing whatever is tracked under their ids, and persists them
+    pub fn upsert_instances(&self, upserted: impl IntoIterator<Item = AppInstance>) {
+        let mut instances = self.instances.lock().unwrap();
+        for instance in upserted {
+            instances.insert(instance.id.clone(), instance);
+        }
+        self.persist_instances(&instances);
+    }
+
+    pub fn upsert_instance(&self, instance: AppInstance) {
+        self.upsert_instances([instance]);
+    }
+
+    /// Stops tracking the instance with id `id`, returning it
+    pub fn remove_instance(&self, id: &str) -> Option<AppInstance> {
+        let mut instances = self.instances.lock().unwrap();
+        let removed = instances.remove(id)?;
+        self.persist_instances(&instances);
+        Some(removed)
+    }
+
+    /// Changes the tracked instance with id `id` in place. The change is persisted unless `update`
+    /// returns None, which leaves nothing to save, e.g. when it found the instance in the wrong state.
+    pub fn update_instance<R>(&self, id: &str, update: impl FnOnce(&mut AppInstance) -> Option<R>) -> Option<R> {
+        let mut instances = self.instances.lock().unwrap();
+        let result = update(instances.get_mut(id)?)?;
+        self.persist_instances(&instances);
+        Some(result)
+    }
+
+    pub fn persist_schedules(&self) {
+        let schedules = self.schedules.lock().unwrap();
+        if let Err(e) = store::save_json(&self.data_dir.join(SCHEDULES_FILE), &*schedules) {
+            tracing::warn!("Failed to persist schedules: {}", e);
+        }
+    }
+
+    pub fn persist_snapshots(&self) {
+        let snapshots = self.snapshots.lock().unwrap();
+        if let Err(e) = store::save_json(&self.data_dir.join(SNAPSHOTS_FILE), &*snapshots) {
+            tracing::warn!("Failed to persist snapshots: {}", e);
+        }
+    }
+
+    /// The current time according to the agent's clock
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    /// Current maintenance: a manual one takes precedence over scheduled windows.
+    /// A manual maintenance whose ends_at has passed is ended here.
+    pub fn maintenance(&self) -> Option<MaintenanceState> {
+        let manual = self.maintenance.lock().unwrap().clone();
+        if let Some(state) = manual {
+            let ended = state.ends_at.as_deref()
+                .and_then(|ends_at| chrono::DateTime::parse_from_rfc3339(ends_at).ok())
+                .is_some_and(|ends_at| ends_at <= self.now());
+            if !ended {
+                return Some(state);
+            }
+
+            tracing::info!("Scheduled end of maintenance reached");
+            self.set_maintenance(None);
+        }
+
+        let period = scheduler::active_period(&self.schedules.lock().unwrap().windows, self.now())?;
+        Some(MaintenanceState {
+            message: period.message.unwrap_or_else(|| SCHEDULED_MAINTENANCE_MESSAGE.to_string()),
+            started_at: period.starts_at,
+            ends_at: Some(period.ends_at),
+            window_ids: period.window_ids,
         })
     }
-}
\ No newline at end of file
+
+    /// Enters manual maintenance with `state`, or leaves it with None, persisting the change and notifying webhooks
+    pub fn set_maintenance(&self, state: Option<MaintenanceState>) {
+        {
+            let mut maintenance = self.maintenance.lock().unwrap();
+            *maintenance = state.clone();
+            if let Err(e) = store::save_json(&self.data_dir.join(MAINTENANCE_FILE), &*maintenance) {
+                tracing::warn!("Failed to persist maintenance state: {}", e);
+            }
+        }
+
+        match state {
+            Some(state) => self.notifier.notify("agent.maintenance_started", json!(state)),
+            None => self.notifier.notify("agent.maintenance_ended", json!({})),
+        }
+    }
+
+    pub fn docker(&self) -> Docker {
+        self.docker_handle.client()
+    }
+
+    // Run a Docker API call through the metrics layer, in a span under the current request's
+    pub async fn instrument<T, E: From<bollard::errors::Error>>(&self, operation: &str, call: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+        #[cfg(feature = "chaos")]
+        let call = async {
+            self.chaos.before_docker_call(operation).await?;
+            call.await
+        };
+        self.metrics.observe(operation, call)
+            .instrument(tracing::info_span!("docker", operation = operation))
+            .await
+    }
+}
+
+/// Request guard for mutating routes: fails with 503 while the Docker daemon is unreachable
+pub struct DockerAvailable;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DockerAvailable {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<AppManager>() {
+            Some(app_manager) if app_manager.docker_handle.is_connected() => Outcome::Success(DockerAvailable),
+            _ => Outcome::Error((Status::ServiceUnavailable, "Docker daemon is unavailable".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{instance, TestAgent};
+
+    // Waits for the writes queued so far to reach the disk
+    async fn flushed(app_manager: &AppManager) {
+        let version = app_manager.instance_writes.version.load(Ordering::SeqCst);
+        while *app_manager.instance_writes.written.lock().unwrap() < version {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_changes_are_neither_lost_nor_persisted_out_of_order() {
+        let agent = TestAgent::start().await;
+
+        // Each task creates its instances, finds them in the listing and then reconciles their status
+        let tasks: Vec<_> = (0..8).map(|task| {
+            let app_manager = agent.app_manager.clone();
+            tokio::spawn(async move {
+                for i in 0..25 {
+                    let id = format!("{:02}{:02}", task, i);
+                    app_manager.upsert_instance(instance(&id, &format!("game-{}", id)));
+                    assert!(app_manager.instances().iter().any(|instance| instance.id == id));
+                    app_manager.update_instance(&id, |instance| {
+                        instance.status = "exited".to_string();
+                        Some(())
+                    });
+                    tokio::task::yield_now().await;
+                }
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(agent.app_manager.instance_count(), 200);
+        assert!(agent.app_manager.instances().iter().all(|instance| instance.status == "exited"));
+
+        flushed(&agent.app_manager).await;
+        let persisted: HashMap<String, AppInstance> = store::load_json(&agent.data_dir.path().join(INSTANCES_FILE)).unwrap();
+        assert_eq!(persisted.len(), 200);
+        assert!(persisted.values().all(|instance| instance.status == "exited"));
+    }
+}
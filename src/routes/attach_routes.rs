@@ -0,0 +1,188 @@
+use bollard::exec::{CreateExecOptions, StartExecOptions, StartExecResults};
+use rocket::get;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use crate::attach::AttachBridge;
+use crate::config::AgentConfig;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::AttachToken;
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::models::AttachSession;
+
+const DEFAULT_ATTACH_COMMAND: &str = "/bin/sh";
+
+/// The Sec-WebSocket-Key of a request asking to be upgraded to a WebSocket
+pub struct WebSocketUpgrade(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebSocketUpgrade {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let upgrade = headers.get_one("Upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+        match headers.get_one("Sec-WebSocket-Key") {
+            Some(key) if upgrade && headers.get_one("Sec-WebSocket-Version") == Some("13") => Outcome::Success(WebSocketUpgrade(key.to_string())),
+            _ => Outcome::Error((Status::BadRequest, "This route expects a WebSocket (version 13) upgrade".to_string())),
+        }
+    }
+}
+
+/// Completes the WebSocket handshake and hands the connection to the bridge. Rocket sets the
+/// 101 status and the Connection and Upgrade headers itself.
+pub struct Attach {
+    accept: String,
+    bridge: AttachBridge,
+}
+
+impl<'r> Responder<'r, 'static> for Attach {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .raw_header("Sec-WebSocket-Accept", self.accept)
+            .upgrade("websocket", self.bridge)
+            .ok()
+    }
+}
+
+// Interactive Sessions
+
+/// Opens a shell (or `cmd`, given once per argument) with a TTY in a running instance and bridges
+/// it to this WebSocket: binary frames are keystrokes and output, text frames are JSON control
+/// messages like {"type": "resize", "cols": 120, "rows": 40} or {"type": "input", "data": "ls\n"}
+#[get("/instances/<id>/attach?<cmd>")]
+pub async fn attach_instance(id: String, cmd: Vec<String>, app_manager: &State<AppManager>, config: &State<AgentConfig>, auth: AttachToken, upgrade: WebSocketUpgrade, _docker: DockerAvailable) -> Result<Attach, AgentError> {
+    let container = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(container) => container,
+        Err(e) => return Err(AgentError::docker("Failed to inspect instance", e)),
+    };
+    if !container.state.as_ref().and_then(|state| state.running).unwrap_or(false) {
+        return Err(AgentError::Conflict(format!("Instance {} is not running", id)));
+    }
+    let instance_id = container.id.unwrap_or(id);
+    let command = if cmd.is_empty() { vec![DEFAULT_ATTACH_COMMAND.to_string()] } else { cmd };
+
+    let options = CreateExecOptions {
+        attach_stdin: Some(true),
+        attach_stdout: Some(true),
+        attach_stderr: Some(true),
+        tty: Some(true),
+        env: Some(vec!["TERM=xterm-256color".to_string()]),
+        cmd: Some(command.clone()),
+        ..Default::default()
+    };
+    let exec = match app_manager.instrument("create_exec", app_manager.docker().create_exec(&instance_id, options)).await {
+        Ok(exec) => exec,
+        Err(e) => return Err(AgentError::docker("Failed to create exec", e)),
+    };
+    let options = StartExecOptions { detach: false, tty: true, output_capacity: None };
+    let (output, input) = match app_manager.instrument("start_exec", app_manager.docker().start_exec(&exec.id, Some(options))).await {
+        Ok(StartExecResults::Attached { output, input }) => (output, input),
+        Ok(StartExecResults::Detached) => return Err(AgentError::Internal("Exec started detached".to_string())),
+        Err(e) => return Err(AgentError::docker("Failed to start exec", e)),
+    };
+
+    let session = AttachSession {
+        id: uuid::Uuid::new_v4().to_string(),
+        instance_id,
+        instance_name: container.name.unwrap_or_default().trim_start_matches('/').to_string(),
+        command,
+        token_id: Some(auth.0),
+        started_at: app_manager.now().to_rfc3339(),
+        ended_at: String::new(),
+        reason: String::new(),
+        bytes_in: 0,
+        bytes_out: 0,
+    };
+    tracing::info!(
+        session = %session.id,
+        instance = %session.instance_name,
+        token = session.token_id.as_deref().unwrap_or("-"),
+        "Attach session opened: {}", session.command.join(" ")
+    );
+
+    Ok(Attach {
+        accept: derive_accept_key(upgrade.0.as_bytes()),
+        bridge: AttachBridge::new(app_manager.inner().clone(), exec.id, output, input, session, &config.attach),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{SinkExt, StreamExt};
+    use rocket::http::{Header, Status};
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::config::ApiTokenConfig;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn token(id: &str, permissions: &[&str]) -> ApiTokenConfig {
+        ApiTokenConfig {
+            id: id.to_string(),
+            token: format!("{}-secret", id),
+            permissions: permissions.iter().map(|permission| permission.to_string()).collect(),
+        }
+    }
+
+    fn upgrade_headers() -> [Header<'static>; 4] {
+        [
+            Header::new("Connection", "Upgrade"),
+            Header::new("Upgrade", "websocket"),
+            Header::new("Sec-WebSocket-Version", "13"),
+            Header::new("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ]
+    }
+
+    async fn attach_status(agent: &TestAgent, bearer: Option<&str>) -> Status {
+        let client = agent.client().await;
+        let mut request = client.get("/instances/game/attach");
+        for header in upgrade_headers() {
+            request = request.header(header);
+        }
+        if let Some(bearer) = bearer {
+            request = request.header(Header::new("Authorization", format!("Bearer {}", bearer)));
+        }
+        let status = request.dispatch().await.status();
+        status
+    }
+
+    #[tokio::test]
+    async fn attaching_is_refused_while_the_api_is_open() {
+        let agent = TestAgent::start().await;
+        assert_eq!(attach_status(&agent, None).await, Status::Forbidden);
+        assert!(agent.docker.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn attaching_requires_the_attach_permission() {
+        let agent = TestAgent::with_config(|config| config.auth.tokens = vec![token("deployer", &["admin"])]).await;
+        assert_eq!(attach_status(&agent, Some("deployer-secret")).await, Status::Forbidden);
+        assert_eq!(attach_status(&agent, None).await, Status::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn input_round_trips_through_the_exec() {
+        let agent = TestAgent::with_config(|config| config.auth.tokens = vec![token("operator", &["attach"])]).await;
+        agent.docker.route("GET", "/containers/game/json", |_| DockerResponse::ok(json!({ "Id": "c0ffee", "Name": "/game", "State": { "Running": true } })));
+        agent.docker.route("POST", "/containers/c0ffee/exec", |_| DockerResponse::Json(201, json!({ "Id": "exec-1" })));
+        agent.docker.route("POST", "/exec/exec-1/start", |_| DockerResponse::Echo);
+        let address = agent.serve().await;
+
+        let mut request = format!("ws://{}/instances/game/attach", address).into_client_request().unwrap();
+        request.headers_mut().insert("Authorization", "Bearer operator-secret".parse().unwrap());
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (mut socket, _) = tokio_tungstenite::client_async(request, stream).await.unwrap();
+
+        socket.send(Message::Binary(b"echo hello\n".to_vec())).await.unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), Message::Binary(b"echo hello\n".to_vec()));
+        socket.send(Message::Text(json!({ "type": "input", "data": "exit\n" }).to_string())).await.unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), Message::Binary(b"exit\n".to_vec()));
+        socket.close(None).await.unwrap();
+
+        let exec = agent.docker.requests_to("POST", "/containers/c0ffee/exec");
+        assert_eq!(exec[0].json()["Cmd"], json!(["/bin/sh"]));
+    }
+}
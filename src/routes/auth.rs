@@ -0,0 +1,213 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+use crate::config::{AgentConfig, ApiTokenConfig};
+
+/// Permission allowing a token to read and change the image policy
+pub const ADMIN_PERMISSION: &str = "admin";
+
+/// Permission allowing a token to skip the image policy with the bypass header
+pub const IMAGE_POLICY_BYPASS_PERMISSION: &str = "image_policy_bypass";
+
+/// Permission allowing a token to open interactive shells in instances
+pub const ATTACH_PERMISSION: &str = "attach";
+
+/// Permission allowing a token to open TCP tunnels to instance ports
+pub const TUNNEL_PERMISSION: &str = "tunnel";
+
+/// Request header asking for the image policy to be skipped on this request
+pub const IMAGE_POLICY_BYPASS_HEADER: &str = "X-Maestro-Image-Policy-Bypass";
+
+// The configured token the request's bearer token matches, or None when no tokens are
+// configured and the API is open
+fn authenticate<'r>(request: &'r Request<'_>) -> Result<Option<&'r ApiTokenConfig>, (Status, String)> {
+    let Some(config) = request.rocket().state::<AgentConfig>() else {
+        return Err((Status::InternalServerError, "Agent configuration is not loaded".to_string()));
+    };
+
+    if config.auth.tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let provided = request.headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "));
+
+    // Digests are compared rather than the tokens, so how long a comparison takes says nothing
+    // about how much of a configured token the caller guessed right
+    let provided = provided.map(|token| Sha256::digest(token.as_bytes()));
+    match provided.and_then(|provided| config.auth.tokens.iter().find(|t| Sha256::digest(t.token.as_bytes()) == provided)) {
+        Some(token) => {
+            tracing::debug!(token = %token.id, "authenticated request");
+            Ok(Some(token))
+        },
+        None => Err((Status::Unauthorized, "Missing or invalid API token".to_string())),
+    }
+}
+
+// Permissions are only ever granted by a configured token, so an open API grants none
+fn permitted(token: Option<&ApiTokenConfig>, permission: &str) -> bool {
+    token.is_some_and(|token| token.permissions.iter().any(|granted| granted == permission))
+}
+
+/// Request guard requiring an `Authorization: Bearer <token>` header matching one of
+/// the configured `auth.tokens`. When no tokens are configured every request is allowed.
+/// Holds who is acting, "token:<id>" or "api" for an open API, for the instance timeline;
+/// the agent's own callers name themselves, e.g. "scheduler".
+pub struct ApiToken(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiToken {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request) {
+            Ok(token) => Outcome::Success(ApiToken(token.map_or("api".to_string(), |token| format!("token:{}", token.id)))),
+            Err(error) => Outcome::Error(error),
+        }
+    }
+}
+
+/// Like `ApiToken`, but the token must also have the "admin" permission. Refused while the API is open.
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request) {
+            Ok(token) if permitted(token, ADMIN_PERMISSION) => Outcome::Success(AdminToken),
+            Ok(_) => Outcome::Error((Status::Forbidden, format!("This route requires a token with the {} permission", ADMIN_PERMISSION))),
+            Err(error) => Outcome::Error(error),
+        }
+    }
+}
+
+/// Like `ApiToken`, but the token must also have the "attach" permission. Unlike other routes,
+/// attaching is refused while the API is open. Holds the token's id so sessions can be attributed to it.
+pub struct AttachToken(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AttachToken {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request) {
+            Ok(Some(token)) if permitted(Some(token), ATTACH_PERMISSION) => Outcome::Success(AttachToken(token.id.clone())),
+            Ok(None) => Outcome::Error((Status::Forbidden, "Attaching requires auth.tokens with the attach permission to be configured".to_string())),
+            Ok(_) => Outcome::Error((Status::Forbidden, format!("This route requires a token with the {} permission", ATTACH_PERMISSION))),
+            Err(error) => Outcome::Error(error),
+        }
+    }
+}
+
+/// Like `AttachToken`, but for the "tunnel" permission: a tunnel exposes a container port on the
+/// agent's bind address, so it's refused while the API is open.
+pub struct TunnelToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TunnelToken {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(request) {
+            Ok(Some(token)) if permitted(Some(token), TUNNEL_PERMISSION) => Outcome::Success(TunnelToken),
+            Ok(None) => Outcome::Error((Status::Forbidden, "Tunnels require auth.tokens with the tunnel permission to be configured".to_string())),
+            Ok(_) => Outcome::Error((Status::Forbidden, format!("This route requires a token with the {} permission", TUNNEL_PERMISSION))),
+            Err(error) => Outcome::Error(error),
+        }
+    }
+}
+
+/// Whether the request asked to skip the image policy with `X-Maestro-Image-Policy-Bypass: true`.
+/// Asking without a token with the "image_policy_bypass" permission, including while the API is
+/// open, fails the request rather than being ignored.
+pub struct ImagePolicyBypass(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ImagePolicyBypass {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let requested = match request.headers().get_one(IMAGE_POLICY_BYPASS_HEADER) {
+            None => return Outcome::Success(ImagePolicyBypass(false)),
+            Some(value) if value.eq_ignore_ascii_case("false") => return Outcome::Success(ImagePolicyBypass(false)),
+            Some(value) if value.eq_ignore_ascii_case("true") => true,
+            Some(_) => return Outcome::Error((Status::BadRequest, format!("{} must be true or false", IMAGE_POLICY_BYPASS_HEADER))),
+        };
+
+        match authenticate(request) {
+            Ok(token) if permitted(token, IMAGE_POLICY_BYPASS_PERMISSION) => Outcome::Success(ImagePolicyBypass(requested)),
+            Ok(_) => Outcome::Error((Status::Forbidden, format!("{} requires a token with the {} permission", IMAGE_POLICY_BYPASS_HEADER, IMAGE_POLICY_BYPASS_PERMISSION))),
+            Err(error) => Outcome::Error(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{ContentType, Header};
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn token(id: &str, permissions: &[&str]) -> ApiTokenConfig {
+        ApiTokenConfig {
+            id: id.to_string(),
+            token: format!("{}-secret", id),
+            permissions: permissions.iter().map(|permission| permission.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn open_api_grants_no_permissions() {
+        let agent = TestAgent::start().await;
+        let client = agent.client().await;
+
+        assert_eq!(client.get("/policies/images").dispatch().await.status(), Status::Forbidden);
+        let bypassed = client.post("/images/pull")
+            .header(ContentType::JSON)
+            .header(Header::new(IMAGE_POLICY_BYPASS_HEADER, "true"))
+            .body(r#"{"image": "nginx:latest"}"#)
+            .dispatch().await;
+        assert_eq!(bypassed.status(), Status::Forbidden);
+        assert!(agent.docker.requests_to("POST", "/images/create").is_empty());
+    }
+
+    #[tokio::test]
+    async fn permissions_come_from_the_token() {
+        let agent = TestAgent::with_config(|config| config.auth.tokens = vec![token("admin", &[ADMIN_PERMISSION]), token("deployer", &[])]).await;
+        let client = agent.client().await;
+
+        let as_admin = client.get("/policies/images").header(Header::new("Authorization", "Bearer admin-secret")).dispatch().await;
+        assert_eq!(as_admin.status(), Status::Ok);
+        let as_deployer = client.get("/policies/images").header(Header::new("Authorization", "Bearer deployer-secret")).dispatch().await;
+        assert_eq!(as_deployer.status(), Status::Forbidden);
+        let bypassed = client.post("/images/pull")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer admin-secret"))
+            .header(Header::new(IMAGE_POLICY_BYPASS_HEADER, "true"))
+            .body(r#"{"image": "nginx:latest"}"#)
+            .dispatch().await;
+        assert_eq!(bypassed.status(), Status::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn only_the_exact_token_is_accepted() {
+        let agent = TestAgent::with_config(|config| config.auth.tokens = vec![token("deployer", &[])]).await;
+        agent.docker.route("POST", "/containers/ab12/pause", |_| DockerResponse::Json(204, serde_json::Value::Null));
+        let client = agent.client().await;
+
+        for (authorization, status) in [
+            ("Bearer deployer-secret", Status::Ok),
+            ("Bearer deployer-secreT", Status::Unauthorized),
+            ("Bearer deployer-secre", Status::Unauthorized),
+            ("Bearer deployer-secret ", Status::Unauthorized),
+            ("Bearer ", Status::Unauthorized),
+            ("deployer-secret", Status::Unauthorized),
+        ] {
+            let response = client.put("/instances/ab12/pause").header(Header::new("Authorization", authorization)).dispatch().await;
+            assert_eq!(response.status(), status, "{:?}", authorization);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use rocket::catch;
+use rocket::http::{Header, MediaType, Status};
+use rocket::serde::json::Json;
+use rocket::{Request, Responder, Route};
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::ErrorResponse;
+
+#[derive(Responder)]
+#[response(status = 503)]
+pub struct ServiceUnavailable {
+    inner: Json<ErrorResponse>,
+    retry_after: Header<'static>,
+}
+
+#[catch(503)]
+pub fn service_unavailable(request: &Request) -> ServiceUnavailable {
+    let retry_after = request.rocket().state::<AppManager>()
+        .map(|app_manager| app_manager.docker_handle.retry_after())
+        .unwrap_or(1);
+    
+    ServiceUnavailable {
+        inner: Json(ErrorResponse::new(Status::ServiceUnavailable, "Docker daemon is unavailable, retry later".to_string())),
+        retry_after: Header::new("Retry-After", retry_after.to_string()),
+    }
+}
+
+// Whether `route` would have taken the request if not for its format
+fn matches_path(route: &Route, request: &Request) -> bool {
+    let mut segments = request.uri().path().segments();
+    for expected in route.uri.path().split('/').filter(|segment| !segment.is_empty()) {
+        if expected.starts_with('<') && expected.ends_with("..>") {
+            return true;
+        }
+        match segments.next() {
+            Some(segment) if segment == expected || (expected.starts_with('<') && expected.ends_with('>')) => {},
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+// The media type a route at this method and path requires, when the body's Content-Type is
+// the only reason none of them matched
+fn expected_format(request: &Request) -> Option<MediaType> {
+    if !request.method().supports_payload() {
+        return None;
+    }
+    request.rocket().routes()
+        .filter(|route| route.method == request.method() && matches_path(route, request))
+        .filter_map(|route| route.format.clone())
+        .find(|format| !request.format().is_some_and(|given| given.top() == format.top() && given.sub() == format.sub()))
+}
+
+// Render everything else rocket rejects (unknown routes, malformed JSON) in the same envelope
+#[catch(default)]
+pub fn default_catcher(status: Status, request: &Request) -> (Status, Json<ErrorResponse>) {
+    // Rocket reports a body of the wrong type as an unknown route
+    if status == Status::NotFound {
+        if let Some(format) = expected_format(request) {
+            let given = request.content_type().map_or("none".to_string(), |content_type| content_type.to_string());
+            let message = format!("{} {}: expected Content-Type {}, got {}", request.method(), request.uri().path(), format, given);
+            return (Status::UnsupportedMediaType, Json(ErrorResponse::new(Status::UnsupportedMediaType, message)));
+        }
+    }
+
+    let message = format!("{} {}: {}", request.method(), request.uri().path(), status.reason_lossy());
+    (status, Json(ErrorResponse::new(status, message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use serde_json::Value;
+    use crate::testing::TestAgent;
+
+    #[tokio::test]
+    async fn bodies_of_the_wrong_type_are_unsupported() {
+        let agent = TestAgent::start().await;
+        let client = agent.client().await;
+
+        for content_type in [Some(ContentType::Plain), Some(ContentType::Form), None] {
+            let mut request = client.post("/volumes").body(r#"{"name": "data"}"#);
+            if let Some(content_type) = content_type {
+                request = request.header(content_type);
+            }
+            let response = request.dispatch().await;
+            assert_eq!(response.status(), Status::UnsupportedMediaType);
+            let body: Value = response.into_json().await.unwrap();
+            assert_eq!(body["error"]["code"], "unsupported_media_type");
+        }
+
+        // Paths no route has are still unknown, whatever the body
+        let response = client.post("/volumes/data/snapshots").header(ContentType::Plain).body("{}").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/volumes/data/snapshots").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_refused() {
+        let agent = TestAgent::start().await;
+        let client = agent.client().await;
+
+        let body = format!(r#"{{"name": "{}"}}"#, "a".repeat(50 * 1024 * 1024));
+        let response = client.post("/volumes").header(ContentType::JSON).body(body).dispatch().await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"]["code"], "payload_too_large");
+        assert!(agent.docker.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deeply_nested_bodies_are_refused() {
+        let agent = TestAgent::start().await;
+        let client = agent.client().await;
+
+        let nested = format!("{}{}", "[".repeat(10_000), "]".repeat(10_000));
+        for body in [nested.clone(), format!(r#"{{"name": "data", "labels": {}}}"#, nested)] {
+            let response = client.post("/volumes").header(ContentType::JSON).body(body).dispatch().await;
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            let body: Value = response.into_json().await.unwrap();
+            assert_eq!(body["error"]["code"], "unprocessable_entity");
+        }
+        assert!(agent.docker.requests().is_empty());
+    }
+}
@@ -0,0 +1,31 @@
+use rocket::{delete, get, post};
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::{ChaosInjection, ChaosRequest};
+
+// Fault Injection (chaos feature only)
+
+/// Injects a time-bounded fault: a delayed or failed Docker API call, a dropped Docker event
+/// or a panic in a background component
+#[post("/agent/chaos", format = "json", data = "<chaos_req>")]
+pub async fn inject_chaos(chaos_req: Json<ChaosRequest>, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<Json<ChaosInjection>, AgentError> {
+    app_manager.chaos.inject(chaos_req.into_inner())
+        .map(Json)
+        .map_err(AgentError::BadRequest)
+}
+
+#[get("/agent/chaos")]
+pub async fn list_chaos(app_manager: &State<AppManager>, _auth: ApiToken) -> Json<Vec<ChaosInjection>> {
+    Json(app_manager.chaos.list())
+}
+
+#[delete("/agent/chaos/<injection_id>")]
+pub async fn remove_chaos(injection_id: String, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<String, AgentError> {
+    if !app_manager.chaos.remove(&injection_id) {
+        return Err(AgentError::NotFound(format!("Chaos injection {} not found", injection_id)));
+    }
+    Ok(format!("Chaos injection {} removed", injection_id))
+}
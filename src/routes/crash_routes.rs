@@ -0,0 +1,25 @@
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::routes::errors::AgentError;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::CrashReport;
+
+/// Crash reports of an instance, oldest first. Reports outlive the instance, so deleted
+/// instances can still be looked up by full id or name.
+#[get("/instances/<id>/crashes")]
+pub async fn get_instance_crashes(id: String, app_manager: &State<AppManager>) -> Json<Vec<CrashReport>> {
+    Json(app_manager.crashes.for_instance(&id))
+}
+
+/// Crash reports of every instance captured since `since` (RFC 3339), or all of them
+#[get("/crashes?<since>")]
+pub async fn list_crashes(since: Option<String>, app_manager: &State<AppManager>) -> Result<Json<Vec<CrashReport>>, AgentError> {
+    let since = match since {
+        Some(since) => chrono::DateTime::parse_from_rfc3339(&since)
+            .map_err(|e| AgentError::BadRequest(format!("since '{}' is not an RFC 3339 time: {}", since, e)))?
+            .with_timezone(&chrono::Utc),
+        None => chrono::DateTime::<chrono::Utc>::MIN_UTC,
+    };
+    Ok(Json(app_manager.crashes.since(since)))
+}
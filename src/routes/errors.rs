@@ -0,0 +1,92 @@
+use rocket::http::Status;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::Request;
+use thiserror::Error;
+use crate::routes::models::{ErrorDetail, ErrorResponse};
+
+/// Error returned by agent routes, rendered as a JSON error envelope with a matching status code
+#[derive(Debug, Error)]
+pub enum AgentError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Docker(String),
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl AgentError {
+    /// Maps a bollard error onto the matching variant, prefixing the message with `context`
+    pub fn docker(context: &str, error: bollard::errors::Error) -> Self {
+        let message = format!("{}: {}", context, error);
+        match error {
+            bollard::errors::Error::DockerResponseServerError { status_code, .. } => match status_code {
+                400 => AgentError::BadRequest(message),
+                404 => AgentError::NotFound(message),
+                409 => AgentError::Conflict(message),
+                _ => AgentError::Docker(message),
+            },
+            _ => AgentError::Docker(message),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            AgentError::BadRequest(_) => Status::BadRequest,
+            AgentError::Forbidden(_) => Status::Forbidden,
+            AgentError::NotFound(_) => Status::NotFound,
+            AgentError::Conflict(_) => Status::Conflict,
+            AgentError::Docker(_) => Status::BadGateway,
+            AgentError::Unavailable(_) => Status::ServiceUnavailable,
+            AgentError::Internal(_) => Status::InternalServerError,
+        }
+    }
+}
+
+impl ErrorResponse {
+    pub fn new(status: Status, message: String) -> Self {
+        let code = match status.code {
+            400 => "bad_request",
+            401 => "unauthorized",
+            403 => "forbidden",
+            404 => "not_found",
+            409 => "conflict",
+            413 => "payload_too_large",
+            415 => "unsupported_media_type",
+            422 => "unprocessable_entity",
+            502 => "docker_error",
+            503 => "unavailable",
+            _ => "internal_error",
+        };
+        
+        ErrorResponse {
+            error: ErrorDetail {
+                code: code.to_string(),
+                status: status.code,
+                message,
+            },
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AgentError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if status.code >= 500 {
+            tracing::warn!("{} {}: {}", request.method(), request.uri().path(), self);
+        }
+
+        Response::build_from(Json(ErrorResponse::new(status, self.to_string())).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
@@ -1,35 +1,41 @@
-use rocket::get;
+use rocket::{get, post};
 use rocket::serde::json::Json;
 use rocket::State;
-use bollard::image::ListImagesOptions;
+use bollard::image::CreateImageOptions;
 use bollard::system::EventsOptions;
-use futures::stream::StreamExt;
-use crate::routes::app_manager::AppManager;
+use futures::stream::{StreamExt, TryStreamExt};
+use crate::docker_cache::Listing;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::models::ImagePullRequest;
 
 #[get("/images")]
-pub async fn list_images(app_manager: &State<AppManager>) -> Json<Vec<String>> {
-    let mut images = Vec::new();
-    
-    // List images via Docker API
-    let options = Some(ListImagesOptions::<String> {
-        all: false,
+pub async fn list_images(app_manager: &State<AppManager>) -> Result<Listing<Vec<String>>, AgentError> {
+    match app_manager.cache.images(app_manager).await {
+        Ok(image_list) => Ok(image_list.map(|image_list| {
+            image_list.into_iter()
+                .flat_map(|image| image.repo_tags)
+                .collect()
+        })),
+        Err(e) => Err(AgentError::docker("Failed to list images", e))
+    }
+}
+
+#[post("/images/pull", format = "json", data = "<pull_req>")]
+pub async fn pull_image(pull_req: Json<ImagePullRequest>, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable, bypass: ImagePolicyBypass) -> Result<String, AgentError> {
+    app_manager.image_policy.check(&pull_req.image, bypass.0)?;
+    let options = Some(CreateImageOptions {
+        from_image: pull_req.image.clone(),
         ..Default::default()
     });
+    // Attach the stored credential for the image's registry, if there is one
+    let credentials = app_manager.registries.credentials_for(&pull_req.image);
     
-    match app_manager.docker.list_images(options).await {
-        Ok(image_list) => {
-            for image in image_list {
-                for tag in &image.repo_tags {
-                    images.push(tag.clone());
-                }
-            }
-        },
-        Err(e) => {
-            eprintln!("Failed to list images: {}", e);
-        }
+    match app_manager.instrument("create_image", app_manager.docker().create_image(options, None, credentials).try_collect::<Vec<_>>()).await {
+        Ok(_) => Ok(format!("Image {} pulled successfully", pull_req.image)),
+        Err(e) => Err(AgentError::docker("Failed to pull image", e))
     }
-    
-    Json(images)
 }
 
 #[get("/events")]
@@ -41,18 +47,18 @@ pub async fn stream_events(app_manager: &State<AppManager>) -> String {
         ..Default::default()
     });
     
-    let mut event_stream = app_manager.docker.events(options);
+    let mut event_stream = app_manager.docker().events(options);
     
     // In a real implementation, you'd stream these to the client
     // Here we'll just return a message
     while let Some(event) = event_stream.next().await {
         match event {
             Ok(event) => {
-                println!("Event: {:?}", event);
+                tracing::debug!("Event: {:?}", event);
                 // In a real implementation, send this to the client
             },
             Err(e) => {
-                eprintln!("Error receiving event: {}", e);
+                tracing::warn!("Error receiving event: {}", e);
                 break;
             }
         }
@@ -1,14 +1,10 @@
 use rocket::get;
 use rocket::response::content;
-use serde::{Deserialize, Serialize};
-use env_logger::{Builder, Target};
+use serde::Serialize;
 use lazy_static::lazy_static;
-use reqwest::Client;
-use rocket::serde::json::Json;
 use rocket::Build;
 use rocket::Rocket;
-use std::{env, sync::Arc};
-use tokio::sync::RwLock;
+use std::sync::Arc;
 use std::sync::Mutex;
 
 /// Route information structure for API documentation
@@ -28,7 +24,7 @@ pub struct RoutesResponse {
 }
 
 /// Routes collection that will be populated during startup
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct RoutesCollection {
     routes: Vec<RouteInfo>,
 }
@@ -59,9 +55,9 @@ impl RoutesCollection {
     }
 }
 
-/// Global singleton instance of the routes collection
-/// Stores information about all registered API routes
 lazy_static! {
+    /// Global singleton instance of the routes collection
+    /// Stores information about all registered API routes
     static ref ROUTES_COLLECTION: Arc<Mutex<RoutesCollection>> = Arc::new(Mutex::new(RoutesCollection::new()));
 }
 
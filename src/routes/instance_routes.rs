@@ -2,107 +2,212 @@ use rocket::{delete, get, post, patch, put};
 use rocket::serde::json::Json;
 use rocket::State;
 use std::collections::HashMap;
-use bollard::container::{CreateContainerOptions, Config, StartContainerOptions, StopContainerOptions, RemoveContainerOptions, ListContainersOptions};
+use bollard::container::{CreateContainerOptions, Config, StartContainerOptions, StopContainerOptions, RemoveContainerOptions};
 use bollard::image::CreateImageOptions;
+use bollard::models::ContainerSummary;
 use futures::stream::TryStreamExt;
+use serde_json::json;
 use chrono;
-use crate::routes::app_manager::AppManager;
-use crate::routes::models::{AppInstance, AppInstanceRequest};
+use crate::docker_cache::Listing;
+use crate::idempotency::{Idempotent, IdempotentJson};
+use crate::labels;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::maintenance_routes::check_maintenance;
+use crate::routes::models::{AppInstance, AppInstanceRequest, InstanceCount, InstanceList, InstanceQuery, SupervisionState, MASKED_SECRET};
+use crate::secrets;
+use crate::timeline;
 
-// API Endpoints
-#[get("/instances")]
-pub async fn list_instances(app_manager: &State<AppManager>) -> Json<Vec<AppInstance>> {
-    let mut instances = Vec::new();
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+// Timeline events included in an instance's details
+const RECENT_EVENTS: usize = 5;
+
+// An AppInstance from a container listing entry, if the daemon reported everything it needs
+pub(crate) fn instance_from_summary(container: ContainerSummary) -> Option<AppInstance> {
+    let (Some(id), Some(image), Some(names), Some(created), Some(status)) =
+        (container.id, container.image, container.names, container.created, container.status) else { return None };
+    let name = names.first()?.trim_start_matches('/').to_string();
+    Some(AppInstance {
+        id,
+        name,
+        image,
+        // Prefer the machine-readable state ("running") so it matches get_instance
+        status: container.state.unwrap_or(status),
+        created_at: created.to_string(),
+        ports: Vec::new(), // Would need to parse from container.ports
+        environment: HashMap::new(), // Would need additional API call
+        volumes: Vec::new(), // Would need additional API call
+        agent_id: "current".to_string(), // In a distributed setup, this would be the agent ID
+        supervision: None,
+        secrets: HashMap::new(),
+        ship_logs: false,
+        labels: labels::maestro_labels(container.labels),
+        recent_events: Vec::new(),
+    })
+}
+
+// Fetch every container on the host as an AppInstance
+async fn fetch_instances(app_manager: &AppManager) -> Result<Listing<Vec<AppInstance>>, AgentError> {
+    let containers = match app_manager.cache.containers(app_manager).await {
+        Ok(containers) => containers,
+        Err(e) => return Err(AgentError::docker("Failed to list containers", e)),
+    };
     
-    // List containers via Docker API
-    let options = Some(ListContainersOptions::<String> {
-        all: true,
-        ..Default::default()
+    Ok(containers.map(|containers| containers.into_iter().filter_map(instance_from_summary).collect()))
+}
+
+// Look up a tracked instance by full id or name
+pub(crate) fn find_tracked(app_manager: &AppManager, key: &str) -> Option<AppInstance> {
+    app_manager.instance(key).or_else(|| app_manager.find_instance(|instance| instance.name == key))
+}
+
+// The full id of the container `key` (an id, id prefix or name) refers to. The daemon refuses
+// prefixes shared by several containers, so whatever is done by this id is done to the one meant.
+pub(crate) async fn resolve_id(app_manager: &AppManager, key: &str, context: &str) -> Result<String, AgentError> {
+    match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(key, None)).await {
+        Ok(container) => Ok(container.id.unwrap_or_else(|| key.to_string())),
+        Err(e) => Err(AgentError::docker(context, e)),
+    }
+}
+
+// Stop tracking the removed container with the full id `id`, dropping its stored secrets with it
+pub(crate) fn forget_instance(app_manager: &AppManager, id: &str) {
+    if app_manager.remove_instance(id).is_none() {
+        return;
+    }
+    if let Err(e) = app_manager.secrets.set(id, &HashMap::new()) {
+        tracing::warn!("Failed to drop secrets of {}: {}", id, e);
+    }
+}
+
+// Update the supervision state of the tracked instance with the full id `id`, if it's supervised
+fn update_supervision(app_manager: &AppManager, id: &str, update: impl FnOnce(&mut SupervisionState)) {
+    app_manager.update_instance(id, |instance| instance.supervision.as_mut().map(update));
+}
+
+// Apply the listing filters, sorting and pagination from an InstanceQuery
+fn paginate_instances(mut instances: Vec<AppInstance>, query: &InstanceQuery) -> InstanceList {
+    instances.retain(|instance| {
+        query.status.as_ref().is_none_or(|status| instance.status.eq_ignore_ascii_case(status))
+            && query.image.as_ref().is_none_or(|image| &instance.image == image)
+            && query.name_contains.as_ref().is_none_or(|needle| instance.name.contains(needle.as_str()))
     });
     
-    match app_manager.docker.list_containers(options).await {
-        Ok(containers) => {
-            for container in containers {
-                if let (Some(id), Some(image), Some(names), Some(created), Some(status)) = 
-                   (container.id, container.image, container.names, container.created, container.status) {
-                    if let Some(name) = names.first() {
-                        let name = name.trim_start_matches('/').to_string();
-                        let app_instance = AppInstance {
-                            id: id.clone(),
-                            name,
-                            image,
-                            status,
-                            created_at: created.to_string(),
-                            ports: Vec::new(), // Would need to parse from container.ports
-                            environment: HashMap::new(), // Would need additional API call
-                            volumes: Vec::new(), // Would need additional API call
-                            agent_id: "current".to_string(), // In a distributed setup, this would be the agent ID
-                        };
-                        instances.push(app_instance);
-                    }
-                }
-            }
-        },
-        Err(e) => {
-            eprintln!("Failed to list containers: {}", e);
-        }
+    match query.sort.as_deref() {
+        Some("created_at") => instances.sort_by(|a, b| a.created_at.cmp(&b.created_at)),
+        _ => instances.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+    if query.order.as_deref() == Some("desc") {
+        instances.reverse();
     }
     
-    Json(instances)
+    let total = instances.len();
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let items = instances.into_iter()
+        .skip(page.saturating_sub(1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+    
+    InstanceList {
+        items,
+        total,
+        page,
+        per_page,
+    }
+}
+
+// API Endpoints
+#[get("/instances?<query..>")]
+pub async fn list_instances(query: InstanceQuery, app_manager: &State<AppManager>) -> Result<Listing<InstanceList>, AgentError> {
+    let instances = fetch_instances(app_manager).await?;
+    Ok(instances.map(|instances| paginate_instances(instances, &query)))
+}
+
+#[get("/instances/count?<status>")]
+pub async fn count_instances(status: Option<String>, app_manager: &State<AppManager>) -> Result<Listing<InstanceCount>, AgentError> {
+    // Served from the container cache so this stays cheap for dashboard polling
+    match app_manager.cache.containers(app_manager).await {
+        Ok(containers) => Ok(containers.map(|containers| InstanceCount {
+            count: containers.iter()
+                .filter(|container| status.as_ref().is_none_or(|status| container.state.as_ref() == Some(status)))
+                .count(),
+            status,
+        })),
+        Err(e) => Err(AgentError::docker("Failed to count instances", e))
+    }
 }
 
 #[get("/instances/<id>")]
-pub async fn get_instance(id: String, app_manager: &State<AppManager>) -> Option<Json<AppInstance>> {
+pub async fn get_instance(id: String, app_manager: &State<AppManager>) -> Result<Json<AppInstance>, AgentError> {
     // Get container details via Docker API
-    match app_manager.docker.inspect_container(&id, None).await {
+    match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
         Ok(container) => {
-            let config = container.config?;
-            let state = container.state?;
+            let incomplete = || AgentError::Docker(format!("Incomplete inspect response for instance {}", id));
+            let config = container.config.ok_or_else(incomplete)?;
+            let state = container.state.ok_or_else(incomplete)?;
             
-            let name = container.name?;
+            let name = container.name.ok_or_else(incomplete)?;
             let name = name.trim_start_matches('/').to_string();
             
+            let id = container.id.unwrap_or(id);
+            let (supervision, secrets, ship_logs, tracked_labels) = app_manager.instance(&id)
+                .map(|instance| (instance.supervision, instance.secrets, instance.ship_logs, instance.labels))
+                .unwrap_or_default();
+            // Labels of instances migrated without being recreated are only known to the agent
+            let labels = Some(labels::maestro_labels(config.labels)).filter(|labels| !labels.is_empty()).unwrap_or(tracked_labels);
+            // Docker only knows the container exited; the supervisor knows it gave up on it
+            let status = match &supervision {
+                Some(supervision) if supervision.state == "failed" => "failed".to_string(),
+                _ => state.status.map(|s| s.to_string()).unwrap_or_else(|| "unknown".to_string()),
+            };
+            
+            let recent_events = app_manager.timeline.recent(&id, RECENT_EVENTS);
             let app_instance = AppInstance {
-                id: container.id.unwrap_or(id),
+                id,
                 name,
                 image: config.image.unwrap_or_default(),
-                status: state.status.map(|s| s.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                status,
                 created_at: container.created.unwrap_or_default(),
                 ports: Vec::new(), // Would need to parse from container.network_settings
                 environment: HashMap::new(), // Would need to parse from config.env
                 volumes: Vec::new(), // Would need to parse from container.mounts
                 agent_id: "current".to_string(),
+                supervision,
+                secrets,
+                ship_logs,
+                labels,
+                recent_events,
             };
             
-            Some(Json(app_instance))
+            Ok(Json(app_instance))
         },
-        Err(_) => None
+        Err(e) => Err(AgentError::docker("Failed to get instance", e))
     }
 }
 
-#[post("/instances", format = "json", data = "<app_req>")]
-pub async fn create_instance(app_req: Json<AppInstanceRequest>, app_manager: &State<AppManager>) -> Result<Json<AppInstance>, String> {
-    // Check if image exists locally, pull if not
-    let image_name = &app_req.image;
-    
-    // Try to pull the image first to ensure it exists
+// Pull the image so the daemon has the latest tag; a failed pull falls back to the local copy
+pub(crate) async fn refresh_image(app_manager: &AppManager, image_name: &str) {
     let create_image_options = Some(CreateImageOptions {
-        from_image: image_name.clone(),
+        from_image: image_name.to_string(),
         ..Default::default()
     });
+    let credentials = app_manager.registries.credentials_for(image_name);
     
-    match app_manager.docker.create_image(create_image_options, None, None).try_collect::<Vec<_>>().await {
+    match app_manager.instrument("create_image", app_manager.docker().create_image(create_image_options, None, credentials).try_collect::<Vec<_>>()).await {
         Ok(_) => {
-            println!("Successfully pulled image: {}", image_name);
+            tracing::info!("Successfully pulled image: {}", image_name);
         },
         Err(e) => {
-            println!("Failed to pull image {}: {}. Attempting to use local image.", image_name, e);
+            tracing::warn!("Failed to pull image {}: {}. Attempting to use local image.", image_name, e);
         }
     }
-    
-    // Prepare container configuration
-    let name = app_req.name.clone();
-    
+}
+
+// Translate an AppInstanceRequest into a Docker container configuration carrying `labels`
+pub(crate) fn container_config(app_req: &AppInstanceRequest, labels: &HashMap<String, String>) -> Config<String> {
     let mut port_bindings = HashMap::new();
     if let Some(ports) = &app_req.ports {
         for port in ports {
@@ -122,6 +227,12 @@ pub async fn create_instance(app_req: Json<AppInstanceRequest>, app_manager: &St
             env_vars.push(format!("{}={}", key, value));
         }
     }
+    // Secrets are only ever in plaintext here, on their way into the container
+    if let Some(secrets) = &app_req.secrets {
+        for (key, value) in secrets {
+            env_vars.push(format!("{}={}", key, value.expose()));
+        }
+    }
     
     let mut volume_bindings = Vec::new();
     if let Some(volumes) = &app_req.volumes {
@@ -130,110 +241,196 @@ pub async fn create_instance(app_req: Json<AppInstanceRequest>, app_manager: &St
         }
     }
     
-    // Create container
-    let options = Some(CreateContainerOptions {
-        name: &name,
-        platform: None,
-    });
-    
-    let config = Config {
+    Config {
         image: Some(app_req.image.clone()),
         env: Some(env_vars),
         exposed_ports: Some(HashMap::new()), // Would need to populate from app_req.ports
+        labels: Some(labels.clone()),
         host_config: Some(bollard::models::HostConfig {
             port_bindings: Some(port_bindings),
             binds: Some(volume_bindings),
             ..Default::default()
         }),
         ..Default::default()
-    };
+    }
+}
+
+// Build the AppInstance we track for a container created from `app_req` with `labels`
+pub(crate) fn tracked_instance(id: &str, app_req: &AppInstanceRequest, labels: HashMap<String, String>, now: chrono::DateTime<chrono::Utc>) -> AppInstance {
+    AppInstance {
+        id: id.to_string(),
+        name: app_req.name.clone(),
+        image: app_req.image.clone(),
+        status: "running".to_string(),
+        created_at: now.to_string(),
+        ports: app_req.ports.clone().unwrap_or_default(),
+        environment: app_req.environment.clone().unwrap_or_default(),
+        volumes: app_req.volumes.clone().unwrap_or_default(),
+        agent_id: "current".to_string(),
+        supervision: app_req.supervise.unwrap_or(false).then(|| SupervisionState::started(now)),
+        secrets: secrets::masked(app_req.secrets.iter().flatten().map(|(name, _)| name)),
+        ship_logs: app_req.ship_logs.unwrap_or(false),
+        labels,
+        recent_events: Vec::new(),
+    }
+}
+
+// Persist the request's secrets for a newly created container
+pub(crate) fn store_secrets(app_manager: &AppManager, id: &str, app_req: &AppInstanceRequest) {
+    let secrets = app_req.secrets.clone().unwrap_or_default();
+    if let Err(e) = app_manager.secrets.set(id, &secrets) {
+        tracing::warn!("Failed to persist secrets of {}: {}", id, e);
+    }
+}
+
+// Secrets can only be accepted when there's a key to encrypt them with
+pub(crate) fn check_secrets_supported(app_manager: &AppManager, app_req: &AppInstanceRequest) -> Result<(), AgentError> {
+    if app_req.secrets.as_ref().is_some_and(|secrets| !secrets.is_empty()) && !app_manager.secrets.is_enabled() {
+        return Err(AgentError::Unavailable("Instance secrets require encryption_key in the agent configuration".to_string()));
+    }
+    Ok(())
+}
+
+// Logs can only be shipped when there's somewhere to ship them
+pub(crate) fn check_log_shipping_supported(app_manager: &AppManager, app_req: &AppInstanceRequest) -> Result<(), AgentError> {
+    if app_req.ship_logs == Some(true) && !app_manager.logs.is_enabled() {
+        return Err(AgentError::Unavailable("Log shipping requires log_shipping.url in the agent configuration".to_string()));
+    }
+    Ok(())
+}
+
+/// Creates and starts an instance. With an Idempotency-Key header a retried request gets the
+/// original response back instead of creating a second container.
+#[post("/instances", format = "json", data = "<app_req>")]
+pub async fn create_instance(app_req: Idempotent<AppInstanceRequest>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable, bypass: ImagePolicyBypass) -> Result<IdempotentJson<AppInstance>, AgentError> {
+    app_req.respond(app_manager, |app_req| async move {
+        app_manager.image_policy.check(&app_req.image, bypass.0)?;
+        launch_instance(app_req, app_manager, &auth.0).await
+    }).await
+}
+
+// Pull the image, then create, start and track the container, on behalf of `actor`
+pub(crate) async fn launch_instance(mut app_req: AppInstanceRequest, app_manager: &AppManager, actor: &str) -> Result<Json<AppInstance>, AgentError> {
+    check_maintenance(app_manager)?;
+    check_secrets_supported(app_manager, &app_req)?;
+    check_log_shipping_supported(app_manager, &app_req)?;
+    let labels = app_manager.conventions.labels(&app_req);
+    app_req.name = app_manager.conventions.container_name(&app_req.name);
+    
+    // Try to pull the image first to ensure it exists
+    refresh_image(app_manager, &app_req.image).await;
     
-    match app_manager.docker.create_container(options, config).await {
+    // Create container
+    let name = app_req.name.clone();
+    let options = Some(CreateContainerOptions {
+        name: &name,
+        platform: None,
+    });
+    
+    match app_manager.instrument("create_container", app_manager.docker().create_container(options, container_config(&app_req, &labels))).await {
         Ok(response) => {
             // Start the container
             let id = response.id;
-            match app_manager.docker.start_container(&id, None::<StartContainerOptions<String>>).await {
+            store_secrets(app_manager, &id, &app_req);
+            match app_manager.instrument("start_container", app_manager.docker().start_container(&id, None::<StartContainerOptions<String>>)).await {
                 Ok(_) => {
-                    // Create app instance object
-                    let app_instance = AppInstance {
-                        id: id.clone(),
-                        name: app_req.name.clone(),
-                        image: app_req.image.clone(),
-                        status: "running".to_string(),
-                        created_at: chrono::Utc::now().to_string(),
-                        ports: app_req.ports.clone().unwrap_or_default(),
-                        environment: app_req.environment.clone().unwrap_or_default(),
-                        volumes: app_req.volumes.clone().unwrap_or_default(),
-                        agent_id: "current".to_string(),
-                    };
+                    let app_instance = tracked_instance(&id, &app_req, labels, app_manager.now());
                     
                     // Store the instance in our local state
-                    app_manager.instances.lock().unwrap().insert(id, app_instance.clone());
+                    app_manager.upsert_instance(app_instance.clone());
+                    timeline::record(app_manager, &id, &app_instance.name, "created", actor, None);
+                    app_manager.notifier.notify("instance.created", json!(app_instance));
                     
                     Ok(Json(app_instance))
                 },
-                Err(e) => Err(format!("Failed to start instance: {}", e))
+                Err(e) => Err(AgentError::docker("Failed to start instance", e))
             }
         },
-        Err(e) => Err(format!("Failed to create instance: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to create instance", e))
     }
 }
 
+// Details of the instance `key` refers to after `event`, which goes into its timeline first
+async fn transitioned(app_manager: &State<AppManager>, key: String, event: &str, actor: &str) -> Result<Json<AppInstance>, AgentError> {
+    let Json(mut instance) = get_instance(key, app_manager).await?;
+    timeline::record(app_manager, &instance.id, &instance.name, event, actor, None);
+    instance.recent_events = app_manager.timeline.recent(&instance.id, RECENT_EVENTS);
+    Ok(Json(instance))
+}
+
 #[put("/instances/<id>/start")]
-pub async fn start_instance(id: String, app_manager: &State<AppManager>) -> Result<Json<AppInstance>, String> {
+pub async fn start_instance(id: String, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<AppInstance>, AgentError> {
+    let id = resolve_id(app_manager, &id, "Failed to start instance").await?;
     // Start container
-    match app_manager.docker.start_container(&id, None::<StartContainerOptions<String>>).await {
+    match app_manager.instrument("start_container", app_manager.docker().start_container(&id, None::<StartContainerOptions<String>>)).await {
         Ok(_) => {
+            // A manual start re-arms supervision with a fresh attempt budget
+            update_supervision(app_manager, &id, |supervision| *supervision = SupervisionState::started(app_manager.now()));
             // Get updated container info
-            match get_instance(id, app_manager).await {
-                Some(instance) => Ok(instance),
-                None => Err("Failed to get instance after starting".to_string())
-            }
+            transitioned(app_manager, id, "started", &auth.0).await
         },
-        Err(e) => Err(format!("Failed to start instance: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to start instance", e))
     }
 }
 
 #[put("/instances/<id>/stop")]
-pub async fn stop_instance(id: String, app_manager: &State<AppManager>) -> Result<Json<AppInstance>, String> {
+pub async fn stop_instance(id: String, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<AppInstance>, AgentError> {
+    let id = resolve_id(app_manager, &id, "Failed to stop instance").await?;
     // Stop container
     let options = Some(StopContainerOptions {
         t: 30, // Give it 30 seconds to shut down gracefully
     });
     
-    match app_manager.docker.stop_container(&id, options).await {
+    // Suspend supervision first so the supervisor doesn't treat the resulting die event as a crash
+    let mut previous_state = None;
+    update_supervision(app_manager, &id, |supervision| {
+        previous_state = Some(std::mem::replace(&mut supervision.state, "stopped".to_string()));
+    });
+    
+    match app_manager.instrument("stop_container", app_manager.docker().stop_container(&id, options)).await {
         Ok(_) => {
             // Get updated container info
-            match get_instance(id, app_manager).await {
-                Some(instance) => Ok(instance),
-                None => Err("Failed to get instance after stopping".to_string())
-            }
+            transitioned(app_manager, id, "stopped", &auth.0).await
         },
-        Err(e) => Err(format!("Failed to stop instance: {}", e))
+        Err(e) => {
+            if let Some(previous_state) = previous_state {
+                update_supervision(app_manager, &id, |supervision| supervision.state = previous_state);
+            }
+            Err(AgentError::docker("Failed to stop instance", e))
+        }
     }
 }
 
 #[put("/instances/<id>/restart")]
-pub async fn restart_instance(id: String, app_manager: &State<AppManager>) -> Result<Json<AppInstance>, String> {
+pub async fn restart_instance(id: String, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<AppInstance>, AgentError> {
+    let id = resolve_id(app_manager, &id, "Failed to restart instance").await?;
     // Restart container
     let options = Some(bollard::container::RestartContainerOptions {
         t: 30, // Give it 30 seconds to shut down gracefully
     });
     
-    match app_manager.docker.restart_container(&id, options).await {
+    // The restart emits a die event of its own, so supervision sits it out
+    update_supervision(app_manager, &id, |supervision| supervision.state = "stopped".to_string());
+    let result = app_manager.instrument("restart_container", app_manager.docker().restart_container(&id, options)).await;
+    update_supervision(app_manager, &id, |supervision| *supervision = SupervisionState::started(app_manager.now()));
+    
+    match result {
         Ok(_) => {
             // Get updated container info
-            match get_instance(id, app_manager).await {
-                Some(instance) => Ok(instance),
-                None => Err("Failed to get instance after restarting".to_string())
-            }
+            transitioned(app_manager, id, "restarted", &auth.0).await
         },
-        Err(e) => Err(format!("Failed to restart instance: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to restart instance", e))
     }
 }
 
 #[patch("/instances/<id>", format = "json", data = "<update_req>")]
-pub async fn update_instance(id: String, update_req: Json<AppInstanceRequest>, app_manager: &State<AppManager>) -> Result<Json<AppInstance>, String> {
+pub async fn update_instance(id: String, update_req: Json<AppInstanceRequest>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable, bypass: ImagePolicyBypass) -> Result<Json<AppInstance>, AgentError> {
+    // Checked up front, since the old container is gone by the time the new one is created
+    app_manager.image_policy.check(&update_req.image, bypass.0)?;
+    check_maintenance(app_manager)?;
+    check_secrets_supported(app_manager, &update_req)?;
+    check_log_shipping_supported(app_manager, &update_req)?;
+
     // For updating, we generally need to:
     // 1. Stop the existing container
     // 2. Remove it (but keep volumes if they're managed externally)
@@ -244,10 +441,7 @@ pub async fn update_instance(id: String, update_req: Json<AppInstanceRequest>, a
     // In practice, you'd want to check what actually changed and handle it accordingly
     
     // First, stop the container
-    let stop_result = stop_instance(id.clone(), app_manager).await;
-    if stop_result.is_err() {
-        return Err(format!("Failed to stop instance for update: {}", stop_result.err().unwrap()));
-    }
+    let stopped = stop_instance(id.clone(), app_manager, ApiToken(auth.0.clone()), DockerAvailable).await?;
     
     // Then remove it
     let options = Some(RemoveContainerOptions {
@@ -255,35 +449,52 @@ pub async fn update_instance(id: String, update_req: Json<AppInstanceRequest>, a
         ..Default::default()
     });
     
-    match app_manager.docker.remove_container(&id, options).await {
+    match app_manager.instrument("remove_container", app_manager.docker().remove_container(&stopped.id, options)).await {
         Ok(_) => {
+            forget_instance(app_manager, &stopped.id);
+            timeline::record(app_manager, &stopped.id, &stopped.name, "deleted", &auth.0, Some("recreated by an update".to_string()));
             // Now create a new one with the updated config
-            create_instance(update_req, app_manager).await
+            launch_instance(update_req.into_inner(), app_manager, &auth.0).await
         },
-        Err(e) => Err(format!("Failed to remove instance for update: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to remove instance for update", e))
     }
 }
 
 #[delete("/instances/<id>")]
-pub async fn delete_instance(id: String, app_manager: &State<AppManager>) -> Result<String, String> {
+pub async fn delete_instance(id: String, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
+    // Resolve the full id first, so the records dropped below are this container's and not those
+    // of another instance whose id starts with `id`
+    let container = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(container) => container,
+        Err(e) => return Err(AgentError::docker("Failed to delete instance", e)),
+    };
+    let instance_id = container.id.unwrap_or_else(|| id.clone());
+    let name = container.name.unwrap_or_default().trim_start_matches('/').to_string();
+
     // Remove container
     let options = Some(RemoveContainerOptions {
         force: true,
         ..Default::default()
     });
     
-    match app_manager.docker.remove_container(&id, options).await {
+    match app_manager.instrument("remove_container", app_manager.docker().remove_container(&instance_id, options)).await {
         Ok(_) => {
             // Remove from our local state
-            app_manager.instances.lock().unwrap().remove(&id);
+            forget_instance(app_manager, &instance_id);
+            timeline::record(app_manager, &instance_id, &name, "deleted", &auth.0, None);
+            // Schedules (and their history) go with the instance
+            if app_manager.schedules.lock().unwrap().remove_instance(&instance_id) {
+                app_manager.persist_schedules();
+            }
+            app_manager.notifier.notify("instance.deleted", json!({ "id": instance_id }));
             Ok(format!("Instance {} deleted successfully", id))
         },
-        Err(e) => Err(format!("Failed to delete instance: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to delete instance", e))
     }
 }
 
 #[get("/instances/<id>/logs")]
-pub async fn get_instance_logs(id: String, app_manager: &State<AppManager>) -> Result<String, String> {
+pub async fn get_instance_logs(id: String, app_manager: &State<AppManager>) -> Result<String, AgentError> {
     let options = Some(bollard::container::LogsOptions::<String> {
         stdout: true,
         stderr: true,
@@ -293,7 +504,7 @@ pub async fn get_instance_logs(id: String, app_manager: &State<AppManager>) -> R
         ..Default::default()
     });
 
-    match app_manager.docker.logs(&id, options).try_collect::<Vec<_>>().await {
+    match app_manager.instrument("logs", app_manager.docker().logs(&id, options).try_collect::<Vec<_>>()).await {
         Ok(logs) => {
             let log_content = logs.iter()
                 .map(|chunk| {
@@ -314,42 +525,293 @@ pub async fn get_instance_logs(id: String, app_manager: &State<AppManager>) -> R
                 .join("");
             Ok(log_content)
         },
-        Err(e) => Err(format!("Failed to fetch logs: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to fetch logs", e))
     }
 }
 
 #[get("/instances/<id>/stats")]
-pub async fn get_instance_stats(id: String, app_manager: &State<AppManager>) -> Result<Json<bollard::container::Stats>, String> {
-    match app_manager.docker.stats(&id, Some(bollard::container::StatsOptions { 
+pub async fn get_instance_stats(id: String, app_manager: &State<AppManager>) -> Result<Json<bollard::container::Stats>, AgentError> {
+    match app_manager.instrument("stats", app_manager.docker().stats(&id, Some(bollard::container::StatsOptions { 
         stream: false,
         one_shot: true,
-    })).try_next().await {
+    })).try_next()).await {
         Ok(Some(stats)) => Ok(Json(stats)),
-        Ok(None) => Err("No stats available".to_string()),
-        Err(e) => Err(format!("Failed to get stats: {}", e))
+        Ok(None) => Err(AgentError::NotFound(format!("No stats available for instance {}", id))),
+        Err(e) => Err(AgentError::docker("Failed to get stats", e))
     }
 }
 
 #[put("/instances/<id>/pause")]
-pub async fn pause_instance(id: String, app_manager: &State<AppManager>) -> Result<String, String> {
-    match app_manager.docker.pause_container(&id).await {
+pub async fn pause_instance(id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
+    match app_manager.instrument("pause_container", app_manager.docker().pause_container(&id)).await {
         Ok(_) => Ok(format!("Instance {} paused", id)),
-        Err(e) => Err(format!("Failed to pause instance: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to pause instance", e))
     }
 }
 
 #[put("/instances/<id>/unpause")]
-pub async fn unpause_instance(id: String, app_manager: &State<AppManager>) -> Result<String, String> {
-    match app_manager.docker.unpause_container(&id).await {
+pub async fn unpause_instance(id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
+    match app_manager.instrument("unpause_container", app_manager.docker().unpause_container(&id)).await {
         Ok(_) => Ok(format!("Instance {} unpaused", id)),
-        Err(e) => Err(format!("Failed to unpause instance: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to unpause instance", e))
     }
 }
 
 #[get("/instances/<id>/inspect")]
-pub async fn inspect_instance(id: String, app_manager: &State<AppManager>) -> Result<Json<bollard::models::ContainerInspectResponse>, String> {
-    match app_manager.docker.inspect_container(&id, None).await {
-        Ok(info) => Ok(Json(info)),
-        Err(e) => Err(format!("Failed to inspect instance: {}", e))
+pub async fn inspect_instance(id: String, app_manager: &State<AppManager>) -> Result<Json<bollard::models::ContainerInspectResponse>, AgentError> {
+    match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(mut info) => {
+            // The raw container config carries secrets in its environment, so mask them here
+            let secret_names = info.id.as_ref()
+                .and_then(|id| app_manager.instance(id).map(|instance| instance.secrets))
+                .unwrap_or_default();
+            if let Some(env) = info.config.as_mut().and_then(|config| config.env.as_mut()) {
+                for var in env.iter_mut() {
+                    let name = var.split('=').next().unwrap_or_default().to_string();
+                    if secret_names.contains_key(&name) {
+                        *var = format!("{}={}", name, MASKED_SECRET);
+                    }
+                }
+            }
+            Ok(Json(info))
+        },
+        Err(e) => Err(AgentError::docker("Failed to inspect instance", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{ContentType, Status};
+    use serde_json::json;
+    use crate::testing::{instance, DockerResponse, TestAgent};
+
+    // 500 instances: game-000..game-499, every third one exited, alternating images,
+    // created in the reverse order of their names
+    fn seeded() -> Vec<AppInstance> {
+        (0..500).map(|i| AppInstance {
+            image: if i % 2 == 0 { "horizon:1".to_string() } else { "horizon:2".to_string() },
+            status: if i % 3 == 0 { "exited".to_string() } else { "running".to_string() },
+            created_at: (2_000_000_000 - i).to_string(),
+            ..instance(&format!("{:064x}", i), &format!("game-{:03}", i))
+        }).collect()
+    }
+
+    fn query() -> InstanceQuery {
+        InstanceQuery {
+            status: None,
+            image: None,
+            name_contains: None,
+            sort: None,
+            order: None,
+            page: None,
+            per_page: None,
+        }
+    }
+
+    fn names(list: &InstanceList) -> Vec<&str> {
+        list.items.iter().map(|instance| instance.name.as_str()).collect()
+    }
+
+    #[test]
+    fn defaults_to_the_first_page_by_name() {
+        let list = paginate_instances(seeded(), &query());
+        assert_eq!((list.total, list.page, list.per_page), (500, 1, DEFAULT_PER_PAGE));
+        assert_eq!(list.items.len(), DEFAULT_PER_PAGE);
+        assert_eq!(names(&list)[..2], ["game-000", "game-001"]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn filters_combine() {
+        let list = paginate_instances(seeded(), &InstanceQuery {
+            status: Some("RUNNING".to_string()),
+            image: Some("horizon:2".to_string()),
+            name_contains: Some("-4".to_string()),
+            per_page: Some(MAX_PER_PAGE),
+            ..query()
+        });
+        let expected = (400..500).filter(|i| i % 3 != 0 && i % 2 == 1).count();
+        assert_eq!(list.total, expected);
+        assert!(list.items.iter().all(|instance| instance.status == "running" && instance.image == "horizon:2" && instance.name.starts_with("game-4")));
+    }
+
+    #[test]
+    fn sorts_by_creation_in_either_order() {
+        let ascending = paginate_instances(seeded(), &InstanceQuery {
+            sort: Some("created_at".to_string()),
+            per_page: Some(3),
+            ..query()
+        });
+        assert_eq!(names(&ascending), ["game-499", "game-498", "game-497"]);
+
+        let descending = paginate_instances(seeded(), &InstanceQuery {
+            sort: Some("name".to_string()),
+            order: Some("desc".to_string()),
+            per_page: Some(3),
+            ..query()
+        });
+        assert_eq!(names(&descending), ["game-499", "game-498", "game-497"]);
+    }
+
+    #[test]
+    fn pages_cover_every_instance_once() {
+        let mut seen = Vec::new();
+        for page in 1..=7 {
+            let list = paginate_instances(seeded(), &InstanceQuery {
+                page: Some(page),
+                per_page: Some(80),
+                ..query()
+            });
+            assert_eq!(list.items.len(), if page == 7 { 20 } else { 80 });
+            seen.extend(list.items.into_iter().map(|instance| instance.name));
+        }
+        let expected: Vec<String> = (0..500).map(|i| format!("game-{:03}", i)).collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn out_of_range_pages_are_empty_or_clamped() {
+        let past_the_end = paginate_instances(seeded(), &InstanceQuery {
+            page: Some(usize::MAX),
+            per_page: Some(usize::MAX),
+            ..query()
+        });
+        assert_eq!((past_the_end.page, past_the_end.per_page), (usize::MAX, MAX_PER_PAGE));
+        assert!(past_the_end.items.is_empty());
+
+        let zero = paginate_instances(seeded(), &InstanceQuery {
+            page: Some(0),
+            per_page: Some(0),
+            ..query()
+        });
+        assert_eq!((zero.page, zero.per_page), (1, 1));
+        assert_eq!(names(&zero), ["game-000"]);
+    }
+
+    // Two supervised instances where "ab" is one's name and a prefix of the other's id
+    async fn supervised_lookalikes() -> TestAgent {
+        let agent = TestAgent::start().await;
+        let now = agent.app_manager.now();
+        agent.app_manager.upsert_instances([
+            AppInstance { supervision: Some(SupervisionState::started(now)), ..instance("ab99ff", "lobby") },
+            AppInstance { supervision: Some(SupervisionState::started(now)), ..instance("cd12ef", "ab") },
+        ]);
+        agent.docker.route("GET", "/containers/*/json", |request| {
+            let id = if request.path.contains("/ab/") { "cd12ef" } else { "ab99ff" };
+            DockerResponse::ok(json!({
+                "Id": id,
+                "Name": "/ab",
+                "Config": { "Image": "horizon:1" },
+                "State": { "Status": "exited", "Running": false },
+            }))
+        });
+        agent.docker.route("POST", "/containers/*/stop", |_| DockerResponse::ok(json!({})));
+        agent
+    }
+
+    fn supervision_state(agent: &TestAgent, id: &str) -> String {
+        agent.app_manager.instance(id).unwrap().supervision.unwrap().state
+    }
+
+    #[tokio::test]
+    async fn stop_suspends_supervision_of_exactly_the_stopped_container() {
+        let agent = supervised_lookalikes().await;
+        let client = agent.client().await;
+
+        assert_eq!(client.put("/instances/ab/stop").dispatch().await.status(), Status::Ok);
+        assert_eq!(agent.docker.requests_to("POST", "/containers/cd12ef/stop").len(), 1);
+        assert_eq!(supervision_state(&agent, "cd12ef"), "stopped");
+        assert_eq!(supervision_state(&agent, "ab99ff"), "running");
+    }
+
+    #[tokio::test]
+    async fn ambiguous_prefixes_are_refused() {
+        let agent = supervised_lookalikes().await;
+        agent.docker.route("GET", "/containers/a/json", |_| DockerResponse::error(400, "multiple IDs found with provided prefix: a"));
+        let client = agent.client().await;
+
+        assert_eq!(client.put("/instances/a/stop").dispatch().await.status(), Status::BadRequest);
+        assert!(agent.docker.requests().iter().all(|request| request.method == "GET"));
+        assert_eq!(supervision_state(&agent, "cd12ef"), "running");
+        assert_eq!(supervision_state(&agent, "ab99ff"), "running");
+    }
+
+    // Two tracked instances where one's name is a prefix of the other's id
+    async fn lookalikes() -> TestAgent {
+        // Any 32 bytes, so secrets can be stored
+        let agent = TestAgent::with_config(|config| config.encryption_key = Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string())).await;
+        agent.app_manager.upsert_instances([instance("ab12cd", "lobby"), instance("ff34ee", "ab")]);
+        agent.app_manager.secrets.set("ab12cd", &HashMap::from([("TOKEN".to_string(), crate::routes::models::SecretValue::new("lobby".to_string()))])).unwrap();
+        agent.docker.route("GET", "/containers/ab/json", |_| DockerResponse::ok(json!({
+            "Id": "ff34ee",
+            "Name": "/ab",
+            "Config": { "Image": "horizon:1" },
+            "State": { "Status": "running", "Running": true },
+        })));
+        agent.docker.route("DELETE", "/containers/*", |_| DockerResponse::ok(json!({})));
+        agent
+    }
+
+    #[tokio::test]
+    async fn delete_forgets_exactly_the_removed_container() {
+        let agent = lookalikes().await;
+        let client = agent.client().await;
+
+        assert_eq!(client.delete("/instances/ab").dispatch().await.status(), Status::Ok);
+        assert_eq!(agent.docker.requests_to("DELETE", "/containers/ff34ee").len(), 1);
+        assert!(agent.app_manager.instance("ff34ee").is_none());
+        assert!(agent.app_manager.instance("ab12cd").is_some());
+        assert!(!agent.app_manager.secrets.get("ab12cd").is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_of_an_unknown_container_keeps_every_record() {
+        let agent = lookalikes().await;
+        agent.docker.route("GET", "/containers/ab1/json", |_| DockerResponse::error(404, "No such container: ab1"));
+        let client = agent.client().await;
+
+        assert_eq!(client.delete("/instances/ab1").dispatch().await.status(), Status::NotFound);
+        assert_eq!(agent.app_manager.instance_count(), 2);
+        assert!(agent.docker.requests_to("DELETE", "/containers/ab1").is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_forgets_exactly_the_replaced_container() {
+        let agent = lookalikes().await;
+        agent.docker.route("POST", "/containers/ff34ee/stop", |_| DockerResponse::ok(json!({})));
+        agent.docker.route("GET", "/containers/ff34ee/json", |_| DockerResponse::ok(json!({
+            "Id": "ff34ee",
+            "Name": "/ab",
+            "Config": { "Image": "horizon:1" },
+            "State": { "Status": "exited", "Running": false },
+        })));
+        agent.docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({})));
+        agent.docker.route("POST", "/containers/create", |_| DockerResponse::Json(201, json!({ "Id": "aa56bb", "Warnings": [] })));
+        agent.docker.route("POST", "/containers/aa56bb/start", |_| DockerResponse::ok(json!({})));
+        let client = agent.client().await;
+
+        let response = client.patch("/instances/ab").header(ContentType::JSON).body(r#"{"name": "ab", "image": "horizon:2"}"#).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(agent.docker.requests_to("DELETE", "/containers/ff34ee").len(), 1);
+        assert!(agent.app_manager.instance("ff34ee").is_none());
+        assert!(agent.app_manager.instance("ab12cd").is_some());
+        assert!(agent.app_manager.instance("aa56bb").is_some());
+    }
+
+    #[tokio::test]
+    async fn update_during_maintenance_leaves_the_old_container_alone() {
+        let agent = lookalikes().await;
+        agent.app_manager.set_maintenance(Some(crate::routes::models::MaintenanceState {
+            message: "Upgrading the host".to_string(),
+            started_at: agent.app_manager.now().to_rfc3339(),
+            ends_at: None,
+            window_ids: Vec::new(),
+        }));
+        let client = agent.client().await;
+
+        let response = client.patch("/instances/ab").header(ContentType::JSON).body(r#"{"name": "ab", "image": "horizon:2"}"#).dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert!(agent.docker.requests().iter().all(|request| request.method == "GET"), "{:?}", agent.docker.requests());
+        assert!(agent.app_manager.instance("ff34ee").is_some());
+    }
+}
@@ -2,7 +2,26 @@
 pub use crate::routes::models::*;
 pub use crate::routes::app_manager::*;
 pub use crate::routes::instance_routes::*;
+pub use crate::routes::replace_routes::*;
+pub use crate::routes::schedule_routes::*;
+pub use crate::routes::secret_routes::*;
 pub use crate::routes::volume_routes::*;
 pub use crate::routes::network_routes::*;
 pub use crate::routes::image_routes::*;
-pub use crate::routes::agent_routes::*;
\ No newline at end of file
+pub use crate::routes::registry_routes::*;
+pub use crate::routes::agent_routes::*;
+pub use crate::routes::metrics_routes::*;
+pub use crate::routes::migration_routes::*;
+pub use crate::routes::maintenance_routes::*;
+pub use crate::routes::adopt_routes::*;
+pub use crate::routes::tunnel_routes::*;
+pub use crate::routes::snapshot_routes::*;
+#[cfg(feature = "chaos")]
+pub use crate::routes::chaos_routes::*;
+pub use crate::routes::stats_routes::*;
+pub use crate::routes::crash_routes::*;
+pub use crate::routes::state_routes::*;
+pub use crate::routes::policy_routes::*;
+pub use crate::routes::attach_routes::*;
+pub use crate::routes::timeline_routes::*;
+pub use crate::routes::label_routes::*;
\ No newline at end of file
@@ -0,0 +1,208 @@
+use rocket::{get, post};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::{HashMap, HashSet};
+use crate::desired_state;
+use crate::docker_cache::Listing;
+use crate::labels::{self, Selector};
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::adopt_routes::adopt_item;
+use crate::routes::instance_routes::instance_from_summary;
+use crate::routes::models::{AppInstance, ImportReport, LabelMigrationRequest};
+use crate::routes::replace_routes::replace_instance;
+
+/// Instances with every `label` ("key" or "key=value", repeatable) and the given `status`, e.g.
+/// /instances/search?label=maestro.deployment=lobby&status=running. Containers are matched on
+/// their own labels and, for instances migrated without being recreated, the agent's record of them.
+#[get("/instances/search?<label>&<status>")]
+pub async fn search_instances(label: Vec<String>, status: Option<String>, app_manager: &State<AppManager>) -> Result<Listing<Vec<AppInstance>>, AgentError> {
+    let selectors = label.iter()
+        .map(|selector| Selector::parse(selector))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AgentError::BadRequest)?;
+    let containers = match app_manager.cache.containers(app_manager).await {
+        Ok(containers) => containers,
+        Err(e) => return Err(AgentError::docker("Failed to list containers", e)),
+    };
+    let tracked: HashMap<String, AppInstance> = app_manager.instances().into_iter()
+        .map(|instance| (instance.id.clone(), instance))
+        .collect();
+
+    Ok(containers.map(|containers| containers.into_iter()
+        .filter_map(|container| {
+            let mut container_labels = container.labels.clone().unwrap_or_default();
+            let mut instance = instance_from_summary(container)?;
+            if let Some(tracked) = tracked.get(&instance.id).filter(|_| instance.labels.is_empty()) {
+                instance.labels = tracked.labels.clone();
+                container_labels.extend(tracked.labels.clone());
+            }
+            let matched = selectors.iter().all(|selector| selector.matches(&container_labels))
+                && status.as_ref().is_none_or(|status| instance.status.eq_ignore_ascii_case(status));
+            matched.then_some(instance)
+        })
+        .collect()))
+}
+
+/// Gives tracked instances created before labeling the labels new instances get. With `recreate`
+/// each one is replaced so the labels are on its container; otherwise they're only recorded by the
+/// agent, which search and reconcile go by, while `docker ps` keeps showing the container unlabeled.
+#[post("/instances/labels/migrate", format = "json", data = "<migrate_req>")]
+pub async fn migrate_labels(migrate_req: Json<LabelMigrationRequest>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<ImportReport>, AgentError> {
+    let containers = match app_manager.cache.containers(app_manager).await {
+        Ok(containers) => containers.items,
+        Err(e) => return Err(AgentError::docker("Failed to list containers", e)),
+    };
+    let labeled: HashSet<String> = containers.into_iter()
+        .filter(|container| container.labels.as_ref().is_some_and(|labels| labels.contains_key(labels::MANAGED)))
+        .filter_map(|container| container.id)
+        .collect();
+    // Instances labeled in the agent's records only can still be recreated
+    let mut legacy: Vec<AppInstance> = app_manager.instances().into_iter()
+        .filter(|instance| !labeled.contains(&instance.id))
+        .filter(|instance| migrate_req.recreate || !instance.labels.contains_key(labels::MANAGED))
+        .collect();
+    legacy.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut items = Vec::new();
+    for instance in legacy {
+        let mut definition = desired_state::definition(&app_manager.conventions, &instance);
+        if migrate_req.dry_run {
+            items.push(adopt_item(&instance.name, if migrate_req.recreate { "would_recreate" } else { "would_label" }, None));
+            continue;
+        }
+
+        if !migrate_req.recreate {
+            let labels = app_manager.conventions.labels(&definition);
+            app_manager.update_instance(&instance.id, |instance| {
+                instance.labels = labels;
+                Some(())
+            });
+            tracing::warn!("Labeled {} in the agent's records only; its container shows no labels until it's recreated", instance.name);
+            items.push(adopt_item(&instance.name, "labeled", Some("labels recorded by the agent only, recreate to put them on the container".to_string())));
+            continue;
+        }
+
+        // The instance keeps its secrets, and its image is already running, so the image policy doesn't apply
+        let secrets = app_manager.secrets.get(&instance.id);
+        definition.secrets = (!secrets.is_empty()).then_some(secrets);
+        // Asking to recreate is asking for the cutover its unchanged ports need
+        match replace_instance(instance.id.clone(), Some(true), Json(definition), app_manager, ApiToken(auth.0.clone()), DockerAvailable, ImagePolicyBypass(true)).await {
+            Ok(report) => items.push(adopt_item(&instance.name, "recreated", Some(format!("now {}", report.instance.id)))),
+            Err(e) => items.push(adopt_item(&instance.name, "failed", Some(e.to_string()))),
+        }
+    }
+
+    Ok(Json(ImportReport {
+        items,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use serde_json::{json, Value};
+    use crate::labels;
+    use crate::routes::models::AppInstance;
+    use crate::testing::{instance, DockerResponse, TestAgent};
+    use std::collections::HashMap;
+
+    fn summary(id: &str, name: &str, state: &str, labels: Value) -> Value {
+        json!({
+            "Id": id,
+            "Names": [format!("/{}", name)],
+            "Image": "horizon:1",
+            "Created": 1_772_334_000,
+            "State": state,
+            "Status": state,
+            "Labels": labels,
+        })
+    }
+
+    // A labeled lobby and arena, one exited, next to an unlabeled legacy instance the agent tracks
+    // and a container it doesn't manage
+    async fn agent() -> TestAgent {
+        let agent = TestAgent::with_config(|config| config.instances.name_prefix = "hz-".to_string()).await;
+        agent.docker.route("GET", "/containers/json", |_| DockerResponse::ok(json!([
+            summary("lobby-id", "hz-lobby", "running", json!({ "maestro.managed": "true", "maestro.instance": "lobby", "maestro.deployment": "eu" })),
+            summary("arena-id", "hz-arena", "exited", json!({ "maestro.managed": "true", "maestro.instance": "arena", "maestro.deployment": "eu" })),
+            summary("legacy-id", "hz-legacy", "running", json!({})),
+            summary("postgres-id", "postgres", "running", json!({ "com.docker.compose.project": "db" })),
+        ])));
+        agent.app_manager.upsert_instance(instance("legacy-id", "hz-legacy"));
+        agent
+    }
+
+    async fn search(agent: &TestAgent, query: &str) -> Vec<String> {
+        let client = agent.client().await;
+        let response = client.get(format!("/instances/search{}", query)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let found: Vec<AppInstance> = response.into_json().await.unwrap();
+        found.into_iter().map(|instance| instance.name).collect()
+    }
+
+    #[tokio::test]
+    async fn search_combines_label_selectors_and_status() {
+        let agent = agent().await;
+
+        assert_eq!(search(&agent, "?label=maestro.deployment=eu").await, ["hz-lobby", "hz-arena"]);
+        assert_eq!(search(&agent, "?label=maestro.deployment=eu&status=RUNNING").await, ["hz-lobby"]);
+        assert_eq!(search(&agent, "?label=maestro.deployment=eu&label=maestro.instance=arena").await, ["hz-arena"]);
+        assert_eq!(search(&agent, "?label=maestro.managed").await, ["hz-lobby", "hz-arena"]);
+        // Any of a container's own labels can be selected on
+        assert_eq!(search(&agent, "?label=com.docker.compose.project=db").await, ["postgres"]);
+        assert_eq!(search(&agent, "").await.len(), 4);
+
+        let client = agent.client().await;
+        assert_eq!(client.get("/instances/search?label==eu").dispatch().await.status(), Status::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn migration_records_labels_for_search_without_touching_containers() {
+        let agent = agent().await;
+        let client = agent.client().await;
+        let migrate = |body: &'static str| client.post("/instances/labels/migrate").header(ContentType::JSON).body(body).dispatch();
+
+        let report: Value = migrate(r#"{"dry_run": true}"#).await.into_json().await.unwrap();
+        assert_eq!(report["items"], json!([{ "kind": "instance", "name": "hz-legacy", "outcome": "would_label", "message": null }]));
+        assert!(agent.app_manager.instance("legacy-id").unwrap().labels.is_empty());
+
+        let report: Value = migrate(r#"{}"#).await.into_json().await.unwrap();
+        assert_eq!(report["items"][0]["outcome"], "labeled");
+        let labels = agent.app_manager.instance("legacy-id").unwrap().labels;
+        assert_eq!(labels.get(labels::INSTANCE).map(String::as_str), Some("legacy"));
+        assert_eq!(labels.get(labels::MANAGED).map(String::as_str), Some("true"));
+        assert_eq!(search(&agent, "?label=maestro.instance=legacy").await, ["hz-legacy"]);
+
+        // Labeled instances aren't migrated again, and nothing was sent to the daemon but listings
+        let report: Value = migrate(r#"{}"#).await.into_json().await.unwrap();
+        assert_eq!(report["items"], json!([]));
+        assert!(agent.docker.requests().iter().all(|request| request.method == "GET"));
+    }
+
+    #[tokio::test]
+    async fn recreating_puts_the_labels_on_the_container() {
+        let agent = agent().await;
+        let docker = &agent.docker;
+        docker.route("GET", "/containers/legacy-id/json", |_| DockerResponse::ok(json!({ "Id": "legacy-id", "Name": "/hz-legacy", "State": { "Running": true } })));
+        docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/create", |_| DockerResponse::Json(201, json!({ "Id": "labeled-id", "Warnings": [] })));
+        docker.route("POST", "/containers/*/start", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/*/stop", |_| DockerResponse::ok(json!({})));
+        docker.route("GET", "/containers/labeled-id/json", |_| DockerResponse::ok(json!({ "Id": "labeled-id", "State": { "Running": true, "Health": { "Status": "healthy" } } })));
+        docker.route("DELETE", "/containers/legacy-id", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/labeled-id/rename", |_| DockerResponse::ok(json!({})));
+        let client = agent.client().await;
+
+        let response = client.post("/instances/labels/migrate").header(ContentType::JSON).body(r#"{"recreate": true}"#).dispatch().await;
+        let report: Value = response.into_json().await.unwrap();
+        assert_eq!(report["items"][0]["outcome"], "recreated", "{}", report);
+        let created = docker.requests_to("POST", "/containers/create");
+        let labels: HashMap<String, String> = serde_json::from_value(created[0].json()["Labels"].clone()).unwrap();
+        assert_eq!(labels.get(labels::INSTANCE).map(String::as_str), Some("legacy"));
+        assert_eq!(labels.get(labels::MANAGED).map(String::as_str), Some("true"));
+        assert!(agent.app_manager.instance("legacy-id").is_none());
+        assert_eq!(agent.app_manager.instance("labeled-id").unwrap().labels, labels);
+    }
+}
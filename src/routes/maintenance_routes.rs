@@ -0,0 +1,141 @@
+use rocket::{delete, get, post, put};
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::AppManager;
+use crate::routes::schedule_routes::display_timezone;
+use crate::routes::models::{MaintenancePeriod, MaintenanceRequest, MaintenanceState, MaintenanceWindow, MaintenanceWindowRequest};
+use crate::scheduler;
+
+const DEFAULT_MAINTENANCE_MESSAGE: &str = "Agent is in maintenance mode";
+const MAX_WINDOW_DURATION_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_UPCOMING_DAYS: u32 = 7;
+const MAX_UPCOMING_DAYS: u32 = 31;
+
+// Maintenance Mode
+
+/// Fails with 503 and the maintenance message while the agent is in maintenance
+pub(crate) fn check_maintenance(app_manager: &AppManager) -> Result<(), AgentError> {
+    match app_manager.maintenance() {
+        Some(state) => Err(AgentError::Unavailable(state.message)),
+        None => Ok(()),
+    }
+}
+
+#[get("/agent/maintenance")]
+pub async fn get_maintenance(app_manager: &State<AppManager>) -> Json<Option<MaintenanceState>> {
+    Json(app_manager.maintenance())
+}
+
+/// Puts the agent into maintenance: running instances are left alone, but new ones are refused
+/// until maintenance is ended or `ends_at` passes
+#[put("/agent/maintenance", format = "json", data = "<maintenance_req>")]
+pub async fn start_maintenance(maintenance_req: Json<MaintenanceRequest>, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<Json<MaintenanceState>, AgentError> {
+    if let Some(ends_at) = &maintenance_req.ends_at {
+        match chrono::DateTime::parse_from_rfc3339(ends_at) {
+            Ok(ends_at) if ends_at > app_manager.now() => {},
+            Ok(_) => return Err(AgentError::BadRequest("ends_at must be in the future".to_string())),
+            Err(e) => return Err(AgentError::BadRequest(format!("Invalid ends_at '{}': {}", ends_at, e))),
+        }
+    }
+
+    let state = MaintenanceState {
+        message: maintenance_req.message.clone()
+            .filter(|message| !message.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_MAINTENANCE_MESSAGE.to_string()),
+        started_at: app_manager.now().to_rfc3339(),
+        ends_at: maintenance_req.ends_at.clone(),
+        window_ids: Vec::new(),
+    };
+    tracing::info!("Entering maintenance mode: {}", state.message);
+    app_manager.set_maintenance(Some(state.clone()));
+
+    Ok(Json(state))
+}
+
+#[delete("/agent/maintenance")]
+pub async fn end_maintenance(app_manager: &State<AppManager>, _auth: ApiToken) -> Result<String, AgentError> {
+    match app_manager.maintenance() {
+        None => return Err(AgentError::NotFound("Agent is not in maintenance mode".to_string())),
+        Some(state) if !state.window_ids.is_empty() => {
+            return Err(AgentError::Conflict(format!("Agent is in scheduled maintenance window {} until {}", state.window_ids.join(", "), state.ends_at.unwrap_or_default())));
+        },
+        Some(_) => {},
+    }
+
+    tracing::info!("Leaving maintenance mode");
+    app_manager.set_maintenance(None);
+    Ok("Maintenance mode ended".to_string())
+}
+
+/// Adds a recurring maintenance window that opens on `cron` (in `timezone`, UTC when unset) and lasts `duration_secs`
+#[post("/agent/maintenance/windows", format = "json", data = "<window_req>")]
+pub async fn create_maintenance_window(window_req: Json<MaintenanceWindowRequest>, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<Json<MaintenanceWindow>, AgentError> {
+    if let Err(e) = scheduler::parse_cron(&window_req.cron) {
+        return Err(AgentError::BadRequest(format!("Invalid cron expression '{}': {}", window_req.cron, e)));
+    }
+    if window_req.duration_secs == 0 || window_req.duration_secs > MAX_WINDOW_DURATION_SECS {
+        return Err(AgentError::BadRequest(format!("duration_secs must be between 1 and {}", MAX_WINDOW_DURATION_SECS)));
+    }
+    let timezone = match window_req.timezone.as_deref().map(scheduler::parse_timezone).transpose() {
+        Ok(timezone) => timezone.map(|timezone| timezone.name().to_string()),
+        Err(e) => return Err(AgentError::BadRequest(e)),
+    };
+
+    let window = MaintenanceWindow {
+        id: uuid::Uuid::new_v4().to_string(),
+        cron: window_req.cron.clone(),
+        timezone,
+        duration_secs: window_req.duration_secs,
+        message: window_req.message.clone().filter(|message| !message.trim().is_empty()),
+        created_at: app_manager.now().to_rfc3339(),
+    };
+    app_manager.schedules.lock().unwrap().windows.push(window.clone());
+    app_manager.persist_schedules();
+
+    Ok(Json(window))
+}
+
+#[get("/agent/maintenance/windows")]
+pub async fn list_maintenance_windows(app_manager: &State<AppManager>) -> Json<Vec<MaintenanceWindow>> {
+    Json(app_manager.schedules.lock().unwrap().windows.clone())
+}
+
+/// Maintenance periods over the next `days` (7 by default), including one in progress.
+/// With `tz` their start and end are also given in that zone.
+#[get("/agent/maintenance/upcoming?<days>&<tz>")]
+pub async fn get_upcoming_maintenance(days: Option<u32>, tz: Option<String>, app_manager: &State<AppManager>) -> Result<Json<Vec<MaintenancePeriod>>, AgentError> {
+    let timezone = display_timezone(tz.as_deref())?;
+    let days = days.unwrap_or(DEFAULT_UPCOMING_DAYS);
+    if days == 0 || days > MAX_UPCOMING_DAYS {
+        return Err(AgentError::BadRequest(format!("days must be between 1 and {}", MAX_UPCOMING_DAYS)));
+    }
+
+    let now = app_manager.now();
+    let windows = app_manager.schedules.lock().unwrap().windows.clone();
+    let mut periods = scheduler::maintenance_periods(&windows, now, now + chrono::TimeDelta::days(days as i64));
+    if let Some(timezone) = timezone {
+        for period in &mut periods {
+            period.starts_at_local = scheduler::local_time(&period.starts_at, timezone);
+            period.ends_at_local = scheduler::local_time(&period.ends_at, timezone);
+        }
+    }
+    Ok(Json(periods))
+}
+
+#[delete("/agent/maintenance/windows/<window_id>")]
+pub async fn delete_maintenance_window(window_id: String, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<String, AgentError> {
+    let removed = {
+        let mut book = app_manager.schedules.lock().unwrap();
+        let before = book.windows.len();
+        book.windows.retain(|window| window.id != window_id);
+        book.windows.len() != before
+    };
+
+    if !removed {
+        return Err(AgentError::NotFound(format!("Maintenance window {} not found", window_id)));
+    }
+    app_manager.persist_schedules();
+    Ok(format!("Maintenance window {} deleted successfully", window_id))
+}
@@ -0,0 +1,82 @@
+use rocket::get;
+use rocket::http::ContentType;
+use rocket::State;
+use std::collections::HashMap;
+use crate::routes::agent_routes::system_resources;
+use crate::routes::errors::AgentError;
+use crate::routes::app_manager::AppManager;
+
+// Metrics
+
+#[get("/metrics")]
+pub async fn get_metrics(app_manager: &State<AppManager>) -> Result<(ContentType, String), AgentError> {
+    let metrics = &app_manager.metrics;
+    
+    // Refresh per-status instance counts from the container cache
+    match app_manager.cache.containers(app_manager).await {
+        Ok(containers) => {
+            let mut counts: HashMap<String, i64> = HashMap::new();
+            for container in containers.items {
+                *counts.entry(container.state.unwrap_or_else(|| "unknown".to_string())).or_default() += 1;
+            }
+            
+            metrics.instances.reset();
+            for (status, count) in counts {
+                metrics.instances.with_label_values(&[status.as_str()]).set(count);
+            }
+        },
+        Err(e) => {
+            tracing::warn!("Failed to list containers for metrics: {}", e);
+        }
+    }
+    
+    let resources = system_resources();
+    let host = &metrics.host_resources;
+    host.with_label_values(&["cpu_count"]).set(resources.cpu_count as f64);
+    host.with_label_values(&["memory_total_bytes"]).set(resources.memory_total as f64);
+    host.with_label_values(&["memory_available_bytes"]).set(resources.memory_available as f64);
+    host.with_label_values(&["disk_total_bytes"]).set(resources.disk_total as f64);
+    host.with_label_values(&["disk_available_bytes"]).set(resources.disk_available as f64);
+    
+    match metrics.render() {
+        // text/plain with the exposition format version, as scrapers expect
+        Ok(body) => Ok((ContentType::parse_flexible(prometheus::TEXT_FORMAT).unwrap_or(ContentType::Plain), body)),
+        Err(e) => Err(AgentError::Internal(format!("Failed to encode metrics: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use prometheus_parse::{Scrape, Value};
+    use serde_json::json;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    #[tokio::test]
+    async fn serves_the_text_exposition_format() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("GET", "/containers/json", |_| DockerResponse::ok(json!([
+            { "Id": "ab12", "Names": ["/game-1"], "State": "running" },
+            { "Id": "cd34", "Names": ["/game-2"], "State": "running" },
+            { "Id": "ef56", "Names": ["/game-3"], "State": "exited" },
+        ])));
+        agent.docker.route("GET", "/containers/gone/json", |_| DockerResponse::error(404, "No such container: gone"));
+        let client = agent.client().await;
+        assert_eq!(client.get("/instances/gone").dispatch().await.status(), Status::NotFound);
+
+        let response = client.get("/metrics").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Content-Type"), Some(prometheus::TEXT_FORMAT));
+        let body = response.into_string().await.unwrap();
+        let scrape = Scrape::parse(body.lines().map(|line| Ok(line.to_string()))).unwrap();
+
+        let sample = |metric: &str, label: &str, value: &str| scrape.samples.iter()
+            .find(|sample| sample.metric == metric && sample.labels.get(label) == Some(value))
+            .map(|sample| sample.value.clone());
+        assert!(matches!(sample("maestro_instances", "status", "running"), Some(Value::Gauge(count)) if count == 2.0));
+        assert!(matches!(sample("maestro_instances", "status", "exited"), Some(Value::Gauge(count)) if count == 1.0));
+        assert!(matches!(sample("maestro_docker_errors_total", "operation", "inspect_container"), Some(Value::Counter(count)) if count == 1.0));
+        assert!(matches!(sample("maestro_docker_request_duration_seconds", "operation", "list_containers"), Some(Value::Histogram(_))));
+        assert!(matches!(sample("maestro_host_resources", "resource", "cpu_count"), Some(Value::Gauge(count)) if count >= 1.0));
+    }
+}
@@ -0,0 +1,438 @@
+use rocket::{get, post};
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::HashMap;
+use crate::agent::Agent;
+use crate::compression;
+use crate::crypto::{self, Sealer};
+use crate::labels;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::instance_routes::{delete_instance, launch_instance};
+use crate::routes::models::{AgentBundle, AppInstanceRequest, ImportItem, ImportReport, InstanceDefinition, RegistryCredentialRequest, RegistryDefinition, ScheduleDefinition, ScheduleRequest, SecretValue};
+use crate::routes::schedule_routes::create_schedule;
+
+const BUNDLE_VERSION: u32 = 1;
+
+/// Header carrying the base64 key that secrets are re-sealed under in a bundle
+pub const BUNDLE_KEY_HEADER: &str = "X-Maestro-Bundle-Key";
+
+/// Name of the data limit (server.import_limit) applied to imported bundles instead of "json"
+pub const BUNDLE_LIMIT: &str = "bundle";
+
+/// An AgentBundle request body, read under the bundle limit since exports of busy agents
+/// easily outgrow the limit for ordinary JSON bodies. It may be sent gzipped.
+pub struct BundleJson(AgentBundle);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for BundleJson {
+    type Error = String;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = request.limits().get(BUNDLE_LIMIT).unwrap_or(Limits::JSON);
+        let body = match compression::read_body(request, data, limit).await {
+            Ok(body) => body,
+            Err(error) => return data::Outcome::Error(error),
+        };
+        match serde_json::from_slice(&body) {
+            Ok(bundle) => data::Outcome::Success(BundleJson(bundle)),
+            Err(e) => data::Outcome::Error((Status::UnprocessableEntity, format!("Invalid bundle: {}", e))),
+        }
+    }
+}
+
+/// Optional bundle key from the X-Maestro-Bundle-Key header. It's a header rather than a
+/// query parameter so it doesn't end up in request logs.
+pub struct BundleKey(Option<Sealer>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BundleKey {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one(BUNDLE_KEY_HEADER) {
+            None => Outcome::Success(BundleKey(None)),
+            Some(key) => match crypto::decode_key(key) {
+                Ok(_) => Outcome::Success(BundleKey(Sealer::from_key(Some(key)))),
+                Err(e) => Outcome::Error((Status::BadRequest, format!("Invalid {}: {}", BUNDLE_KEY_HEADER, e))),
+            },
+        }
+    }
+}
+
+fn import_item(kind: &str, name: &str, outcome: &str, message: Option<String>) -> ImportItem {
+    ImportItem {
+        kind: kind.to_string(),
+        name: name.to_string(),
+        outcome: outcome.to_string(),
+        message,
+    }
+}
+
+/// Exports the instances, schedules and registry credentials managed by this agent.
+/// Secret values and registry passwords are only included, re-sealed under the bundle
+/// key, when one is provided in the X-Maestro-Bundle-Key header.
+#[get("/agent/export")]
+pub async fn export_state(agent: &State<Agent>, app_manager: &State<AppManager>, bundle_key: BundleKey, _auth: ApiToken) -> Result<Json<AgentBundle>, AgentError> {
+    let reseal = |value: &str| match &bundle_key.0 {
+        Some(sealer) => sealer.seal(value)
+            .map(Some)
+            .map_err(|e| AgentError::Internal(format!("Failed to seal bundle value: {}", e))),
+        None => Ok(None),
+    };
+
+    let mut tracked: Vec<_> = app_manager.instances();
+    tracked.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    let mut instances = Vec::new();
+    for instance in tracked {
+        let values = app_manager.secrets.get(&instance.id);
+        let mut secrets = HashMap::new();
+        for name in instance.secrets.keys() {
+            let sealed = match values.get(name) {
+                Some(value) => reseal(value.expose())?,
+                None => None,
+            };
+            secrets.insert(name.clone(), sealed);
+        }
+
+        instances.push(InstanceDefinition {
+            name: instance.name,
+            image: instance.image,
+            ports: instance.ports,
+            environment: instance.environment,
+            volumes: instance.volumes,
+            supervise: instance.supervision.is_some(),
+            secrets,
+            ship_logs: instance.ship_logs,
+            deployment: instance.labels.get(labels::DEPLOYMENT).cloned(),
+            project: instance.labels.get(labels::PROJECT).cloned(),
+        });
+    }
+
+    let schedules = app_manager.schedules.lock().unwrap().schedules.iter()
+        .map(|schedule| ScheduleDefinition {
+            instance_name: schedule.instance_name.clone(),
+            cron: schedule.cron.clone(),
+            action: schedule.action.clone(),
+            timezone: schedule.timezone.clone(),
+        })
+        .collect();
+
+    let mut registries = Vec::new();
+    for (info, password) in app_manager.registries.export() {
+        registries.push(RegistryDefinition {
+            server: info.server,
+            username: info.username,
+            password: match password {
+                Some(password) => reseal(&password)?,
+                None => None,
+            },
+        });
+    }
+
+    Ok(Json(AgentBundle {
+        version: BUNDLE_VERSION,
+        exported_at: app_manager.now().to_rfc3339(),
+        agent_id: agent.id().to_string(),
+        instances,
+        schedules,
+        registries,
+    }))
+}
+
+/// Recreates a bundle on this agent: registries first so image pulls can authenticate,
+/// then instances in bundle (creation) order, then their schedules. Existing instances
+/// with the same name are skipped unless `on_conflict=overwrite`.
+#[post("/agent/import?<on_conflict>", format = "json", data = "<bundle>")]
+pub async fn import_state(on_conflict: Option<String>, bundle: BundleJson, app_manager: &State<AppManager>, bundle_key: BundleKey, auth: ApiToken, _docker: DockerAvailable, bypass: ImagePolicyBypass) -> Result<Json<ImportReport>, AgentError> {
+    let overwrite = match on_conflict.as_deref() {
+        None | Some("skip") => false,
+        Some("overwrite") => true,
+        Some(other) => return Err(AgentError::BadRequest(format!("Unknown on_conflict '{}', expected skip or overwrite", other))),
+    };
+    let BundleJson(bundle) = bundle;
+    if bundle.version != BUNDLE_VERSION {
+        return Err(AgentError::BadRequest(format!("Unsupported bundle version {}", bundle.version)));
+    }
+
+    let open = |sealed: &Option<crypto::Sealed>| -> Result<Option<String>, String> {
+        match (sealed, &bundle_key.0) {
+            (None, _) => Ok(None),
+            (Some(_), None) => Err(format!("bundle contains sealed values but no {} was provided", BUNDLE_KEY_HEADER)),
+            (Some(sealed), Some(sealer)) => sealer.open(sealed)
+                .map(Some)
+                .ok_or_else(|| format!("{} doesn't match the key the bundle was exported with", BUNDLE_KEY_HEADER)),
+        }
+    };
+
+    let mut items = Vec::new();
+
+    let existing_registries: Vec<String> = app_manager.registries.list().into_iter().map(|info| info.server).collect();
+    for registry in &bundle.registries {
+        if existing_registries.contains(&registry.server) && !overwrite {
+            items.push(import_item("registry", &registry.server, "skipped", Some("already configured".to_string())));
+            continue;
+        }
+        let password = match open(&registry.password) {
+            Ok(Some(password)) => password,
+            Ok(None) => {
+                items.push(import_item("registry", &registry.server, "skipped", Some("exported without its password".to_string())));
+                continue;
+            },
+            Err(e) => {
+                items.push(import_item("registry", &registry.server, "failed", Some(e)));
+                continue;
+            }
+        };
+        let request = RegistryCredentialRequest {
+            server: registry.server.clone(),
+            username: registry.username.clone(),
+            password,
+        };
+        let outcome = if existing_registries.contains(&registry.server) { "overwritten" } else { "created" };
+        match app_manager.registries.upsert(&request) {
+            Ok(_) => items.push(import_item("registry", &registry.server, outcome, None)),
+            Err(e) => items.push(import_item("registry", &registry.server, "failed", Some(e.to_string()))),
+        }
+    }
+
+    for definition in &bundle.instances {
+        let mut secrets = HashMap::new();
+        let mut missing = Vec::new();
+        let mut failure = None;
+        for (name, sealed) in &definition.secrets {
+            match open(sealed) {
+                Ok(Some(value)) => {
+                    secrets.insert(name.clone(), SecretValue::new(value));
+                },
+                Ok(None) => missing.push(name.clone()),
+                Err(e) => failure = Some(e),
+            }
+        }
+        if let Some(e) = failure {
+            items.push(import_item("instance", &definition.name, "failed", Some(e)));
+            continue;
+        }
+
+        // Before anything is overwritten, so a rejected image leaves the existing instance alone
+        if let Err(e) = app_manager.image_policy.check(&definition.image, bypass.0) {
+            items.push(import_item("instance", &definition.name, "failed", Some(e.to_string())));
+            continue;
+        }
+
+        let exists = app_manager.docker().inspect_container(&definition.name, None).await.is_ok();
+        if exists && !overwrite {
+            items.push(import_item("instance", &definition.name, "skipped", Some("an instance with this name already exists".to_string())));
+            continue;
+        }
+        if exists {
+            if let Err(e) = delete_instance(definition.name.clone(), app_manager, ApiToken(auth.0.clone()), DockerAvailable).await {
+                items.push(import_item("instance", &definition.name, "failed", Some(e.to_string())));
+                continue;
+            }
+        }
+
+        let app_req = AppInstanceRequest {
+            name: definition.name.clone(),
+            image: definition.image.clone(),
+            ports: Some(definition.ports.clone()),
+            environment: Some(definition.environment.clone()),
+            volumes: Some(definition.volumes.clone()),
+            supervise: Some(definition.supervise),
+            secrets: Some(secrets),
+            ship_logs: Some(definition.ship_logs),
+            deployment: definition.deployment.clone(),
+            project: definition.project.clone(),
+        };
+        let message = (!missing.is_empty()).then(|| {
+            missing.sort();
+            format!("secrets exported without values: {}", missing.join(", "))
+        });
+        match launch_instance(app_req, app_manager, &auth.0).await {
+            Ok(_) => items.push(import_item("instance", &definition.name, if exists { "overwritten" } else { "created" }, message)),
+            Err(e) => items.push(import_item("instance", &definition.name, "failed", Some(e.to_string()))),
+        }
+    }
+
+    for definition in &bundle.schedules {
+        let label = format!("{} {} ({})", definition.action, definition.instance_name, definition.cron);
+        let duplicate = app_manager.schedules.lock().unwrap().schedules.iter()
+            .any(|schedule| schedule.instance_name == definition.instance_name
+                && schedule.cron == definition.cron
+                && schedule.action == definition.action
+                && schedule.timezone == definition.timezone);
+        if duplicate {
+            items.push(import_item("schedule", &label, "skipped", Some("an identical schedule already exists".to_string())));
+            continue;
+        }
+
+        let schedule_req = ScheduleRequest {
+            cron: definition.cron.clone(),
+            action: definition.action.clone(),
+            timezone: definition.timezone.clone(),
+        };
+        match create_schedule(definition.instance_name.clone(), Json(schedule_req), app_manager, ApiToken(auth.0.clone())).await {
+            Ok(_) => items.push(import_item("schedule", &label, "created", None)),
+            Err(e) => items.push(import_item("schedule", &label, "failed", Some(e.to_string()))),
+        }
+    }
+
+    Ok(Json(ImportReport {
+        items,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use rocket::http::{ContentType, Header};
+    use serde_json::{json, Value};
+    use crate::routes::models::{AppInstance, Schedule};
+    use crate::secrets;
+    use crate::testing::{instance, DockerResponse, TestAgent};
+
+    // Each agent's own key, and the one bundles are sealed under on the way between them
+    const SOURCE_KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const TARGET_KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+    const BUNDLE_KEY: &str = "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=";
+
+    // An export, sealed under BUNDLE_KEY, of an agent running "game" with a secret, a nightly
+    // restart and a registry login
+    async fn exported_bundle() -> Value {
+        let source = TestAgent::with_config(|config| config.encryption_key = Some(SOURCE_KEY.to_string())).await;
+        let app_manager = &source.app_manager;
+        let token = HashMap::from([("TOKEN".to_string(), SecretValue::new("hunter2".to_string()))]);
+        app_manager.upsert_instance(AppInstance {
+            secrets: secrets::masked(token.keys()),
+            ..instance("source-id", "game")
+        });
+        app_manager.secrets.set("source-id", &token).unwrap();
+        app_manager.registries.upsert(&RegistryCredentialRequest {
+            server: "ghcr.io/my-org".to_string(),
+            username: "deployer".to_string(),
+            password: "registry-password".to_string(),
+        }).unwrap();
+        app_manager.schedules.lock().unwrap().schedules.push(Schedule {
+            id: "nightly".to_string(),
+            instance_id: "source-id".to_string(),
+            instance_name: "game".to_string(),
+            cron: "0 0 4 * * *".to_string(),
+            action: "restart".to_string(),
+            timezone: None,
+            created_at: app_manager.now().to_rfc3339(),
+            next_run_at: None,
+            next_run_at_local: None,
+        });
+
+        let client = source.client().await;
+        let response = client.get("/agent/export").header(Header::new(BUNDLE_KEY_HEADER, BUNDLE_KEY)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().await.unwrap()
+    }
+
+    // An agent with its own key on a daemon where "game" exists once `exists` is set, as creating it does
+    async fn target(exists: bool) -> (TestAgent, Arc<AtomicBool>) {
+        let target = TestAgent::with_config(|config| config.encryption_key = Some(TARGET_KEY.to_string())).await;
+        let exists = Arc::new(AtomicBool::new(exists));
+        let docker = &target.docker;
+        let game = exists.clone();
+        docker.route("GET", "/containers/game/json", move |_| match game.load(Ordering::SeqCst) {
+            true => DockerResponse::ok(json!({ "Id": "target-id", "Name": "/game" })),
+            false => DockerResponse::error(404, "No such container: game"),
+        });
+        docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({})));
+        let game = exists.clone();
+        docker.route("POST", "/containers/create", move |_| {
+            game.store(true, Ordering::SeqCst);
+            DockerResponse::Json(201, json!({ "Id": "target-id", "Warnings": [] }))
+        });
+        docker.route("POST", "/containers/target-id/start", |_| DockerResponse::ok(json!({})));
+        docker.route("DELETE", "/containers/target-id", |_| DockerResponse::ok(json!({})));
+        (target, exists)
+    }
+
+    async fn import(target: &TestAgent, bundle: &Value, query: &str, bundle_key: &str) -> Vec<(String, String)> {
+        let client = target.client().await;
+        let response = client.post(format!("/agent/import{}", query))
+            .header(ContentType::JSON)
+            .header(Header::new(BUNDLE_KEY_HEADER, bundle_key.to_string()))
+            .body(bundle.to_string())
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let report: Value = response.into_json().await.unwrap();
+        report["items"].as_array().unwrap().iter()
+            .map(|item| (format!("{} {}", item["kind"].as_str().unwrap(), item["name"].as_str().unwrap()), item["outcome"].as_str().unwrap().to_string()))
+            .collect()
+    }
+
+    fn outcomes(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(name, outcome)| (name.to_string(), outcome.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn bundles_move_between_agents_with_different_keys() {
+        let bundle = exported_bundle().await;
+        assert!(!bundle.to_string().contains("hunter2") && !bundle.to_string().contains("registry-password"));
+        let (target, _) = target(false).await;
+
+        let items = import(&target, &bundle, "", BUNDLE_KEY).await;
+        assert_eq!(items, outcomes(&[
+            ("registry ghcr.io/my-org", "created"),
+            ("instance game", "created"),
+            ("schedule restart game (0 0 4 * * *)", "created"),
+        ]));
+
+        let created = target.docker.requests_to("POST", "/containers/create");
+        assert!(created[0].json()["Env"].as_array().unwrap().contains(&json!("TOKEN=hunter2")));
+        assert_eq!(target.app_manager.secrets.get("target-id")["TOKEN"].expose(), "hunter2");
+        let credentials = target.app_manager.registries.credentials_for("ghcr.io/my-org/game:1").unwrap();
+        assert_eq!(credentials.password.as_deref(), Some("registry-password"));
+        assert_eq!(target.app_manager.schedules.lock().unwrap().schedules[0].instance_id, "target-id");
+        // Re-sealed under the target's own key
+        let stored = std::fs::read_to_string(target.data_dir.path().join("secrets.json")).unwrap();
+        assert!(!stored.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn conflicts_are_skipped_unless_overwritten() {
+        let bundle = exported_bundle().await;
+        let (target, _) = target(true).await;
+
+        let items = import(&target, &bundle, "?on_conflict=skip", BUNDLE_KEY).await;
+        assert_eq!(items[1], ("instance game".to_string(), "skipped".to_string()));
+        assert!(target.docker.requests_to("POST", "/containers/create").is_empty());
+        assert!(target.docker.requests_to("DELETE", "/containers/target-id").is_empty());
+
+        let items = import(&target, &bundle, "?on_conflict=overwrite", BUNDLE_KEY).await;
+        assert_eq!(items, outcomes(&[
+            ("registry ghcr.io/my-org", "overwritten"),
+            ("instance game", "overwritten"),
+            // The one the first import added went with the overwritten container
+            ("schedule restart game (0 0 4 * * *)", "created"),
+        ]));
+        assert_eq!(target.docker.requests_to("DELETE", "/containers/target-id").len(), 1);
+        assert_eq!(target.docker.requests_to("POST", "/containers/create").len(), 1);
+        assert_eq!(target.app_manager.schedules.lock().unwrap().schedules.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn bundles_sealed_under_another_key_are_rejected() {
+        let bundle = exported_bundle().await;
+        let (target, _) = target(false).await;
+
+        let items = import(&target, &bundle, "", SOURCE_KEY).await;
+        assert_eq!(items[..2], outcomes(&[
+            ("registry ghcr.io/my-org", "failed"),
+            ("instance game", "failed"),
+        ]));
+        assert!(target.docker.requests_to("POST", "/containers/create").is_empty());
+        assert!(target.app_manager.registries.list().is_empty());
+    }
+}
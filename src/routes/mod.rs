@@ -2,8 +2,30 @@ pub mod index;
 pub mod instances;
 pub mod models;
 pub mod app_manager;
+pub mod auth;
+pub mod catchers;
+pub mod errors;
 pub mod instance_routes;
+pub mod replace_routes;
+pub mod schedule_routes;
+pub mod secret_routes;
 pub mod volume_routes;
 pub mod network_routes;
 pub mod image_routes;
-pub mod agent_routes;
\ No newline at end of file
+pub mod registry_routes;
+pub mod agent_routes;
+pub mod metrics_routes;
+pub mod migration_routes;
+pub mod maintenance_routes;
+pub mod adopt_routes;
+pub mod tunnel_routes;
+pub mod snapshot_routes;
+pub mod stats_routes;
+pub mod crash_routes;
+pub mod state_routes;
+pub mod policy_routes;
+pub mod attach_routes;
+pub mod timeline_routes;
+pub mod label_routes;
+#[cfg(feature = "chaos")]
+pub mod chaos_routes;
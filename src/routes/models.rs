@@ -1,5 +1,10 @@
-use rocket::serde::{Serialize, Deserialize};
+use rocket::serde::{Serialize, Serializer, Deserialize};
 use std::collections::HashMap;
+use std::fmt;
+use crate::crypto::Sealed;
+
+/// Placeholder rendered in place of every secret value
+pub const MASKED_SECRET: &str = "***";
 
 // Data structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,6 +18,32 @@ pub struct AppInstance {
     pub environment: HashMap<String, String>,
     pub volumes: Vec<VolumeMapping>,
     pub agent_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supervision: Option<SupervisionState>,
+    /// Names of the injected secrets, each mapped to MASKED_SECRET
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secrets: HashMap<String, String>,
+    /// Whether the container's output is forwarded to log_shipping.url
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ship_logs: bool,
+    /// The container's maestro.* labels. Instances created before labeling only have them here,
+    /// not on the container, if they were migrated without being recreated.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+    /// Latest timeline events, only filled in by GET /instances/<id>
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recent_events: Vec<InstanceEvent>,
+}
+
+/// Restart bookkeeping for instances created with `supervise: true`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisionState {
+    /// "running", "backoff", "stopped" (stopped through the API) or "failed"
+    pub state: String,
+    pub attempts: u32,
+    pub next_retry_at: Option<String>,
+    pub last_started_at: Option<String>,
+    pub last_exit_code: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +67,78 @@ pub struct AppInstanceRequest {
     pub ports: Option<Vec<PortMapping>>,
     pub environment: Option<HashMap<String, String>>,
     pub volumes: Option<Vec<VolumeMapping>>,
+    pub supervise: Option<bool>,
+    /// Environment variables whose values are stored encrypted and never echoed back
+    pub secrets: Option<HashMap<String, SecretValue>>,
+    /// Forward the container's output to log_shipping.url
+    pub ship_logs: Option<bool>,
+    /// Recorded in the maestro.deployment label
+    pub deployment: Option<String>,
+    /// Recorded in the maestro.project label
+    pub project: Option<String>,
+}
+
+/// A secret accepted from a client. It can be read with `expose` but serializes
+/// and debug-prints as MASKED_SECRET so it can't leak through responses or logs.
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde", transparent)]
+pub struct SecretValue(String);
+
+impl SecretValue {
+    pub fn new(value: String) -> Self {
+        SecretValue(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASKED_SECRET)
+    }
+}
+
+impl Serialize for SecretValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(MASKED_SECRET)
+    }
+}
+
+#[derive(Debug, Clone, rocket::serde::Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SecretsUpdateRequest {
+    /// New values by name; null removes a secret
+    pub secrets: HashMap<String, Option<SecretValue>>,
+    /// Recreate the container so the new values take effect now rather than on its next replacement
+    #[serde(default)]
+    pub restart: bool,
+}
+
+#[derive(Debug, Clone, rocket::FromForm)]
+pub struct InstanceQuery {
+    pub status: Option<String>,
+    pub image: Option<String>,
+    pub name_contains: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceList {
+    pub items: Vec<AppInstance>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceCount {
+    pub status: Option<String>,
+    pub count: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +187,48 @@ pub struct AgentInfo {
     pub instance_count: usize,
     pub status: String,
     pub resources: SystemResources,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<MaintenanceState>,
+    pub build: BuildInfo,
+    /// Set when the API is served over HTTPS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsInfo {
+    /// When the server certificate expires
+    pub expires_at: String,
+    /// Negative once it has expired
+    pub days_remaining: i64,
+    /// Whether client certificates are "required", "optional" or not checked ("none")
+    pub client_auth: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_commit: String,
+    pub build_date: String,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceRequest {
+    /// Shown to clients whose requests are refused during maintenance
+    pub message: Option<String>,
+    /// RFC 3339 time at which maintenance ends on its own
+    pub ends_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceState {
+    pub message: String,
+    pub started_at: String,
+    pub ends_at: Option<String>,
+    /// Scheduled windows this maintenance comes from; empty when it was entered manually
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub window_ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,4 +238,449 @@ pub struct SystemResources {
     pub memory_available: u64,
     pub disk_total: u64,
     pub disk_available: u64,
-}
\ No newline at end of file
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    pub code: String,
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplaceReport {
+    pub instance: AppInstance,
+    pub replaced_id: String,
+    /// "blue_green" when both containers could run side by side, "cutover" when they share host ports
+    pub strategy: String,
+    pub phases: Vec<PhaseTiming>,
+    pub total_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRequest {
+    /// Standard 5-field cron expression, or 6 fields with leading seconds
+    pub cron: String,
+    /// "restart", "stop" or "start"
+    pub action: String,
+    /// IANA time zone the cron expression is evaluated in, e.g. "America/New_York"; UTC when unset
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: String,
+    pub instance_id: String,
+    pub instance_name: String,
+    pub cron: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    pub created_at: String,
+    pub next_run_at: Option<String>,
+    /// next_run_at in the zone asked for with ?tz=
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run_at_local: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleExecution {
+    pub schedule_id: String,
+    pub action: String,
+    pub started_at: String,
+    pub finished_at: String,
+    /// "success", "failed" or "skipped"
+    pub outcome: String,
+    pub message: Option<String>,
+    /// Maintenance window that was active when the action ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<String>,
+    /// started_at and finished_at in the zone asked for with ?tz=
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at_local: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at_local: Option<String>,
+}
+
+/// Schedules, their per-instance execution history and maintenance windows, persisted as schedules.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleBook {
+    pub schedules: Vec<Schedule>,
+    pub history: HashMap<String, Vec<ScheduleExecution>>,
+    #[serde(default)]
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindowRequest {
+    /// When the window opens, as a cron expression evaluated in `timezone`
+    pub cron: String,
+    pub duration_secs: u64,
+    pub message: Option<String>,
+    /// IANA time zone the cron expression is evaluated in; UTC when unset
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub id: String,
+    pub cron: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    pub duration_secs: u64,
+    pub message: Option<String>,
+    pub created_at: String,
+}
+
+/// One occurrence of maintenance, with overlapping window occurrences merged into a single period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenancePeriod {
+    pub window_ids: Vec<String>,
+    pub starts_at: String,
+    pub ends_at: String,
+    pub message: Option<String>,
+    /// starts_at and ends_at in the zone asked for with ?tz=
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at_local: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at_local: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdoptRequest {
+    /// Glob over container names, e.g. "game-*"
+    pub name_pattern: Option<String>,
+    /// Docker label filter, "key" or "key=value"
+    pub label: Option<String>,
+    /// Report what would be adopted without tracking anything
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub supervise: bool,
+    /// Record the management labels for adopted containers that don't carry them. Docker can't
+    /// relabel a running container, so like /instances/labels/migrate without `recreate` they're
+    /// only recorded by the agent until the instance is recreated.
+    #[serde(default)]
+    pub apply_labels: bool,
+}
+
+/// Labels tracked instances created before the agent labeled its containers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LabelMigrationRequest {
+    /// Recreate each instance through the replace flow so its container carries the labels.
+    /// Otherwise the labels are only recorded by the agent, and `docker ps` doesn't show them.
+    #[serde(default)]
+    pub recreate: bool,
+    /// Report what would be migrated without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelRequest {
+    /// Port inside the container to forward to
+    pub container_port: u16,
+    pub ttl_secs: Option<u64>,
+    pub max_connections: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelInfo {
+    pub id: String,
+    pub instance_id: String,
+    pub instance_name: String,
+    /// Address on the agent host to connect to
+    pub address: String,
+    pub target: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub max_connections: u32,
+    pub active_connections: u32,
+    pub total_connections: u64,
+    /// Bytes sent from tunnel clients to the container
+    pub bytes_in: u64,
+    /// Bytes sent from the container back to tunnel clients
+    pub bytes_out: u64,
+}
+
+/// An interactive session opened through /instances/<id>/attach. Only its size is recorded,
+/// never what was typed or printed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachSession {
+    pub id: String,
+    pub instance_id: String,
+    pub instance_name: String,
+    pub command: Vec<String>,
+    /// Id of the API token that opened the session, None on an open API
+    pub token_id: Option<String>,
+    pub started_at: String,
+    pub ended_at: String,
+    /// Why the session ended: "closed", "exited", "idle_timeout", "max_duration" or "error"
+    pub reason: String,
+    /// Bytes typed into the session
+    pub bytes_in: u64,
+    /// Bytes the session printed
+    pub bytes_out: u64,
+}
+
+/// A committed image of an instance's filesystem, with what's needed to launch clones of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub instance_id: String,
+    pub instance_name: String,
+    /// Image reference the snapshot was committed as, e.g. "maestro-snapshot/game-01:20250101-120000"
+    pub image: String,
+    pub image_id: String,
+    pub size_bytes: i64,
+    pub created_at: String,
+    pub ports: Vec<PortMapping>,
+    pub environment: HashMap<String, String>,
+    pub volumes: Vec<VolumeMapping>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotCloneRequest {
+    /// Name of the new instance, "<instance>-clone-<suffix>" by default
+    pub name: Option<String>,
+    /// Mount the source instance's volumes too. Off by default so a clone can't write into live data.
+    #[serde(default)]
+    pub keep_volumes: bool,
+}
+
+/// Averaged resource usage over one resolution step of an instance's stats history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsPoint {
+    /// Start of the step
+    pub timestamp: String,
+    pub cpu_percent: f64,
+    pub memory_bytes: u64,
+    /// Bytes received and sent during the step, summed over every network
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Samples folded into this point
+    pub samples: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsHistory {
+    pub instance_id: String,
+    pub window_secs: u64,
+    pub resolution_secs: u64,
+    pub points: Vec<StatsPoint>,
+}
+
+/// What was known about a supervised instance when it crashed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashReport {
+    pub id: String,
+    pub instance_id: String,
+    pub instance_name: String,
+    pub image: String,
+    pub exit_code: Option<i64>,
+    /// Whether the kernel killed the container for running out of memory
+    pub oom_killed: bool,
+    /// Error the daemon recorded for the container, if any
+    pub error: Option<String>,
+    pub finished_at: Option<String>,
+    pub captured_at: String,
+    /// Restart attempt the supervisor scheduled for this crash, or the attempts it made if it gave up
+    pub restart_attempt: u32,
+    /// Last lines of output before the crash
+    pub last_logs: Vec<String>,
+    /// Most recent stats history sample, when stats history is enabled
+    pub last_stats: Option<StatsPoint>,
+}
+
+/// A state transition of an instance, as recorded in its timeline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceEvent {
+    pub instance_id: String,
+    pub instance_name: String,
+    /// "created", "started", "stopped", "restarted", "crashed", "failed", "drifted",
+    /// "replaced", "adopted" or "deleted"
+    pub event: String,
+    /// Who caused it: "token:<id>", "api" when no tokens are configured, "supervisor",
+    /// "reconciler" or "scheduler"
+    pub actor: String,
+    pub at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// The instances this agent should be running, converged to by PUT /state
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DesiredState {
+    pub instances: Vec<AppInstanceRequest>,
+    /// Names of tracked instances that are left alone rather than removed when they're not in `instances`
+    #[serde(default)]
+    pub protected: Vec<String>,
+}
+
+/// One step of converging on the desired state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateChange {
+    /// "create", "replace", "remove" or "keep" for protected instances outside the document
+    pub action: String,
+    pub name: String,
+    pub instance_id: Option<String>,
+    /// How a tracked instance differs from its desired definition
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub differences: Vec<String>,
+    /// "planned", "applied", "failed" or "skipped" for protected instances
+    pub outcome: String,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatePlan {
+    pub changes: Vec<StateChange>,
+    pub computed_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryCredentialRequest {
+    /// Registry host, optionally with a path prefix (e.g. "ghcr.io/my-org")
+    pub server: String,
+    pub username: String,
+    /// Password or access token
+    pub password: String,
+}
+
+/// A stored registry credential as listed by the API; the secret is never returned
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryInfo {
+    pub server: String,
+    pub username: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagePullRequest {
+    pub image: String,
+}
+
+/// Rules every image has to pass before it's pulled or run; empty lists don't restrict anything
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagePolicy {
+    /// Registries images may come from, optionally with a path prefix (e.g. "ghcr.io/my-org");
+    /// Docker Hub is "docker.io"
+    pub allowed_registries: Vec<String>,
+    /// Tags that may not be run. An image without a tag runs as latest, so denying "latest"
+    /// denies those too, while "" denies only images without a tag. Not checked for images
+    /// pinned by digest.
+    pub denied_tags: Vec<String>,
+    /// Whether images must be pinned with @sha256:<digest>
+    pub require_digest: bool,
+    /// Patterns ('*' and '?' wildcards) the image name without tag or digest has to match,
+    /// either as written or fully qualified (e.g. "docker.io/library/nginx")
+    pub allowed_images: Vec<String>,
+}
+
+/// Portable snapshot of everything this agent manages, produced by GET /agent/export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentBundle {
+    pub version: u32,
+    pub exported_at: String,
+    pub agent_id: String,
+    pub instances: Vec<InstanceDefinition>,
+    pub schedules: Vec<ScheduleDefinition>,
+    pub registries: Vec<RegistryDefinition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceDefinition {
+    pub name: String,
+    pub image: String,
+    pub ports: Vec<PortMapping>,
+    pub environment: HashMap<String, String>,
+    pub volumes: Vec<VolumeMapping>,
+    pub supervise: bool,
+    /// Secret values sealed under the export key, or null when exported without one
+    #[serde(default)]
+    pub secrets: HashMap<String, Option<Sealed>>,
+    #[serde(default)]
+    pub ship_logs: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleDefinition {
+    pub instance_name: String,
+    pub cron: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryDefinition {
+    pub server: String,
+    pub username: String,
+    /// Password sealed under the export key, or null when exported without one
+    pub password: Option<Sealed>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub items: Vec<ImportItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportItem {
+    /// "registry", "instance" or "schedule"
+    pub kind: String,
+    pub name: String,
+    /// "created", "overwritten", "skipped" or "failed"
+    pub outcome: String,
+    pub message: Option<String>,
+}
+
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChaosRequest {
+    /// "docker_delay", "docker_error", "drop_event" or "panic"
+    pub kind: String,
+    /// Docker operation (e.g. "create_container") for the docker_ kinds, where unset means every
+    /// operation; component ("supervisor", "scheduler", "docker_cache", "log_shipper") otherwise
+    pub target: Option<String>,
+    /// Chance of each eligible call or event being hit, 1.0 when unset
+    pub probability: Option<f64>,
+    /// Stop after this many hits
+    pub count: Option<u32>,
+    /// Delay added by docker_delay
+    pub delay_ms: Option<u64>,
+    pub duration_secs: u64,
+}
+
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChaosInjection {
+    pub id: String,
+    pub kind: String,
+    pub target: Option<String>,
+    pub probability: f64,
+    /// Hits left before the injection is spent, or null for no limit
+    pub remaining: Option<u32>,
+    pub delay_ms: Option<u64>,
+    pub hits: u64,
+    pub created_at: String,
+    pub expires_at: String,
+}
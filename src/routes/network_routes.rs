@@ -2,16 +2,19 @@ use rocket::{delete, get, post, put};
 use rocket::serde::json::Json;
 use rocket::State;
 use std::collections::HashMap;
-use crate::routes::app_manager::AppManager;
+use crate::docker_cache::Listing;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::{AppManager, DockerAvailable};
 use crate::routes::models::{NetworkInfo, NetworkCreateRequest, NetworkContainerInfo};
 
 // Network Management
 
 #[get("/networks")]
-pub async fn list_networks(app_manager: &State<AppManager>) -> Result<Json<Vec<NetworkInfo>>, String> {
-    match app_manager.docker.list_networks::<String>(None).await {
-        Ok(networks) => {
-            let network_list = networks.into_iter()
+pub async fn list_networks(app_manager: &State<AppManager>) -> Result<Listing<Vec<NetworkInfo>>, AgentError> {
+    match app_manager.cache.networks(app_manager).await {
+        Ok(networks) => Ok(networks.map(|networks| {
+            networks.into_iter()
                 .filter_map(|net| {
                     let id = net.id?;
                     let name = net.name?;
@@ -40,16 +43,14 @@ pub async fn list_networks(app_manager: &State<AppManager>) -> Result<Json<Vec<N
                         containers,
                     })
                 })
-                .collect();
-            
-            Ok(Json(network_list))
-        },
-        Err(e) => Err(format!("Failed to list networks: {}", e))
+                .collect()
+        })),
+        Err(e) => Err(AgentError::docker("Failed to list networks", e))
     }
 }
 
 #[post("/networks", format = "json", data = "<network_req>")]
-pub async fn create_network(network_req: Json<NetworkCreateRequest>, app_manager: &State<AppManager>) -> Result<Json<NetworkInfo>, String> {
+pub async fn create_network(network_req: Json<NetworkCreateRequest>, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<Json<NetworkInfo>, AgentError> {
     let options = bollard::network::CreateNetworkOptions {
         name: network_req.name.clone(),
         driver: network_req.driver.clone().unwrap_or_default(),
@@ -57,10 +58,10 @@ pub async fn create_network(network_req: Json<NetworkCreateRequest>, app_manager
         ..Default::default()
     };
     
-    match app_manager.docker.create_network(options).await {
+    match app_manager.instrument("create_network", app_manager.docker().create_network(options)).await {
         Ok(response) => {
             // Inspect network to get full details
-            match app_manager.docker.inspect_network::<String>(response.id.as_str(), None).await {
+            match app_manager.instrument("inspect_network", app_manager.docker().inspect_network::<String>(response.id.as_str(), None)).await {
                 Ok(network) => {
                     let mut containers = HashMap::new();
                     if let Some(net_containers) = network.containers {
@@ -86,43 +87,63 @@ pub async fn create_network(network_req: Json<NetworkCreateRequest>, app_manager
                     
                     Ok(Json(network_info))
                 },
-                Err(e) => Err(format!("Failed to inspect created network: {}", e))
+                Err(e) => Err(AgentError::docker("Failed to inspect created network", e))
             }
         },
-        Err(e) => Err(format!("Failed to create network: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to create network", e))
     }
 }
 
 #[delete("/networks/<id>")]
-pub async fn delete_network(id: String, app_manager: &State<AppManager>) -> Result<String, String> {
-    match app_manager.docker.remove_network(&id).await {
+pub async fn delete_network(id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
+    match app_manager.instrument("remove_network", app_manager.docker().remove_network(&id)).await {
         Ok(_) => Ok(format!("Network {} deleted successfully", id)),
-        Err(e) => Err(format!("Failed to delete network: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to delete network", e))
     }
 }
 
 #[put("/instances/<id>/connect/<network_id>")]
-pub async fn connect_instance_to_network(id: String, network_id: String, app_manager: &State<AppManager>) -> Result<String, String> {
+pub async fn connect_instance_to_network(id: String, network_id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
     let options = bollard::network::ConnectNetworkOptions {
         container: id.clone(),
         ..Default::default()
     };
     
-    match app_manager.docker.connect_network(&network_id, options).await {
+    match app_manager.instrument("connect_network", app_manager.docker().connect_network(&network_id, options)).await {
         Ok(_) => Ok(format!("Instance {} connected to network {}", id, network_id)),
-        Err(e) => Err(format!("Failed to connect instance to network: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to connect instance to network", e))
     }
 }
 
 #[put("/instances/<id>/disconnect/<network_id>")]
-pub async fn disconnect_instance_from_network(id: String, network_id: String, app_manager: &State<AppManager>) -> Result<String, String> {
+pub async fn disconnect_instance_from_network(id: String, network_id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
     let options = bollard::network::DisconnectNetworkOptions {
         container: id.clone(),
         force: false,
     };
     
-    match app_manager.docker.disconnect_network(&network_id, options).await {
+    match app_manager.instrument("disconnect_network", app_manager.docker().disconnect_network(&network_id, options)).await {
         Ok(_) => Ok(format!("Instance {} disconnected from network {}", id, network_id)),
-        Err(e) => Err(format!("Failed to disconnect instance from network: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to disconnect instance from network", e))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use serde_json::Value;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    #[tokio::test]
+    async fn deleting_a_missing_network_is_not_found() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("DELETE", "/networks/*", |_| DockerResponse::error(404, "network missing not found"));
+        let client = agent.client().await;
+
+        let response = client.delete("/networks/missing").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"]["code"], "not_found");
+        assert_eq!(body["error"]["status"], 404);
+    }
+}
@@ -0,0 +1,32 @@
+use rocket::{get, put};
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::AdminToken;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::ImagePolicy;
+
+// Image Policy
+
+#[get("/policies/images")]
+pub async fn get_image_policy(app_manager: &State<AppManager>, _auth: AdminToken) -> Json<ImagePolicy> {
+    Json(app_manager.image_policy.get())
+}
+
+/// Replaces the image policy. It applies to instances created, updated or replaced and images
+/// pulled from now on; running instances aren't touched.
+#[put("/policies/images", format = "json", data = "<policy>")]
+pub async fn put_image_policy(policy: Json<ImagePolicy>, app_manager: &State<AppManager>, _auth: AdminToken) -> Result<Json<ImagePolicy>, AgentError> {
+    let policy = policy.into_inner();
+    if policy.allowed_registries.iter().chain(&policy.allowed_images).any(|entry| entry.trim().is_empty()) {
+        return Err(AgentError::BadRequest("allowed_registries and allowed_images must not contain empty entries".to_string()));
+    }
+
+    match app_manager.image_policy.set(policy.clone()) {
+        Ok(()) => {
+            tracing::info!("Image policy updated");
+            Ok(Json(policy))
+        },
+        Err(e) => Err(AgentError::Internal(format!("Failed to persist the image policy: {}", e)))
+    }
+}
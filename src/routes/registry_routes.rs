@@ -0,0 +1,40 @@
+use rocket::{delete, get, post};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::path::PathBuf;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::{RegistryCredentialRequest, RegistryInfo};
+
+// Registry Credentials
+
+#[get("/registries")]
+pub async fn list_registries(app_manager: &State<AppManager>) -> Json<Vec<RegistryInfo>> {
+    Json(app_manager.registries.list())
+}
+
+#[post("/registries", format = "json", data = "<registry_req>")]
+pub async fn create_registry(registry_req: Json<RegistryCredentialRequest>, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<Json<RegistryInfo>, AgentError> {
+    if !app_manager.registries.is_enabled() {
+        return Err(AgentError::Unavailable("Registry credentials require encryption_key in the agent configuration".to_string()));
+    }
+    if registry_req.server.trim().is_empty() || registry_req.username.trim().is_empty() {
+        return Err(AgentError::BadRequest("server and username must not be empty".to_string()));
+    }
+    
+    match app_manager.registries.upsert(&registry_req) {
+        Ok(info) => Ok(Json(info)),
+        Err(e) => Err(AgentError::Internal(format!("Failed to store registry credential: {}", e)))
+    }
+}
+
+#[delete("/registries/<server..>")]
+pub async fn delete_registry(server: PathBuf, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<String, AgentError> {
+    let server = server.to_string_lossy().to_string();
+    match app_manager.registries.remove(&server) {
+        Ok(true) => Ok(format!("Registry {} deleted successfully", server)),
+        Ok(false) => Err(AgentError::NotFound(format!("No credential stored for registry {}", server))),
+        Err(e) => Err(AgentError::Internal(format!("Failed to delete registry credential: {}", e)))
+    }
+}
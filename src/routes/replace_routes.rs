@@ -0,0 +1,393 @@
+use rocket::post;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use bollard::container::{CreateContainerOptions, RemoveContainerOptions, RenameContainerOptions, StartContainerOptions, StopContainerOptions};
+use bollard::models::HealthStatusEnum;
+use bollard::network::ConnectNetworkOptions;
+use serde_json::json;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::instance_routes::{check_log_shipping_supported, check_secrets_supported, container_config, refresh_image, store_secrets, tracked_instance};
+use crate::routes::models::{AppInstance, AppInstanceRequest, PhaseTiming, ReplaceReport};
+use crate::timeline;
+
+const READINESS_TIMEOUT: Duration = Duration::from_secs(60);
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Containers without a healthcheck count as ready once they've stayed up this long
+const READINESS_SETTLE: Duration = Duration::from_secs(3);
+
+// Records how long each phase of a replacement took
+struct PhaseTimer {
+    started: Instant,
+    last: Instant,
+    phases: Vec<PhaseTiming>,
+}
+
+impl PhaseTimer {
+    fn new() -> Self {
+        let now = Instant::now();
+        PhaseTimer {
+            started: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    fn mark(&mut self, phase: &str) {
+        let now = Instant::now();
+        self.phases.push(PhaseTiming {
+            phase: phase.to_string(),
+            duration_ms: now.duration_since(self.last).as_millis() as u64,
+        });
+        self.last = now;
+    }
+}
+
+// Wait for the container's healthcheck to pass, or for it to stay running if it has none
+async fn wait_ready(app_manager: &AppManager, id: &str) -> Result<(), AgentError> {
+    let deadline = Instant::now() + READINESS_TIMEOUT;
+    let mut running_since: Option<Instant> = None;
+
+    loop {
+        let info = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(id, None)).await {
+            Ok(info) => info,
+            Err(e) => return Err(AgentError::docker("Failed to inspect replacement", e)),
+        };
+        let state = info.state.unwrap_or_default();
+
+        match state.health.and_then(|health| health.status) {
+            Some(HealthStatusEnum::HEALTHY) => return Ok(()),
+            Some(HealthStatusEnum::UNHEALTHY) => {
+                return Err(AgentError::Docker("Replacement container reported unhealthy".to_string()));
+            },
+            Some(HealthStatusEnum::STARTING) => {},
+            _ if state.running == Some(true) => {
+                if running_since.get_or_insert_with(Instant::now).elapsed() >= READINESS_SETTLE {
+                    return Ok(());
+                }
+            },
+            _ => {
+                return Err(AgentError::Docker(format!("Replacement container exited with code {} before becoming ready", state.exit_code.unwrap_or_default())));
+            }
+        }
+
+        if Instant::now() >= deadline {
+            return Err(AgentError::Docker(format!("Replacement container was not ready within {}s", READINESS_TIMEOUT.as_secs())));
+        }
+        tokio::time::sleep(READINESS_POLL_INTERVAL).await;
+    }
+}
+
+async fn stop_container(app_manager: &AppManager, id: &str) -> Result<(), AgentError> {
+    let options = Some(StopContainerOptions {
+        t: 30,
+    });
+    match app_manager.instrument("stop_container", app_manager.docker().stop_container(id, options)).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(AgentError::docker("Failed to stop the old instance", e)),
+    }
+}
+
+async fn start_container(app_manager: &AppManager, id: &str) -> Result<(), AgentError> {
+    match app_manager.instrument("start_container", app_manager.docker().start_container(id, None::<StartContainerOptions<String>>)).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(AgentError::docker("Failed to start the replacement", e)),
+    }
+}
+
+async fn remove_container(app_manager: &AppManager, id: &str) -> Result<(), bollard::errors::Error> {
+    let options = Some(RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    });
+    app_manager.instrument("remove_container", app_manager.docker().remove_container(id, options)).await
+}
+
+// Connects the replacement to the networks of the old container besides the one it was created on.
+// Containers sharing the host's or another container's network stack have no networks of their own.
+async fn connect_networks(app_manager: &AppManager, id: &str, network_mode: Option<&str>, networks: &[String]) -> Result<(), AgentError> {
+    let primary = match network_mode {
+        None | Some("default") => "bridge",
+        Some("host") | Some("none") => return Ok(()),
+        Some(mode) if mode.starts_with("container:") => return Ok(()),
+        Some(mode) => mode,
+    };
+    for network in networks.iter().filter(|network| network.as_str() != primary) {
+        let options = ConnectNetworkOptions {
+            container: id.to_string(),
+            ..Default::default()
+        };
+        if let Err(e) = app_manager.instrument("connect_network", app_manager.docker().connect_network(network, options)).await {
+            return Err(AgentError::docker(&format!("Failed to connect the replacement to network {}", network), e));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces an instance with a new container built from `app_req`.
+///
+/// When the new ports don't collide with the old ones both containers run side by side
+/// and the old one is only stopped once the replacement is ready. Otherwise the old
+/// container has to release its host ports first, so the swap is a short cutover with
+/// downtime, which is refused with 409 unless `allow_downtime` is set.
+/// The replacement joins every network the old container is on.
+/// On failure the replacement is removed and the old instance is left (or put back) running.
+#[post("/instances/<id>/replace?<allow_downtime>", format = "json", data = "<app_req>")]
+pub async fn replace_instance(id: String, allow_downtime: Option<bool>, app_req: Json<AppInstanceRequest>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable, bypass: ImagePolicyBypass) -> Result<Json<ReplaceReport>, AgentError> {
+    app_manager.image_policy.check(&app_req.image, bypass.0)?;
+    check_secrets_supported(app_manager, &app_req)?;
+    check_log_shipping_supported(app_manager, &app_req)?;
+    let mut timer = PhaseTimer::new();
+
+    let old = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(old) => old,
+        Err(e) => return Err(AgentError::docker("Failed to inspect instance", e)),
+    };
+    let old_id = old.id.unwrap_or(id);
+    // A request without secrets keeps the ones the old container was created with
+    let mut app_req = app_req.into_inner();
+    if app_req.secrets.is_none() {
+        let secrets = app_manager.secrets.get(&old_id);
+        app_req.secrets = (!secrets.is_empty()).then_some(secrets);
+    }
+    let old_name = old.name.unwrap_or_default().trim_start_matches('/').to_string();
+    let old_host_config = old.host_config.unwrap_or_default();
+    let old_networks: Vec<String> = old.network_settings.and_then(|settings| settings.networks)
+        .map(|networks| networks.into_keys().collect())
+        .unwrap_or_default();
+
+    let old_ports: HashSet<String> = old_host_config.port_bindings.iter()
+        .flat_map(|bindings| bindings.values())
+        .flatten()
+        .flatten()
+        .filter_map(|binding| binding.host_port.clone())
+        .collect();
+    let new_ports: HashSet<String> = app_req.ports.iter()
+        .flatten()
+        .map(|port| port.host_port.to_string())
+        .collect();
+    let cutover = !old_ports.is_disjoint(&new_ports);
+    if cutover && !allow_downtime.unwrap_or(false) {
+        let mut shared: Vec<&String> = old_ports.intersection(&new_ports).collect();
+        shared.sort();
+        let shared: Vec<&str> = shared.into_iter().map(String::as_str).collect();
+        return Err(AgentError::Conflict(format!(
+            "The replacement needs host ports {} that {} holds, so it would be down while they're handed over; pass allow_downtime=true to accept that",
+            shared.join(", "), old_name,
+        )));
+    }
+
+    refresh_image(app_manager, &app_req.image).await;
+    timer.mark("pull");
+
+    // Create the replacement under a temporary name on the old container's network
+    let temp_name = format!("{}-replacement-{}", old_name, &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let labels = app_manager.conventions.labels(&app_req);
+    let mut config = container_config(&app_req, &labels);
+    if let Some(host_config) = config.host_config.as_mut() {
+        host_config.network_mode = old_host_config.network_mode.clone();
+    }
+    let options = Some(CreateContainerOptions {
+        name: temp_name.as_str(),
+        platform: None,
+    });
+    let new_id = match app_manager.instrument("create_container", app_manager.docker().create_container(options, config)).await {
+        Ok(response) => response.id,
+        Err(e) => return Err(AgentError::docker("Failed to create the replacement", e)),
+    };
+    if let Err(e) = connect_networks(app_manager, &new_id, old_host_config.network_mode.as_deref(), &old_networks).await {
+        if let Err(e) = remove_container(app_manager, &new_id).await {
+            tracing::warn!("Failed to remove replacement container {}: {}", new_id, e);
+        }
+        return Err(e);
+    }
+    timer.mark("create");
+
+    // Take the old instance out of supervision so stopping it isn't treated as a crash
+    let old_tracked = app_manager.remove_instance(&old_id);
+
+    let swapped = if cutover {
+        match stop_container(app_manager, &old_id).await {
+            Ok(_) => {
+                timer.mark("stop_old");
+                start_container(app_manager, &new_id).await
+            },
+            Err(e) => Err(e),
+        }
+    } else {
+        start_container(app_manager, &new_id).await
+    };
+    let swapped = match swapped {
+        Ok(_) => {
+            timer.mark("start");
+            wait_ready(app_manager, &new_id).await
+        },
+        Err(e) => Err(e),
+    };
+    let swapped = match swapped {
+        Ok(_) if !cutover => {
+            timer.mark("readiness");
+            stop_container(app_manager, &old_id).await
+        },
+        other => other,
+    };
+
+    if let Err(e) = swapped {
+        tracing::warn!("Replacement of {} failed, rolling back: {}", old_id, e);
+        if let Err(e) = remove_container(app_manager, &new_id).await {
+            tracing::warn!("Failed to remove replacement container {}: {}", new_id, e);
+        }
+        if cutover {
+            if let Err(e) = app_manager.instrument("start_container", app_manager.docker().start_container(&old_id, None::<StartContainerOptions<String>>)).await {
+                tracing::error!("Failed to restart {} after a failed replacement: {}", old_id, e);
+            }
+        }
+        if let Some(old_tracked) = old_tracked {
+            app_manager.upsert_instance(old_tracked);
+        }
+        return Err(e);
+    }
+    timer.mark(if cutover { "readiness" } else { "stop_old" });
+
+    // Past this point the replacement is serving, so cleanup problems are only logged
+    if let Err(e) = remove_container(app_manager, &old_id).await {
+        tracing::warn!("Failed to remove replaced container {}: {}", old_id, e);
+    }
+    let final_name = match app_manager.instrument("rename_container", app_manager.docker().rename_container(&new_id, RenameContainerOptions { name: old_name.clone() })).await {
+        Ok(_) => old_name,
+        Err(e) => {
+            tracing::warn!("Failed to rename replacement {} to {}: {}", new_id, old_name, e);
+            temp_name
+        }
+    };
+    timer.mark("cleanup");
+
+    let instance = AppInstance {
+        name: final_name,
+        ..tracked_instance(&new_id, &app_req, labels, app_manager.now())
+    };
+    store_secrets(app_manager, &new_id, &app_req);
+    if let Err(e) = app_manager.secrets.set(&old_id, &HashMap::new()) {
+        tracing::warn!("Failed to drop secrets of replaced container {}: {}", old_id, e);
+    }
+    app_manager.upsert_instance(instance.clone());
+    timeline::record(app_manager, &new_id, &instance.name, "replaced", &auth.0, Some(format!("replaced {}", old_id)));
+    app_manager.notifier.notify("instance.replaced", json!({
+        "replaced_id": old_id,
+        "instance": instance,
+    }));
+
+    Ok(Json(ReplaceReport {
+        instance,
+        replaced_id: old_id,
+        strategy: if cutover { "cutover" } else { "blue_green" }.to_string(),
+        total_ms: timer.started.elapsed().as_millis() as u64,
+        phases: timer.phases,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use rocket::http::{ContentType, Status};
+    use serde_json::{json, Value};
+    use crate::routes::models::SecretValue;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    const REPLACEMENT: &str = r#"{"name": "game", "image": "horizon:2", "ports": [{"host_port": 7777, "container_port": 7777, "protocol": "tcp"}]}"#;
+
+    // An instance on two networks holding host port 7777, and a daemon that accepts its replacement
+    async fn agent() -> TestAgent {
+        // Any 32 bytes, so secrets can be stored
+        let agent = TestAgent::with_config(|config| config.encryption_key = Some("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string())).await;
+        let docker = &agent.docker;
+        docker.route("GET", "/containers/game/json", |_| DockerResponse::ok(json!({
+            "Id": "old-id",
+            "Name": "/game",
+            "State": { "Running": true },
+            "HostConfig": {
+                "NetworkMode": "game-net",
+                "PortBindings": { "7777/tcp": [{ "HostIp": "0.0.0.0", "HostPort": "7777" }] },
+            },
+            "NetworkSettings": { "Networks": { "game-net": {}, "metrics": {} } },
+        })));
+        docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({ "status": "Downloaded newer image" })));
+        docker.route("POST", "/containers/create", |_| DockerResponse::Json(201, json!({ "Id": "new-id", "Warnings": [] })));
+        docker.route("POST", "/networks/*/connect", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/*/stop", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/*/start", |_| DockerResponse::ok(json!({})));
+        docker.route("GET", "/containers/new-id/json", |_| DockerResponse::ok(json!({
+            "Id": "new-id",
+            "State": { "Running": true, "Health": { "Status": "healthy" } },
+        })));
+        docker.route("DELETE", "/containers/old-id", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/new-id/rename", |_| DockerResponse::ok(json!({})));
+        agent
+    }
+
+    #[tokio::test]
+    async fn overlapping_ports_need_downtime_to_be_allowed() {
+        let agent = agent().await;
+        let client = agent.client().await;
+
+        let response = client.post("/instances/game/replace").header(ContentType::JSON).body(REPLACEMENT).dispatch().await;
+        assert_eq!(response.status(), Status::Conflict);
+        let body: Value = response.into_json().await.unwrap();
+        assert!(body["error"]["message"].as_str().unwrap().contains("7777"));
+        assert!(agent.docker.requests_to("POST", "/containers/create").is_empty());
+        assert!(agent.docker.requests_to("POST", "/containers/old-id/stop").is_empty());
+    }
+
+    #[tokio::test]
+    async fn allowed_cutover_joins_every_network() {
+        let agent = agent().await;
+        let client = agent.client().await;
+
+        let response = client.post("/instances/game/replace?allow_downtime=true").header(ContentType::JSON).body(REPLACEMENT).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let report: Value = response.into_json().await.unwrap();
+        assert_eq!(report["strategy"], "cutover");
+        assert_eq!(report["instance"]["id"], "new-id");
+
+        let created = agent.docker.requests_to("POST", "/containers/create");
+        assert_eq!(created[0].json()["HostConfig"]["NetworkMode"], "game-net");
+        let connected = agent.docker.requests_to("POST", "/networks/metrics/connect");
+        assert_eq!(connected.len(), 1);
+        assert_eq!(connected[0].json()["Container"], "new-id");
+        assert!(agent.docker.requests_to("POST", "/networks/game-net/connect").is_empty());
+        assert_eq!(agent.docker.requests_to("POST", "/containers/old-id/stop").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_to_join_a_network_removes_the_replacement() {
+        let agent = agent().await;
+        agent.docker.route("POST", "/networks/metrics/connect", |_| DockerResponse::error(500, "network metrics is full"));
+        agent.docker.route("DELETE", "/containers/new-id", |_| DockerResponse::ok(json!({})));
+        let client = agent.client().await;
+
+        let response = client.post("/instances/game/replace?allow_downtime=true").header(ContentType::JSON).body(REPLACEMENT).dispatch().await;
+        assert_eq!(response.status(), Status::BadGateway);
+        assert_eq!(agent.docker.requests_to("DELETE", "/containers/new-id").len(), 1);
+        assert!(agent.docker.requests_to("POST", "/containers/old-id/stop").is_empty());
+    }
+
+    #[tokio::test]
+    async fn replacing_without_secrets_keeps_the_old_ones() {
+        let agent = agent().await;
+        let token = HashMap::from([("TOKEN".to_string(), SecretValue::new("hunter2".to_string()))]);
+        agent.app_manager.secrets.set("old-id", &token).unwrap();
+        let client = agent.client().await;
+
+        let response = client.post("/instances/game/replace?allow_downtime=true").header(ContentType::JSON).body(REPLACEMENT).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let report: Value = response.into_json().await.unwrap();
+        assert_eq!(report["instance"]["secrets"], json!({ "TOKEN": "***" }));
+        let created = agent.docker.requests_to("POST", "/containers/create");
+        assert!(created[0].json()["Env"].as_array().unwrap().contains(&json!("TOKEN=hunter2")));
+        assert_eq!(agent.app_manager.secrets.get("new-id")["TOKEN"].expose(), "hunter2");
+        assert!(agent.app_manager.secrets.get("old-id").is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use rocket::{delete, get, post};
+use rocket::serde::json::Json;
+use rocket::State;
+use chrono_tz::Tz;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::{Schedule, ScheduleExecution, ScheduleRequest};
+use crate::scheduler;
+
+// Resolve an instance id, id prefix or name to its full container id and name
+async fn resolve_instance(app_manager: &AppManager, id: &str) -> Result<(String, String), AgentError> {
+    match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(id, None)).await {
+        Ok(container) => Ok((
+            container.id.unwrap_or_else(|| id.to_string()),
+            container.name.unwrap_or_default().trim_start_matches('/').to_string(),
+        )),
+        Err(e) => Err(AgentError::docker("Failed to get instance", e))
+    }
+}
+
+// The zone asked for with ?tz=, which list routes add local display times for
+pub(crate) fn display_timezone(tz: Option<&str>) -> Result<Option<Tz>, AgentError> {
+    tz.map(scheduler::parse_timezone).transpose().map_err(AgentError::BadRequest)
+}
+
+#[post("/instances/<id>/schedule", format = "json", data = "<schedule_req>")]
+pub async fn create_schedule(id: String, schedule_req: Json<ScheduleRequest>, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<Json<Schedule>, AgentError> {
+    if !scheduler::ACTIONS.contains(&schedule_req.action.as_str()) {
+        return Err(AgentError::BadRequest(format!("Unknown action '{}', expected one of {}", schedule_req.action, scheduler::ACTIONS.join(", "))));
+    }
+    if let Err(e) = scheduler::parse_cron(&schedule_req.cron) {
+        return Err(AgentError::BadRequest(format!("Invalid cron expression '{}': {}", schedule_req.cron, e)));
+    }
+    let timezone = match schedule_req.timezone.as_deref().map(scheduler::parse_timezone).transpose() {
+        Ok(timezone) => timezone.map(|timezone| timezone.name().to_string()),
+        Err(e) => return Err(AgentError::BadRequest(e)),
+    };
+    
+    let (instance_id, instance_name) = resolve_instance(app_manager, &id).await?;
+    let now = app_manager.now();
+    let schedule = Schedule {
+        id: uuid::Uuid::new_v4().to_string(),
+        instance_id,
+        instance_name,
+        cron: schedule_req.cron.clone(),
+        action: schedule_req.action.clone(),
+        next_run_at: scheduler::next_run(&schedule_req.cron, timezone.as_deref(), now),
+        timezone,
+        created_at: now.to_rfc3339(),
+        next_run_at_local: None,
+    };
+    
+    app_manager.schedules.lock().unwrap().schedules.push(schedule.clone());
+    app_manager.persist_schedules();
+    
+    Ok(Json(schedule))
+}
+
+/// Schedules of an instance. With `tz` (e.g. "Europe/Berlin") each also has its next run in that zone.
+#[get("/instances/<id>/schedules?<tz>")]
+pub async fn list_schedules(id: String, tz: Option<String>, app_manager: &State<AppManager>) -> Result<Json<Vec<Schedule>>, AgentError> {
+    let timezone = display_timezone(tz.as_deref())?;
+    let (instance_id, _) = resolve_instance(app_manager, &id).await?;
+    let mut schedules: Vec<Schedule> = app_manager.schedules.lock().unwrap().schedules.iter()
+        .filter(|schedule| schedule.instance_id == instance_id)
+        .cloned()
+        .collect();
+    if let Some(timezone) = timezone {
+        for schedule in &mut schedules {
+            schedule.next_run_at_local = schedule.next_run_at.as_deref().and_then(|next| scheduler::local_time(next, timezone));
+        }
+    }
+    Ok(Json(schedules))
+}
+
+/// Recent scheduled executions of an instance. With `tz` their times are also given in that zone.
+#[get("/instances/<id>/schedules/history?<tz>")]
+pub async fn get_schedule_history(id: String, tz: Option<String>, app_manager: &State<AppManager>) -> Result<Json<Vec<ScheduleExecution>>, AgentError> {
+    let timezone = display_timezone(tz.as_deref())?;
+    let (instance_id, _) = resolve_instance(app_manager, &id).await?;
+    let mut history = app_manager.schedules.lock().unwrap().history
+        .get(&instance_id)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(timezone) = timezone {
+        for execution in &mut history {
+            execution.started_at_local = scheduler::local_time(&execution.started_at, timezone);
+            execution.finished_at_local = scheduler::local_time(&execution.finished_at, timezone);
+        }
+    }
+    Ok(Json(history))
+}
+
+#[delete("/instances/<id>/schedules/<schedule_id>")]
+pub async fn delete_schedule(id: String, schedule_id: String, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<String, AgentError> {
+    let (instance_id, _) = resolve_instance(app_manager, &id).await?;
+    let removed = {
+        let mut book = app_manager.schedules.lock().unwrap();
+        let before = book.schedules.len();
+        book.schedules.retain(|schedule| !(schedule.id == schedule_id && schedule.instance_id == instance_id));
+        book.schedules.len() != before
+    };
+    
+    if !removed {
+        return Err(AgentError::NotFound(format!("Schedule {} not found for instance {}", schedule_id, id)));
+    }
+    app_manager.persist_schedules();
+    Ok(format!("Schedule {} deleted successfully", schedule_id))
+}
@@ -0,0 +1,182 @@
+use rocket::put;
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::labels;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::instance_routes::{find_tracked, resolve_id};
+use crate::routes::models::{AppInstance, AppInstanceRequest, SecretsUpdateRequest};
+use crate::routes::replace_routes::replace_instance;
+use crate::secrets;
+
+/// Rotates the secrets of an instance created by this agent. Containers read their
+/// environment at creation, so new values only take effect once the container is
+/// recreated: immediately with `restart: true`, otherwise on its next replacement.
+#[put("/instances/<id>/secrets", format = "json", data = "<secrets_req>")]
+pub async fn update_secrets(id: String, secrets_req: Json<SecretsUpdateRequest>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<AppInstance>, AgentError> {
+    if !app_manager.secrets.is_enabled() {
+        return Err(AgentError::Unavailable("Instance secrets require encryption_key in the agent configuration".to_string()));
+    }
+    let full_id = resolve_id(app_manager, &id, "Failed to update secrets").await?;
+    let Some(instance) = find_tracked(app_manager, &full_id) else {
+        return Err(AgentError::NotFound(format!("Instance {} is not managed by this agent", id)));
+    };
+    
+    let mut values = app_manager.secrets.get(&instance.id);
+    for (name, value) in secrets_req.secrets.iter() {
+        match value {
+            Some(value) => values.insert(name.clone(), value.clone()),
+            None => values.remove(name),
+        };
+    }
+    
+    if secrets_req.restart {
+        // Recreate from the tracked spec with the new values; the replacement stores them under its new id
+        let app_req = AppInstanceRequest {
+            name: instance.name.clone(),
+            image: instance.image.clone(),
+            ports: Some(instance.ports.clone()),
+            environment: Some(instance.environment.clone()),
+            volumes: Some(instance.volumes.clone()),
+            supervise: Some(instance.supervision.is_some()),
+            secrets: Some(values),
+            ship_logs: Some(instance.ship_logs),
+            deployment: instance.labels.get(labels::DEPLOYMENT).cloned(),
+            project: instance.labels.get(labels::PROJECT).cloned(),
+        };
+        // The image is the one already running, so a stricter policy since doesn't block rotating
+        // secrets; the ports are too, so restarting means a short cutover
+        let report = replace_instance(instance.id, Some(true), Json(app_req), app_manager, auth, DockerAvailable, ImagePolicyBypass(true)).await?;
+        return Ok(Json(report.into_inner().instance));
+    }
+    
+    if let Err(e) = app_manager.secrets.set(&instance.id, &values) {
+        return Err(AgentError::Internal(format!("Failed to store secrets: {}", e)));
+    }
+    let updated = app_manager.update_instance(&instance.id, |tracked| {
+        tracked.secrets = secrets::masked(values.keys());
+        Some(tracked.clone())
+    });
+    let updated = updated.ok_or_else(|| AgentError::NotFound(format!("Instance {} was removed", id)))?;
+    
+    Ok(Json(updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use rocket::http::{ContentType, Status};
+    use serde_json::{json, Value};
+    use crate::testing::{instance, DockerResponse, TestAgent};
+
+    const KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    // Everything logged on this thread, as the agent's log output would show it
+    #[derive(Clone, Default)]
+    struct LogOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl LogOutput {
+        fn capture(&self) -> tracing::subscriber::DefaultGuard {
+            let output = self.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(move || output.clone())
+                .finish();
+            tracing::subscriber::set_default(subscriber)
+        }
+
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).to_string()
+        }
+    }
+
+    // A daemon running "game" as sec-id with the secret in its environment, as Docker reports it
+    async fn agent() -> TestAgent {
+        let agent = TestAgent::with_config(|config| config.encryption_key = Some(KEY.to_string())).await;
+        let docker = &agent.docker;
+        docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({})));
+        docker.route("POST", "/containers/create", |_| DockerResponse::Json(201, json!({ "Id": "sec-id", "Warnings": [] })));
+        docker.route("POST", "/containers/sec-id/start", |_| DockerResponse::ok(json!({})));
+        docker.route("GET", "/containers/json", |_| DockerResponse::ok(json!([{
+            "Id": "sec-id",
+            "Names": ["/game"],
+            "Image": "horizon:1",
+            "Created": 1_772_334_000,
+            "State": "running",
+            "Status": "Up 2 minutes",
+        }])));
+        docker.route("GET", "/containers/*/json", |_| DockerResponse::ok(json!({
+            "Id": "sec-id",
+            "Name": "/game",
+            "Config": { "Image": "horizon:1", "Env": ["MODE=ranked", "TOKEN=hunter2"] },
+            "State": { "Status": "running", "Running": true },
+        })));
+        agent
+    }
+
+    #[tokio::test]
+    async fn plaintext_never_leaves_the_agent_except_into_the_container() {
+        let logs = LogOutput::default();
+        let _logs = logs.capture();
+        let agent = agent().await;
+        let client = agent.client().await;
+
+        let response = client.post("/instances").header(ContentType::JSON)
+            .body(r#"{"name": "game", "image": "horizon:1", "environment": {"MODE": "ranked"}, "secrets": {"TOKEN": "hunter2"}}"#)
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let created: Value = response.into_json().await.unwrap();
+        assert_eq!(created["secrets"], json!({ "TOKEN": "***" }));
+        let env = agent.docker.requests_to("POST", "/containers/create")[0].json()["Env"].clone();
+        assert!(env.as_array().unwrap().contains(&json!("TOKEN=hunter2")));
+
+        let list = client.get("/instances").dispatch().await.into_string().await.unwrap();
+        assert!(list.contains("sec-id") && !list.contains("hunter2"));
+
+        let detail: Value = client.get("/instances/sec-id").dispatch().await.into_json().await.unwrap();
+        assert_eq!(detail["secrets"], json!({ "TOKEN": "***" }));
+        assert!(!detail.to_string().contains("hunter2"));
+
+        let inspect: Value = client.get("/instances/sec-id/inspect").dispatch().await.into_json().await.unwrap();
+        assert_eq!(inspect["Config"]["Env"], json!(["MODE=ranked", "TOKEN=***"]));
+
+        let rotated = client.put("/instances/sec-id/secrets").header(ContentType::JSON)
+            .body(r#"{"secrets": {"TOKEN": "hunter3", "SALT": "pepper"}}"#)
+            .dispatch().await.into_string().await.unwrap();
+        assert!(rotated.contains(r#""SALT":"***""#) && !rotated.contains("hunter3") && !rotated.contains("pepper"));
+        assert_eq!(agent.app_manager.secrets.get("sec-id")["TOKEN"].expose(), "hunter3");
+
+        let logs = logs.text();
+        assert!(logs.contains("sec-id"), "nothing was logged");
+        assert!(!logs.contains("hunter") && !logs.contains("pepper"), "{}", logs);
+        let stored = std::fs::read_to_string(agent.data_dir.path().join("secrets.json")).unwrap();
+        assert!(!stored.contains("hunter") && !stored.contains("pepper"));
+    }
+
+    #[tokio::test]
+    async fn rotation_goes_to_the_container_the_daemon_resolves() {
+        let agent = TestAgent::with_config(|config| config.encryption_key = Some(KEY.to_string())).await;
+        // "ab" is one instance's name and a prefix of the other's id
+        agent.app_manager.upsert_instances([instance("ab12cd", "lobby"), instance("ff34ee", "ab")]);
+        agent.docker.route("GET", "/containers/ab/json", |_| DockerResponse::ok(json!({ "Id": "ff34ee", "Name": "/ab" })));
+        let client = agent.client().await;
+
+        let response = client.put("/instances/ab/secrets").header(ContentType::JSON).body(r#"{"secrets": {"TOKEN": "hunter2"}}"#).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(agent.app_manager.secrets.get("ff34ee")["TOKEN"].expose(), "hunter2");
+        assert!(agent.app_manager.secrets.get("ab12cd").is_empty());
+    }
+}
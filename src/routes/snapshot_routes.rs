@@ -0,0 +1,168 @@
+use rocket::{delete, get, post};
+use rocket::serde::json::Json;
+use rocket::State;
+use bollard::container::Config;
+use bollard::image::{CommitContainerOptions, RemoveImageOptions};
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::adopt_routes::instance_from_container;
+use crate::routes::instance_routes::{find_tracked, launch_instance};
+use crate::routes::models::{AppInstance, AppInstanceRequest, PortMapping, Snapshot, SnapshotCloneRequest};
+
+const SNAPSHOT_REPOSITORY: &str = "maestro-snapshot";
+// Older snapshots of an instance are deleted once it has more than this many
+const SNAPSHOT_RETENTION: usize = 5;
+
+async fn remove_snapshot_image(app_manager: &AppManager, snapshot: &Snapshot) {
+    match app_manager.instrument("remove_image", app_manager.docker().remove_image(&snapshot.image, None::<RemoveImageOptions>, None)).await {
+        Ok(_) => {},
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {},
+        Err(e) => tracing::warn!("Failed to remove snapshot image {}: {}", snapshot.image, e),
+    }
+}
+
+// A host port nothing is listening on right now, so a clone doesn't collide with its source
+fn free_host_port() -> Result<u16, AgentError> {
+    std::net::TcpListener::bind(("0.0.0.0", 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|address| address.port())
+        .map_err(|e| AgentError::Internal(format!("Failed to find a free host port: {}", e)))
+}
+
+/// Commits an instance's filesystem to an image tagged with the instance name and time.
+/// Secret values are blanked in the committed image's environment.
+#[post("/instances/<id>/snapshot")]
+pub async fn create_snapshot(id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<Json<Snapshot>, AgentError> {
+    let container = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(container) => container,
+        Err(e) => return Err(AgentError::docker("Failed to inspect instance", e)),
+    };
+    let source = instance_from_container(container, None);
+    let secret_names: Vec<String> = find_tracked(app_manager, &source.id)
+        .map(|tracked| tracked.secrets.into_keys().collect())
+        .unwrap_or_default();
+
+    let now = app_manager.now();
+    let repository = format!("{}/{}", SNAPSHOT_REPOSITORY, source.name.to_lowercase());
+    let tag = now.format("%Y%m%d-%H%M%S").to_string();
+    let options = CommitContainerOptions {
+        container: source.id.clone(),
+        repo: repository.clone(),
+        tag: tag.clone(),
+        comment: format!("Snapshot of {}", source.name),
+        pause: true,
+        ..Default::default()
+    };
+    // The committed config would otherwise carry the injected secret values
+    let config = Config {
+        env: Some(secret_names.iter().map(|name| format!("{}=", name)).collect::<Vec<_>>()),
+        ..Default::default()
+    };
+    if let Err(e) = app_manager.instrument("commit_container", app_manager.docker().commit_container(options, config)).await {
+        return Err(AgentError::docker("Failed to commit instance", e));
+    }
+
+    let image = format!("{}:{}", repository, tag);
+    let inspected = match app_manager.instrument("inspect_image", app_manager.docker().inspect_image(&image)).await {
+        Ok(inspected) => inspected,
+        Err(e) => return Err(AgentError::docker("Failed to inspect snapshot image", e)),
+    };
+
+    let mut environment = source.environment;
+    environment.retain(|key, _| !secret_names.contains(key));
+    let snapshot = Snapshot {
+        id: uuid::Uuid::new_v4().to_string(),
+        instance_id: source.id,
+        instance_name: source.name,
+        image,
+        image_id: inspected.id.unwrap_or_default(),
+        size_bytes: inspected.size.unwrap_or_default(),
+        created_at: now.to_rfc3339(),
+        ports: source.ports,
+        environment,
+        volumes: source.volumes,
+    };
+
+    let expired: Vec<Snapshot> = {
+        let mut snapshots = app_manager.snapshots.lock().unwrap();
+        snapshots.push(snapshot.clone());
+        let mut own: Vec<&Snapshot> = snapshots.iter()
+            .filter(|existing| existing.instance_id == snapshot.instance_id)
+            .collect();
+        own.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        let expired: Vec<Snapshot> = own.iter()
+            .take(own.len().saturating_sub(SNAPSHOT_RETENTION))
+            .map(|&expired| expired.clone())
+            .collect();
+        snapshots.retain(|existing| !expired.iter().any(|expired| expired.id == existing.id));
+        expired
+    };
+    app_manager.persist_snapshots();
+    for expired in &expired {
+        tracing::info!("Removing snapshot {} of {} past the retention limit", expired.image, expired.instance_name);
+        remove_snapshot_image(app_manager, expired).await;
+    }
+
+    tracing::info!("Snapshot {} of {} is {} bytes", snapshot.image, snapshot.instance_name, snapshot.size_bytes);
+    Ok(Json(snapshot))
+}
+
+#[get("/snapshots?<instance>")]
+pub async fn list_snapshots(instance: Option<String>, app_manager: &State<AppManager>) -> Json<Vec<Snapshot>> {
+    let snapshots = app_manager.snapshots.lock().unwrap().iter()
+        .filter(|snapshot| instance.as_deref().is_none_or(|key| snapshot.instance_id.starts_with(key) || snapshot.instance_name == key))
+        .cloned()
+        .collect();
+    Json(snapshots)
+}
+
+#[delete("/snapshots/<snapshot_id>")]
+pub async fn delete_snapshot(snapshot_id: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
+    let snapshot = {
+        let mut snapshots = app_manager.snapshots.lock().unwrap();
+        let Some(index) = snapshots.iter().position(|snapshot| snapshot.id == snapshot_id) else {
+            return Err(AgentError::NotFound(format!("Snapshot {} not found", snapshot_id)));
+        };
+        snapshots.remove(index)
+    };
+    app_manager.persist_snapshots();
+    remove_snapshot_image(app_manager, &snapshot).await;
+
+    Ok(format!("Snapshot {} deleted successfully", snapshot_id))
+}
+
+/// Launches a new instance from a snapshot. Every host port is remapped to a free one so the
+/// clone can run next to its source, and the source's secrets are reused while it's still tracked.
+#[post("/snapshots/<snapshot_id>/clone", format = "json", data = "<clone_req>")]
+pub async fn clone_snapshot(snapshot_id: String, clone_req: Option<Json<SnapshotCloneRequest>>, app_manager: &State<AppManager>, auth: ApiToken, _docker: DockerAvailable) -> Result<Json<AppInstance>, AgentError> {
+    let clone_req = clone_req.map(|clone_req| clone_req.into_inner()).unwrap_or_default();
+    let snapshot = app_manager.snapshots.lock().unwrap().iter()
+        .find(|snapshot| snapshot.id == snapshot_id)
+        .cloned()
+        .ok_or_else(|| AgentError::NotFound(format!("Snapshot {} not found", snapshot_id)))?;
+
+    let mut ports = Vec::new();
+    for port in &snapshot.ports {
+        ports.push(PortMapping {
+            host_port: free_host_port()?,
+            ..port.clone()
+        });
+    }
+    let secrets = app_manager.secrets.get(&snapshot.instance_id);
+
+    let app_req = AppInstanceRequest {
+        name: clone_req.name.unwrap_or_else(|| format!("{}-clone-{}", snapshot.instance_name, &uuid::Uuid::new_v4().simple().to_string()[..8])),
+        image: snapshot.image,
+        ports: Some(ports),
+        environment: Some(snapshot.environment),
+        volumes: Some(if clone_req.keep_volumes { snapshot.volumes } else { Vec::new() }),
+        supervise: None,
+        secrets: (!secrets.is_empty()).then_some(secrets),
+        ship_logs: None,
+        deployment: None,
+        project: None,
+    };
+    launch_instance(app_req, app_manager, &auth.0).await
+}
+
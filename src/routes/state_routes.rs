@@ -0,0 +1,73 @@
+use rocket::{get, put};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::HashSet;
+use crate::desired_state;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, ImagePolicyBypass};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::instance_routes::check_log_shipping_supported;
+use crate::routes::maintenance_routes::check_maintenance;
+use crate::routes::models::{DesiredState, StatePlan};
+
+// Desired State
+
+/// Sets the instances this agent should run and converges on them: missing instances are created,
+/// drifted ones replaced and the rest removed unless listed in `protected`. With `dry_run` the plan
+/// is returned without changing anything. The state keeps being reconciled in the background.
+#[put("/state?<dry_run>", format = "json", data = "<desired>")]
+pub async fn put_state(dry_run: Option<bool>, desired: Json<DesiredState>, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable, bypass: ImagePolicyBypass) -> Result<Json<StatePlan>, AgentError> {
+    let desired = desired.into_inner();
+    let mut names = HashSet::new();
+    for instance in &desired.instances {
+        if instance.name.trim().is_empty() {
+            return Err(AgentError::BadRequest("Every instance in the desired state needs a name".to_string()));
+        }
+        if !names.insert(&instance.name) {
+            return Err(AgentError::BadRequest(format!("Instance {} appears more than once in the desired state", instance.name)));
+        }
+        // The document is persisted in plain JSON, so secret values have no place in it
+        if instance.secrets.is_some() {
+            return Err(AgentError::BadRequest(format!("Instance {} has secrets; set them through PUT /instances/<id>/secrets instead", instance.name)));
+        }
+        check_log_shipping_supported(app_manager, instance)?;
+        app_manager.image_policy.check(&instance.image, bypass.0)?;
+    }
+
+    if dry_run.unwrap_or(false) {
+        return Ok(Json(desired_state::reconcile(app_manager, &desired, true).await));
+    }
+    check_maintenance(app_manager)?;
+    if let Err(e) = app_manager.desired_state.set(desired.clone()) {
+        return Err(AgentError::Internal(format!("Failed to persist the desired state: {}", e)));
+    }
+    Ok(Json(desired_state::reconcile(app_manager, &desired, false).await))
+}
+
+/// The tracked instances as a desired state document, with the protected names of the last one set
+#[get("/state")]
+pub async fn get_state(app_manager: &State<AppManager>) -> Json<DesiredState> {
+    let mut instances: Vec<_> = app_manager.instances().iter()
+        .map(|instance| desired_state::definition(&app_manager.conventions, instance))
+        .collect();
+    instances.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Json(DesiredState {
+        instances,
+        protected: app_manager.desired_state.get().map(|desired| desired.protected).unwrap_or_default(),
+    })
+}
+
+/// What the next reconcile would change, i.e. how far the tracked instances have drifted
+#[get("/state/drift")]
+pub async fn get_state_drift(app_manager: &State<AppManager>) -> Result<Json<StatePlan>, AgentError> {
+    let Some(desired) = app_manager.desired_state.get() else {
+        return Err(AgentError::NotFound("No desired state has been set".to_string()));
+    };
+    Ok(Json(StatePlan {
+        changes: desired_state::plan(app_manager, &desired).into_iter()
+            .filter(|change| change.action != "keep")
+            .collect(),
+        computed_at: app_manager.now().to_rfc3339(),
+    }))
+}
@@ -0,0 +1,59 @@
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::time::Duration;
+use crate::routes::errors::AgentError;
+use crate::routes::app_manager::AppManager;
+use crate::routes::instance_routes::{find_tracked, resolve_id};
+use crate::routes::models::StatsHistory;
+
+const DEFAULT_WINDOW_SECS: u64 = 60 * 60;
+
+// "90", "30s", "15m", "1h" or "7d"
+fn parse_span(field: &str, value: &str) -> Result<Duration, AgentError> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 && multiplier > 0 => Ok(Duration::from_secs(number.saturating_mul(multiplier))),
+        _ => Err(AgentError::BadRequest(format!("{} '{}' is not a duration like 30s, 15m, 1h or 7d", field, value))),
+    }
+}
+
+/// CPU, memory and network usage of a tracked instance over the last `window` (1h by default),
+/// one point per `resolution` step (the sampling interval by default)
+#[get("/instances/<id>/stats/history?<window>&<resolution>")]
+pub async fn get_instance_stats_history(id: String, window: Option<String>, resolution: Option<String>, app_manager: &State<AppManager>) -> Result<Json<StatsHistory>, AgentError> {
+    if !app_manager.stats.is_enabled() {
+        return Err(AgentError::Unavailable("Stats history is disabled in the agent configuration".to_string()));
+    }
+    let window = match window {
+        Some(window) => parse_span("window", &window)?,
+        None => Duration::from_secs(DEFAULT_WINDOW_SECS),
+    };
+    let resolution = match resolution {
+        Some(resolution) => parse_span("resolution", &resolution)?,
+        None => app_manager.stats.interval(),
+    };
+    if resolution > window {
+        return Err(AgentError::BadRequest("resolution must not be longer than window".to_string()));
+    }
+    let full_id = resolve_id(app_manager, &id, "Failed to get stats history").await?;
+    let instance = find_tracked(app_manager, &full_id)
+        .ok_or_else(|| AgentError::NotFound(format!("Instance {} is not tracked by this agent", id)))?;
+
+    Ok(Json(StatsHistory {
+        points: app_manager.stats.downsample(&instance.id, app_manager.now(), window, resolution),
+        instance_id: instance.id,
+        window_secs: window.as_secs(),
+        resolution_secs: resolution.as_secs(),
+    }))
+}
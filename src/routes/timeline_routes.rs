@@ -0,0 +1,64 @@
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket::State;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::InstanceEvent;
+
+/// State transitions of an instance, oldest first, with who caused each. The timeline outlives
+/// the instance, so deleted and replaced instances can still be looked up by full id or name.
+#[get("/instances/<id>/timeline")]
+pub async fn get_instance_timeline(id: String, app_manager: &State<AppManager>) -> Json<Vec<InstanceEvent>> {
+    Json(app_manager.timeline.for_instance(&id))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use serde_json::json;
+    use crate::routes::models::InstanceEvent;
+    use crate::testing::{DockerResponse, TestAgent};
+    use crate::timeline;
+
+    fn events(timeline: &[InstanceEvent]) -> Vec<(&str, &str)> {
+        timeline.iter().map(|event| (event.event.as_str(), event.actor.as_str())).collect()
+    }
+
+    #[tokio::test]
+    async fn lifecycle_is_recorded_in_order() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("POST", "/images/create", |_| DockerResponse::ok(json!({})));
+        agent.docker.route("POST", "/containers/create", |_| DockerResponse::Json(201, json!({ "Id": "ab12cd", "Warnings": [] })));
+        agent.docker.route("POST", "/containers/ab12cd/*", |_| DockerResponse::Json(204, json!(null)));
+        agent.docker.route("DELETE", "/containers/ab12cd", |_| DockerResponse::Json(204, json!(null)));
+        agent.docker.route("GET", "/containers/ab12cd/json", |_| DockerResponse::ok(json!({
+            "Id": "ab12cd",
+            "Name": "/game",
+            "Config": { "Image": "horizon:1" },
+            "State": { "Status": "running", "Running": true },
+        })));
+        // Another instance whose id the first one's is a prefix of
+        timeline::record(&agent.app_manager, "ab12cdef", "lobby", "created", "api", None);
+        let client = agent.client().await;
+
+        let response = client.post("/instances").header(ContentType::JSON).body(r#"{"name": "game", "image": "horizon:1"}"#).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        for action in ["stop", "start", "restart"] {
+            let response = client.put(format!("/instances/ab12cd/{}", action)).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", action);
+        }
+        assert_eq!(client.delete("/instances/ab12cd").dispatch().await.status(), Status::Ok);
+
+        let expected = [("created", "api"), ("stopped", "api"), ("started", "api"), ("restarted", "api"), ("deleted", "api")];
+        for key in ["ab12cd", "game"] {
+            let timeline: Vec<InstanceEvent> = client.get(format!("/instances/{}/timeline", key)).dispatch().await.into_json().await.unwrap();
+            assert_eq!(events(&timeline), expected, "{}", key);
+            assert!(timeline.iter().all(|event| event.instance_id == "ab12cd"));
+        }
+
+        // Neither an id prefix nor a name prefix picks up anything
+        for key in ["ab12", "gam"] {
+            let timeline: Vec<InstanceEvent> = client.get(format!("/instances/{}/timeline", key)).dispatch().await.into_json().await.unwrap();
+            assert!(timeline.is_empty(), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use rocket::{delete, get, post};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::{ApiToken, TunnelToken};
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::models::{TunnelInfo, TunnelRequest};
+
+const DEFAULT_TUNNEL_TTL_SECS: u64 = 15 * 60;
+const MAX_TUNNEL_TTL_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_TUNNEL_CONNECTIONS: u32 = 4;
+const MAX_TUNNEL_CONNECTIONS: u32 = 64;
+
+// Debug Tunnels
+
+/// Opens a temporary TCP forward from a random port on the agent host to `container_port`
+/// of a running instance, e.g. to reach a game server's admin port behind NAT. Anyone who can
+/// reach the agent can connect to the port, so opening one takes a token with the tunnel permission.
+#[post("/instances/<id>/tunnel", format = "json", data = "<tunnel_req>")]
+pub async fn open_tunnel(id: String, tunnel_req: Json<TunnelRequest>, app_manager: &State<AppManager>, _auth: TunnelToken, _docker: DockerAvailable) -> Result<Json<TunnelInfo>, AgentError> {
+    let ttl_secs = tunnel_req.ttl_secs.unwrap_or(DEFAULT_TUNNEL_TTL_SECS);
+    if ttl_secs == 0 || ttl_secs > MAX_TUNNEL_TTL_SECS {
+        return Err(AgentError::BadRequest(format!("ttl_secs must be between 1 and {}", MAX_TUNNEL_TTL_SECS)));
+    }
+    let max_connections = tunnel_req.max_connections.unwrap_or(DEFAULT_TUNNEL_CONNECTIONS);
+    if max_connections == 0 || max_connections > MAX_TUNNEL_CONNECTIONS {
+        return Err(AgentError::BadRequest(format!("max_connections must be between 1 and {}", MAX_TUNNEL_CONNECTIONS)));
+    }
+    if tunnel_req.container_port == 0 {
+        return Err(AgentError::BadRequest("container_port must not be 0".to_string()));
+    }
+
+    let container = match app_manager.instrument("inspect_container", app_manager.docker().inspect_container(&id, None)).await {
+        Ok(container) => container,
+        Err(e) => return Err(AgentError::docker("Failed to inspect instance", e)),
+    };
+    if !container.state.as_ref().and_then(|state| state.running).unwrap_or(false) {
+        return Err(AgentError::Conflict(format!("Instance {} is not running", id)));
+    }
+
+    // Containers on the host network are reached through loopback, others through their first network's address
+    let host_network = container.host_config.as_ref()
+        .and_then(|host_config| host_config.network_mode.as_deref())
+        == Some("host");
+    let ip = if host_network {
+        Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    } else {
+        container.network_settings.as_ref()
+            .and_then(|settings| settings.networks.as_ref())
+            .into_iter()
+            .flat_map(|networks| networks.values())
+            .filter_map(|network| network.ip_address.as_deref())
+            .find_map(|ip| ip.parse().ok())
+    };
+    let Some(ip) = ip else {
+        return Err(AgentError::Conflict(format!("Instance {} has no network address to tunnel to", id)));
+    };
+
+    let instance_id = container.id.unwrap_or(id);
+    let instance_name = container.name.unwrap_or_default().trim_start_matches('/').to_string();
+    match app_manager.tunnels.open(&instance_id, &instance_name, SocketAddr::new(ip, tunnel_req.container_port), Duration::from_secs(ttl_secs), max_connections).await {
+        Ok(tunnel) => Ok(Json(tunnel)),
+        Err(e) => Err(AgentError::Internal(format!("Failed to open tunnel listener: {}", e))),
+    }
+}
+
+#[get("/tunnels")]
+pub async fn list_tunnels(app_manager: &State<AppManager>, _auth: ApiToken) -> Json<Vec<TunnelInfo>> {
+    Json(app_manager.tunnels.list())
+}
+
+#[delete("/tunnels/<tunnel_id>")]
+pub async fn close_tunnel(tunnel_id: String, app_manager: &State<AppManager>, _auth: ApiToken) -> Result<String, AgentError> {
+    if !app_manager.tunnels.close(&tunnel_id) {
+        return Err(AgentError::NotFound(format!("Tunnel {} not found", tunnel_id)));
+    }
+    Ok(format!("Tunnel {} closed", tunnel_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Header, Status};
+    use serde_json::{json, Value};
+    use crate::config::ApiTokenConfig;
+    use crate::routes::auth::TUNNEL_PERMISSION;
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn token(id: &str, permissions: &[&str]) -> ApiTokenConfig {
+        ApiTokenConfig {
+            id: id.to_string(),
+            token: format!("{}-secret", id),
+            permissions: permissions.iter().map(|permission| permission.to_string()).collect(),
+        }
+    }
+
+    fn running(agent: &TestAgent) {
+        agent.docker.route("GET", "/containers/game/json", |_| DockerResponse::ok(json!({
+            "Id": "ab12",
+            "Name": "/game",
+            "State": { "Running": true },
+            "HostConfig": { "NetworkMode": "host" },
+        })));
+    }
+
+    #[tokio::test]
+    async fn tunnels_are_refused_while_the_api_is_open() {
+        let agent = TestAgent::start().await;
+        running(&agent);
+        let client = agent.client().await;
+
+        let response = client.post("/instances/game/tunnel").header(ContentType::JSON).body(r#"{"container_port": 7777}"#).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(agent.app_manager.tunnels.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tunnels_need_the_tunnel_permission() {
+        let agent = TestAgent::with_config(|config| config.auth.tokens = vec![token("deployer", &[]), token("ops", &[TUNNEL_PERMISSION])]).await;
+        running(&agent);
+        let client = agent.client().await;
+        let open = |bearer: &'static str| client.post("/instances/game/tunnel")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", bearer))
+            .body(r#"{"container_port": 7777}"#)
+            .dispatch();
+
+        assert_eq!(open("Bearer guess").await.status(), Status::Unauthorized);
+        assert_eq!(open("Bearer deployer-secret").await.status(), Status::Forbidden);
+        assert!(agent.app_manager.tunnels.list().is_empty());
+
+        let response = open("Bearer ops-secret").await;
+        assert_eq!(response.status(), Status::Ok);
+        let tunnel: Value = response.into_json().await.unwrap();
+        assert_eq!(tunnel["instance_id"], "ab12");
+        assert!(agent.app_manager.tunnels.close(tunnel["id"].as_str().unwrap()));
+    }
+}
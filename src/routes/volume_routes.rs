@@ -1,46 +1,54 @@
 use rocket::{delete, get, post};
 use rocket::serde::json::Json;
 use rocket::State;
-use crate::routes::app_manager::AppManager;
+use crate::docker_cache::Listing;
+use crate::routes::errors::AgentError;
+use crate::routes::auth::ApiToken;
+use crate::routes::app_manager::{AppManager, DockerAvailable};
 use crate::routes::models::{VolumeInfo, VolumeCreateRequest};
 
 // Volume Management
 
 #[get("/volumes")]
-pub async fn list_volumes(app_manager: &State<AppManager>) -> Result<Json<Vec<VolumeInfo>>, String> {
-    match app_manager.docker.list_volumes::<String>(None).await {
-        Ok(volumes) => {
-            let volume_list = volumes.volumes.unwrap_or_default().into_iter()
-                .filter_map(|vol| {
+pub async fn list_volumes(app_manager: &State<AppManager>) -> Result<Listing<Vec<VolumeInfo>>, AgentError> {
+    match app_manager.cache.volumes(app_manager).await {
+        Ok(volumes) => Ok(volumes.map(|volumes| {
+            volumes.into_iter()
+                .map(|vol| {
                     let name = vol.name;
                     let mountpoint = vol.mountpoint;
                     let labels = vol.labels;
                     let created_at = vol.created_at.unwrap_or_default();
                     
-                    Some(VolumeInfo {
+                    VolumeInfo {
                         name,
                         mountpoint,
                         labels,
                         created_at,
-                    })
+                    }
                 })
-                .collect();
-            
-            Ok(Json(volume_list))
-        },
-        Err(e) => Err(format!("Failed to list volumes: {}", e))
+                .collect()
+        })),
+        Err(e) => Err(AgentError::docker("Failed to list volumes", e))
     }
 }
 
 #[post("/volumes", format = "json", data = "<volume_req>")]
-pub async fn create_volume(volume_req: Json<VolumeCreateRequest>, app_manager: &State<AppManager>) -> Result<Json<VolumeInfo>, String> {
+pub async fn create_volume(volume_req: Json<VolumeCreateRequest>, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<Json<VolumeInfo>, AgentError> {
+    // The daemon hands back an existing volume of the same name as if it had created it
+    match app_manager.instrument("inspect_volume", app_manager.docker().inspect_volume(&volume_req.name)).await {
+        Ok(_) => return Err(AgentError::Conflict(format!("Volume {} already exists", volume_req.name))),
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {},
+        Err(e) => return Err(AgentError::docker("Failed to check for an existing volume", e)),
+    }
+
     let options = bollard::volume::CreateVolumeOptions {
         name: volume_req.name.clone(),
         labels: volume_req.labels.clone().unwrap_or_default(),
         ..Default::default()
     };
     
-    match app_manager.docker.create_volume(options).await {
+    match app_manager.instrument("create_volume", app_manager.docker().create_volume(options)).await {
         Ok(volume) => {
             let volume_info = VolumeInfo {
                 name: volume.name,
@@ -51,14 +59,46 @@ pub async fn create_volume(volume_req: Json<VolumeCreateRequest>, app_manager: &
             
             Ok(Json(volume_info))
         },
-        Err(e) => Err(format!("Failed to create volume: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to create volume", e))
     }
 }
 
 #[delete("/volumes/<name>")]
-pub async fn delete_volume(name: String, app_manager: &State<AppManager>) -> Result<String, String> {
-    match app_manager.docker.remove_volume(&name, None).await {
+pub async fn delete_volume(name: String, app_manager: &State<AppManager>, _auth: ApiToken, _docker: DockerAvailable) -> Result<String, AgentError> {
+    match app_manager.instrument("remove_volume", app_manager.docker().remove_volume(&name, None)).await {
         Ok(_) => Ok(format!("Volume {} deleted successfully", name)),
-        Err(e) => Err(format!("Failed to delete volume: {}", e))
+        Err(e) => Err(AgentError::docker("Failed to delete volume", e))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use serde_json::{json, Value};
+    use crate::testing::{DockerResponse, TestAgent};
+
+    #[tokio::test]
+    async fn creating_an_existing_volume_conflicts() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("GET", "/volumes/data", |_| DockerResponse::ok(json!({ "Name": "data", "Driver": "local", "Mountpoint": "/var/lib/docker/volumes/data/_data", "Labels": {}, "Scope": "local", "Options": {} })));
+        let client = agent.client().await;
+
+        let response = client.post("/volumes").header(ContentType::JSON).body(r#"{"name": "data"}"#).dispatch().await;
+        assert_eq!(response.status(), Status::Conflict);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["error"]["code"], "conflict");
+        assert!(agent.docker.requests_to("POST", "/volumes/create").is_empty());
+    }
+
+    #[tokio::test]
+    async fn creates_a_new_volume() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("POST", "/volumes/create", |request| DockerResponse::Json(201, json!({ "Name": request.json()["Name"], "Driver": "local", "Mountpoint": "/var/lib/docker/volumes/data/_data", "Labels": {}, "Scope": "local", "Options": {} })));
+        let client = agent.client().await;
+
+        let response = client.post("/volumes").header(ContentType::JSON).body(r#"{"name": "data"}"#).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().await.unwrap();
+        assert_eq!(body["name"], "data");
+    }
+}
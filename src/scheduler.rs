@@ -0,0 +1,567 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use rocket::State;
+use crate::routes::app_manager::{AppManager, DockerAvailable};
+use crate::routes::auth::ApiToken;
+use crate::routes::errors::AgentError;
+use crate::routes::instance_routes::{restart_instance, start_instance, stop_instance};
+use crate::routes::models::{MaintenancePeriod, MaintenanceWindow, Schedule, ScheduleBook, ScheduleExecution};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const HISTORY_LIMIT: usize = 50;
+// Who scheduled actions are attributed to in instance timelines
+const SCHEDULER_ACTOR: &str = "scheduler";
+// Bounds the work for windows with very frequent cron expressions
+const WINDOW_OCCURRENCE_LIMIT: usize = 1000;
+const ACTIVE_PERIOD_PASSES: usize = 8;
+
+/// Actions a schedule can run against its instance
+pub const ACTIONS: [&str; 3] = ["restart", "stop", "start"];
+
+/// Parses a standard 5-field cron expression, or a 6-field one with leading seconds
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, String> {
+    let expression = expression.trim();
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&expression).map_err(|e| e.to_string())
+}
+
+/// Parses an IANA time zone name such as "Europe/Berlin"
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.trim().parse::<Tz>().map_err(|_| format!("Unknown time zone '{}'", name))
+}
+
+// The stored zone of a schedule or window; names were validated when it was created
+fn stored_timezone(name: Option<&str>) -> Tz {
+    name.and_then(|name| parse_timezone(name).ok()).unwrap_or(Tz::UTC)
+}
+
+// The first instant whose wall-clock time in `timezone` is at or past `local`, i.e. the moment
+// the clock jumps over a time that doesn't exist because of a DST change
+fn end_of_gap(timezone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    // UTC offsets are well within a day, so the instant lies between these; DST changes fall on whole seconds
+    let (mut low, mut high) = (local.and_utc().timestamp() - 86_400, local.and_utc().timestamp() + 86_400);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        match DateTime::from_timestamp(middle, 0) {
+            Some(instant) if instant.with_timezone(&timezone).naive_local() >= local => high = middle,
+            _ => low = middle,
+        }
+    }
+    DateTime::from_timestamp(high, 0).unwrap_or_else(|| local.and_utc())
+}
+
+/// Times `cron` fires strictly after `after`, with its fields read as wall-clock time in `timezone`.
+/// A time skipped by a DST change fires when the clock jumps past it, and a time repeated when
+/// the clock falls back fires once, the first time round.
+pub fn fire_times(cron: &cron::Schedule, timezone: Tz, after: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+    // cron walks the wall-clock times as if they were UTC, so it never sees a DST change
+    let local_after = after.with_timezone(&timezone).naive_local().and_utc();
+    let mut last: Option<DateTime<Utc>> = None;
+    cron.after(&local_after).filter_map(move |local| {
+        let local = local.naive_utc();
+        let instant = match timezone.from_local_datetime(&local) {
+            LocalResult::Single(instant) | LocalResult::Ambiguous(instant, _) => instant.with_timezone(&Utc),
+            LocalResult::None => end_of_gap(timezone, local),
+        };
+        // Every time in a gap maps to its end, and the repeated hour maps back before `after`
+        let fresh = instant > after && last.is_none_or(|last| instant > last);
+        if fresh {
+            last = Some(instant);
+        }
+        fresh.then_some(instant)
+    })
+}
+
+/// First time `expression` fires strictly after `after` in `timezone` (UTC when unset), formatted as RFC 3339
+pub fn next_run(expression: &str, timezone: Option<&str>, after: DateTime<Utc>) -> Option<String> {
+    fire_times(&parse_cron(expression).ok()?, stored_timezone(timezone), after)
+        .next()
+        .map(|next| next.to_rfc3339())
+}
+
+/// `timestamp` (RFC 3339) as wall-clock time in `timezone`, for display next to the UTC value
+pub fn local_time(timestamp: &str, timezone: Tz) -> Option<String> {
+    DateTime::parse_from_rfc3339(timestamp).ok()
+        .map(|timestamp| timestamp.with_timezone(&timezone).to_rfc3339())
+}
+
+// One window occurrence or a merged run of them
+struct Period {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    window_ids: Vec<String>,
+    message: Option<String>,
+}
+
+impl Period {
+    fn to_model(&self) -> MaintenancePeriod {
+        MaintenancePeriod {
+            window_ids: self.window_ids.clone(),
+            starts_at: self.start.to_rfc3339(),
+            ends_at: self.end.to_rfc3339(),
+            message: self.message.clone(),
+            starts_at_local: None,
+            ends_at_local: None,
+        }
+    }
+}
+
+// Window occurrences overlapping [from, until), merged where they overlap or touch
+fn periods(windows: &[MaintenanceWindow], from: DateTime<Utc>, until: DateTime<Utc>) -> Vec<Period> {
+    let mut occurrences = Vec::new();
+    for window in windows {
+        let Ok(cron) = parse_cron(&window.cron) else { continue };
+        let duration = TimeDelta::seconds(window.duration_secs as i64);
+        // Start early enough to catch an occurrence that is already in progress at `from`
+        for start in fire_times(&cron, stored_timezone(window.timezone.as_deref()), from - duration).take(WINDOW_OCCURRENCE_LIMIT) {
+            if start >= until {
+                break;
+            }
+            occurrences.push(Period {
+                start,
+                end: start + duration,
+                window_ids: vec![window.id.clone()],
+                message: window.message.clone(),
+            });
+        }
+    }
+    occurrences.sort_by_key(|period| period.start);
+
+    let mut periods: Vec<Period> = Vec::new();
+    for occurrence in occurrences {
+        match periods.last_mut() {
+            Some(last) if occurrence.start <= last.end => {
+                last.end = last.end.max(occurrence.end);
+                for id in occurrence.window_ids {
+                    if !last.window_ids.contains(&id) {
+                        last.window_ids.push(id);
+                    }
+                }
+                last.message = last.message.take().or(occurrence.message);
+            },
+            _ => periods.push(occurrence),
+        }
+    }
+    periods.retain(|period| period.end > from);
+    periods
+}
+
+/// Maintenance periods overlapping `[from, until)`, in start order, with overlapping
+/// occurrences of different windows merged into one period
+pub fn maintenance_periods(windows: &[MaintenanceWindow], from: DateTime<Utc>, until: DateTime<Utc>) -> Vec<MaintenancePeriod> {
+    periods(windows, from, until).iter().map(Period::to_model).collect()
+}
+
+/// The maintenance period in progress at `now`, if any
+pub fn active_period(windows: &[MaintenanceWindow], now: DateTime<Utc>) -> Option<MaintenancePeriod> {
+    let longest = windows.iter().map(|window| window.duration_secs).max()?;
+
+    // Merged periods can chain past any single window's duration, so widen the range
+    // until the period around `now` lies strictly inside it
+    let mut reach = TimeDelta::seconds(longest.max(1) as i64);
+    let mut active = None;
+    for _ in 0..ACTIVE_PERIOD_PASSES {
+        let (from, until) = (now - reach, now + reach);
+        let period = periods(windows, from, until).into_iter()
+            .find(|period| period.start <= now && now < period.end)?;
+        let complete = period.start > from && period.end < until;
+        active = Some(period);
+        if complete {
+            break;
+        }
+        reach = reach * 2;
+    }
+    active.as_ref().map(Period::to_model)
+}
+
+impl ScheduleBook {
+    /// Drops the schedules and history of the instance with the full container id `instance_id`
+    pub fn remove_instance(&mut self, instance_id: &str) -> bool {
+        let before = self.schedules.len() + self.history.len();
+        self.schedules.retain(|schedule| schedule.instance_id != instance_id);
+        self.history.remove(instance_id);
+        self.schedules.len() + self.history.len() != before
+    }
+}
+
+/// Fires due schedules once a second. At most one scheduled action runs per instance at a time;
+/// anything that comes due while one is in flight is skipped and recorded as such.
+pub async fn run(app_manager: AppManager) {
+    // Runs missed while the agent was down aren't caught up on
+    {
+        let now = app_manager.now();
+        let mut book = app_manager.schedules.lock().unwrap();
+        for schedule in book.schedules.iter_mut() {
+            schedule.next_run_at = next_run(&schedule.cron, schedule.timezone.as_deref(), now);
+        }
+    }
+
+    // Window state is derived from the definitions, so a window that was open before a restart is still open after it
+    let mut window = current_period(&app_manager);
+    if let Some(period) = &window {
+        tracing::info!("Resuming maintenance window {} until {}", period.window_ids.join(", "), period.ends_at);
+    }
+
+    let running: Arc<Mutex<HashSet<String>>> = Arc::default();
+    loop {
+        tokio::time::sleep(TICK_INTERVAL).await;
+        #[cfg(feature = "chaos")]
+        app_manager.chaos.maybe_panic("scheduler");
+        track_window(&app_manager, &mut window);
+        tick(&app_manager, &running);
+    }
+}
+
+fn current_period(app_manager: &AppManager) -> Option<MaintenancePeriod> {
+    active_period(&app_manager.schedules.lock().unwrap().windows, app_manager.now())
+}
+
+// Notify webhooks when a scheduled maintenance window opens or closes
+fn track_window(app_manager: &AppManager, window: &mut Option<MaintenancePeriod>) {
+    let current = current_period(app_manager);
+    match (&*window, &current) {
+        (None, Some(period)) => {
+            tracing::info!("Maintenance window {} opened until {}", period.window_ids.join(", "), period.ends_at);
+            app_manager.notifier.notify("agent.maintenance_started", serde_json::json!(period));
+        },
+        (Some(period), None) => {
+            tracing::info!("Maintenance window {} closed", period.window_ids.join(", "));
+            app_manager.notifier.notify("agent.maintenance_ended", serde_json::json!(period));
+        },
+        _ => {},
+    }
+    *window = current;
+}
+
+fn tick(app_manager: &AppManager, running: &Arc<Mutex<HashSet<String>>>) {
+    let now = app_manager.now();
+    let due: Vec<Schedule> = {
+        let mut book = app_manager.schedules.lock().unwrap();
+        let mut due = Vec::new();
+        for schedule in book.schedules.iter_mut() {
+            let is_due = schedule.next_run_at.as_deref()
+                .and_then(|next| DateTime::parse_from_rfc3339(next).ok())
+                .is_some_and(|next| next <= now);
+            if is_due {
+                due.push(schedule.clone());
+                schedule.next_run_at = next_run(&schedule.cron, schedule.timezone.as_deref(), now);
+            }
+        }
+        due
+    };
+
+    for schedule in due {
+        if !running.lock().unwrap().insert(schedule.instance_id.clone()) {
+            tracing::warn!("Skipping scheduled {} of {} (schedule {}): a previous scheduled action is still running", schedule.action, schedule.instance_name, schedule.id);
+            record(app_manager, &schedule, now, now, "skipped", Some("A previous scheduled action is still running".to_string()));
+            continue;
+        }
+
+        let app_manager = app_manager.clone();
+        let running = running.clone();
+        tokio::spawn(async move {
+            tracing::info!("Running scheduled {} of {} (schedule {})", schedule.action, schedule.instance_name, schedule.id);
+            let result = execute(&app_manager, &schedule).await;
+            running.lock().unwrap().remove(&schedule.instance_id);
+
+            match result {
+                Ok(_) => record(&app_manager, &schedule, now, app_manager.now(), "success", None),
+                Err(e) => {
+                    tracing::warn!("Scheduled {} of {} failed: {}", schedule.action, schedule.instance_name, e);
+                    record(&app_manager, &schedule, now, app_manager.now(), "failed", Some(e.to_string()));
+                }
+            }
+        });
+    }
+}
+
+// Goes through the route handlers so supervision is suspended and re-armed the same way as for API calls
+async fn execute(app_manager: &AppManager, schedule: &Schedule) -> Result<(), AgentError> {
+    let state = State::from(app_manager);
+    let id = schedule.instance_id.clone();
+    match schedule.action.as_str() {
+        "restart" => restart_instance(id, state, ApiToken(SCHEDULER_ACTOR.to_string()), DockerAvailable).await.map(|_| ()),
+        "stop" => stop_instance(id, state, ApiToken(SCHEDULER_ACTOR.to_string()), DockerAvailable).await.map(|_| ()),
+        "start" => start_instance(id, state, ApiToken(SCHEDULER_ACTOR.to_string()), DockerAvailable).await.map(|_| ()),
+        other => Err(AgentError::BadRequest(format!("Unknown scheduled action '{}'", other))),
+    }
+}
+
+fn record(app_manager: &AppManager, schedule: &Schedule, started_at: DateTime<Utc>, finished_at: DateTime<Utc>, outcome: &str, message: Option<String>) {
+    let window_id = {
+        let book = app_manager.schedules.lock().unwrap();
+        active_period(&book.windows, started_at).and_then(|period| period.window_ids.into_iter().next())
+    };
+    {
+        let mut book = app_manager.schedules.lock().unwrap();
+        let history = book.history.entry(schedule.instance_id.clone()).or_default();
+        history.push(ScheduleExecution {
+            schedule_id: schedule.id.clone(),
+            action: schedule.action.clone(),
+            started_at: started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            outcome: outcome.to_string(),
+            message,
+            window_id,
+            started_at_local: None,
+            finished_at_local: None,
+        });
+        if history.len() > HISTORY_LIMIT {
+            let excess = history.len() - HISTORY_LIMIT;
+            history.drain(..excess);
+        }
+    }
+    app_manager.persist_schedules();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::clock::{Clock, TestClock};
+    use crate::testing::{DockerResponse, TestAgent};
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)
+    }
+
+    fn window(id: &str, cron: &str, duration_secs: u64) -> MaintenanceWindow {
+        MaintenanceWindow {
+            id: id.to_string(),
+            cron: cron.to_string(),
+            timezone: None,
+            duration_secs,
+            message: None,
+            created_at: "2026-01-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn next_run_follows_the_clock() {
+        let clock = TestClock::at(at("2026-03-01T00:00:00Z"));
+        assert_eq!(next_run("*/5 * * * *", None, clock.now()).as_deref(), Some("2026-03-01T00:05:00+00:00"));
+
+        // A run that is exactly due moves on to the next one
+        clock.advance(TimeDelta::minutes(5));
+        assert_eq!(next_run("*/5 * * * *", None, clock.now()).as_deref(), Some("2026-03-01T00:10:00+00:00"));
+        clock.advance(TimeDelta::seconds(299));
+        assert_eq!(next_run("*/5 * * * *", None, clock.now()).as_deref(), Some("2026-03-01T00:10:00+00:00"));
+    }
+
+    #[test]
+    fn window_opens_and_closes_with_the_clock() {
+        let windows = [window("nightly", "0 3 * * *", 3600)];
+        let clock = TestClock::at(at("2026-03-01T02:59:00Z"));
+        assert!(active_period(&windows, clock.now()).is_none());
+
+        clock.advance(TimeDelta::minutes(1));
+        let period = active_period(&windows, clock.now()).unwrap();
+        assert_eq!(period.starts_at, "2026-03-01T03:00:00+00:00");
+        assert_eq!(period.ends_at, "2026-03-01T04:00:00+00:00");
+
+        clock.advance(TimeDelta::minutes(59));
+        assert!(active_period(&windows, clock.now()).is_some());
+        clock.advance(TimeDelta::minutes(1));
+        assert!(active_period(&windows, clock.now()).is_none());
+    }
+
+    #[test]
+    fn touching_windows_merge_into_one_period() {
+        let windows = [window("first", "0 3 * * *", 3600), window("second", "0 4 * * *", 1800)];
+        let clock = TestClock::at(at("2026-03-01T04:15:00Z"));
+        let period = active_period(&windows, clock.now()).unwrap();
+        assert_eq!(period.starts_at, "2026-03-01T03:00:00+00:00");
+        assert_eq!(period.ends_at, "2026-03-01T04:30:00+00:00");
+        assert_eq!(period.window_ids, ["first", "second"]);
+
+        let periods = maintenance_periods(&windows, clock.now(), clock.now() + TimeDelta::days(1));
+        assert_eq!(periods.len(), 2);
+        assert_eq!(periods[1].starts_at, "2026-03-02T03:00:00+00:00");
+    }
+
+    fn fires(cron: &str, timezone: &str, after: &str, count: usize) -> Vec<String> {
+        let cron = parse_cron(cron).unwrap();
+        fire_times(&cron, parse_timezone(timezone).unwrap(), at(after))
+            .take(count)
+            .map(|instant| instant.to_rfc3339())
+            .collect()
+    }
+
+    fn local(timestamp: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn gap_ends_when_the_clock_springs_forward() {
+        assert_eq!(end_of_gap(chrono_tz::America::New_York, local("2026-03-08T02:30:00")), at("2026-03-08T07:00:00Z"));
+        assert_eq!(end_of_gap(chrono_tz::Europe::London, local("2026-03-29T01:30:00")), at("2026-03-29T01:00:00Z"));
+    }
+
+    #[test]
+    fn skipped_time_fires_at_the_end_of_the_gap() {
+        // 02:30 doesn't exist in New York on 8 March; 03:00 EDT is 07:00 UTC
+        assert_eq!(fires("30 2 * * *", "America/New_York", "2026-03-07T12:00:00Z", 2), [
+            "2026-03-08T07:00:00+00:00",
+            "2026-03-09T06:30:00+00:00",
+        ]);
+        // 01:30 doesn't exist in London on 29 March; 02:00 BST is 01:00 UTC
+        assert_eq!(fires("30 1 * * *", "Europe/London", "2026-03-28T12:00:00Z", 2), [
+            "2026-03-29T01:00:00+00:00",
+            "2026-03-30T00:30:00+00:00",
+        ]);
+    }
+
+    #[test]
+    fn hourly_runs_fire_once_across_the_gap() {
+        // 02:00 and 03:00 local are the same instant on the day the clocks go forward
+        assert_eq!(fires("0 * * * *", "America/New_York", "2026-03-08T05:30:00Z", 3), [
+            "2026-03-08T06:00:00+00:00",
+            "2026-03-08T07:00:00+00:00",
+            "2026-03-08T08:00:00+00:00",
+        ]);
+        assert_eq!(fires("0 * * * *", "Europe/London", "2026-03-29T00:30:00Z", 2), [
+            "2026-03-29T01:00:00+00:00",
+            "2026-03-29T02:00:00+00:00",
+        ]);
+    }
+
+    #[test]
+    fn repeated_time_fires_the_first_time_round() {
+        // 01:30 happens twice in New York on 1 November, first as EDT (05:30 UTC) then as EST
+        assert_eq!(fires("30 1 * * *", "America/New_York", "2026-10-31T12:00:00Z", 2), [
+            "2026-11-01T05:30:00+00:00",
+            "2026-11-02T06:30:00+00:00",
+        ]);
+        assert_eq!(fires("30 1 * * *", "Europe/London", "2026-10-24T12:00:00Z", 2), [
+            "2026-10-25T00:30:00+00:00",
+            "2026-10-26T01:30:00+00:00",
+        ]);
+    }
+
+    #[test]
+    fn repeated_hour_does_not_fire_again() {
+        // Hourly runs skip the second 01:00 in New York, which is 06:00 UTC
+        assert_eq!(fires("0 * * * *", "America/New_York", "2026-11-01T03:30:00Z", 3), [
+            "2026-11-01T04:00:00+00:00",
+            "2026-11-01T05:00:00+00:00",
+            "2026-11-01T07:00:00+00:00",
+        ]);
+        // Asked during the second 01:xx, the one that already happened isn't run again
+        assert_eq!(fires("30 1 * * *", "America/New_York", "2026-11-01T06:10:00Z", 1), ["2026-11-02T06:30:00+00:00"]);
+        assert_eq!(fires("30 1 * * *", "Europe/London", "2026-10-25T01:10:00Z", 1), ["2026-10-26T01:30:00+00:00"]);
+    }
+
+    fn schedule(id: &str, instance_id: &str, cron: &str, now: DateTime<Utc>) -> Schedule {
+        Schedule {
+            id: id.to_string(),
+            instance_id: instance_id.to_string(),
+            instance_name: format!("game-{}", instance_id),
+            cron: cron.to_string(),
+            action: "restart".to_string(),
+            timezone: None,
+            created_at: now.to_rfc3339(),
+            next_run_at: next_run(cron, None, now),
+            next_run_at_local: None,
+        }
+    }
+
+    fn execution(schedule_id: &str) -> ScheduleExecution {
+        ScheduleExecution {
+            schedule_id: schedule_id.to_string(),
+            action: "restart".to_string(),
+            started_at: "2026-03-01T03:00:00+00:00".to_string(),
+            finished_at: "2026-03-01T03:00:01+00:00".to_string(),
+            outcome: "success".to_string(),
+            message: None,
+            window_id: None,
+            started_at_local: None,
+            finished_at_local: None,
+        }
+    }
+
+    #[test]
+    fn removing_an_instance_leaves_ids_it_prefixes_alone() {
+        let now = at("2026-03-01T00:00:00Z");
+        let mut book = ScheduleBook {
+            schedules: vec![schedule("s1", "ab", "0 3 * * *", now), schedule("s2", "ab12", "0 4 * * *", now)],
+            history: [("ab".to_string(), vec![execution("s1")]), ("ab12".to_string(), vec![execution("s2")])].into(),
+            windows: Vec::new(),
+        };
+
+        assert!(book.remove_instance("ab"));
+        assert_eq!(book.schedules.iter().map(|schedule| schedule.id.as_str()).collect::<Vec<_>>(), ["s2"]);
+        assert_eq!(book.history.keys().collect::<Vec<_>>(), ["ab12"]);
+
+        // Neither a prefix nor an unknown id removes anything
+        assert!(!book.remove_instance("ab1"));
+        assert!(!book.remove_instance("ab"));
+        assert_eq!(book.schedules.len(), 1);
+    }
+
+    async fn history(agent: &TestAgent, instance_id: &str) -> Vec<ScheduleExecution> {
+        for _ in 0..200 {
+            let history = agent.app_manager.schedules.lock().unwrap().history.get(instance_id).cloned().unwrap_or_default();
+            if !history.is_empty() {
+                return history;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Vec::new()
+    }
+
+    #[tokio::test]
+    async fn due_schedules_run_on_the_second_they_are_due() {
+        let agent = TestAgent::start().await;
+        agent.docker.route("POST", "/containers/ab12/restart", |_| DockerResponse::Json(204, json!(null)));
+        agent.docker.route("GET", "/containers/ab12/json", |_| DockerResponse::ok(json!({
+            "Id": "ab12",
+            "Name": "/game-ab12",
+            "Config": { "Image": "horizon:1" },
+            "State": { "Status": "running", "Running": true },
+        })));
+        agent.clock.advance(at("2026-03-01T02:59:59Z") - agent.clock.now());
+        agent.app_manager.schedules.lock().unwrap().schedules.push(schedule("s1", "ab12", "0 3 * * *", agent.clock.now()));
+        let running = Arc::default();
+
+        tick(&agent.app_manager, &running);
+        assert!(agent.docker.requests_to("POST", "/containers/ab12/restart").is_empty());
+
+        agent.clock.advance(TimeDelta::seconds(1));
+        tick(&agent.app_manager, &running);
+        let history = history(&agent, "ab12").await;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].outcome, "success");
+        assert_eq!(history[0].started_at, "2026-03-01T03:00:00+00:00");
+        assert_eq!(agent.docker.requests_to("POST", "/containers/ab12/restart").len(), 1);
+        assert_eq!(agent.app_manager.schedules.lock().unwrap().schedules[0].next_run_at.as_deref(), Some("2026-03-02T03:00:00+00:00"));
+
+        // Not due again until tomorrow
+        agent.clock.advance(TimeDelta::seconds(1));
+        tick(&agent.app_manager, &running);
+        assert!(running.lock().unwrap().is_empty());
+        assert_eq!(agent.docker.requests_to("POST", "/containers/ab12/restart").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn runs_overlapping_one_in_flight_are_skipped() {
+        let agent = TestAgent::start().await;
+        agent.clock.advance(at("2026-03-01T02:59:59Z") - agent.clock.now());
+        agent.app_manager.schedules.lock().unwrap().schedules.push(schedule("s1", "ab12", "0 3 * * *", agent.clock.now()));
+        let running = Arc::new(Mutex::new(HashSet::from(["ab12".to_string()])));
+
+        agent.clock.advance(TimeDelta::seconds(1));
+        tick(&agent.app_manager, &running);
+        let history = history(&agent, "ab12").await;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].outcome, "skipped");
+        assert!(agent.docker.requests_to("POST", "/containers/ab12/restart").is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::crypto::{Sealed, Sealer};
+use crate::routes::models::{SecretValue, MASKED_SECRET};
+use crate::store;
+
+const SECRETS_FILE: &str = "secrets.json";
+
+/// Instance secrets by container id, persisted encrypted under encryption_key
+pub struct SecretStore {
+    sealer: Option<Sealer>,
+    path: PathBuf,
+    entries: Mutex<HashMap<String, HashMap<String, Sealed>>>,
+}
+
+/// The API view of a set of secrets: names only, values masked
+pub fn masked<'a>(names: impl IntoIterator<Item = &'a String>) -> HashMap<String, String> {
+    names.into_iter()
+        .map(|name| (name.clone(), MASKED_SECRET.to_string()))
+        .collect()
+}
+
+impl SecretStore {
+    pub fn load(sealer: Option<Sealer>, data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(SECRETS_FILE);
+        let entries = store::load_json(&path)?;
+
+        Ok(SecretStore {
+            sealer,
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Whether a key is configured, i.e. whether secrets can be stored at all
+    pub fn is_enabled(&self) -> bool {
+        self.sealer.is_some()
+    }
+
+    /// Replaces the stored secrets of `instance_id`; an empty map removes them
+    pub fn set(&self, instance_id: &str, secrets: &HashMap<String, SecretValue>) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if secrets.is_empty() {
+            if entries.remove(instance_id).is_none() {
+                return Ok(());
+            }
+        } else {
+            let sealer = self.sealer.as_ref()
+                .ok_or_else(|| io::Error::other("encryption_key is not configured"))?;
+            let sealed = secrets.iter()
+                .map(|(name, value)| Ok((name.clone(), sealer.seal(value.expose())?)))
+                .collect::<io::Result<HashMap<_, _>>>()?;
+            entries.insert(instance_id.to_string(), sealed);
+        }
+        store::save_json(&self.path, &*entries)
+    }
+
+    /// Decrypted secrets of `instance_id`; values that can't be decrypted are skipped with a warning
+    pub fn get(&self, instance_id: &str) -> HashMap<String, SecretValue> {
+        let entries = self.entries.lock().unwrap();
+        let (Some(sealer), Some(sealed)) = (self.sealer.as_ref(), entries.get(instance_id)) else {
+            return HashMap::new();
+        };
+
+        sealed.iter()
+            .filter_map(|(name, value)| match sealer.open(value) {
+                Some(value) => Some((name.clone(), SecretValue::new(value))),
+                None => {
+                    tracing::warn!("Secret {} of {} can't be decrypted with the configured key", name, instance_id);
+                    None
+                }
+            })
+            .collect()
+    }
+
+}
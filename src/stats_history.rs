@@ -0,0 +1,324 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use bollard::container::{Stats, StatsOptions};
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use crate::config::StatsHistoryConfig;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::StatsPoint;
+use crate::store;
+
+const STATS_HISTORY_FILE: &str = "stats_history.json";
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sample {
+    timestamp: DateTime<Utc>,
+    cpu_percent: f64,
+    memory_bytes: u64,
+    /// Bytes received and sent since the previous sample
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+// Cumulative counters from the previous stats read, which the next sample is a delta against
+#[derive(Clone, Copy)]
+struct Counters {
+    cpu_total: u64,
+    system_total: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+impl Counters {
+    fn read(stats: &Stats) -> Self {
+        let networks = stats.networks.iter().flat_map(|networks| networks.values());
+        Counters {
+            cpu_total: stats.cpu_stats.cpu_usage.total_usage,
+            system_total: stats.cpu_stats.system_cpu_usage.unwrap_or_default(),
+            rx_bytes: networks.clone().map(|network| network.rx_bytes).sum(),
+            tx_bytes: networks.map(|network| network.tx_bytes).sum(),
+        }
+    }
+}
+
+// Counters go back to zero when the container restarts, in which case everything counted so far is new
+fn delta(current: u64, previous: u64) -> u64 {
+    current.checked_sub(previous).unwrap_or(current)
+}
+
+/// Recent resource usage samples per tracked instance, each series capped at max_points
+pub struct StatsRecorder {
+    enabled: bool,
+    interval: Duration,
+    max_points: usize,
+    path: Option<PathBuf>,
+    series: Mutex<HashMap<String, VecDeque<Sample>>>,
+}
+
+impl StatsRecorder {
+    /// Restores persisted samples when `persist` is set; unreadable history is dropped with a warning
+    pub fn load(config: &StatsHistoryConfig, data_dir: &Path) -> Self {
+        let path = config.persist.then(|| data_dir.join(STATS_HISTORY_FILE));
+        let mut series: HashMap<String, VecDeque<Sample>> = match &path {
+            Some(path) => store::load_json(path).unwrap_or_else(|e| {
+                tracing::warn!("Discarding unreadable stats history: {}", e);
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+        for samples in series.values_mut() {
+            while samples.len() > config.max_points {
+                samples.pop_front();
+            }
+        }
+
+        StatsRecorder {
+            enabled: config.enabled,
+            interval: Duration::from_secs(config.interval_secs),
+            max_points: config.max_points,
+            path,
+            series: Mutex::new(series),
+        }
+    }
+
+    /// Whether stats_history.enabled is set, i.e. whether anything is being sampled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Time between samples
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn record(&self, instance_id: &str, sample: Sample) {
+        let mut series = self.series.lock().unwrap();
+        let samples = series.entry(instance_id.to_string()).or_default();
+        if samples.len() >= self.max_points {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else { return };
+        if let Err(e) = store::save_json(path, &*self.series.lock().unwrap()) {
+            tracing::warn!("Failed to persist stats history: {}", e);
+        }
+    }
+
+    /// The most recent sample of an instance as a single point
+    pub fn latest(&self, instance_id: &str) -> Option<StatsPoint> {
+        let series = self.series.lock().unwrap();
+        let sample = series.get(instance_id)?.back()?;
+        Some(StatsPoint {
+            timestamp: sample.timestamp.to_rfc3339(),
+            cpu_percent: sample.cpu_percent,
+            memory_bytes: sample.memory_bytes,
+            rx_bytes: sample.rx_bytes,
+            tx_bytes: sample.tx_bytes,
+            samples: 1,
+        })
+    }
+
+    /// Samples of the `window` up to `now`, folded into `resolution` steps: CPU and memory are
+    /// averaged and network traffic summed per step. Steps without samples are left out.
+    pub fn downsample(&self, instance_id: &str, now: DateTime<Utc>, window: Duration, resolution: Duration) -> Vec<StatsPoint> {
+        let series = self.series.lock().unwrap();
+        let Some(samples) = series.get(instance_id) else { return Vec::new() };
+        let step = resolution.as_secs().max(1) as i64;
+        let start = now - chrono::Duration::seconds(window.as_secs() as i64);
+
+        let mut points: Vec<(i64, StatsPoint)> = Vec::new();
+        for sample in samples.iter().filter(|sample| sample.timestamp >= start) {
+            // Steps are aligned to the epoch so points line up across requests
+            let bucket = sample.timestamp.timestamp().div_euclid(step);
+            match points.last_mut() {
+                Some((last, point)) if *last == bucket => {
+                    // Running means, so long windows don't need a second pass
+                    point.samples += 1;
+                    let count = point.samples as f64;
+                    point.cpu_percent += (sample.cpu_percent - point.cpu_percent) / count;
+                    point.memory_bytes = (point.memory_bytes as f64 + (sample.memory_bytes as f64 - point.memory_bytes as f64) / count) as u64;
+                    point.rx_bytes += sample.rx_bytes;
+                    point.tx_bytes += sample.tx_bytes;
+                },
+                _ => points.push((bucket, StatsPoint {
+                    timestamp: DateTime::from_timestamp(bucket * step, 0).unwrap_or(start).to_rfc3339(),
+                    cpu_percent: sample.cpu_percent,
+                    memory_bytes: sample.memory_bytes,
+                    rx_bytes: sample.rx_bytes,
+                    tx_bytes: sample.tx_bytes,
+                    samples: 1,
+                })),
+            }
+        }
+        points.into_iter().map(|(_, point)| point).collect()
+    }
+}
+
+async fn read_stats(app_manager: &AppManager, id: &str) -> Option<Stats> {
+    let options = Some(StatsOptions {
+        stream: false,
+        one_shot: true,
+    });
+    match app_manager.instrument("stats", app_manager.docker().stats(id, options).try_next()).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            tracing::debug!("Failed to sample stats of {}: {}", id, e);
+            None
+        }
+    }
+}
+
+/// Samples every tracked instance each stats_history.interval_secs. An instance's first read only sets the
+/// baseline its CPU percentage and network deltas are measured against.
+pub async fn run(app_manager: AppManager) {
+    let history = app_manager.stats.clone();
+    let mut previous: HashMap<String, Counters> = HashMap::new();
+    let mut persisted_at = tokio::time::Instant::now();
+    let mut ticker = tokio::time::interval(history.interval());
+
+    loop {
+        ticker.tick().await;
+        let ids: Vec<String> = app_manager.instances().into_iter().map(|instance| instance.id).collect();
+        let reads = futures::future::join_all(ids.iter().map(|id| read_stats(&app_manager, id))).await;
+        let now = app_manager.now();
+
+        for (id, stats) in ids.iter().zip(reads) {
+            let Some(stats) = stats else {
+                previous.remove(id);
+                continue;
+            };
+            let counters = Counters::read(&stats);
+            let Some(last) = previous.insert(id.clone(), counters) else { continue };
+
+            let system_delta = delta(counters.system_total, last.system_total);
+            let cpu_percent = if system_delta > 0 {
+                let cpus = stats.cpu_stats.online_cpus.unwrap_or(1) as f64;
+                delta(counters.cpu_total, last.cpu_total) as f64 / system_delta as f64 * cpus * 100.0
+            } else {
+                0.0
+            };
+            history.record(id, Sample {
+                timestamp: now,
+                cpu_percent,
+                memory_bytes: stats.memory_stats.usage.unwrap_or_default(),
+                rx_bytes: delta(counters.rx_bytes, last.rx_bytes),
+                tx_bytes: delta(counters.tx_bytes, last.tx_bytes),
+            });
+        }
+
+        // Deleted instances take their history with them
+        previous.retain(|id, _| ids.contains(id));
+        history.series.lock().unwrap().retain(|id, _| ids.contains(id));
+        if persisted_at.elapsed() >= PERSIST_INTERVAL {
+            history.persist();
+            persisted_at = tokio::time::Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+    use crate::clock::{Clock, TestClock};
+
+    const INTERVAL: TimeDelta = TimeDelta::seconds(30);
+
+    fn recorder(max_points: usize, data_dir: &Path) -> StatsRecorder {
+        StatsRecorder::load(&StatsHistoryConfig {
+            enabled: true,
+            interval_secs: INTERVAL.num_seconds() as u64,
+            max_points,
+            persist: true,
+        }, data_dir)
+    }
+
+    // `count` samples of "game" one interval apart, the n-th using n% CPU, n KB of memory and
+    // receiving 10 and sending 1 byte, leaving the clock one interval past the last
+    fn sample(recorder: &StatsRecorder, clock: &TestClock, count: u64) {
+        for n in 0..count {
+            recorder.record("game", Sample {
+                timestamp: clock.now(),
+                cpu_percent: n as f64,
+                memory_bytes: n * 1000,
+                rx_bytes: 10,
+                tx_bytes: 1,
+            });
+            clock.advance(INTERVAL);
+        }
+    }
+
+    fn noon() -> TestClock {
+        TestClock::at(Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn samples_are_folded_into_steps_of_the_window() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let recorder = recorder(1000, data_dir.path());
+        let clock = noon();
+        sample(&recorder, &clock, 120);
+
+        let hour = recorder.downsample("game", clock.now(), Duration::from_secs(3600), Duration::from_secs(300));
+        assert_eq!(hour.len(), 12);
+        assert_eq!(hour[0].timestamp, "2026-03-01T12:00:00+00:00");
+        assert_eq!(hour[11].timestamp, "2026-03-01T12:55:00+00:00");
+        assert!(hour.iter().all(|point| point.samples == 10 && point.rx_bytes == 100 && point.tx_bytes == 10));
+        assert!((hour[0].cpu_percent - 4.5).abs() < 1e-9);
+        assert_eq!(hour[0].memory_bytes, 4500);
+        assert!((hour[11].cpu_percent - 114.5).abs() < 1e-9);
+
+        // Only what falls in the window, which ends at the clock's now
+        let quarter = recorder.downsample("game", clock.now(), Duration::from_secs(900), Duration::from_secs(300));
+        assert_eq!(quarter.len(), 3);
+        assert_eq!(quarter[0].timestamp, "2026-03-01T12:45:00+00:00");
+        clock.advance(TimeDelta::minutes(10));
+        assert_eq!(recorder.downsample("game", clock.now(), Duration::from_secs(900), Duration::from_secs(300)).len(), 1);
+
+        assert_eq!(recorder.series.lock().unwrap()["game"].back().unwrap().cpu_percent, 119.0);
+        assert!(recorder.downsample("lobby", clock.now(), Duration::from_secs(3600), Duration::from_secs(300)).is_empty());
+    }
+
+    #[test]
+    fn steps_without_samples_are_left_out() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let recorder = recorder(1000, data_dir.path());
+        let clock = noon();
+        sample(&recorder, &clock, 2);
+        clock.advance(TimeDelta::minutes(10));
+        sample(&recorder, &clock, 2);
+
+        let points = recorder.downsample("game", clock.now(), Duration::from_secs(3600), Duration::from_secs(60));
+        let timestamps: Vec<&str> = points.iter().map(|point| point.timestamp.as_str()).collect();
+        assert_eq!(timestamps, ["2026-03-01T12:00:00+00:00", "2026-03-01T12:11:00+00:00"]);
+        assert!(points.iter().all(|point| point.samples == 2));
+    }
+
+    #[test]
+    fn the_oldest_samples_are_evicted_past_max_points() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let recorder = recorder(5, data_dir.path());
+        let clock = noon();
+        sample(&recorder, &clock, 8);
+
+        let points = recorder.downsample("game", clock.now(), Duration::from_secs(3600), Duration::from_secs(30));
+        let cpu: Vec<f64> = points.iter().map(|point| point.cpu_percent).collect();
+        assert_eq!(cpu, [3.0, 4.0, 5.0, 6.0, 7.0]);
+
+        // A lower cap applies to history restored from disk too
+        recorder.persist();
+        let restored = self::recorder(3, data_dir.path());
+        let points = restored.downsample("game", clock.now(), Duration::from_secs(3600), Duration::from_secs(30));
+        let cpu: Vec<f64> = points.iter().map(|point| point.cpu_percent).collect();
+        assert_eq!(cpu, [5.0, 6.0, 7.0]);
+        sample(&restored, &clock, 1);
+        assert_eq!(restored.downsample("game", clock.now(), Duration::from_secs(3600), Duration::from_secs(30)).len(), 3);
+    }
+}
@@ -0,0 +1,21 @@
+use std::io;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Reads a JSON document from `path`, returning the default value when the file doesn't exist yet
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes `value` to `path` as JSON, going through a temporary file so readers never see a partial write
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let bytes = serde_json::to_vec_pretty(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
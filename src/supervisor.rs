@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use bollard::container::StartContainerOptions;
+use bollard::models::EventMessage;
+use bollard::system::EventsOptions;
+use futures::StreamExt;
+use serde_json::json;
+use crate::config::SupervisorConfig;
+use crate::crash_reports;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::SupervisionState;
+use crate::timeline;
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+// Who crashes and restarts are attributed to in instance timelines
+const SUPERVISOR_ACTOR: &str = "supervisor";
+
+impl SupervisionState {
+    pub fn started(now: chrono::DateTime<chrono::Utc>) -> Self {
+        SupervisionState {
+            state: "running".to_string(),
+            attempts: 0,
+            next_retry_at: None,
+            last_started_at: Some(now.to_rfc3339()),
+            last_exit_code: None,
+        }
+    }
+}
+
+/// Watches container die events and restarts supervised instances with exponential backoff
+pub async fn run(app_manager: AppManager, config: SupervisorConfig) {
+    loop {
+        let mut filters = HashMap::new();
+        filters.insert("type".to_string(), vec!["container".to_string()]);
+        filters.insert("event".to_string(), vec!["die".to_string()]);
+        let options = Some(EventsOptions::<String> {
+            filters,
+            ..Default::default()
+        });
+
+        let mut events = app_manager.docker().events(options);
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => {
+                    #[cfg(feature = "chaos")]
+                    {
+                        app_manager.chaos.maybe_panic("supervisor");
+                        if app_manager.chaos.drops_event("supervisor") {
+                            continue;
+                        }
+                    }
+                    handle_die(&app_manager, &config, event)
+                },
+                Err(e) => {
+                    tracing::warn!("Supervisor lost the Docker event stream: {}", e);
+                    break;
+                }
+            }
+        }
+
+        // The DockerHandle monitor takes care of reconnecting; just resubscribe once it has
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+fn handle_die(app_manager: &AppManager, config: &SupervisorConfig, event: EventMessage) {
+    let Some(actor) = event.actor else { return };
+    let Some(id) = actor.id else { return };
+    let exit_code = actor.attributes
+        .and_then(|attributes| attributes.get("exitCode").and_then(|code| code.parse().ok()));
+
+    let outcome = app_manager.update_instance(&id, |instance| {
+        let supervision = instance.supervision.as_mut()?;
+        // Stops through the API and restarts we already scheduled aren't crashes
+        if supervision.state != "running" {
+            return None;
+        }
+
+        let uptime = supervision.last_started_at.as_deref()
+            .and_then(|started| chrono::DateTime::parse_from_rfc3339(started).ok())
+            .map(|started| app_manager.now().signed_duration_since(started));
+        if uptime.is_some_and(|uptime| uptime.num_seconds() >= config.healthy_uptime_secs as i64) {
+            supervision.attempts = 0;
+        }
+
+        supervision.last_exit_code = exit_code;
+        if supervision.attempts >= config.max_attempts {
+            supervision.state = "failed".to_string();
+            supervision.next_retry_at = None;
+            instance.status = "failed".to_string();
+            Some((instance.name.clone(), supervision.attempts, true))
+        } else {
+            supervision.attempts += 1;
+            let delay = backoff(config, supervision.attempts);
+            supervision.state = "backoff".to_string();
+            supervision.next_retry_at = Some((app_manager.now() + delay).to_rfc3339());
+            Some((instance.name.clone(), supervision.attempts, false))
+        }
+    });
+    let Some((name, attempts, failed)) = outcome else { return };
+    let exit = exit_code.map_or("exited".to_string(), |code| format!("exit code {}", code));
+    timeline::record(app_manager, &id, &name, "crashed", SUPERVISOR_ACTOR, Some(exit));
+
+    if failed {
+        tokio::spawn(crash_reports::capture(app_manager.clone(), id.clone(), exit_code, attempts));
+        tracing::error!("Instance {} is still crashing after {} restarts, giving up", id, attempts);
+        timeline::record(app_manager, &id, &name, "failed", SUPERVISOR_ACTOR, Some(format!("gave up after {} restarts", attempts)));
+        notify_failed(app_manager, json!({
+            "id": id,
+            "attempts": attempts,
+            "exit_code": exit_code,
+        }));
+        return;
+    }
+
+    let delay = backoff(config, attempts);
+    tracing::warn!("Instance {} exited with {:?}, restart {} of {} in {}s", id, exit_code, attempts, config.max_attempts, delay.as_secs());
+    // The report is captured before the restart is scheduled, so its logs and state are the crash's own
+    let app_manager = app_manager.clone();
+    tokio::spawn(async move {
+        crash_reports::capture(app_manager.clone(), id.clone(), exit_code, attempts).await;
+        restart_after(app_manager, id, delay).await;
+    });
+}
+
+// Crashes during maintenance are expected restarts, so they're logged without alerting
+fn notify_failed(app_manager: &AppManager, data: serde_json::Value) {
+    if let Some(maintenance) = app_manager.maintenance() {
+        tracing::info!("Not sending instance.failed during maintenance ({})", maintenance.message);
+        return;
+    }
+    app_manager.notifier.notify("instance.failed", data);
+}
+
+// initial * 2^(attempt - 1), capped at max_backoff_secs
+fn backoff(config: &SupervisorConfig, attempt: u32) -> Duration {
+    let secs = config.initial_backoff_secs
+        .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+        .min(config.max_backoff_secs);
+    Duration::from_secs(secs)
+}
+
+async fn restart_after(app_manager: AppManager, id: String, delay: Duration) {
+    tokio::time::sleep(delay).await;
+
+    // Flip back to running before starting so an immediate crash is still seen as one.
+    // The instance may have been stopped or deleted while we were waiting.
+    let flipped = app_manager.update_instance(&id, |instance| {
+        let supervision = instance.supervision.as_mut()?;
+        if supervision.state != "backoff" {
+            return None;
+        }
+        supervision.state = "running".to_string();
+        supervision.next_retry_at = None;
+        supervision.last_started_at = Some(app_manager.now().to_rfc3339());
+        instance.status = "running".to_string();
+        Some((instance.name.clone(), supervision.attempts))
+    });
+    let Some((name, attempt)) = flipped else { return };
+
+    match app_manager.instrument("start_container", app_manager.docker().start_container(&id, None::<StartContainerOptions<String>>)).await {
+        Ok(_) => {
+            tracing::info!("Restarted supervised instance {}", id);
+            timeline::record(&app_manager, &id, &name, "restarted", SUPERVISOR_ACTOR, Some(format!("restart attempt {}", attempt)));
+        },
+        Err(e) => {
+            // A container that can't even be started won't fix itself on the next attempt
+            tracing::error!("Failed to restart supervised instance {}: {}", id, e);
+            app_manager.update_instance(&id, |instance| {
+                if let Some(supervision) = instance.supervision.as_mut() {
+                    supervision.state = "failed".to_string();
+                }
+                instance.status = "failed".to_string();
+                Some(())
+            });
+            timeline::record(&app_manager, &id, &name, "failed", SUPERVISOR_ACTOR, Some(e.to_string()));
+            notify_failed(&app_manager, json!({
+                "id": id,
+                "error": e.to_string(),
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{instance, DockerResponse, TestAgent};
+
+    fn died(id: &str) -> EventMessage {
+        serde_json::from_value(json!({
+            "Type": "container",
+            "Action": "die",
+            "Actor": { "ID": id, "Attributes": { "exitCode": "137" } },
+        })).unwrap()
+    }
+
+    async fn supervised(attempts: u32) -> TestAgent {
+        let agent = TestAgent::start().await;
+        agent.docker.route("GET", "/containers/ab12/json", |_| DockerResponse::ok(json!({
+            "Id": "ab12",
+            "Name": "/game",
+            "State": { "Status": "exited", "Running": false, "ExitCode": 137, "OOMKilled": true },
+        })));
+        agent.docker.route("POST", "/containers/ab12/start", |_| DockerResponse::Json(204, json!(null)));
+        agent.app_manager.upsert_instance(crate::routes::models::AppInstance {
+            supervision: Some(SupervisionState {
+                attempts,
+                ..SupervisionState::started(agent.app_manager.now())
+            }),
+            ..instance("ab12", "game")
+        });
+        agent
+    }
+
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition never held");
+    }
+
+    fn config() -> SupervisorConfig {
+        SupervisorConfig {
+            max_attempts: 3,
+            initial_backoff_secs: 0,
+            ..SupervisorConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn crash_is_captured_before_the_restart() {
+        let agent = supervised(0).await;
+        handle_die(&agent.app_manager, &config(), died("ab12"));
+
+        eventually(|| !agent.docker.requests_to("POST", "/containers/ab12/start").is_empty()).await;
+        let requests: Vec<String> = agent.docker.requests().iter().map(|request| format!("{} {}", request.method, request.path)).collect();
+        let started = requests.iter().position(|request| request == "POST /containers/ab12/start").unwrap();
+        let inspected = requests.iter().position(|request| request == "GET /containers/ab12/json").unwrap();
+        let logs = requests.iter().position(|request| request == "GET /containers/ab12/logs").unwrap();
+        assert!(inspected < started && logs < started, "{:?}", requests);
+
+        let reports = agent.app_manager.crashes.for_instance("ab12");
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].restart_attempt, 1);
+        assert!(reports[0].oom_killed);
+    }
+
+    #[tokio::test]
+    async fn giving_up_reports_the_attempts_made() {
+        let agent = supervised(3).await;
+        handle_die(&agent.app_manager, &config(), died("ab12"));
+
+        eventually(|| !agent.app_manager.crashes.for_instance("ab12").is_empty()).await;
+        assert_eq!(agent.app_manager.crashes.for_instance("ab12")[0].restart_attempt, 3);
+        assert_eq!(agent.app_manager.instance("ab12").unwrap().status, "failed");
+        assert!(agent.docker.requests_to("POST", "/containers/ab12/start").is_empty());
+    }
+}
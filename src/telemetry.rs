@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use opentelemetry::global;
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Response, Route};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+/// Header used to correlate a request across the dashboard, API and agent
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// W3C trace context header, propagated from callers and returned on responses
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+const SERVICE_NAME: &str = "horizon-maestro-agent";
+
+// Longest caller-supplied request id that is reused; it ends up in every log line of the request
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Installs the global tracing subscriber and the W3C trace context propagator.
+///
+/// `MAESTRO_LOG` holds the filter directives (defaults to `info`) and
+/// `MAESTRO_LOG_FORMAT` selects `pretty` (default) or `json` output.
+/// Spans are exported over OTLP/HTTP when `OTEL_EXPORTER_OTLP_ENDPOINT` or
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` is set; the returned provider has to be
+/// shut down on exit so pending spans get flushed.
+pub fn init() -> SdkTracerProvider {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = tracer_provider();
+
+    let filter = EnvFilter::try_from_env("MAESTRO_LOG").unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    // Rocket installs its own `log` logger, so we deliberately don't bridge `log` records here
+    let result = match std::env::var("MAESTRO_LOG_FORMAT").as_deref() {
+        Ok("json") => tracing::subscriber::set_global_default(registry.with(tracing_subscriber::fmt::layer().json())),
+        _ => tracing::subscriber::set_global_default(registry.with(tracing_subscriber::fmt::layer())),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to install tracing subscriber: {}", e);
+    }
+    provider
+}
+
+// Without an exporter spans still get trace ids, they just aren't sent anywhere
+fn tracer_provider() -> SdkTracerProvider {
+    let resource = match std::env::var_os("OTEL_SERVICE_NAME") {
+        Some(_) => Resource::builder().build(),
+        None => Resource::builder().with_service_name(SERVICE_NAME).build(),
+    };
+    let mut builder = SdkTracerProvider::builder().with_resource(resource);
+
+    let configured = ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"].iter()
+        .any(|var| std::env::var_os(var).is_some());
+    if configured {
+        match opentelemetry_otlp::SpanExporter::builder().with_http().build() {
+            Ok(exporter) => builder = builder.with_batch_exporter(exporter),
+            Err(e) => eprintln!("Failed to create the OTLP span exporter, spans won't be exported: {}", e),
+        }
+    }
+    builder.build()
+}
+
+/// Wraps every route handler so it runs inside its request's `http_request` span,
+/// which makes the Docker calls it makes child spans of the request
+pub fn traced(routes: Vec<Route>) -> Vec<Route> {
+    routes.into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(TracedHandler(route.handler));
+            route
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct TracedHandler(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for TracedHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let span = request.local_cache(|| RequestSpan(tracing::Span::none())).0.clone();
+        self.0.handle(request, data).instrument(span).await
+    }
+}
+
+/// Correlation id assigned to the current request
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Time at which the current request was received
+struct RequestStart(Instant);
+
+/// Span covering the current request, parented to the caller's traceparent if it sent one
+struct RequestSpan(tracing::Span);
+
+// Ids from callers are echoed into logs and response headers, so only short, plain ones are kept
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
+
+/// Fairing that assigns every request an `x-request-id` (reusing the caller's
+/// when it's a plain one of at most 128 characters), opens its span and records method, path, status and duration once it completes
+pub struct RequestTracer;
+
+#[rocket::async_trait]
+impl Fairing for RequestTracer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Tracer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let id = request.headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(|id| is_valid_request_id(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let carrier: HashMap<String, String> = request.headers().iter()
+            .map(|header| (header.name().as_str().to_ascii_lowercase(), header.value().to_string()))
+            .collect();
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+
+        let span = tracing::info_span!(
+            "http_request",
+            request_id = %id,
+            method = %request.method(),
+            path = %request.uri().path(),
+            trace_id = tracing::field::Empty,
+        );
+        if let Err(e) = span.set_parent(parent) {
+            tracing::debug!("Failed to attach the caller's trace context: {}", e);
+        }
+        span.record("trace_id", tracing::field::display(span.context().span().span_context().trace_id()));
+
+        request.local_cache(|| RequestId(id));
+        request.local_cache(|| RequestStart(Instant::now()));
+        request.local_cache(|| RequestSpan(span));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let id = request.local_cache(|| RequestId(Uuid::new_v4().to_string())).0.clone();
+        let started = request.local_cache(|| RequestStart(Instant::now())).0;
+
+        let span = &request.local_cache(|| RequestSpan(tracing::Span::none())).0;
+        span.in_scope(|| tracing::info!(
+            status = response.status().code,
+            duration_ms = started.elapsed().as_secs_f64() * 1000.0,
+            "request completed"
+        ));
+
+        let mut carrier = HashMap::new();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&span.context(), &mut carrier));
+        if let Some(traceparent) = carrier.remove(TRACEPARENT_HEADER) {
+            response.set_header(Header::new(TRACEPARENT_HEADER, traceparent));
+        }
+        response.set_header(Header::new(REQUEST_ID_HEADER, id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use crate::testing::TestAgent;
+
+    #[rocket::get("/")]
+    fn index() -> &'static str {
+        "ok"
+    }
+
+    #[tokio::test]
+    async fn only_plain_request_ids_are_echoed() {
+        let client = Client::tracked(rocket::build().attach(RequestTracer).mount("/", rocket::routes![index])).await.unwrap();
+        let long = "a".repeat(MAX_REQUEST_ID_LEN);
+
+        for (sent, echoed) in [
+            ("dash-7f3a.2:1", true),
+            (long.as_str(), true),
+            (&format!("{}a", long), false),
+            ("id with spaces", false),
+            ("evil\u{1b}[31m", false),
+            ("", false),
+        ] {
+            let response = client.get("/").header(Header::new(REQUEST_ID_HEADER, sent.to_string())).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            let id = response.headers().get_one(REQUEST_ID_HEADER).unwrap();
+            assert_eq!(id == sent, echoed, "{:?}", sent);
+            if !echoed {
+                assert!(Uuid::parse_str(id).is_ok(), "{:?}", id);
+            }
+        }
+    }
+
+    // The subscriber and propagator main installs, for the current thread
+    fn traced_thread() -> tracing::subscriber::DefaultGuard {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = tracer_provider();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+        tracing::subscriber::set_default(subscriber)
+    }
+
+    // (trace id, span id) of a traceparent header
+    fn ids(traceparent: &str) -> (&str, &str) {
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4, "{}", traceparent);
+        (parts[1], parts[2])
+    }
+
+    #[tokio::test]
+    async fn trace_context_is_continued_from_the_caller() {
+        let _tracing = traced_thread();
+        let agent = TestAgent::start().await;
+        let client = agent.client().await;
+
+        let caller = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let response = client.get("/instances").header(Header::new(TRACEPARENT_HEADER, caller)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let (trace_id, span_id) = ids(response.headers().get_one(TRACEPARENT_HEADER).unwrap());
+        assert_eq!(trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        // The agent's own span, a child of the caller's
+        assert_ne!(span_id, "00f067aa0ba902b7");
+
+        // Without one, every request starts a trace of its own
+        let first = client.get("/instances").dispatch().await;
+        let second = client.get("/instances").dispatch().await;
+        let first = ids(first.headers().get_one(TRACEPARENT_HEADER).unwrap()).0.to_string();
+        let second = ids(second.headers().get_one(TRACEPARENT_HEADER).unwrap()).0.to_string();
+        assert_ne!(first, "0".repeat(32));
+        assert_ne!(first, second);
+    }
+}
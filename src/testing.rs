@@ -0,0 +1,322 @@
+//! Test doubles: a fake Docker daemon that speaks just enough of the Engine API over TCP, and an
+//! agent wired to it with a fake clock and a scratch data dir
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use rocket::local::asynchronous::Client;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use crate::agent::Agent;
+use crate::clock::TestClock;
+use crate::config::AgentConfig;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::AppInstance;
+
+/// A request the fake daemon received, with the API version prefix stripped from the path
+#[derive(Debug, Clone)]
+pub struct DockerRequest {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+impl DockerRequest {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+}
+
+pub enum DockerResponse {
+    Json(u16, Value),
+    /// A chunked 200 with one JSON document per line as they're sent, ending when the sender is dropped
+    Stream(mpsc::UnboundedReceiver<Value>),
+    /// A 101 upgrade, after which everything written to the connection is echoed back
+    Echo,
+}
+
+impl DockerResponse {
+    pub fn ok(body: Value) -> Self {
+        DockerResponse::Json(200, body)
+    }
+
+    /// An error as the daemon reports them
+    pub fn error(status: u16, message: &str) -> Self {
+        DockerResponse::Json(status, json!({ "message": message }))
+    }
+}
+
+type Handler = Box<dyn Fn(&DockerRequest) -> DockerResponse + Send + Sync>;
+
+struct Route {
+    method: String,
+    pattern: String,
+    handler: Handler,
+}
+
+impl Route {
+    // Patterns match whole paths, with * standing for one segment
+    fn matches(&self, request: &DockerRequest) -> bool {
+        let pattern: Vec<&str> = self.pattern.split('/').collect();
+        let path: Vec<&str> = request.path.split('/').collect();
+        self.method == request.method
+            && pattern.len() == path.len()
+            && pattern.iter().zip(&path).all(|(expected, segment)| *expected == "*" || expected == segment)
+    }
+}
+
+#[derive(Default)]
+struct Daemon {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<DockerRequest>>,
+}
+
+/// A Docker daemon on a local TCP port. Routes registered later take precedence, so a test can
+/// override the empty listings it starts with; anything unrouted is a 404.
+#[derive(Clone)]
+pub struct FakeDocker {
+    address: SocketAddr,
+    daemon: Arc<Daemon>,
+}
+
+impl FakeDocker {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let docker = FakeDocker {
+            address: listener.local_addr().unwrap(),
+            daemon: Arc::default(),
+        };
+        docker.route("GET", "/_ping", |_| DockerResponse::ok(json!("OK")));
+        docker.route("GET", "/version", |_| DockerResponse::ok(json!({ "Version": "27.0.0", "ApiVersion": "1.47" })));
+        docker.route("GET", "/containers/json", |_| DockerResponse::ok(json!([])));
+        docker.route("GET", "/images/json", |_| DockerResponse::ok(json!([])));
+        docker.route("GET", "/networks", |_| DockerResponse::ok(json!([])));
+        docker.route("GET", "/volumes", |_| DockerResponse::ok(json!({ "Volumes": [], "Warnings": [] })));
+
+        let daemon = docker.daemon.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(daemon.clone(), socket));
+            }
+        });
+        docker
+    }
+
+    /// The endpoint to put in docker.endpoint
+    pub fn endpoint(&self) -> String {
+        format!("tcp://{}", self.address)
+    }
+
+    pub fn route(&self, method: &str, pattern: &str, handler: impl Fn(&DockerRequest) -> DockerResponse + Send + Sync + 'static) {
+        self.daemon.routes.lock().unwrap().push(Route {
+            method: method.to_string(),
+            pattern: pattern.to_string(),
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Requests received so far other than health checks, oldest first
+    pub fn requests(&self) -> Vec<DockerRequest> {
+        self.daemon.requests.lock().unwrap().iter()
+            .filter(|request| request.path != "/_ping")
+            .cloned()
+            .collect()
+    }
+
+    /// Received requests with `method` to exactly `path`
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<DockerRequest> {
+        self.requests().into_iter()
+            .filter(|request| request.method == method && request.path == path)
+            .collect()
+    }
+}
+
+async fn serve(daemon: Arc<Daemon>, socket: TcpStream) {
+    let mut socket = BufReader::new(socket);
+    while let Some(request) = read_request(&mut socket).await {
+        daemon.requests.lock().unwrap().push(request.clone());
+        let response = {
+            let routes = daemon.routes.lock().unwrap();
+            match routes.iter().rev().find(|route| route.matches(&request)) {
+                Some(route) => (route.handler)(&request),
+                None => DockerResponse::error(404, &format!("no route for {} {}", request.method, request.path)),
+            }
+        };
+
+        let socket = socket.get_mut();
+        match response {
+            DockerResponse::Json(status, body) => {
+                let body = body.to_string();
+                let head = format!("HTTP/1.1 {} Fake\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", status, body.len());
+                if socket.write_all(head.as_bytes()).await.is_err() || socket.write_all(body.as_bytes()).await.is_err() {
+                    return;
+                }
+            },
+            DockerResponse::Stream(mut documents) => {
+                let head = "HTTP/1.1 200 Fake\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n";
+                if socket.write_all(head.as_bytes()).await.is_err() {
+                    return;
+                }
+                while let Some(document) = documents.recv().await {
+                    let line = format!("{}\n", document);
+                    if socket.write_all(format!("{:x}\r\n{}\r\n", line.len(), line).as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+                if socket.write_all(b"0\r\n\r\n").await.is_err() {
+                    return;
+                }
+            },
+            DockerResponse::Echo => {
+                let head = "HTTP/1.1 101 UPGRADED\r\nContent-Type: application/vnd.docker.raw-stream\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\n";
+                if socket.write_all(head.as_bytes()).await.is_err() {
+                    return;
+                }
+                let (mut reader, mut writer) = socket.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                return;
+            },
+        }
+    }
+}
+
+async fn read_request(socket: &mut BufReader<TcpStream>) -> Option<DockerRequest> {
+    let mut line = String::new();
+    socket.read_line(&mut line).await.ok().filter(|read| *read > 0)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+
+    let mut content_length = 0;
+    let mut chunked = false;
+    loop {
+        let mut header = String::new();
+        socket.read_line(&mut header).await.ok().filter(|read| *read > 0)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse().ok()?,
+            "transfer-encoding" => chunked = value.trim().eq_ignore_ascii_case("chunked"),
+            _ => {},
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let mut size = String::new();
+            socket.read_line(&mut size).await.ok()?;
+            let size = usize::from_str_radix(size.trim(), 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            socket.read_exact(&mut chunk).await.ok()?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else {
+        body.resize(content_length, 0);
+        socket.read_exact(&mut body).await.ok()?;
+    }
+
+    let path = target.split_once('?').map_or(target.as_str(), |(path, _)| path);
+    // bollard prefixes every path with the API version, e.g. /v1.47/containers/json
+    let path = match path.strip_prefix("/v").and_then(|rest| rest.split_once('/')) {
+        Some((version, rest)) if version.contains('.') => format!("/{}", rest),
+        _ => path.to_string(),
+    };
+    Some(DockerRequest {
+        method,
+        path,
+        body,
+    })
+}
+
+/// A running instance tracked by the agent, with nothing but its id and name set
+pub fn instance(id: &str, name: &str) -> AppInstance {
+    AppInstance {
+        id: id.to_string(),
+        name: name.to_string(),
+        image: "horizon:1".to_string(),
+        status: "running".to_string(),
+        created_at: "2000000000".to_string(),
+        ports: Vec::new(),
+        environment: HashMap::new(),
+        volumes: Vec::new(),
+        agent_id: "current".to_string(),
+        supervision: None,
+        secrets: HashMap::new(),
+        ship_logs: false,
+        labels: HashMap::new(),
+        recent_events: Vec::new(),
+    }
+}
+
+/// An agent talking to a fake daemon, with its data dir removed when dropped
+pub struct TestAgent {
+    pub docker: FakeDocker,
+    pub app_manager: AppManager,
+    pub config: AgentConfig,
+    pub clock: TestClock,
+    pub data_dir: tempfile::TempDir,
+}
+
+impl TestAgent {
+    pub async fn start() -> Self {
+        Self::with_config(|_| {}).await
+    }
+
+    pub async fn with_config(configure: impl FnOnce(&mut AgentConfig)) -> Self {
+        let docker = FakeDocker::start().await;
+        let data_dir = tempfile::tempdir().unwrap();
+        let mut config = AgentConfig {
+            data_dir: data_dir.path().to_path_buf(),
+            ..AgentConfig::default()
+        };
+        config.docker.endpoint = Some(docker.endpoint());
+        configure(&mut config);
+
+        let clock = TestClock::at(Utc::now());
+        let app_manager = AppManager::new(&config, Arc::new(clock.clone())).await.unwrap();
+        TestAgent {
+            docker,
+            app_manager,
+            config,
+            clock,
+            data_dir,
+        }
+    }
+
+    /// The agent's full rocket, as main builds it
+    fn rocket(&self, config: AgentConfig) -> rocket::Rocket<rocket::Build> {
+        let agent = Agent::load(config.name.clone(), env!("CARGO_PKG_VERSION").to_string(), self.data_dir.path()).unwrap();
+        let certificate = config.server.tls.as_ref().map(|tls| crate::tls::ServerCertificate::load(tls).unwrap());
+        crate::agent_rocket(config, self.app_manager.clone(), agent, certificate)
+    }
+
+    pub async fn client(&self) -> Client {
+        Client::tracked(self.rocket(self.config.clone())).await.unwrap()
+    }
+
+    /// Launches the agent on a free local port, for what the local client can't do such as
+    /// serving TLS or upgrading connections. Returns its address once it accepts connections.
+    pub async fn serve(&self) -> SocketAddr {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut config = self.config.clone();
+        config.server.address = "127.0.0.1".to_string();
+        config.server.port = port;
+        tokio::spawn(self.rocket(config).launch());
+
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        while TcpStream::connect(address).await.is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        address
+    }
+}
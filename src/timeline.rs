@@ -0,0 +1,178 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::routes::app_manager::AppManager;
+use crate::routes::models::InstanceEvent;
+use crate::store;
+
+const TIMELINE_FILE: &str = "timeline.json";
+// Older events of an instance are dropped once it has more than this many
+const EVENTS_PER_INSTANCE: usize = 200;
+// and the oldest overall once there are more than this many, e.g. from instances long deleted
+const MAX_EVENTS: usize = 5000;
+// Events recorded within this long of each other are written in one go
+const FLUSH_DELAY: Duration = Duration::from_secs(1);
+
+/// Append-only record of the state transitions of instances, oldest first. Events are written
+/// to the data dir shortly after they're recorded, off the lock and a burst at a time.
+pub struct TimelineStore {
+    path: PathBuf,
+    events: Arc<Mutex<Vec<InstanceEvent>>>,
+    flush_pending: Arc<AtomicBool>,
+    writing: Arc<Mutex<()>>,
+}
+
+impl TimelineStore {
+    pub fn load(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(TIMELINE_FILE);
+        let events = store::load_json(&path)?;
+
+        Ok(TimelineStore {
+            path,
+            events: Arc::new(Mutex::new(events)),
+            flush_pending: Arc::default(),
+            writing: Arc::default(),
+        })
+    }
+
+    // A write function that saves the events as they are when it runs. Writes are serialized
+    // and each takes its snapshot once it has its turn, so a later write never saves older events.
+    fn writer(&self) -> impl FnOnce() + Send + 'static {
+        let (path, events, writing) = (self.path.clone(), self.events.clone(), self.writing.clone());
+        move || {
+            let _writing = writing.lock().unwrap();
+            let events = events.lock().unwrap().clone();
+            if let Err(e) = store::save_json(&path, &events) {
+                tracing::warn!("Failed to persist the instance timeline: {}", e);
+            }
+        }
+    }
+
+    // Writes the events after FLUSH_DELAY unless a write is already waiting
+    fn schedule_flush(&self) {
+        if self.flush_pending.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            self.flush();
+            return;
+        };
+        let (pending, write) = (self.flush_pending.clone(), self.writer());
+        runtime.spawn(async move {
+            tokio::time::sleep(FLUSH_DELAY).await;
+            // Cleared first, so events recorded while this write runs schedule another
+            pending.store(false, Ordering::SeqCst);
+            if let Err(e) = tokio::task::spawn_blocking(write).await {
+                tracing::warn!("Failed to persist the instance timeline: {}", e);
+            }
+        });
+    }
+
+    /// Writes the events now, for shutdown
+    pub fn flush(&self) {
+        self.flush_pending.store(false, Ordering::SeqCst);
+        self.writer()();
+    }
+
+    fn add(&self, event: InstanceEvent) {
+        let mut events = self.events.lock().unwrap();
+        let instance_id = event.instance_id.clone();
+        events.push(event);
+
+        let own = events.iter().filter(|event| event.instance_id == instance_id).count();
+        let mut excess = own.saturating_sub(EVENTS_PER_INSTANCE);
+        events.retain(|event| {
+            let expired = excess > 0 && event.instance_id == instance_id;
+            if expired {
+                excess -= 1;
+            }
+            !expired
+        });
+        let overflow = events.len().saturating_sub(MAX_EVENTS);
+        events.drain(..overflow);
+        drop(events);
+
+        self.schedule_flush();
+    }
+
+    /// Events of the instance with the full id `key` or the name `key`, including deleted ones,
+    /// so an instance keeps its history across replacements under the same name
+    pub fn for_instance(&self, key: &str) -> Vec<InstanceEvent> {
+        self.events.lock().unwrap().iter()
+            .filter(|event| event.instance_id == key || event.instance_name == key)
+            .cloned()
+            .collect()
+    }
+
+    /// The last `count` events of the instance with exactly this id, oldest first
+    pub fn recent(&self, instance_id: &str, count: usize) -> Vec<InstanceEvent> {
+        let events = self.events.lock().unwrap();
+        let mut recent: Vec<InstanceEvent> = events.iter().rev()
+            .filter(|event| event.instance_id == instance_id)
+            .take(count)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
+
+/// Appends `event` (e.g. "started") to the timeline of an instance. `actor` is who caused it:
+/// "token:<id>" or "api" for requests, or "supervisor", "reconciler" or "scheduler".
+pub fn record(app_manager: &AppManager, instance_id: &str, instance_name: &str, event: &str, actor: &str, detail: Option<String>) {
+    app_manager.timeline.add(InstanceEvent {
+        instance_id: instance_id.to_string(),
+        instance_name: instance_name.to_string(),
+        event: event.to_string(),
+        actor: actor.to_string(),
+        at: app_manager.now().to_rfc3339(),
+        detail,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(instance_id: &str, event: &str) -> InstanceEvent {
+        InstanceEvent {
+            instance_id: instance_id.to_string(),
+            instance_name: "game".to_string(),
+            event: event.to_string(),
+            actor: "api".to_string(),
+            at: "2026-03-01T03:00:00+00:00".to_string(),
+            detail: None,
+        }
+    }
+
+    fn persisted(data_dir: &Path) -> Vec<String> {
+        let events: Vec<InstanceEvent> = store::load_json(&data_dir.join(TIMELINE_FILE)).unwrap();
+        events.into_iter().map(|event| event.event).collect()
+    }
+
+    #[tokio::test]
+    async fn a_burst_of_events_is_written_once_after_the_delay() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let timeline = TimelineStore::load(data_dir.path()).unwrap();
+        for name in ["created", "stopped", "started"] {
+            timeline.add(event("ab12", name));
+        }
+        assert!(persisted(data_dir.path()).is_empty());
+
+        tokio::time::sleep(FLUSH_DELAY).await;
+        for _ in 0..500 {
+            if !persisted(data_dir.path()).is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(persisted(data_dir.path()), ["created", "stopped", "started"]);
+
+        // Events after that are written by the next flush, or on shutdown
+        timeline.add(event("ab12", "deleted"));
+        timeline.flush();
+        assert_eq!(persisted(data_dir.path()), ["created", "stopped", "started", "deleted"]);
+    }
+}
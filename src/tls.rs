@@ -0,0 +1,147 @@
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use rocket::config::{MutualTls, TlsConfig};
+use x509_parser::pem::parse_x509_pem;
+use crate::config::ServerTlsConfig;
+use crate::routes::models::TlsInfo;
+
+/// Agent info reports the agent as degraded once its certificate expires within this many days
+const EXPIRY_WARNING_DAYS: i64 = 14;
+
+/// Expiry of the first certificate in a PEM file
+pub fn certificate_expiry(path: &Path) -> Result<DateTime<Utc>, String> {
+    let pem = std::fs::read(path).map_err(|e| e.to_string())?;
+    let pem = match parse_x509_pem(&pem) {
+        Ok((_, pem)) if pem.label == "CERTIFICATE" => pem,
+        Ok((_, pem)) => return Err(format!("expected a PEM certificate, found {}", pem.label)),
+        Err(e) => return Err(format!("not a PEM certificate: {}", e)),
+    };
+    let certificate = pem.parse_x509().map_err(|e| format!("invalid certificate: {}", e))?;
+    DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| "certificate expiry is out of range".to_string())
+}
+
+/// The certificate the API is served with, read once at startup since rocket doesn't reload it
+pub struct ServerCertificate {
+    expires_at: DateTime<Utc>,
+    client_auth: &'static str,
+}
+
+impl ServerCertificate {
+    pub fn load(config: &ServerTlsConfig) -> Result<Self, String> {
+        Ok(ServerCertificate {
+            expires_at: certificate_expiry(&config.cert)?,
+            client_auth: match (&config.client_ca, config.require_client_cert) {
+                (Some(_), true) => "required",
+                (Some(_), false) => "optional",
+                (None, _) => "none",
+            },
+        })
+    }
+
+    pub fn info(&self, now: DateTime<Utc>) -> TlsInfo {
+        TlsInfo {
+            expires_at: self.expires_at.to_rfc3339(),
+            days_remaining: (self.expires_at - now).num_days(),
+            client_auth: self.client_auth.to_string(),
+        }
+    }
+
+    /// Whether the certificate has expired or is about to
+    pub fn is_expiring(&self, now: DateTime<Utc>) -> bool {
+        (self.expires_at - now).num_days() < EXPIRY_WARNING_DAYS
+    }
+}
+
+/// Rocket's TLS settings for server.tls. Clients presenting a certificate not signed by client_ca
+/// fail the handshake; with require_client_cert so do clients presenting none.
+pub fn rocket_tls(config: &ServerTlsConfig) -> TlsConfig {
+    let tls = TlsConfig::from_paths(&config.cert, &config.key);
+    match &config.client_ca {
+        Some(ca) => tls.with_mutual(MutualTls::from_path(ca).mandatory(config.require_client_cert)),
+        None => tls,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use serde_json::Value;
+    use crate::testing::TestAgent;
+
+    // Runs the openssl CLI in `dir` to generate test certificates
+    fn openssl(dir: &Path, args: &[&str]) {
+        let output = Command::new("openssl").args(args).current_dir(dir).output()
+            .expect("the openssl CLI is needed to generate test certificates");
+        assert!(output.status.success(), "openssl {}: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    }
+
+    // A self-signed certificate for localhost valid for 10 days, a CA, and a client certificate it signed
+    fn generate(dir: &Path) {
+        let key = ["-newkey", "ec", "-pkeyopt", "ec_paramgen_curve:prime256v1", "-nodes"];
+        openssl(dir, &[&["req", "-x509"][..], &key, &["-keyout", "server.key", "-out", "server.pem", "-days", "10", "-subj", "/CN=localhost", "-addext", "subjectAltName=DNS:localhost"]].concat());
+        openssl(dir, &[&["req", "-x509"][..], &key, &["-keyout", "ca.key", "-out", "ca.pem", "-days", "30", "-subj", "/CN=Maestro Test CA"]].concat());
+        openssl(dir, &[&["req"][..], &key, &["-keyout", "client.key", "-out", "client.csr", "-subj", "/CN=master"]].concat());
+        openssl(dir, &["x509", "-req", "-in", "client.csr", "-CA", "ca.pem", "-CAkey", "ca.key", "-CAcreateserial", "-out", "client.pem", "-days", "30"]);
+    }
+
+    fn tls_config(dir: &Path, client_ca: Option<PathBuf>) -> ServerTlsConfig {
+        ServerTlsConfig {
+            cert: dir.join("server.pem"),
+            key: dir.join("server.key"),
+            require_client_cert: client_ca.is_some(),
+            client_ca,
+        }
+    }
+
+    // A client trusting only the generated certificate, presenting the client certificate if asked to
+    fn client(dir: &Path, address: SocketAddr, identity: bool) -> reqwest::Client {
+        let server = std::fs::read(dir.join("server.pem")).unwrap();
+        let mut builder = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&server).unwrap())
+            .resolve("localhost", address);
+        if identity {
+            let cert = std::fs::read(dir.join("client.pem")).unwrap();
+            let key = std::fs::read(dir.join("client.key")).unwrap();
+            builder = builder.identity(reqwest::Identity::from_pkcs8_pem(&cert, &key).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_generated_certificate_is_loaded_and_served() {
+        let certs = tempfile::tempdir().unwrap();
+        generate(certs.path());
+        let agent = TestAgent::with_config(|config| config.server.tls = Some(tls_config(certs.path(), None))).await;
+        let address = agent.serve().await;
+
+        let url = format!("https://localhost:{}/agent/info", address.port());
+        let info: Value = client(certs.path(), address, false).get(&url).send().await.unwrap().json().await.unwrap();
+        assert_eq!(info["tls"]["client_auth"], "none");
+        assert_eq!(info["tls"]["days_remaining"], 9);
+        // Expiring within EXPIRY_WARNING_DAYS
+        assert_eq!(info["status"], "degraded");
+
+        // Nothing is served in plaintext, and clients that don't trust the certificate are turned away
+        assert!(reqwest::get(format!("http://localhost:{}/agent/info", address.port())).await.is_err());
+        assert!(reqwest::get(&url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn clients_without_a_certificate_from_the_ca_are_rejected() {
+        let certs = tempfile::tempdir().unwrap();
+        generate(certs.path());
+        let agent = TestAgent::with_config(|config| config.server.tls = Some(tls_config(certs.path(), Some(certs.path().join("ca.pem"))))).await;
+        let address = agent.serve().await;
+        let url = format!("https://localhost:{}/agent/info", address.port());
+
+        assert!(client(certs.path(), address, false).get(&url).send().await.is_err());
+        let response = client(certs.path(), address, true).get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let info: Value = response.json().await.unwrap();
+        assert_eq!(info["tls"]["client_auth"], "required");
+    }
+}
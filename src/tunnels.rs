@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use crate::clock::Clock;
+use crate::routes::models::TunnelInfo;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+struct Tunnel {
+    info: TunnelInfo,
+    target: SocketAddr,
+    active_connections: AtomicU32,
+    total_connections: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    close: watch::Sender<bool>,
+}
+
+impl Tunnel {
+    fn snapshot(&self) -> TunnelInfo {
+        TunnelInfo {
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            ..self.info.clone()
+        }
+    }
+}
+
+/// Temporary TCP forwards from a port on the agent host to a container port
+pub struct TunnelRegistry {
+    bind_address: String,
+    tunnels: Mutex<HashMap<String, Arc<Tunnel>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TunnelRegistry {
+    /// Tunnel listeners bind to `bind_address`, the same address the API listens on
+    pub fn new(bind_address: String, clock: Arc<dyn Clock>) -> Self {
+        TunnelRegistry {
+            bind_address,
+            tunnels: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    /// Opens a tunnel on a random port forwarding to `target`. It closes by itself after `ttl`,
+    /// and connections beyond `max_connections` at a time are refused.
+    pub async fn open(self: &Arc<Self>, instance_id: &str, instance_name: &str, target: SocketAddr, ttl: Duration, max_connections: u32) -> io::Result<TunnelInfo> {
+        let listener = TcpListener::bind((self.bind_address.as_str(), 0)).await?;
+        let address = listener.local_addr()?;
+        let now = self.clock.now();
+
+        let (close, _) = watch::channel(false);
+        let tunnel = Arc::new(Tunnel {
+            info: TunnelInfo {
+                id: uuid::Uuid::new_v4().to_string(),
+                instance_id: instance_id.to_string(),
+                instance_name: instance_name.to_string(),
+                address: address.to_string(),
+                target: target.to_string(),
+                created_at: now.to_rfc3339(),
+                expires_at: (now + ttl).to_rfc3339(),
+                max_connections,
+                active_connections: 0,
+                total_connections: 0,
+                bytes_in: 0,
+                bytes_out: 0,
+            },
+            target,
+            active_connections: AtomicU32::new(0),
+            total_connections: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            close,
+        });
+        self.tunnels.lock().unwrap().insert(tunnel.info.id.clone(), tunnel.clone());
+        tracing::info!("Opened tunnel {} on {} to {} ({}) for {}s", tunnel.info.id, address, instance_name, target, ttl.as_secs());
+
+        // Subscribed before serving starts, so a close right after opening isn't missed
+        let closed = tunnel.close.subscribe();
+        tokio::spawn(self.clone().serve(tunnel.clone(), listener, closed, ttl));
+        Ok(tunnel.info.clone())
+    }
+
+    async fn serve(self: Arc<Self>, tunnel: Arc<Tunnel>, listener: TcpListener, mut closed: watch::Receiver<bool>, ttl: Duration) {
+        let expiry = tokio::time::sleep(ttl);
+        tokio::pin!(expiry);
+
+        loop {
+            tokio::select! {
+                _ = &mut expiry => {
+                    tracing::info!("Tunnel {} expired", tunnel.info.id);
+                    break;
+                },
+                _ = closed.changed() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((socket, peer)) => {
+                        if tunnel.active_connections.load(Ordering::Relaxed) >= tunnel.info.max_connections {
+                            tracing::warn!("Refused connection from {} to tunnel {}: {} connections already open", peer, tunnel.info.id, tunnel.info.max_connections);
+                            continue;
+                        }
+                        tunnel.active_connections.fetch_add(1, Ordering::Relaxed);
+                        tunnel.total_connections.fetch_add(1, Ordering::Relaxed);
+                        tokio::spawn(forward(tunnel.clone(), socket, tunnel.close.subscribe()));
+                    },
+                    Err(e) => tracing::warn!("Tunnel {} failed to accept a connection: {}", tunnel.info.id, e),
+                },
+            }
+        }
+
+        // Dropping the listener frees the port; open connections are told to wind down
+        tunnel.close.send_replace(true);
+        self.tunnels.lock().unwrap().remove(&tunnel.info.id);
+    }
+
+    pub fn list(&self) -> Vec<TunnelInfo> {
+        let mut tunnels: Vec<TunnelInfo> = self.tunnels.lock().unwrap().values()
+            .map(|tunnel| tunnel.snapshot())
+            .collect();
+        tunnels.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        tunnels
+    }
+
+    /// Closes a tunnel and its connections, returning whether it was open
+    pub fn close(&self, id: &str) -> bool {
+        match self.tunnels.lock().unwrap().remove(id) {
+            Some(tunnel) => {
+                tracing::info!("Closing tunnel {}", id);
+                tunnel.close.send_replace(true);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn close_all(&self) {
+        for (_, tunnel) in self.tunnels.lock().unwrap().drain() {
+            tunnel.close.send_replace(true);
+        }
+    }
+}
+
+async fn forward(tunnel: Arc<Tunnel>, client: TcpStream, mut closed: watch::Receiver<bool>) {
+    match TcpStream::connect(tunnel.target).await {
+        Ok(upstream) => {
+            let (mut client_read, mut client_write) = client.into_split();
+            let (mut upstream_read, mut upstream_write) = upstream.into_split();
+            tokio::select! {
+                _ = async {
+                    tokio::join!(
+                        pipe(&mut client_read, &mut upstream_write, &tunnel.bytes_in),
+                        pipe(&mut upstream_read, &mut client_write, &tunnel.bytes_out),
+                    )
+                } => {},
+                _ = closed.changed() => {},
+            }
+        },
+        Err(e) => tracing::warn!("Tunnel {} couldn't reach {}: {}", tunnel.info.id, tunnel.target, e),
+    }
+    tunnel.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+// Copy one direction until EOF, then pass the half-close on so request/response protocols still work
+async fn pipe(reader: &mut (impl AsyncRead + Unpin), writer: &mut (impl AsyncWrite + Unpin), counter: &AtomicU64) {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                if writer.write_all(&buffer[..read]).await.is_err() {
+                    break;
+                }
+                counter.fetch_add(read as u64, Ordering::Relaxed);
+            }
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use crate::clock::TestClock;
+
+    // A local TCP echo server standing in for the container port
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = socket.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        address
+    }
+
+    fn registry() -> Arc<TunnelRegistry> {
+        Arc::new(TunnelRegistry::new("127.0.0.1".to_string(), Arc::new(TestClock::at(Utc::now()))))
+    }
+
+    async fn round_trip(address: &str, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut socket = TcpStream::connect(address).await?;
+        socket.write_all(message).await?;
+        socket.shutdown().await?;
+        let mut echoed = Vec::new();
+        socket.read_to_end(&mut echoed).await?;
+        Ok(echoed)
+    }
+
+    #[tokio::test]
+    async fn bytes_make_the_round_trip_and_are_counted() {
+        let registry = registry();
+        let tunnel = registry.open("ab12", "game", echo_server().await, Duration::from_secs(60), 4).await.unwrap();
+
+        assert_eq!(round_trip(&tunnel.address, b"ping").await.unwrap(), b"ping");
+        assert_eq!(round_trip(&tunnel.address, b"hello tunnel").await.unwrap(), b"hello tunnel");
+
+        // Counters are updated by the forwarding tasks as they wind down
+        for _ in 0..100 {
+            if registry.list()[0].active_connections == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let listed = &registry.list()[0];
+        assert_eq!(listed.total_connections, 2);
+        assert_eq!(listed.active_connections, 0);
+        assert_eq!(listed.bytes_in, 16);
+        assert_eq!(listed.bytes_out, 16);
+    }
+
+    #[tokio::test]
+    async fn closed_and_expired_tunnels_stop_listening() {
+        let registry = registry();
+        let target = echo_server().await;
+        let closed = registry.open("ab12", "game", target, Duration::from_secs(60), 4).await.unwrap();
+        let expiring = registry.open("ab12", "game", target, Duration::from_millis(100), 4).await.unwrap();
+
+        assert!(registry.close(&closed.id));
+        assert!(!registry.close(&closed.id));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert!(registry.list().is_empty());
+        for tunnel in [closed, expiring] {
+            assert!(round_trip(&tunnel.address, b"ping").await.is_err_and(|e| e.kind() == io::ErrorKind::ConnectionRefused), "{}", tunnel.address);
+        }
+    }
+}
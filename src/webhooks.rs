@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use std::time::Duration;
+use prometheus::IntCounterVec;
+use serde_json::{json, Value};
+use crate::clock::Clock;
+use crate::config::WebhookConfig;
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delivers agent events to the webhooks configured in agent.toml
+#[derive(Clone)]
+pub struct Notifier {
+    client: reqwest::Client,
+    hooks: Vec<WebhookConfig>,
+    deliveries: IntCounterVec,
+    clock: Arc<dyn Clock>,
+}
+
+impl Notifier {
+    pub fn new(hooks: Vec<WebhookConfig>, client: reqwest::Client, deliveries: IntCounterVec, clock: Arc<dyn Clock>) -> Self {
+        Notifier {
+            client,
+            hooks,
+            deliveries,
+            clock,
+        }
+    }
+
+    /// Posts `event` to every subscribed webhook in the background; failures are logged and counted, never returned
+    pub fn notify(&self, event: &str, data: Value) {
+        let body = json!({
+            "event": event,
+            "timestamp": self.clock.now().to_rfc3339(),
+            "data": data,
+        });
+
+        let subscribed = self.hooks.iter()
+            .filter(|hook| hook.events.is_empty() || hook.events.iter().any(|e| e == event));
+
+        for hook in subscribed {
+            let request = self.client.post(&hook.url)
+                .timeout(DELIVERY_TIMEOUT)
+                .json(&body);
+            let deliveries = self.deliveries.clone();
+            let url = hook.url.clone();
+            let event = event.to_string();
+
+            tokio::spawn(async move {
+                match request.send().await.and_then(|response| response.error_for_status()) {
+                    Ok(_) => deliveries.with_label_values(&["success"]).inc(),
+                    Err(e) => {
+                        tracing::warn!("Webhook delivery of {} to {} failed: {}", event, url, e);
+                        deliveries.with_label_values(&["failure"]).inc();
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use chrono::TimeDelta;
+    use serde_json::json;
+    use crate::config::WebhookConfig;
+    use crate::testing::{DockerResponse, FakeDocker, TestAgent};
+
+    #[tokio::test]
+    async fn events_are_stamped_with_the_agent_clock() {
+        // The fake daemon doubles as a plain HTTP receiver
+        let receiver = FakeDocker::start().await;
+        receiver.route("POST", "/hooks/maestro", |_| DockerResponse::ok(json!({})));
+        let url = format!("{}/hooks/maestro", receiver.endpoint().replace("tcp://", "http://"));
+        let agent = TestAgent::with_config(|config| config.webhooks = vec![WebhookConfig {
+            url,
+            events: vec!["instance.created".to_string()],
+        }]).await;
+        agent.clock.advance(TimeDelta::days(-30));
+
+        agent.app_manager.notifier.notify("instance.deleted", json!({ "id": "unsubscribed" }));
+        agent.app_manager.notifier.notify("instance.created", json!({ "id": "abc123" }));
+        let mut delivered = Vec::new();
+        for _ in 0..200 {
+            delivered = receiver.requests_to("POST", "/hooks/maestro");
+            if !delivered.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(delivered.len(), 1);
+        let body = delivered[0].json();
+        assert_eq!(body["event"], "instance.created");
+        assert_eq!(body["data"]["id"], "abc123");
+        assert_eq!(body["timestamp"], agent.app_manager.now().to_rfc3339());
+    }
+}